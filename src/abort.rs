@@ -0,0 +1,76 @@
+use crate::app;
+use crate::commits_map;
+use crate::error::Error;
+use crate::info;
+use crate::keep_refs;
+use crate::log;
+use crate::rerere;
+use crate::state;
+use crate::sync;
+
+/// Find the branch whose synchronization stopped, when no state was saved
+///
+/// This is the checked out branch if it is synchronized, the first branch otherwise.
+fn find_stopped_branch(repo: &git2::Repository, opts: &app::Options) -> Result<String, Error> {
+    let head = repo.head().ok();
+    let head_name = head.as_ref().and_then(|head| head.name());
+
+    opts.branches
+        .iter()
+        .find(|branch| Some(branch.refname.as_str()) == head_name)
+        .or_else(|| opts.branches.first())
+        .map(|branch| branch.refname.clone())
+        .ok_or(Error::StoppedBranchUnknown)
+}
+
+/// Abort a synchronization stopped on conflicts
+///
+/// The working directory and the index are reset on the local branch, which is set on the
+/// last copied commit. The commits copied on a detached HEAD are abandoned: their
+/// references and their entries in the cache are removed.
+pub fn abort(repo: &git2::Repository, opts: &app::Options) -> Result<(), Error> {
+    let state = state::read(repo)?;
+    let in_progress = state.is_some()
+        || repo.state() != git2::RepositoryState::Clean
+        || repo.index()?.has_conflicts();
+    if !in_progress {
+        return Err(Error::NoSyncInProgress);
+    }
+
+    let refname = match &state {
+        Some(state) => state.refname.clone(),
+        None => find_stopped_branch(repo, opts)?,
+    };
+    let branch_id = repo.refname_to_id(&refname)?;
+
+    repo.cleanup_state()?;
+    sync::checkout_ref(repo, &refname)?;
+    repo.reset(
+        &repo.find_object(branch_id, None)?,
+        git2::ResetType::Hard,
+        None,
+    )?;
+
+    keep_refs::release_all(repo)?;
+    let mut heads = vec![branch_id];
+    heads.extend(
+        opts.branches
+            .iter()
+            .filter_map(|branch| repo.refname_to_id(&branch.refname).ok()),
+    );
+    let nb_removed = commits_map::prune_cache(repo, &heads)?;
+    rerere::forget_preimages(repo);
+    state::remove(repo);
+
+    if let Some(state) = &state {
+        info!(
+            "Aborted the copy of commit {}.",
+            log::remote_commit(state.remote_id)
+        );
+    }
+    info!("Reset {} on commit {}.", refname, branch_id);
+    if nb_removed > 0 {
+        info!("Removed {} abandoned commits from the cache.", nb_removed);
+    }
+    Ok(())
+}
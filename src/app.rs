@@ -1,15 +1,278 @@
+use crate::authors;
 use crate::error;
+use crate::log;
+use crate::tag;
+use crate::util;
 use clap::{
     builder::{Arg, Command},
-    ArgAction,
+    ArgAction, ArgMatches,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
 pub struct Branch {
-    // name of the branch to synchronize
+    // name of the local branch to synchronize
     pub name: String,
+    // name of the branch in the remote, the name of the local branch by default
+    pub remote_name: String,
     // full ref name for the local branch
     pub refname: String,
+    // full ref name of the branch in the remote repository
+    pub remote_refname: String,
+    // full ref name in which the remote branch is fetched
+    pub tracking_refname: String,
+    // recreate the local branch if it is missing
+    pub create_missing: bool,
+    // allow uprooting the commits with unknown parents
+    pub uproot: bool,
+    // remote to which the branch is pushed once synchronized
+    pub push_remote: Option<String>,
+    // remote revision at which the branch is bootstrapped, given with --at
+    pub until: Option<String>,
+}
+
+// Branches of the remote matching a glob pattern, added to the branches to synchronize
+// once the branches of the remote are listed
+pub struct BranchPattern {
+    pub pattern: String,
+    // settings of the matching branches
+    create_missing: bool,
+    uproot: bool,
+    push_remote: Option<String>,
+}
+
+impl BranchPattern {
+    pub fn matches(&self, name: &str) -> bool {
+        util::glob_match(&self.pattern, name)
+    }
+
+    /// Branch to synchronize for a matching branch of the remote
+    pub fn branch(&self, name: &str, remote: &str) -> Branch {
+        Branch {
+            name: name.to_owned(),
+            remote_name: name.to_owned(),
+            refname: format!("refs/heads/{}", name),
+            remote_refname: format!("refs/heads/{}", name),
+            tracking_refname: format!("refs/remotes/{}/{}", remote, name),
+            create_missing: self.create_missing,
+            uproot: self.uproot,
+            push_remote: self.push_remote.clone(),
+            until: None,
+        }
+    }
+}
+
+pub enum SubCommand {
+    // print statistics about the synchronized commits
+    Stats {
+        // only consider commits more recent than this timestamp
+        since: Option<i64>,
+        json: bool,
+    },
+    // export local commits to the remote
+    Export {
+        // local revision from which to export commits
+        since: Option<String>,
+        // directory in which patches are written, instead of pushing to the remote
+        patches: Option<String>,
+    },
+    // list the remote commits, and whether they were synchronized
+    Audit {
+        // remote revision from which to list commits
+        since: Option<String>,
+        json: bool,
+    },
+    // map the lines of a file to the remote commits that introduced them
+    Blame {
+        path: String,
+        // local revision to blame, HEAD if unset
+        rev: Option<String>,
+    },
+    // record in notes the ripit tags of the messages of the synchronized commits
+    MigrateNotes,
+}
+
+// Group of paths, whose changes are copied in a separate commit
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplitRule {
+    // name of the group, added in the message of the commit
+    pub name: String,
+    // directories or files of the group
+    pub paths: Vec<String>,
+}
+
+// Version kept when resolving automatically the conflicts of a path
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictSide {
+    // the version of the local branch
+    Ours,
+    // the version of the remote commit being copied
+    Theirs,
+}
+
+// Paths whose conflicts are resolved automatically, with the version to keep
+#[derive(Clone)]
+pub struct ConflictRule {
+    // glob pattern, matched against the path or the name of the file
+    pub pattern: String,
+    pub side: ConflictSide,
+}
+
+// Patch copied instead of the changes of a remote commit
+#[derive(Clone)]
+pub struct Replacement {
+    // full or abbreviated id of the remote commit, in lowercase
+    pub sha: String,
+    // path of the patch, relative to the directory of the configuration file
+    pub path: String,
+    pub patch: Vec<u8>,
+}
+
+// How to handle the ripit tags found in the messages of the remote commits
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForeignTags {
+    // keep them as is
+    Keep,
+    // remove them from the message
+    Strip,
+    // rename them, so that they are not recognized as ripit tags
+    Neutralize,
+}
+
+// Output left out of a run that synchronized nothing, with --quiet-if-unchanged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuietIfUnchanged {
+    // the messages and the JSON summary
+    All,
+    // the messages only, the JSON summary being printed anyway
+    Text,
+}
+
+// Where the ripit tags of the synchronized commits are recorded
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provenance {
+    // in the message of the commits
+    Trailers,
+    // in notes attached to the commits, under refs/notes/ripit
+    Notes,
+    // in both the messages and the notes
+    Both,
+    // only in the tags file next to the cache, the commits carrying no remote commit id
+    #[serde(rename = "cache-only")]
+    CacheOnly,
+    // in the message of the commits, with the MAC of the remote commit id instead of the id
+    Hmac,
+}
+
+impl Provenance {
+    /// Returns whether the ids of the remote commits must not appear in the local commits
+    pub fn hides_remote_ids(self) -> bool {
+        matches!(self, Provenance::CacheOnly | Provenance::Hmac)
+    }
+}
+
+// Answer given to a question asked to the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Answer {
+    Yes,
+    No,
+    // no answer could be obtained, such as when the input is closed
+    Abort,
+}
+
+// Dates of the commits created from remote commits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dates {
+    // author and committer dates of the remote commit
+    Preserve,
+    // time of the synchronization
+    Now,
+    // author date of the remote commit, and time of the synchronization as committer date
+    AuthorOnly,
+}
+
+// Outcome of a run, for which a notification can be sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyEvent {
+    Success,
+    // the synchronization stopped on conflicts
+    Conflict,
+    Error,
+}
+
+// Notification of the outcome of the runs to a webhook
+pub struct Notify {
+    pub url: String,
+    // seconds to wait for the connection, and for the answer of the server
+    pub timeout: u64,
+    // outcomes notified
+    pub on: Vec<NotifyEvent>,
+}
+
+// Callback asking a question to the user
+pub type Confirm = dyn Fn(&str) -> Result<Answer, error::Error> + Send + Sync;
+
+// Credentials used to connect to the remotes
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Auth {
+    // private key tried after the SSH agent, its passphrase is read from
+    // RIPIT_SSH_PASSPHRASE
+    pub ssh_key: Option<String>,
+    // try the keys of the SSH agent, true if unset
+    ssh_agent: Option<bool>,
+}
+
+// Format of the signatures of the commits, from the gpg.format git config
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SigningFormat {
+    OpenPgp,
+    Ssh,
+}
+
+// How the commits created by ripit are signed
+pub struct Signing {
+    pub format: SigningFormat,
+    // program creating the signatures, gpg or ssh-keygen by default
+    pub program: String,
+    // key used to sign, the default key of gpg if unset
+    pub key: Option<String>,
+}
+
+// Commands run at given points of the synchronization
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    // run before creating each copied commit, its failure aborts the synchronization
+    pub pre_commit: Option<String>,
+    // run after creating each copied commit
+    pub post_commit: Option<String>,
+    // abort the synchronization when the post_commit hook fails, instead of warning
+    #[serde(default)]
+    pub post_commit_fatal: bool,
+    // run once every branch is synchronized
+    pub post_run: Option<String>,
+}
+
+impl Auth {
+    pub fn use_ssh_agent(&self) -> bool {
+        self.ssh_agent.unwrap_or(true)
+    }
+}
+
+// Maintenance step run after large synchronizations
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Maintenance {
+    // write the commit-graph file of the repository
+    CommitGraph,
 }
 
 pub struct Options {
@@ -17,33 +280,988 @@ pub struct Options {
     pub repo: String,
     // name of the remote to synchronize from
     pub remote: String,
+    // url with which the remote is created if missing
+    pub remote_url: Option<String>,
+    // mirrors of the remote, fetched in order instead of the remote
+    pub remote_urls: Vec<String>,
 
     // branches to synchronize
     pub branches: Vec<Branch>,
+    // patterns of remote branches to synchronize as well
+    pub branch_patterns: Vec<BranchPattern>,
+    // patterns of remote branches never matched by the branch patterns
+    pub exclude_branches: Vec<String>,
 
     pub commit_msg_filters: regex::RegexSet,
+    // command through which the messages of the commits are piped
+    pub message_filter_cmd: Option<String>,
+    // commits whose message matches are not synchronized
+    pub skip_commits: regex::RegexSet,
+    // full or abbreviated ids of the remote commits not synchronized, in lowercase
+    pub skip_shas: Vec<String>,
+    // remote commits whose changes are replaced with local patches
+    pub replacements: Vec<Replacement>,
+
+    // stop the synchronization before the first commit matching this regex
+    pub stop_before: Option<regex::Regex>,
+    // remote revision up to which the branches are synchronized, instead of their heads
+    pub until: Option<String>,
+    // maximum number of commits synchronized on each branch
+    pub max_commits: Option<usize>,
+    // number of first-parent commits copied after the bootstrap commit, keeping their history
+    pub keep_history: Option<usize>,
 
     pub bootstrap: bool,
-    pub uproot: bool,
-    pub verbose: bool,
+    // remote revision whose state the local branches already have, instead of bootstrapping
+    pub adopt: Option<String>,
+    // abort a synchronization stopped on conflicts
+    pub abort: bool,
+    // undo the last synchronization of the branches
+    pub rollback: bool,
+    // commit the resolution of the conflicts of a stopped synchronization, and resume it
+    pub resume: bool,
+    // skip the commit whose copy caused conflicts, and resume the synchronization
+    pub skip: bool,
+    // copy the changes of the commits to synchronize in a single commit per branch
+    pub squash: bool,
+    // re-anchor the branches whose remote branch was rewritten on its new history
+    pub force_resync: bool,
+    // level of the messages printed during the run
+    pub log_level: log::Level,
+    // file to which the messages are appended as well
+    pub log_file: Option<String>,
+    pub color: log::ColorMode,
+    // print nothing if nothing was synchronized
+    pub quiet_if_unchanged: Option<QuietIfUnchanged>,
     pub yes: bool,
     pub fetch: bool,
+    pub detached_worktree: bool,
+    // stash the local changes during the run, instead of aborting it
+    pub autostash: bool,
+    // stash the untracked files as well
+    pub autostash_untracked: bool,
+    // remove the lock of the repository, even if held by a running process
+    pub break_lock: bool,
+    // only list the commits to synchronize, without modifying anything
+    pub dry_run: bool,
+    // only check the configuration, the repository and the remote
+    pub check_config: bool,
+    // only verify the consistency between the local and remote branches and the cache
+    pub verify: bool,
+    // also verify that the trees of the local and remote branches match
+    pub verify_trees: bool,
+    // print a JSON summary of the run on stdout, the other outputs going to stderr
+    pub json: bool,
+    // rebuild the cache file from the ripit tags of the branches
+    pub rebuild_cache: bool,
+    // only list the records of the runs
+    pub list_runs: bool,
+    // only write the mapping between the remote and local commits in this file
+    pub export_map: Option<String>,
+    // only seed the cache with the mapping of this file
+    pub import_map: Option<String>,
+    // list the imported local commits without a ripit tag in their message
+    pub write_trailers: bool,
+    // synchronize in a loop, waiting this number of seconds between the cycles
+    pub watch: Option<u64>,
+    // bundle from which the remote commits are fetched, instead of the remote
+    pub source_bundle: Option<String>,
+    // bundle in which the synchronized commits are written
+    pub emit_bundle: Option<String>,
+
+    // persist the synchronization progress every N copied commits, 0 to disable
+    pub checkpoint_every: usize,
+    // number of branches synchronized in parallel
+    pub jobs: usize,
+    // command deciding whether each commit can be synchronized
+    pub commit_gate_cmd: Option<String>,
+    pub foreign_tags: ForeignTags,
+    // command used to resolve conflicts
+    pub mergetool: Option<String>,
+    // paths whose conflicts are resolved automatically, the first matching rule applying
+    pub conflict_strategy: Vec<ConflictRule>,
+    // reuse the recorded resolutions of conflicts
+    pub rerere: bool,
+    // number of days after which an unused resolution is forgotten
+    pub rerere_max_age: u64,
+    // editor command used to edit the messages of the commits
+    pub edit_messages: Option<String>,
+    // pathspecs of the files never copied in the local repository
+    pub exclude_paths: Vec<String>,
+    // directory of the remote whose content is copied at the root of the local repository
+    pub subtree: Option<String>,
+    // directory of the local repository in which the content of the remote is copied
+    pub prefix: Option<String>,
+    // split commits whose changes span several groups of paths
+    pub split_rules: Vec<SplitRule>,
+    // fold fixup and squash commits in the commits they reference
+    pub autosquash: bool,
+    // do not create the commits whose changes already exist locally
+    pub skip_empty: bool,
+    // copy the first-parent history of the remote branches, merges becoming simple commits
+    pub linearize: bool,
+    // group the commits copied by each run under a merge commit
+    pub group_runs: bool,
+    // number of commits above which only the total diffstat is shown before confirming
+    pub diffstat_max_commits: usize,
+    pub maintenance: Option<Maintenance>,
+    // minimum number of copied commits triggering the maintenance step
+    pub maintenance_min_commits: usize,
+    // record each successful run in a reference under refs/ripit/runs
+    pub record_runs: bool,
+    // record the runs in annotated tags of the new tips instead of blobs
+    pub tag_runs: bool,
+    // number of days after which the records of the runs are pruned
+    pub runs_retention_days: Option<u64>,
+    // prefix of the tags written in the messages of the synchronized commits
+    pub tag_prefix: String,
+    pub provenance: Provenance,
+    // keys of the MACs of the hmac provenance, the first one being used to write the tags
+    pub provenance_keys: Vec<Vec<u8>>,
+    // message of the bootstrap commits, with placeholders
+    pub bootstrap_message: Option<String>,
+    // identities used as authors and committers of the synchronized commits
+    pub authors: authors::AuthorsMap,
+    // email replacing the one of the committers of the synchronized commits
+    pub committer_email: Option<String>,
+    // dates of the synchronized commits
+    pub dates: Dates,
+    // identity of the Signed-off-by trailer added to the synchronized commits
+    pub signoff: Option<authors::Identity>,
+    // trailers added to the synchronized commits, in order, with placeholders in values
+    pub trailers: Vec<(String, String)>,
+    // credentials used to fetch from and push to the remotes
+    pub auth: Auth,
+    // commands run before and after each copied commit, and at the end of the run
+    pub hooks: Hooks,
+    // sign the created commits
+    pub signing: Option<Signing>,
+    // create the local copies of the remote tags pointing to synchronized commits
+    pub sync_tags: bool,
+    // fetch the whole remote instead of the synchronized branches only
+    pub fetch_all: bool,
+    // post the outcome of the run to a webhook
+    pub notify: Option<Notify>,
+
+    pub subcommand: Option<SubCommand>,
+
+    // asks the given question to the user, and returns the answer
+    pub confirm: Box<Confirm>,
+}
+
+impl Options {
+    /// Options synchronizing branches of a remote in a repository, with default settings
+    ///
+    /// The other settings can then be changed through the fields of the options. Unlike on
+    /// the command line, confirmations are not asked: set `yes` to false and `confirm` to a
+    /// callback to ask for them.
+    pub fn new(repo: &str, remote: &str, branches: &[&str]) -> Result<Self, error::Error> {
+        let branches = branches
+            .iter()
+            .map(|name| {
+                build_branch(
+                    YamlBranchCfg::named((*name).to_owned()),
+                    remote,
+                    false,
+                    false,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            repo: repo.to_owned(),
+            remote: remote.to_owned(),
+            remote_url: None,
+            remote_urls: Vec::new(),
+            branches,
+            branch_patterns: Vec::new(),
+            exclude_branches: Vec::new(),
+            commit_msg_filters: regex::RegexSet::empty(),
+            message_filter_cmd: None,
+            skip_commits: regex::RegexSet::empty(),
+            skip_shas: Vec::new(),
+            replacements: Vec::new(),
+            stop_before: None,
+            until: None,
+            max_commits: None,
+            keep_history: None,
+
+            bootstrap: false,
+            adopt: None,
+            abort: false,
+            rollback: false,
+            resume: false,
+            skip: false,
+            squash: false,
+            force_resync: false,
+            log_level: log::Level::Info,
+            log_file: None,
+            color: log::ColorMode::Auto,
+            quiet_if_unchanged: None,
+            yes: true,
+            fetch: true,
+            detached_worktree: false,
+            autostash: false,
+            autostash_untracked: false,
+            break_lock: false,
+            dry_run: false,
+            check_config: false,
+            verify: false,
+            verify_trees: false,
+            json: false,
+            rebuild_cache: false,
+            list_runs: false,
+            export_map: None,
+            import_map: None,
+            write_trailers: false,
+            watch: None,
+            source_bundle: None,
+            emit_bundle: None,
+
+            checkpoint_every: 0,
+            jobs: 1,
+            commit_gate_cmd: None,
+            foreign_tags: ForeignTags::Keep,
+            mergetool: None,
+            conflict_strategy: Vec::new(),
+            rerere: true,
+            rerere_max_age: 60,
+            edit_messages: None,
+            exclude_paths: Vec::new(),
+            subtree: None,
+            prefix: None,
+            split_rules: Vec::new(),
+            autosquash: false,
+            skip_empty: false,
+            linearize: false,
+            group_runs: false,
+            diffstat_max_commits: 20,
+            maintenance: None,
+            maintenance_min_commits: 1000,
+            record_runs: false,
+            tag_runs: false,
+            runs_retention_days: None,
+            tag_prefix: tag::DEFAULT_PREFIX.to_owned(),
+            provenance: Provenance::Trailers,
+            provenance_keys: Vec::new(),
+            bootstrap_message: None,
+            authors: authors::AuthorsMap::default(),
+            committer_email: None,
+            dates: Dates::AuthorOnly,
+            signoff: None,
+            trailers: Vec::new(),
+            auth: Auth::default(),
+            hooks: Hooks::default(),
+            signing: None,
+            sync_tags: false,
+            fetch_all: false,
+            notify: None,
+
+            subcommand: None,
+
+            confirm: Box::new(|question| util::confirm(question, false)),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YamlBranchCfg {
+    // the local name if unset, or else the remote name
+    name: Option<String>,
+    // name of the branch in the remote, the name by default
+    remote: Option<String>,
+    // name of the local branch, the name by default
+    local: Option<String>,
+    // full ref name in the remote, refs/heads/<remote> by default
+    source_ref: Option<String>,
+    // full ref name of the local branch, refs/heads/<local> by default
+    local_ref: Option<String>,
+    #[serde(default)]
+    create_missing: bool,
+    #[serde(default)]
+    uproot: bool,
+    // overrides the global push_remote option
+    push_remote: Option<String>,
 }
 
+impl YamlBranchCfg {
+    /// Configuration of a branch given with its name only
+    fn named(name: String) -> Self {
+        Self {
+            name: Some(name),
+            remote: None,
+            local: None,
+            source_ref: None,
+            local_ref: None,
+            create_missing: false,
+            uproot: false,
+            push_remote: None,
+        }
+    }
+
+    /// Name of the branch, displayed and used to select it with --branch
+    fn name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.local.as_deref())
+            .or(self.remote.as_deref())
+            .unwrap_or("")
+    }
+}
+
+// A branch can be configured with its name only, or with a mapping of options
 #[derive(Deserialize)]
+#[serde(untagged)]
+enum YamlBranch {
+    Name(String),
+    Cfg(YamlBranchCfg),
+}
+
+impl YamlBranch {
+    fn name(&self) -> &str {
+        match self {
+            YamlBranch::Name(name) => name,
+            YamlBranch::Cfg(cfg) => cfg.name(),
+        }
+    }
+}
+
+// The remote can be configured with its name only, or with its url as well
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum YamlRemote {
+    Name(String),
+    Cfg(YamlRemoteCfg),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YamlRemoteCfg {
+    name: String,
+    // url with which the remote is created if missing
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct YamlCfg {
     repo: Option<String>,
-    remote: String,
-    // TODO:  add uproot option per branch
+    remote: Option<YamlRemote>,
+    remote_urls: Option<Vec<String>>,
     branch: Option<String>,
-    branches: Option<Vec<String>>,
+    branches: Option<Vec<YamlBranch>>,
     filters: Option<Vec<String>>,
+    message_filter_cmd: Option<String>,
+    checkpoint_every: Option<usize>,
+    jobs: Option<usize>,
+    commit_gate_cmd: Option<String>,
+    foreign_tags: Option<ForeignTags>,
+    mergetool_cmd: Option<String>,
+    conflict_strategy: Option<serde_yaml::Mapping>,
+    rerere: Option<bool>,
+    rerere_max_age: Option<u64>,
+    editor_cmd: Option<String>,
+    log_file: Option<String>,
+    split_rules: Option<Vec<SplitRule>>,
+    autosquash: Option<bool>,
+    skip_empty: Option<bool>,
+    linearize: Option<bool>,
+    group_runs: Option<bool>,
+    diffstat_max_commits: Option<usize>,
+    default_answer: Option<Answer>,
+    maintenance: Option<Maintenance>,
+    maintenance_min_commits: Option<usize>,
+    record_runs: Option<bool>,
+    tag_runs: Option<bool>,
+    runs_retention_days: Option<u64>,
+    source_bundle: Option<String>,
+    tag_prefix: Option<String>,
+    provenance: Option<Provenance>,
+    // names of the environment variables containing the keys of the hmac provenance
+    provenance_key: Option<String>,
+    provenance_previous_key: Option<String>,
+    bootstrap_message: Option<String>,
+    // original email -> `name <email>`
+    authors: Option<std::collections::HashMap<String, String>>,
+    dates: Option<Dates>,
+    default_author: Option<String>,
+    committer_email: Option<String>,
+    keep_original_author_trailer: Option<bool>,
+    signoff: Option<bool>,
+    signoff_identity: Option<String>,
+    trailers: Option<serde_yaml::Mapping>,
+    sync_tags: Option<bool>,
+    fetch_all: Option<bool>,
+    autostash: Option<bool>,
+    autostash_untracked: Option<bool>,
+    notify_url: Option<String>,
+    notify_timeout: Option<u64>,
+    notify_on: Option<Vec<NotifyEvent>>,
+    max_commits: Option<usize>,
+    keep_history: Option<usize>,
+    skip_commits: Option<Vec<String>>,
+    skip_shas: Option<Vec<String>>,
+    // remote commit id -> path of the patch
+    replacements: Option<std::collections::BTreeMap<String, String>>,
+    exclude_branches: Option<Vec<String>>,
+    exclude_paths: Option<Vec<String>>,
+    subtree: Option<String>,
+    prefix: Option<String>,
+    push_remote: Option<String>,
+    auth: Option<Auth>,
+    hooks: Option<Hooks>,
+    sign_commits: Option<bool>,
+    signing_key: Option<String>,
+    signing_program: Option<String>,
+}
+
+// Known options of the configuration file, and of its nested sections. The options are
+// checked before the deserialization, to suggest the closest known one on typos.
+const CFG_KEYS: &[&str] = &[
+    "repo",
+    "remote",
+    "remote_urls",
+    "branch",
+    "branches",
+    "filters",
+    "message_filter_cmd",
+    "checkpoint_every",
+    "jobs",
+    "commit_gate_cmd",
+    "foreign_tags",
+    "mergetool_cmd",
+    "conflict_strategy",
+    "rerere",
+    "rerere_max_age",
+    "editor_cmd",
+    "log_file",
+    "split_rules",
+    "autosquash",
+    "skip_empty",
+    "linearize",
+    "group_runs",
+    "diffstat_max_commits",
+    "default_answer",
+    "maintenance",
+    "maintenance_min_commits",
+    "record_runs",
+    "tag_runs",
+    "runs_retention_days",
+    "source_bundle",
+    "tag_prefix",
+    "provenance",
+    "provenance_key",
+    "provenance_previous_key",
+    "bootstrap_message",
+    "authors",
+    "dates",
+    "default_author",
+    "committer_email",
+    "keep_original_author_trailer",
+    "signoff",
+    "signoff_identity",
+    "trailers",
+    "sync_tags",
+    "fetch_all",
+    "autostash",
+    "autostash_untracked",
+    "notify_url",
+    "notify_timeout",
+    "notify_on",
+    "max_commits",
+    "keep_history",
+    "skip_commits",
+    "skip_shas",
+    "replacements",
+    "exclude_branches",
+    "exclude_paths",
+    "subtree",
+    "prefix",
+    "push_remote",
+    "auth",
+    "hooks",
+    "sign_commits",
+    "signing_key",
+    "signing_program",
+];
+const BRANCH_CFG_KEYS: &[&str] = &[
+    "name",
+    "remote",
+    "local",
+    "source_ref",
+    "local_ref",
+    "create_missing",
+    "uproot",
+    "push_remote",
+];
+const REMOTE_CFG_KEYS: &[&str] = &["name", "url"];
+const SPLIT_RULE_KEYS: &[&str] = &["name", "paths"];
+const AUTH_KEYS: &[&str] = &["ssh_key", "ssh_agent"];
+const HOOKS_KEYS: &[&str] = &["pre_commit", "post_commit", "post_commit_fatal", "post_run"];
+
+/// Find the first unknown key of a mapping, with the closest known key
+fn find_unknown_key(
+    value: &serde_yaml::Value,
+    known: &[&'static str],
+) -> Option<(String, Option<&'static str>)> {
+    let mapping = value.as_mapping()?;
+    mapping.keys().find_map(|key| {
+        let key = match key {
+            serde_yaml::Value::String(key) => key.clone(),
+            // non string keys are reported by serde
+            _ => return None,
+        };
+        if known.contains(&key.as_str()) {
+            None
+        } else {
+            let suggestion = util::closest_match(&key, known);
+            Some((key, suggestion))
+        }
+    })
+}
+
+/// Check that the configuration only contains known options
+///
+/// Unknown options are reported with the closest known option, as serde only lists the
+/// expected ones.
+fn check_cfg_keys(path: &str, value: &serde_yaml::Value) -> Result<(), error::Error> {
+    let mut sections = vec![(None, value, CFG_KEYS)];
+    if let Some(remote) = value.get("remote") {
+        sections.push((Some("remote"), remote, REMOTE_CFG_KEYS));
+    }
+    if let Some(auth) = value.get("auth") {
+        sections.push((Some("auth"), auth, AUTH_KEYS));
+    }
+    if let Some(hooks) = value.get("hooks") {
+        sections.push((Some("hooks"), hooks, HOOKS_KEYS));
+    }
+    let items = |key| {
+        value
+            .get(key)
+            .and_then(serde_yaml::Value::as_sequence)
+            .into_iter()
+            .flatten()
+    };
+    sections.extend(items("branches").map(|b| (Some("branches"), b, BRANCH_CFG_KEYS)));
+    sections.extend(items("split_rules").map(|r| (Some("split_rules"), r, SPLIT_RULE_KEYS)));
+
+    for (section, value, known) in sections {
+        if let Some((key, suggestion)) = find_unknown_key(value, known) {
+            return Err(error::Error::UnknownCfgKey {
+                path: path.to_owned(),
+                key,
+                section,
+                suggestion,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Parse the path of a directory of a repository, without its leading and trailing slashes
+fn parse_dir_path(field: &'static str, path: String) -> Result<String, error::Error> {
+    let trimmed = path.trim_matches('/');
+    if trimmed.is_empty()
+        || trimmed
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        return Err(error::Error::InvalidValue { field, value: path });
+    }
+    Ok(trimmed.to_owned())
+}
+
+/// Build a branch to synchronize from its configuration
+///
+/// Branches are looked up in the heads namespace, unless full ref names are given. Remote
+/// branches are fetched in the remote-tracking namespace, and other remote refs under
+/// `refs/ripit/remotes/<remote>/`.
+fn build_branch(
+    cfg: YamlBranchCfg,
+    remote: &str,
+    create_missing_branch: bool,
+    uproot_all: bool,
+) -> Result<Branch, error::Error> {
+    let invalid = |value: String| error::Error::InvalidValue {
+        field: "branches",
+        value,
+    };
+    let name = cfg.name().to_owned();
+    if name.is_empty() {
+        return Err(invalid(
+            "a branch needs a name, or a remote or local name".to_owned(),
+        ));
+    }
+    let YamlBranchCfg {
+        remote: remote_name,
+        local,
+        source_ref,
+        local_ref,
+        create_missing,
+        uproot,
+        push_remote,
+        ..
+    } = cfg;
+    if (remote_name.is_some() && source_ref.is_some()) || (local.is_some() && local_ref.is_some()) {
+        return Err(invalid(format!(
+            "branch {} cannot have both a name and a full ref name",
+            name
+        )));
+    }
+    let local = local.unwrap_or_else(|| name.clone());
+    let remote_name = remote_name.unwrap_or_else(|| name.clone());
+    let refname = local_ref.unwrap_or_else(|| format!("refs/heads/{}", local));
+    let remote_refname = source_ref.unwrap_or_else(|| format!("refs/heads/{}", remote_name));
+    let tracking_refname = match remote_refname.strip_prefix("refs/heads/") {
+        Some(name) => format!("refs/remotes/{}/{}", remote, name),
+        None => format!(
+            "refs/ripit/remotes/{}/{}",
+            remote,
+            remote_refname.trim_start_matches("refs/")
+        ),
+    };
+
+    for name in &[&refname, &remote_refname, &tracking_refname] {
+        if !name.starts_with("refs/") || !git2::Reference::is_valid_name(name) {
+            return Err(invalid(format!("invalid ref name '{}'", name)));
+        }
+    }
+
+    Ok(Branch {
+        name,
+        remote_name,
+        refname,
+        remote_refname,
+        tracking_refname,
+        create_missing: create_missing || create_missing_branch,
+        uproot: uproot || uproot_all,
+        push_remote,
+        until: None,
+    })
+}
+
+/// Returns whether a configured branch is a glob pattern, matching branches of the remote
+fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Parse the revisions given with --at, returning the one given for all branches
+///
+/// The revisions given for a branch with `BRANCH=REV` are set on the branch, which must be
+/// one of the branches to synchronize.
+fn parse_bootstrap_revs(
+    matches: &ArgMatches,
+    branches: &mut [Branch],
+) -> Result<Option<String>, error::Error> {
+    let invalid = |value: String| error::Error::InvalidValue { field: "at", value };
+
+    let mut until = matches.get_one::<String>("until").cloned();
+    for value in matches.get_many::<String>("at").into_iter().flatten() {
+        match value.split_once('=') {
+            Some((name, rev)) => {
+                let branch = branches
+                    .iter_mut()
+                    .find(|b| b.name == name)
+                    .ok_or_else(|| invalid(format!("branch {} is not synchronized", name)))?;
+                if rev.is_empty() || branch.until.is_some() {
+                    return Err(invalid(format!("expected a single revision for {}", name)));
+                }
+                branch.until = Some(rev.to_owned());
+            }
+            None if until.is_some() => {
+                return Err(invalid(
+                    "a single revision can be given for all branches".to_owned(),
+                ))
+            }
+            None => until = Some(value.clone()),
+        }
+    }
+    Ok(until)
+}
+
+/// Parse a trailer of the configuration, whose key and value must be strings
+///
+/// The key cannot contain whitespaces nor colons, so that the trailer is recognized by git.
+fn parse_trailer(
+    key: serde_yaml::Value,
+    value: serde_yaml::Value,
+) -> Result<(String, String), error::Error> {
+    let invalid = |desc: &str| error::Error::InvalidValue {
+        field: "trailers",
+        value: desc.to_owned(),
+    };
+    let key = match key {
+        serde_yaml::Value::String(key) => key,
+        _ => return Err(invalid("the keys must be strings")),
+    };
+    if key.is_empty() || key.contains(|c: char| c == ':' || c.is_whitespace()) {
+        return Err(invalid(&format!("invalid trailer key '{}'", key)));
+    }
+    let value = match value {
+        serde_yaml::Value::String(value) => value,
+        serde_yaml::Value::Number(value) => value.to_string(),
+        serde_yaml::Value::Bool(value) => value.to_string(),
+        _ => return Err(invalid(&format!("invalid value of trailer '{}'", key))),
+    };
+    if value.contains('\n') {
+        return Err(invalid(&format!("multi-line value of trailer '{}'", key)));
+    }
+    Ok((key, value))
+}
+
+/// Returns whether a string is a full or abbreviated commit id
+fn is_commit_id(sha: &str) -> bool {
+    (4..=40).contains(&sha.len()) && sha.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Parse an entry of the replacements option, reading its patch
+///
+/// A relative path is taken from `cfg_dir`, the directory of the configuration file.
+fn parse_replacement(
+    sha: String,
+    path: String,
+    cfg_dir: &std::path::Path,
+) -> Result<Replacement, error::Error> {
+    let invalid = |desc: String| error::Error::InvalidValue {
+        field: "replacements",
+        value: desc,
+    };
+    let sha = sha.to_ascii_lowercase();
+    if !is_commit_id(&sha) {
+        return Err(invalid(format!(
+            "'{}' is not a commit id of 4 to 40 hex digits",
+            sha
+        )));
+    }
+    let patch = std::fs::read(cfg_dir.join(&path))
+        .map_err(|err| invalid(format!("cannot read the patch {}: {}", path, err)))?;
+    if let Err(err) = git2::Diff::from_buffer(&patch) {
+        return Err(invalid(format!(
+            "{} is not a valid patch: {}",
+            path,
+            err.message()
+        )));
+    }
+    Ok(Replacement { sha, path, patch })
+}
+
+/// Parse a rule of the conflict_strategy option, mapping a glob pattern to a side
+fn parse_conflict_rule(
+    key: serde_yaml::Value,
+    value: serde_yaml::Value,
+) -> Result<ConflictRule, error::Error> {
+    let invalid = |desc: String| error::Error::InvalidValue {
+        field: "conflict_strategy",
+        value: desc,
+    };
+    let pattern = match key {
+        serde_yaml::Value::String(pattern) if !pattern.is_empty() => pattern,
+        _ => return Err(invalid("the patterns must be non-empty strings".to_owned())),
+    };
+    let side = serde_yaml::from_value(value).map_err(|_| {
+        invalid(format!(
+            "the strategy of '{}' must be 'ours' or 'theirs'",
+            pattern
+        ))
+    })?;
+    Ok(ConflictRule { pattern, side })
+}
+
+fn expand_var(field: &'static str, value: &mut String) -> Result<(), error::Error> {
+    *value = util::expand_env(value).map_err(|var| error::Error::UnsetVariable { field, var })?;
+    Ok(())
+}
+
+/// Read a key of the hmac provenance from the environment variable of the configuration
+///
+/// The keys are not written in the configuration file, which may be shared.
+fn read_key(field: &'static str, var: &str) -> Result<Vec<u8>, error::Error> {
+    match std::env::var_os(var) {
+        Some(key) if !key.is_empty() => Ok(key.to_string_lossy().into_owned().into_bytes()),
+        _ => Err(error::Error::UnsetVariable {
+            field,
+            var: var.to_owned(),
+        }),
+    }
+}
+
+/// Expand the references to environment variables in the options of the configuration
+///
+/// Commands are left untouched, as they are run in a shell which already expands them.
+fn expand_cfg_vars(cfg: &mut YamlCfg) -> Result<(), error::Error> {
+    for (field, value) in [
+        ("repo", &mut cfg.repo),
+        ("branch", &mut cfg.branch),
+        ("source_bundle", &mut cfg.source_bundle),
+    ] {
+        if let Some(value) = value {
+            expand_var(field, value)?;
+        }
+    }
+
+    match &mut cfg.remote {
+        Some(YamlRemote::Name(name)) => expand_var("remote", name)?,
+        Some(YamlRemote::Cfg(remote)) => {
+            expand_var("remote", &mut remote.name)?;
+            if let Some(url) = &mut remote.url {
+                expand_var("remote", url)?;
+            }
+        }
+        None => (),
+    }
+    if let Some(ssh_key) = cfg.auth.as_mut().and_then(|auth| auth.ssh_key.as_mut()) {
+        expand_var("auth", ssh_key)?;
+    }
+    if let Some(signing_key) = &mut cfg.signing_key {
+        expand_var("signing_key", signing_key)?;
+    }
+    for url in cfg.remote_urls.iter_mut().flatten() {
+        expand_var("remote_urls", url)?;
+    }
+    for branch in cfg.branches.iter_mut().flatten() {
+        match branch {
+            YamlBranch::Name(name) => expand_var("branches", name)?,
+            YamlBranch::Cfg(branch) => {
+                for value in [
+                    &mut branch.name,
+                    &mut branch.remote,
+                    &mut branch.local,
+                    &mut branch.source_ref,
+                    &mut branch.local_ref,
+                ]
+                .iter_mut()
+                .filter_map(|value| value.as_mut())
+                {
+                    expand_var("branches", value)?;
+                }
+            }
+        }
+    }
+    for filter in cfg.filters.iter_mut().flatten() {
+        expand_var("filters", filter)?;
+    }
+    for rule in cfg.split_rules.iter_mut().flatten() {
+        for path in &mut rule.paths {
+            expand_var("split_rules", path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Per-machine settings, read from the `ripit.*` keys of the git configuration
+#[derive(Default)]
+struct GitCfg {
+    remote: Option<String>,
+    filters: Vec<String>,
+    checkpoint_every: Option<usize>,
+    // command of the merge tool configured with merge.tool
+    mergetool_cmd: Option<String>,
+    // signing settings of git, from gpg.format, gpg.*.program and user.signingkey
+    gpg_format: Option<String>,
+    gpg_program: Option<String>,
+    signing_key: Option<String>,
+    // identity of the user, for the sign-offs
+    user_name: Option<String>,
+    user_email: Option<String>,
+    log_file: Option<String>,
+    committer_email: Option<String>,
+}
+
+// Convert a missing key into None
+fn git_cfg_value<T>(res: Result<T, git2::Error>) -> Result<Option<T>, git2::Error> {
+    match res {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check an email replacing the one of a signature
+fn parse_email(field: &'static str, email: String) -> Result<String, error::Error> {
+    let email = email.trim();
+    if email.is_empty() || email.contains(['<', '>', '\n']) {
+        return Err(error::Error::InvalidValue {
+            field,
+            value: format!("'{}' is not a valid email", email),
+        });
+    }
+    Ok(email.to_owned())
+}
+
+/// Read the ripit settings from the git configuration of the repository
+///
+/// If the repository cannot be opened, no settings are read: the error will be reported
+/// when opening it for the synchronization.
+fn read_git_cfg(repo_path: &str) -> Result<GitCfg, error::Error> {
+    let config = match git2::Repository::open(repo_path).and_then(|repo| repo.config()) {
+        Ok(mut config) => config.snapshot()?,
+        Err(_) => return Ok(GitCfg::default()),
+    };
+
+    let mut filters = Vec::new();
+    if let Some(entries) = git_cfg_value(config.multivar("ripit.filter", None))? {
+        entries.for_each(|entry| {
+            if let Some(value) = entry.value() {
+                filters.push(value.to_owned());
+            }
+        })?;
+    }
+
+    let checkpoint_every = match git_cfg_value(config.get_i64("ripit.checkpointEvery"))? {
+        Some(v) => match usize::try_from(v) {
+            Ok(v) => Some(v),
+            Err(_) => {
+                return Err(error::Error::InvalidValue {
+                    field: "ripit.checkpointEvery",
+                    value: v.to_string(),
+                })
+            }
+        },
+        None => None,
+    };
+
+    let mergetool_cmd = match git_cfg_value(config.get_string("merge.tool"))? {
+        Some(tool) => git_cfg_value(config.get_string(&format!("mergetool.{}.cmd", tool)))?,
+        None => None,
+    };
+
+    let gpg_format = git_cfg_value(config.get_string("gpg.format"))?;
+    let gpg_program = match gpg_format.as_deref() {
+        Some("ssh") => git_cfg_value(config.get_string("gpg.ssh.program"))?,
+        _ => match git_cfg_value(config.get_string("gpg.openpgp.program"))? {
+            Some(program) => Some(program),
+            None => git_cfg_value(config.get_string("gpg.program"))?,
+        },
+    };
+
+    Ok(GitCfg {
+        remote: git_cfg_value(config.get_string("ripit.remote"))?,
+        filters,
+        checkpoint_every,
+        mergetool_cmd,
+        gpg_format,
+        gpg_program,
+        signing_key: git_cfg_value(config.get_string("user.signingkey"))?,
+        user_name: git_cfg_value(config.get_string("user.name"))?,
+        user_email: git_cfg_value(config.get_string("user.email"))?,
+        log_file: git_cfg_value(config.get_string("ripit.logFile"))?,
+        committer_email: git_cfg_value(config.get_string("ripit.committerEmail"))?,
+    })
 }
 
 fn command() -> Command {
     Command::new("ripit")
         .version("1.0.0")
         .about("Copy commits between git repositories")
+        .after_long_help(
+            "Exit codes:\n  \
+             0  success, including when the branches are already up to date\n  \
+             1  error\n  \
+             2  the synchronization is stopped on conflicts to resolve\n  \
+             3  the repository contains non committed changes\n  \
+             4  nothing to synchronize, with --dry-run",
+        )
         // Configuration
         .arg(
             Arg::new("config_file")
@@ -56,6 +1274,38 @@ fn command() -> Command {
             which contains descriptions of all possible options.",
                 ),
         )
+        .arg(
+            Arg::new("repo")
+                .long("repo")
+                .value_name("PATH")
+                .help("Path to the git repository")
+                .long_help(
+                    "Path to the local git repository. Overrides the repo option of the \
+            configuration file.",
+                ),
+        )
+        .arg(
+            Arg::new("remote")
+                .long("remote")
+                .value_name("NAME")
+                .help("Name of the remote containing the commits to copy")
+                .long_help(
+                    "Name of the remote containing the commits to copy. Overrides the \
+            remote option of the configuration file.",
+                ),
+        )
+        .arg(
+            Arg::new("branch")
+                .action(ArgAction::Append)
+                .long("branch")
+                .value_name("NAME")
+                .help("Branch to synchronize, can be repeated")
+                .long_help(
+                    "Branch to synchronize. Can be given several times, and replaces the \
+            list of branches of the configuration file. The options of a configured \
+            branch with the same name are kept.",
+                ),
+        )
         // Type of action
         .arg(
             Arg::new("bootstrap")
@@ -69,6 +1319,85 @@ fn command() -> Command {
             is done for each branch to synchronize.",
                 ),
         )
+        .arg(
+            Arg::new("adopt")
+                .long("adopt")
+                .value_name("REV")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "abort",
+                    "rollback",
+                    "continue",
+                    "skip",
+                    "until",
+                    "squash",
+                    "dry_run",
+                ])
+                .help("Adopt local branches having the state of a remote revision")
+                .long_help(
+                    "Instead of bootstrapping, adopt local branches maintained without \
+            ripit, whose tree is the one of the given revision of the remote, once \
+            filtered. An empty commit referencing the revision is added on each \
+            branch, from which the synchronizations start. If the trees differ, the \
+            differences are printed and nothing is done.",
+                ),
+        )
+        .arg(
+            Arg::new("abort")
+                .action(ArgAction::SetTrue)
+                .long("abort")
+                .conflicts_with_all(["bootstrap", "dry_run", "emit_bundle"])
+                .help("Abort a synchronization stopped on conflicts")
+                .long_help(
+                    "Abort a synchronization stopped on conflicts: the index and the \
+            working directory are reset on the last synchronized commit of the \
+            branch, and the commits copied before the conflicts on a detached HEAD \
+            are abandoned, and removed from the cache. Nothing is done if no \
+            synchronization is in progress.",
+                ),
+        )
+        .arg(
+            Arg::new("rollback")
+                .action(ArgAction::SetTrue)
+                .long("rollback")
+                .conflicts_with_all(["abort", "bootstrap", "dry_run", "emit_bundle", "squash"])
+                .help("Undo the last synchronization")
+                .long_help(
+                    "Undo the last synchronization of the branches: they are set back on \
+            their commits before the synchronization, and the commits it copied are \
+            removed from the cache. The synchronizations are recorded in a journal in \
+            the ripit directory of the git directory. A branch with new commits since \
+            its synchronization is not rolled back. Combined with --branch, only the \
+            given branches are rolled back.",
+                ),
+        )
+        .arg(
+            Arg::new("continue")
+                .action(ArgAction::SetTrue)
+                .long("continue")
+                .conflicts_with_all(["abort", "bootstrap", "dry_run"])
+                .help("Commit the resolved conflicts, and resume the synchronization")
+                .long_help(
+                    "Once the conflicts of a stopped synchronization are resolved and \
+            added to the index, commit the resolution with the message and the \
+            author of the remote commit, then synchronize the remaining commits. \
+            The conflicts can also be committed manually before running ripit \
+            again.",
+                ),
+        )
+        .arg(
+            Arg::new("skip")
+                .action(ArgAction::SetTrue)
+                .long("skip")
+                .conflicts_with_all(["abort", "continue", "bootstrap", "dry_run"])
+                .help("Skip the conflicting commit, and resume the synchronization")
+                .long_help(
+                    "Reset the conflicts of a stopped synchronization, and resume it \
+            without the commit that caused them. The commit is recorded as skipped, \
+            and is never synchronized again: its descendants are copied on top of \
+            the copy of its parent.",
+                ),
+        )
         // behavioral features
         .arg(
             Arg::new("uproot")
@@ -85,7 +1414,57 @@ fn command() -> Command {
                 one ancestor dating from prior to the bootstrap. \
                 In that case, we want to cherry-pick the commits brought by \
                 the merge (or in this context, \"uproot\" them). \
-                This behavior can be activated with this flag.",
+                This behavior can be activated with this flag for every branch, \
+                or for some branches only with the `uproot` option of their \
+                configuration.",
+                ),
+        )
+        .arg(
+            Arg::new("squash")
+                .action(ArgAction::SetTrue)
+                .long("squash")
+                .conflicts_with_all(["abort", "continue", "skip", "bootstrap", "edit_messages"])
+                .help("Squash the commits to synchronize in a single commit")
+                .long_help(
+                    "Create a single local commit on each branch, with the filtered tree \
+            of the last remote commit to synchronize. Its message lists the summaries \
+            of the synchronized commits, which are all recorded as synchronized. No \
+            conflicts can happen, but the commits cannot be skipped either.",
+                ),
+        )
+        .arg(
+            Arg::new("force_resync")
+                .action(ArgAction::SetTrue)
+                .long("force-resync")
+                .conflicts_with_all(["abort", "continue", "skip", "bootstrap"])
+                .help("Resynchronize the branches rewritten in the remote")
+                .long_help(
+                    "When a remote branch was rewritten, e.g. rebased, its commits are \
+            matched by patch-id with the synchronized commits that are no longer in \
+            it. The matching commits are recorded as synchronized, and only the new \
+            or modified commits are copied, on top of the local branch.",
+                ),
+        )
+        .arg(
+            Arg::new("signoff")
+                .action(ArgAction::SetTrue)
+                .long("signoff")
+                .help("Add a Signed-off-by trailer to the synchronized commits")
+                .long_help(
+                    "Add a Signed-off-by trailer to the messages of the synchronized \
+            commits, before the ripit tag, with the identity set with the \
+            signoff_identity option, or the user.name and user.email git config. \
+            Same as the signoff option.",
+                ),
+        )
+        .arg(
+            Arg::new("no_push")
+                .action(ArgAction::SetTrue)
+                .long("no-push")
+                .help("Do not push the synchronized branches")
+                .long_help(
+                    "Do not push the synchronized branches to the remote configured \
+            with the push_remote option.",
                 ),
         )
         .arg(
@@ -98,17 +1477,491 @@ fn command() -> Command {
                     "By default, ripit will fetch the last commits from the private \
             repository before computing the differences with the local \
             repository. This behavior can be deactivated with this option, \
-            which can be useful if the fetch requires authentication which \
-            is not handled in ripit.",
+            for example to fetch the commits with git beforehand.",
+                ),
+        )
+        .arg(
+            Arg::new("create_missing_branch")
+                .action(ArgAction::SetTrue)
+                .long("create-missing-branch")
+                .help("Recreate missing local branches")
+                .long_help(
+                    "If a local branch to synchronize does not exist, but commits of \
+            the remote branch are known to have been synchronized, the local branch \
+            is recreated on the last synchronized commit before synchronizing it. \
+            This can also be enabled per branch in the configuration.",
+                ),
+        )
+        .arg(
+            Arg::new("detached_worktree")
+                .action(ArgAction::SetTrue)
+                .long("detached-worktree")
+                .conflicts_with("bootstrap")
+                .help("Synchronize in a temporary worktree")
+                .long_help(
+                    "Do the synchronization in a temporary linked worktree, so that \
+            the working directory is left untouched. Only the references of the \
+            synchronized branches are updated, which means those branches must \
+            not be checked out. On conflicts, the worktree is kept so that the \
+            conflicts can be solved in it. It is removed otherwise.",
+                ),
+        )
+        .arg(
+            Arg::new("autostash")
+                .action(ArgAction::SetTrue)
+                .long("autostash")
+                .help("Stash the local changes during the run")
+                .long_help(
+                    "Stash the changes of the index and of the working directory before \
+            the run, instead of aborting it, and apply them again once it is done. If \
+            the run stops on conflicts, or if the changes cannot be applied again, they \
+            are kept in the stash, whose entry is printed. The untracked files are left \
+            in place. Same as the autostash option.",
+                ),
+        )
+        .arg(
+            Arg::new("autostash_untracked")
+                .action(ArgAction::SetTrue)
+                .long("autostash-untracked")
+                .help("Stash the untracked files as well during the run")
+                .long_help(
+                    "Same as --autostash, but the untracked files are stashed as well, so \
+            that the synchronized commits can add files with the same paths. Same as the \
+            autostash_untracked option.",
+                ),
+        )
+        .arg(
+            Arg::new("break_lock")
+                .action(ArgAction::SetTrue)
+                .long("break-lock")
+                .help("Remove the lock of the repository before synchronizing")
+                .long_help(
+                    "A lock prevents several ripit processes from synchronizing the same \
+            repository. The lock of a process that is no longer running is removed \
+            automatically. This option removes the lock in any case, for example if \
+            its process was killed and its pid reused.",
+                ),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .action(ArgAction::SetTrue)
+                .short('n')
+                .long("dry-run")
+                .conflicts_with_all(["bootstrap", "emit_bundle", "edit_messages"])
+                .help("Only list the commits that would be synchronized")
+                .long_help(
+                    "List the commits that would be synchronized on each branch, with \
+            their filtered summary, and exit without modifying the local repository \
+            or the cache file. The remote is still fetched, unless --no-fetch is \
+            given. Commits with unknown parents are flagged, as they need --uproot \
+            to be synchronized. The exit code is 4 if there is nothing to \
+            synchronize.",
+                ),
+        )
+        .arg(
+            Arg::new("check_config")
+                .action(ArgAction::SetTrue)
+                .long("check-config")
+                .conflicts_with_all(["bootstrap", "abort", "continue", "skip", "dry_run"])
+                .help("Only check the configuration, without synchronizing")
+                .long_help(
+                    "Check that the configuration can be used: the configuration file \
+            is parsed, the local repository is opened, and the remote and the \
+            branches to synchronize are looked up, after fetching them unless \
+            --no-fetch is given. Every problem found is reported, and the exit code \
+            is 1 if there is any.",
+                ),
+        )
+        .arg(
+            Arg::new("verify")
+                .action(ArgAction::SetTrue)
+                .long("verify")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "abort",
+                    "continue",
+                    "skip",
+                    "dry_run",
+                    "check_config",
+                ])
+                .help("Only verify the consistency of the synchronized branches")
+                .long_help(
+                    "Verify that the remote commits referenced by the ripit tags of the \
+            local branches exist and are in the remote branches, as last fetched, that \
+            each of them is copied only once, and that the cache agrees with the tags. \
+            Every inconsistency found is reported, and the exit code is 1 if there is \
+            any.",
+                ),
+        )
+        .arg(
+            Arg::new("verify_trees")
+                .action(ArgAction::SetTrue)
+                .long("verify-trees")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "abort",
+                    "continue",
+                    "skip",
+                    "dry_run",
+                    "check_config",
+                ])
+                .help("Also verify that the trees of the branches match")
+                .long_help(
+                    "Verify the branches as with --verify, and check that the tree of \
+            each local branch matches the tree of the remote branch, without the \
+            paths that are not copied.",
+                ),
+        )
+        .arg(
+            Arg::new("json")
+                .action(ArgAction::SetTrue)
+                .long("json")
+                .conflicts_with_all(["abort", "dry_run", "check_config"])
+                .help("Print a JSON summary of the synchronization")
+                .long_help(
+                    "Print on stdout a JSON summary of the synchronization once it is \
+            done, with for every branch the remote and local tips before and after \
+            the synchronization, and the remote commits processed. The other outputs \
+            are printed on stderr.",
+                ),
+        )
+        .arg(
+            Arg::new("rebuild_cache")
+                .action(ArgAction::SetTrue)
+                .long("rebuild-cache")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "abort",
+                    "continue",
+                    "skip",
+                    "dry_run",
+                    "check_config",
+                    "json",
+                ])
+                .help("Rebuild the cache file from the ripit tags of the branches")
+                .long_help(
+                    "Rebuild the cache file listing the synchronized commits from the \
+            ripit tags of the commits of the local branches, and of their reflogs. \
+            The existing cache file is backed up in the cache.bak file, and nothing \
+            is synchronized.",
+                ),
+        )
+        .arg(
+            Arg::new("list_runs")
+                .action(ArgAction::SetTrue)
+                .long("list-runs")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "abort",
+                    "continue",
+                    "skip",
+                    "dry_run",
+                    "check_config",
+                    "json",
+                    "rebuild_cache",
+                ])
+                .help("List the records of the synchronizations")
+                .long_help(
+                    "List the records of the synchronizations made with the record_runs \
+            option, newest first: their date, the remote, and for each branch its tips \
+            before and after the synchronization, the remote tip, and the synchronized \
+            remote commits with their copies. Nothing is synchronized.",
+                ),
+        )
+        .arg(
+            Arg::new("export_map")
+                .long("export-map")
+                .value_name("PATH")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "abort",
+                    "continue",
+                    "skip",
+                    "dry_run",
+                    "check_config",
+                    "json",
+                    "rebuild_cache",
+                    "list_runs",
+                ])
+                .help("Write the mapping between the remote and local commits")
+                .long_help(
+                    "Write the mapping between the remote commits and their local copies, \
+            read from the ripit tags of the local branches, in a JSON file if the path \
+            ends with .json, or a CSV file if it ends with .csv. Each entry gives the \
+            remote and local commits, the branch, the date and the summary of the \
+            local commit, and whether the cache disagrees with the tag. Nothing is \
+            synchronized.",
+                ),
+        )
+        .arg(
+            Arg::new("import_map")
+                .long("import-map")
+                .value_name("PATH")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "abort",
+                    "continue",
+                    "skip",
+                    "dry_run",
+                    "check_config",
+                    "json",
+                    "rebuild_cache",
+                    "list_runs",
+                    "export_map",
+                ])
+                .help("Seed the cache with a mapping between remote and local commits")
+                .long_help(
+                    "Seed the cache with the mapping between the remote commits and their \
+            local copies of a JSON file if the path ends with .json, or of a CSV file if \
+            it ends with .csv, to synchronize a mirror that was maintained without \
+            ripit. Each entry gives the full ids of a remote commit and of its local \
+            copy, in the remote and local columns of the CSV file, or the first two \
+            without a header. The remote must have been fetched. The local commits \
+            without a ripit tag get one in the ripit/tags file, and are then \
+            synchronized as if ripit had copied them. Nothing is synchronized.",
+                ),
+        )
+        .arg(
+            Arg::new("write_trailers")
+                .long("write-trailers")
+                .action(ArgAction::SetTrue)
+                .requires("import_map")
+                .help("List the imported local commits without a provenance trailer")
+                .long_help(
+                    "With --import-map, list the imported local commits whose messages \
+            have no ripit tag: their provenance is only recorded in the files of ripit, \
+            which must then be kept.",
+                ),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .value_name("INTERVAL")
+                .conflicts_with_all([
+                    "bootstrap",
+                    "adopt",
+                    "abort",
+                    "rollback",
+                    "continue",
+                    "skip",
+                    "dry_run",
+                    "check_config",
+                    "json",
+                    "rebuild_cache",
+                    "list_runs",
+                    "export_map",
+                    "import_map",
+                ])
+                .help("Synchronize in a loop, every INTERVAL")
+                .long_help(
+                    "Synchronize in a loop: fetch the remote, synchronize the branches \
+            without asking for confirmation if the remote or local branches changed, \
+            then wait for INTERVAL, in seconds or suffixed with s, m or h. A failed fetch \
+            is retried after twice the previous delay, up to an hour or INTERVAL. While a \
+            synchronization is stopped on conflicts, nothing is done, and a reminder is \
+            printed at each cycle. SIGINT and SIGTERM stop the loop once the current \
+            cycle is done, a second signal stops at once.",
+                ),
+        )
+        .arg(
+            Arg::new("until")
+                .long("until")
+                .value_name("REV")
+                .conflicts_with("abort")
+                .help("Synchronize up to a remote revision")
+                .long_help(
+                    "Synchronize up to the given revision of the remote, instead of \
+            the head of the remote branch. The revision can be a tag or a branch of \
+            the remote, or a commit id, and must be reachable from the remote branch. \
+            With --bootstrap, the branch is bootstrapped at this revision.",
+                ),
+        )
+        .arg(
+            Arg::new("at")
+                .long("at")
+                .value_name("[BRANCH=]REV")
+                .action(ArgAction::Append)
+                .requires("bootstrap")
+                .conflicts_with("until")
+                .help("Bootstrap at a remote revision")
+                .long_help(
+                    "Bootstrap the branches at the given revision of the remote, \
+            instead of the head of the remote branch. The revision can be a tag or a \
+            branch of the remote, or a commit id, and must be reachable from the remote \
+            branch. With BRANCH=REV, only this branch is bootstrapped at the revision. \
+            The option can be repeated to give a revision for each branch.",
+                ),
+        )
+        .arg(
+            Arg::new("max_commits")
+                .long("max-commits")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Synchronize at most N commits on each branch")
+                .long_help(
+                    "Only synchronize the N oldest commits to synchronize on each \
+            branch. Merge commits are only synchronized with their parents. \
+            Running ripit again synchronizes the next commits. Overrides the \
+            max_commits option of the configuration file.",
+                ),
+        )
+        .arg(
+            Arg::new("keep_history")
+                .long("keep-history")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("bootstrap")
+                .help("Keep the history of the last N commits when bootstrapping")
+                .long_help(
+                    "Bootstrap the branches N first-parent commits before the remote \
+            revision, and copy the commits made since then as in a synchronization, \
+            so that their history is kept. Overrides the keep_history option of the \
+            configuration file.",
+                ),
+        )
+        .arg(
+            Arg::new("jobs")
+                .long("jobs")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Synchronize up to N branches in parallel")
+                .long_help(
+                    "Synchronize up to N branches in parallel. The branches sharing \
+            commits to synchronize are synchronized one after the other, as well as the \
+            branch checked out. Overrides the jobs option of the configuration file.",
+                ),
+        )
+        .arg(
+            Arg::new("stop_before")
+                .long("stop-before")
+                .value_name("REGEX")
+                .help("Stop the synchronization before a matching commit")
+                .long_help(
+                    "Synchronize commits up to, but not including, the first commit \
+            whose message matches the given regular expression. This allows a \
+            human to review this commit before it is synchronized. Running \
+            ripit again without this option will synchronize the remaining \
+            commits.",
+                ),
+        )
+        .arg(
+            Arg::new("from_bundle")
+                .long("from-bundle")
+                .value_name("PATH")
+                .conflicts_with("nofetch")
+                .help("Fetch the remote commits from a git bundle")
+                .long_help(
+                    "Fetch the commits of the branches to synchronize from a git \
+            bundle instead of the remote, for repositories that cannot reach it. \
+            The bundle must contain the branches to synchronize, and its \
+            prerequisites must already be in the local repository. This can also \
+            be set with the `source_bundle` option of the configuration.",
+                ),
+        )
+        .arg(
+            Arg::new("emit_bundle")
+                .long("emit-bundle")
+                .value_name("PATH")
+                .conflicts_with("bootstrap")
+                .help("Write a bundle of the synchronized commits")
+                .long_help(
+                    "After the synchronization, write a git bundle containing the new \
+            commits of every synchronized branch, with the previous heads of the \
+            branches as prerequisites. It can then be applied on another copy of \
+            the local repository with `git fetch`. No bundle is written if nothing \
+            was synchronized.",
+                ),
+        )
+        .arg(
+            Arg::new("edit_messages")
+                .action(ArgAction::SetTrue)
+                .long("edit-messages")
+                .conflicts_with("yes")
+                .help("Edit the message of every commit to synchronize")
+                .long_help(
+                    "Before copying the commits, open the filtered message of every \
+            commit in an editor, and use the edited message for the copy. Emptying \
+            the message skips the commit, after confirmation. Failing the editor \
+            aborts the synchronization. The editor is the `editor_cmd` command of \
+            the configuration file, or $EDITOR. This requires a terminal.",
+                ),
+        )
+        .arg(
+            Arg::new("mergetool")
+                .action(ArgAction::SetTrue)
+                .long("mergetool")
+                .help("Resolve conflicts with a merge tool")
+                .long_help(
+                    "On conflicts, run a merge tool on every conflicted file, and \
+            continue the synchronization if all conflicts are resolved. The tool \
+            is the `mergetool_cmd` command of the configuration file, or the \
+            command of the tool configured with `merge.tool` in the git \
+            configuration.",
                 ),
         )
         // common options shared by every action
         .arg(
             Arg::new("quiet")
-                .action(ArgAction::SetTrue)
+                .action(ArgAction::Count)
                 .short('q')
                 .long("quiet")
-                .help("Do not print detailed logs of the execution's progress"),
+                .help("Only print warnings and errors, or only errors if repeated"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .action(ArgAction::Count)
+                .short('v')
+                .long("verbose")
+                .help("Also print the details of the execution's progress"),
+        )
+        .arg(
+            Arg::new("log_file")
+                .long("log-file")
+                .value_name("PATH")
+                .help("Append the messages to a file as well")
+                .long_help(
+                    "Append the messages printed during the run to a file as well, \
+            without colors. Same as the log_file option, or the ripit.logFile git \
+            config.",
+                ),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .help("Colorize the output: always, never, or auto when in a terminal"),
+        )
+        .arg(
+            Arg::new("quiet_if_unchanged")
+                .long("quiet-if-unchanged")
+                .value_name("WHAT")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("all")
+                .value_parser(["all", "text"])
+                .conflicts_with_all([
+                    "bootstrap",
+                    "adopt",
+                    "abort",
+                    "rollback",
+                    "check_config",
+                    "verify",
+                    "rebuild_cache",
+                    "list_runs",
+                    "export_map",
+                    "import_map",
+                    "watch",
+                ])
+                .help("Print nothing if nothing is synchronized")
+                .long_help(
+                    "Print nothing, and exit successfully, if no commits are found to \
+            synchronize on any branch, as for runs from cron. The messages are kept \
+            until commits are found, and are then printed as usual. With \
+            --quiet-if-unchanged=text, the JSON summary of --json is printed anyway, \
+            only the messages being left out.",
+                ),
         )
         .arg(
             Arg::new("yes")
@@ -117,10 +1970,101 @@ fn command() -> Command {
                 .long("yes")
                 .help("Automatic yes to prompts"),
         )
+        // subcommands
+        .subcommand(
+            Command::new("stats")
+                .about("Print statistics about the synchronized commits")
+                .long_about(
+                    "Walk the local branches, and print the number of synchronized \
+            commits, grouped by month and by author. Uprooted commits, and commits \
+            created after the resolution of conflicts are counted as well.",
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("DATE")
+                        .help("Only consider commits made since this date (YYYY-MM-DD)"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .action(ArgAction::SetTrue)
+                        .long("json")
+                        .help("Print the statistics in JSON"),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export local commits to the remote")
+                .long_about(
+                    "Export the commits made in the local repository on top of the last \
+            synchronized commit, by cherry-picking them on top of the matching remote \
+            commit, and pushing them in a `ripit-export/<branch>` branch of the remote. \
+            An export tag is added to their messages, so that they are not copied \
+            again once integrated in the remote.",
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("REV")
+                        .help("Only export commits made after this local revision"),
+                )
+                .arg(
+                    Arg::new("patches")
+                        .long("patches")
+                        .value_name("DIR")
+                        .help("Write the exported commits as patches in this directory"),
+                ),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("List the remote commits and whether they were synchronized")
+                .long_about(
+                    "Walk the history of the remote branches since the bootstrap, and \
+            classify every commit as synced, exported, skipped or pending. Commits \
+            in none of those categories are missing from the local repository \
+            without explanation: they are flagged, and the audit fails.",
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("REV")
+                        .help("Only list commits made after this remote revision"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .action(ArgAction::SetTrue)
+                        .long("json")
+                        .help("Print the result in JSON"),
+                ),
+        )
+        .subcommand(
+            Command::new("blame")
+                .about("Show the remote commits that introduced the lines of a file")
+                .long_about(
+                    "Blame a file in the local repository, and translate every local \
+            commit to the remote commit it was copied from. Lines introduced by \
+            local commits are labeled as such.",
+                )
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .help("Path of the file, relative to the root of the repository"),
+                )
+                .arg(Arg::new("rev").help("Local revision to blame, HEAD by default")),
+        )
+        .subcommand(
+            Command::new("migrate-notes")
+                .about("Record the ripit tags of the synchronized commits in notes")
+                .long_about(
+                    "Record the ripit tags found in the messages of the commits of the \
+            branches in notes under refs/notes/ripit, as written with the `notes` \
+            provenance. The messages of the commits are left untouched.",
+                ),
+        )
 }
 
 pub fn parse_args() -> Result<Options, error::Error> {
-    let matches = command().get_matches();
+    let matches = command().try_get_matches().map_err(error::Error::Cli)?;
 
     let path = matches.get_one::<String>("config_file").unwrap();
     let file = match std::fs::File::open(path) {
@@ -133,30 +2077,111 @@ pub fn parse_args() -> Result<Options, error::Error> {
         }
     };
 
-    let cfg: YamlCfg = match serde_yaml::from_reader(file) {
-        Ok(cfg) => cfg,
-        Err(error) => {
-            return Err(error::Error::FailedParseCfg {
-                path: path.to_owned(),
-                error,
+    let parse_error = |error| error::Error::FailedParseCfg {
+        path: path.to_owned(),
+        error,
+    };
+    let value: serde_yaml::Value = serde_yaml::from_reader(file).map_err(parse_error)?;
+    check_cfg_keys(path, &value)?;
+    let mut cfg: YamlCfg = serde_yaml::from_value(value).map_err(parse_error)?;
+    expand_cfg_vars(&mut cfg)?;
+    // settings are taken from the command line, then the yaml config, then the git config
+    let repo = matches
+        .get_one::<String>("repo")
+        .cloned()
+        .or(cfg.repo)
+        .unwrap_or_else(|| ".".to_owned());
+    let git_cfg = read_git_cfg(&repo)?;
+    let (cfg_remote, cfg_remote_url) = match cfg.remote {
+        Some(YamlRemote::Name(name)) => (Some(name), None),
+        Some(YamlRemote::Cfg(remote)) => (Some(remote.name), remote.url),
+        None => (None, None),
+    };
+    // the url is only used for the remote it is configured with
+    let remote_url = match matches.get_one::<String>("remote") {
+        Some(name) if Some(name) != cfg_remote.as_ref() => None,
+        _ => cfg_remote_url,
+    };
+    let remote = match matches
+        .get_one::<String>("remote")
+        .cloned()
+        .or(cfg_remote)
+        .or(git_cfg.remote)
+    {
+        Some(remote) => remote,
+        None => {
+            return Err(error::Error::MissingConfig {
+                field: "remote",
+                git_key: "ripit.remote",
             })
         }
     };
+
     // backward compatibility on legacy branch option
     let branch = cfg.branch.unwrap_or_else(|| "master".to_owned());
     let mut branches = cfg.branches.unwrap_or_default();
     if branches.is_empty() {
-        branches.push(branch);
+        branches.push(YamlBranch::Name(branch));
+    }
+    if let Some(names) = matches.get_many::<String>("branch") {
+        let mut configured = branches;
+        branches = names
+            .map(
+                |name| match configured.iter().position(|b| b.name() == name) {
+                    Some(idx) => configured.swap_remove(idx),
+                    None => YamlBranch::Name(name.to_owned()),
+                },
+            )
+            .collect();
     }
-    let branches = branches
+    let create_missing_branch = matches.get_flag("create_missing_branch");
+    let uproot_all = matches.get_flag("uproot");
+    let no_push = matches.get_flag("no_push");
+    let global_push_remote = &cfg.push_remote;
+    let push_remote = |branch_remote: Option<String>| match no_push {
+        true => None,
+        false => branch_remote.or_else(|| global_push_remote.clone()),
+    };
+    let (patterns, branches): (Vec<_>, Vec<_>) = branches
         .into_iter()
-        .map(|name| {
-            let refname = format!("refs/heads/{}", name);
-            Branch { name, refname }
+        .map(|branch| match branch {
+            YamlBranch::Name(name) => YamlBranchCfg::named(name),
+            YamlBranch::Cfg(cfg) => cfg,
         })
-        .collect();
+        .partition(|cfg| is_glob(cfg.name()));
+    let mut branches = branches
+        .into_iter()
+        .map(|cfg| build_branch(cfg, &remote, create_missing_branch, uproot_all))
+        .collect::<Result<Vec<_>, _>>()?;
+    for branch in &mut branches {
+        branch.push_remote = push_remote(branch.push_remote.take());
+    }
+    let branch_patterns = patterns
+        .into_iter()
+        .map(|cfg| {
+            let pattern = cfg.name().to_owned();
+            if cfg.remote.is_some()
+                || cfg.local.is_some()
+                || cfg.source_ref.is_some()
+                || cfg.local_ref.is_some()
+            {
+                return Err(error::Error::InvalidValue {
+                    field: "branches",
+                    value: format!("the pattern {} cannot have other names", pattern),
+                });
+            }
+            Ok(BranchPattern {
+                pattern,
+                create_missing: cfg.create_missing || create_missing_branch,
+                uproot: cfg.uproot || uproot_all,
+                push_remote: push_remote(cfg.push_remote),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let until = parse_bootstrap_revs(&matches, &mut branches)?;
 
-    let filters = cfg.filters.unwrap_or_default();
+    let filters = cfg.filters.unwrap_or(git_cfg.filters);
     let commit_msg_filters = match regex::RegexSet::new(filters) {
         Ok(set) => set,
         Err(regex_err) => {
@@ -167,17 +2192,488 @@ pub fn parse_args() -> Result<Options, error::Error> {
         }
     };
 
+    let skip_commits = match regex::RegexSet::new(cfg.skip_commits.unwrap_or_default()) {
+        Ok(set) => set,
+        Err(regex_err) => {
+            return Err(error::Error::InvalidConfig {
+                field: "skip_commits",
+                error: regex_err,
+            });
+        }
+    };
+
+    let skip_shas: Vec<String> = cfg
+        .skip_shas
+        .unwrap_or_default()
+        .iter()
+        .map(|sha| sha.to_ascii_lowercase())
+        .collect();
+    if let Some(sha) = skip_shas.iter().find(|sha| !is_commit_id(sha)) {
+        return Err(error::Error::InvalidValue {
+            field: "skip_shas",
+            value: format!("'{}' is not a commit id of 4 to 40 hex digits", sha),
+        });
+    }
+    let cfg_dir = std::path::Path::new(path)
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""));
+    let replacements = cfg
+        .replacements
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(sha, patch)| parse_replacement(sha, patch, cfg_dir))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let stop_before = match matches.get_one::<String>("stop_before") {
+        Some(pattern) => match regex::Regex::new(pattern) {
+            Ok(re) => Some(re),
+            Err(regex_err) => {
+                return Err(error::Error::InvalidConfig {
+                    field: "stop-before",
+                    error: regex_err,
+                });
+            }
+        },
+        None => None,
+    };
+
+    let max_commits = match matches.get_one::<u64>("max_commits") {
+        Some(max) => Some(usize::try_from(*max).unwrap_or(usize::MAX)),
+        None => cfg.max_commits,
+    };
+    if max_commits == Some(0) {
+        return Err(error::Error::InvalidValue {
+            field: "max_commits",
+            value: "0, at least one commit must be synchronized".to_owned(),
+        });
+    }
+    let keep_history = match matches.get_one::<u64>("keep_history") {
+        Some(n) => Some(usize::try_from(*n).unwrap_or(usize::MAX)),
+        None => cfg.keep_history,
+    };
+    if keep_history == Some(0) {
+        return Err(error::Error::InvalidValue {
+            field: "keep_history",
+            value: "0, leave it unset to bootstrap without history".to_owned(),
+        });
+    }
+
+    let watch = match matches.get_one::<String>("watch") {
+        Some(value) => match util::parse_interval(value) {
+            Some(secs) => Some(secs),
+            None => {
+                return Err(error::Error::InvalidValue {
+                    field: "watch",
+                    value: format!(
+                        "{}, expected a number of seconds, optionally suffixed with s, m or h",
+                        value
+                    ),
+                })
+            }
+        },
+        None => None,
+    };
+
+    let jobs = match matches.get_one::<u64>("jobs") {
+        Some(n) => usize::try_from(*n).unwrap_or(usize::MAX),
+        None => cfg.jobs.unwrap_or(1),
+    };
+    if jobs == 0 {
+        return Err(error::Error::InvalidValue {
+            field: "jobs",
+            value: "0, at least one branch must be synchronized at a time".to_owned(),
+        });
+    }
+
+    let exclude_paths = cfg.exclude_paths.unwrap_or_default();
+    if exclude_paths.iter().any(|path| path.trim().is_empty()) {
+        return Err(error::Error::InvalidValue {
+            field: "exclude_paths",
+            value: "empty pattern".to_owned(),
+        });
+    }
+
+    let subtree = cfg
+        .subtree
+        .map(|path| parse_dir_path("subtree", path))
+        .transpose()?;
+    let prefix = cfg
+        .prefix
+        .map(|path| parse_dir_path("prefix", path))
+        .transpose()?;
+
+    let split_rules = cfg.split_rules.unwrap_or_default();
+    for rule in &split_rules {
+        if rule.name.is_empty() || rule.paths.is_empty() {
+            return Err(error::Error::InvalidValue {
+                field: "split_rules",
+                value: format!("rule '{}' must have a name and paths", rule.name),
+            });
+        }
+    }
+
+    let parse_identity = |field, value: String| {
+        authors::Identity::parse(&value).ok_or(error::Error::InvalidValue {
+            field,
+            value: format!("'{}' is not formatted as `name <email>`", value),
+        })
+    };
+    let identities = cfg
+        .authors
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(email, identity)| Ok((email, parse_identity("authors", identity)?)))
+        .collect::<Result<_, error::Error>>()?;
+    let default_author = cfg
+        .default_author
+        .map(|identity| parse_identity("default_author", identity))
+        .transpose()?;
+    let authors = authors::AuthorsMap::new(
+        identities,
+        default_author,
+        cfg.keep_original_author_trailer.unwrap_or(false),
+    );
+
+    let committer_email = match (cfg.committer_email, git_cfg.committer_email) {
+        (Some(email), _) => Some(parse_email("committer_email", email)?),
+        (None, Some(email)) => Some(parse_email("ripit.committerEmail", email)?),
+        (None, None) => None,
+    };
+
+    let signoff = if matches.get_flag("signoff") || cfg.signoff.unwrap_or(false) {
+        match (cfg.signoff_identity, git_cfg.user_name, git_cfg.user_email) {
+            (Some(identity), _, _) => Some(parse_identity("signoff_identity", identity)?),
+            (None, Some(name), Some(email)) => Some(authors::Identity { name, email }),
+            _ => {
+                return Err(error::Error::InvalidValue {
+                    field: "signoff",
+                    value: "no identity set with signoff_identity, or with the user.name \
+                            and user.email git config"
+                        .to_owned(),
+                })
+            }
+        }
+    } else {
+        None
+    };
+
+    let trailers = cfg
+        .trailers
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(key, value)| parse_trailer(key, value))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let conflict_strategy = cfg
+        .conflict_strategy
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(key, value)| parse_conflict_rule(key, value))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mergetool = if matches.get_flag("mergetool") {
+        match cfg.mergetool_cmd.or(git_cfg.mergetool_cmd) {
+            Some(cmd) => Some(cmd),
+            None => {
+                return Err(error::Error::MissingConfig {
+                    field: "mergetool_cmd",
+                    git_key: "merge.tool",
+                })
+            }
+        }
+    } else {
+        None
+    };
+
+    let signing = if cfg.sign_commits.unwrap_or(false) {
+        let format = match git_cfg.gpg_format.as_deref() {
+            None | Some("openpgp") => SigningFormat::OpenPgp,
+            Some("ssh") => SigningFormat::Ssh,
+            Some(format) => {
+                return Err(error::Error::InvalidValue {
+                    field: "gpg.format",
+                    value: format!("unsupported signature format '{}'", format),
+                })
+            }
+        };
+        let key = cfg.signing_key.or(git_cfg.signing_key);
+        if format == SigningFormat::Ssh && key.is_none() {
+            return Err(error::Error::InvalidValue {
+                field: "signing_key",
+                value: "a key is required to sign with SSH".to_owned(),
+            });
+        }
+        let program = cfg.signing_program.or(git_cfg.gpg_program);
+        Some(Signing {
+            format,
+            program: program.unwrap_or_else(|| match format {
+                SigningFormat::OpenPgp => "gpg".to_owned(),
+                SigningFormat::Ssh => "ssh-keygen".to_owned(),
+            }),
+            key,
+        })
+    } else {
+        None
+    };
+
+    let bootstrap_message = cfg.bootstrap_message;
+    if bootstrap_message
+        .as_deref()
+        .is_some_and(|msg| msg.trim().is_empty())
+    {
+        return Err(error::Error::InvalidValue {
+            field: "bootstrap_message",
+            value: "the message cannot be empty".to_owned(),
+        });
+    }
+    // the ids of the remote commits must not appear in the local commits
+    let provenance = cfg.provenance.unwrap_or(Provenance::Trailers);
+    if provenance.hides_remote_ids() {
+        let remote_oid_in = |field, value: Option<&str>| match value {
+            Some(value) if value.contains("{remote_oid}") => Err(error::Error::InvalidValue {
+                field,
+                value: format!(
+                    "{{remote_oid}} cannot be used with the {} provenance",
+                    match provenance {
+                        Provenance::Hmac => "hmac",
+                        _ => "cache-only",
+                    }
+                ),
+            }),
+            _ => Ok(()),
+        };
+        remote_oid_in("bootstrap_message", bootstrap_message.as_deref())?;
+        for (_, value) in &trailers {
+            remote_oid_in("trailers", Some(value))?;
+        }
+    }
+    let provenance_keys = match (provenance, cfg.provenance_key) {
+        (Provenance::Hmac, Some(var)) => {
+            let mut keys = vec![read_key("provenance_key", &var)?];
+            if let Some(var) = cfg.provenance_previous_key {
+                keys.push(read_key("provenance_previous_key", &var)?);
+            }
+            keys
+        }
+        (Provenance::Hmac, None) => {
+            return Err(error::Error::InvalidValue {
+                field: "provenance_key",
+                value: "a key is required with the hmac provenance".to_owned(),
+            })
+        }
+        _ => Vec::new(),
+    };
+
+    let notify = match cfg.notify_url {
+        Some(url) => {
+            let timeout = cfg.notify_timeout.unwrap_or(10);
+            if timeout == 0 {
+                return Err(error::Error::InvalidValue {
+                    field: "notify_timeout",
+                    value: "the timeout must be positive".to_owned(),
+                });
+            }
+            Some(Notify {
+                url,
+                timeout,
+                on: cfg.notify_on.unwrap_or_else(|| {
+                    vec![
+                        NotifyEvent::Success,
+                        NotifyEvent::Conflict,
+                        NotifyEvent::Error,
+                    ]
+                }),
+            })
+        }
+        None => None,
+    };
+
+    let tag_prefix = cfg
+        .tag_prefix
+        .unwrap_or_else(|| tag::DEFAULT_PREFIX.to_owned());
+    if tag_prefix.is_empty() || tag_prefix.contains(|c: char| c == ':' || c.is_whitespace()) {
+        return Err(error::Error::InvalidValue {
+            field: "tag_prefix",
+            value: tag_prefix,
+        });
+    }
+
+    let default_yes = match cfg.default_answer {
+        None | Some(Answer::No) => false,
+        Some(Answer::Yes) => true,
+        Some(Answer::Abort) => {
+            return Err(error::Error::InvalidValue {
+                field: "default_answer",
+                value: "must be yes or no".to_owned(),
+            })
+        }
+    };
+
+    let edit_messages = if matches.get_flag("edit_messages") {
+        if !util::is_interactive() {
+            return Err(error::Error::NotInteractive {
+                option: "--edit-messages",
+            });
+        }
+        cfg.editor_cmd
+            .or_else(|| std::env::var("EDITOR").ok())
+            .or_else(|| Some("vi".to_owned()))
+    } else {
+        None
+    };
+
+    let subcommand = match matches.subcommand() {
+        Some(("stats", sub_matches)) => {
+            let since = match sub_matches.get_one::<String>("since") {
+                Some(date) => match util::parse_date(date) {
+                    Some(ts) => Some(ts),
+                    None => {
+                        return Err(error::Error::InvalidDate {
+                            date: date.to_owned(),
+                        })
+                    }
+                },
+                None => None,
+            };
+            Some(SubCommand::Stats {
+                since,
+                json: sub_matches.get_flag("json"),
+            })
+        }
+        Some(("export", sub_matches)) => Some(SubCommand::Export {
+            since: sub_matches.get_one::<String>("since").cloned(),
+            patches: sub_matches.get_one::<String>("patches").cloned(),
+        }),
+        Some(("audit", sub_matches)) => Some(SubCommand::Audit {
+            since: sub_matches.get_one::<String>("since").cloned(),
+            json: sub_matches.get_flag("json"),
+        }),
+        Some(("blame", sub_matches)) => Some(SubCommand::Blame {
+            path: sub_matches.get_one::<String>("path").cloned().unwrap(),
+            rev: sub_matches.get_one::<String>("rev").cloned(),
+        }),
+        Some(("migrate-notes", _)) => Some(SubCommand::MigrateNotes),
+        _ => None,
+    };
+
     Ok(Options {
-        repo: cfg.repo.unwrap_or_else(|| ".".to_owned()),
-        remote: cfg.remote,
+        repo,
+        remote,
+        remote_url,
+        remote_urls: cfg.remote_urls.unwrap_or_default(),
         branches,
+        branch_patterns,
+        exclude_branches: cfg.exclude_branches.unwrap_or_default(),
         commit_msg_filters,
+        message_filter_cmd: cfg.message_filter_cmd,
+        skip_commits,
+        skip_shas,
+        replacements,
+        stop_before,
+        until,
+        max_commits,
+        keep_history,
 
         bootstrap: matches.get_flag("bootstrap"),
-        uproot: matches.get_flag("uproot"),
-        verbose: !matches.get_flag("quiet"),
-        yes: matches.get_flag("yes"),
+        adopt: matches.get_one::<String>("adopt").cloned(),
+        abort: matches.get_flag("abort"),
+        rollback: matches.get_flag("rollback"),
+        resume: matches.get_flag("continue"),
+        skip: matches.get_flag("skip"),
+        squash: matches.get_flag("squash"),
+        force_resync: matches.get_flag("force_resync"),
+        log_level: log::Level::from_flags(matches.get_count("verbose"), matches.get_count("quiet")),
+        log_file: matches
+            .get_one::<String>("log_file")
+            .cloned()
+            .or(cfg.log_file)
+            .or(git_cfg.log_file),
+        color: match matches.get_one::<String>("color").map(String::as_str) {
+            Some("always") => log::ColorMode::Always,
+            Some("never") => log::ColorMode::Never,
+            _ => log::ColorMode::Auto,
+        },
+        quiet_if_unchanged: match matches
+            .get_one::<String>("quiet_if_unchanged")
+            .map(String::as_str)
+        {
+            Some("text") => Some(QuietIfUnchanged::Text),
+            Some(_) => Some(QuietIfUnchanged::All),
+            None => None,
+        },
+        // nobody answers the questions in watch mode
+        yes: matches.get_flag("yes") || watch.is_some(),
         fetch: !matches.get_flag("nofetch"),
+        detached_worktree: matches.get_flag("detached_worktree"),
+        autostash: matches.get_flag("autostash")
+            || matches.get_flag("autostash_untracked")
+            || cfg.autostash.unwrap_or(false)
+            || cfg.autostash_untracked.unwrap_or(false),
+        autostash_untracked: matches.get_flag("autostash_untracked")
+            || cfg.autostash_untracked.unwrap_or(false),
+        break_lock: matches.get_flag("break_lock"),
+        dry_run: matches.get_flag("dry_run"),
+        check_config: matches.get_flag("check_config"),
+        verify: matches.get_flag("verify") || matches.get_flag("verify_trees"),
+        verify_trees: matches.get_flag("verify_trees"),
+        json: matches.get_flag("json"),
+        rebuild_cache: matches.get_flag("rebuild_cache"),
+        list_runs: matches.get_flag("list_runs"),
+        export_map: matches.get_one::<String>("export_map").cloned(),
+        import_map: matches.get_one::<String>("import_map").cloned(),
+        write_trailers: matches.get_flag("write_trailers"),
+        watch,
+        source_bundle: matches
+            .get_one::<String>("from_bundle")
+            .cloned()
+            .or(cfg.source_bundle),
+        emit_bundle: matches.get_one::<String>("emit_bundle").cloned(),
+        checkpoint_every: cfg
+            .checkpoint_every
+            .or(git_cfg.checkpoint_every)
+            .unwrap_or(0),
+        jobs,
+        commit_gate_cmd: cfg.commit_gate_cmd,
+        foreign_tags: cfg.foreign_tags.unwrap_or(ForeignTags::Keep),
+        mergetool,
+        conflict_strategy,
+        rerere: cfg.rerere.unwrap_or(true),
+        rerere_max_age: cfg.rerere_max_age.unwrap_or(60),
+        edit_messages,
+        exclude_paths,
+        subtree,
+        prefix,
+        split_rules,
+        autosquash: cfg.autosquash.unwrap_or(false),
+        skip_empty: cfg.skip_empty.unwrap_or(false),
+        linearize: cfg.linearize.unwrap_or(false),
+        group_runs: cfg.group_runs.unwrap_or(false),
+        diffstat_max_commits: cfg.diffstat_max_commits.unwrap_or(20),
+        maintenance: cfg.maintenance,
+        maintenance_min_commits: cfg.maintenance_min_commits.unwrap_or(1000),
+        // tagging the runs records them
+        record_runs: cfg.record_runs.unwrap_or(false) || cfg.tag_runs.unwrap_or(false),
+        tag_runs: cfg.tag_runs.unwrap_or(false),
+        runs_retention_days: cfg.runs_retention_days,
+        tag_prefix,
+        provenance,
+        provenance_keys,
+        bootstrap_message,
+        authors,
+        committer_email,
+        dates: cfg.dates.unwrap_or(Dates::AuthorOnly),
+        signoff,
+        trailers,
+        auth: cfg.auth.unwrap_or_default(),
+        hooks: cfg.hooks.unwrap_or_default(),
+        signing,
+        sync_tags: cfg.sync_tags.unwrap_or(false),
+        fetch_all: cfg.fetch_all.unwrap_or(false),
+        notify,
+
+        subcommand,
+
+        confirm: Box::new(move |question| util::confirm(question, default_yes)),
     })
 }
 
@@ -185,3 +2681,81 @@ pub fn parse_args() -> Result<Options, error::Error> {
 fn verify_app() {
     command().debug_assert();
 }
+
+#[test]
+fn test_cfg_keys() {
+    // every known option is an option of the configuration
+    let all_keys: String = CFG_KEYS
+        .iter()
+        .map(|key| format!("{}: null\n", key))
+        .collect();
+    assert!(serde_yaml::from_str::<YamlCfg>(&all_keys).is_ok());
+
+    let check = |cfg: &str| {
+        let value = serde_yaml::from_str(cfg).unwrap();
+        match check_cfg_keys("cfg.yml", &value) {
+            Err(error::Error::UnknownCfgKey {
+                key,
+                section,
+                suggestion,
+                ..
+            }) => Some((key, section, suggestion)),
+            _ => None,
+        }
+    };
+    assert!(check(&all_keys).is_none());
+
+    // typos of every option are reported with the right suggestion
+    for key in CFG_KEYS {
+        let typo = format!("{}x", key);
+        assert_eq!(
+            check(&format!("remote: private\n{}: 1\n", typo)),
+            Some((typo, None, Some(*key)))
+        );
+    }
+    assert_eq!(
+        check("filter:\n  - ^Refs\n"),
+        Some(("filter".to_owned(), None, Some("filters")))
+    );
+    assert_eq!(
+        check("unrelated: 1\n"),
+        Some(("unrelated".to_owned(), None, None))
+    );
+
+    // nested sections are checked as well
+    assert_eq!(
+        check("branches:\n  - master\n  - name: dev\n    uprot: true\n"),
+        Some(("uprot".to_owned(), Some("branches"), Some("uproot")))
+    );
+    assert_eq!(
+        check("auth:\n  ssh_keys: /key\n"),
+        Some(("ssh_keys".to_owned(), Some("auth"), Some("ssh_key")))
+    );
+    assert_eq!(
+        check("split_rules:\n  - name: doc\n    path: [doc]\n"),
+        Some(("path".to_owned(), Some("split_rules"), Some("paths")))
+    );
+}
+
+#[test]
+fn test_branches_cfg() {
+    let cfg: YamlCfg = serde_yaml::from_str(
+        "remote: private\n\
+         branches:\n\
+         \x20 - master\n\
+         \x20 - name: dev\n\
+         \x20   create_missing: true\n\
+         \x20 - name: release-1.0\n\
+         \x20   uproot: true\n",
+    )
+    .unwrap();
+    let branches = cfg.branches.unwrap();
+
+    assert!(matches!(&branches[0], YamlBranch::Name(name) if name == "master"));
+    assert!(
+        matches!(&branches[1], YamlBranch::Cfg(cfg) if cfg.name() == "dev" && cfg.create_missing)
+    );
+    assert!(
+        matches!(&branches[2], YamlBranch::Cfg(cfg) if cfg.name() == "release-1.0" && cfg.uproot && !cfg.create_missing)
+    );
+}
@@ -0,0 +1,205 @@
+use crate::app;
+use crate::commits_map::CommitsMap;
+use crate::error::Error;
+use crate::sync;
+use crate::tag;
+use serde::Serialize;
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    // copied in the local repository
+    Synced,
+    // exported from a local commit
+    Exported,
+    // skipped by a rule
+    Skipped,
+    // will be copied by the next synchronization
+    Pending,
+    // none of the above: the commit is missing from the local repository
+    Gap,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Synced => "synced",
+            Status::Exported => "exported",
+            Status::Skipped => "skipped",
+            Status::Pending => "pending",
+            Status::Gap => "GAP",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Entry {
+    branch: String,
+    commit: String,
+    status: Status,
+    // local counterpart of a synced or exported commit
+    local: Option<String>,
+    // rule that skipped the commit
+    rule: Option<String>,
+    summary: String,
+}
+
+/// Remote commits from which the given local branch was bootstrapped
+fn bootstrap_commits(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+) -> Result<Vec<git2::Oid>, Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_ref(&branch.refname)?;
+
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        if tags.is_bootstrap(&commit) {
+            let (remote_id, _) = tags.retrieve_ripit_tag_or_throw(&commit)?;
+            commits.push(git2::Oid::from_str(&remote_id)?);
+        }
+    }
+    Ok(commits)
+}
+
+/// Classify the remote commits of a branch made after its bootstrap
+fn audit_branch(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    commits_map: &CommitsMap,
+    since: Option<&str>,
+    visited: &mut HashSet<git2::Oid>,
+) -> Result<Vec<Entry>, Error> {
+    let local_commit = repo.revparse_single(&branch.refname)?.peel_to_commit()?;
+    let remote_commit = repo.revparse_single(&branch.tracking_refname)?;
+    let (remote_start, _) = sync::find_sync_start(repo, tags, local_commit.id())?;
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(remote_commit.id())?;
+    // commits predating the bootstrap are never synchronized
+    for oid in bootstrap_commits(repo, tags, branch)? {
+        revwalk.hide(oid)?;
+    }
+    if let Some(rev) = since {
+        revwalk.hide(repo.revparse_single(rev)?.peel_to_commit()?.id())?;
+    }
+
+    let mut entries = Vec::new();
+    for oid in revwalk {
+        let oid = oid?;
+        // commits shared between branches are only listed once
+        if !visited.insert(oid) {
+            continue;
+        }
+        let commit = repo.find_commit(oid)?;
+
+        let mut local = None;
+        let mut rule = None;
+        let status = if let Some(synced) = commits_map.get(oid) {
+            local = Some(synced.commit.id().to_string());
+            Status::Synced
+        } else if let Some(local_ci) = sync::find_exported_commit(repo, tags, &commit) {
+            local = Some(local_ci.id().to_string());
+            Status::Exported
+        } else if let Some(skip_rule) = commits_map.skip_rule(oid) {
+            rule = Some(skip_rule.to_owned());
+            Status::Skipped
+        } else if oid != remote_start.id() && !repo.graph_descendant_of(remote_start.id(), oid)? {
+            Status::Pending
+        } else {
+            Status::Gap
+        };
+
+        entries.push(Entry {
+            branch: branch.name.clone(),
+            commit: oid.to_string(),
+            status,
+            local,
+            rule,
+            summary: commit.summary().unwrap_or("").to_owned(),
+        });
+    }
+    Ok(entries)
+}
+
+fn print_table(entries: &[Entry]) {
+    let mut branch = None;
+    for entry in entries {
+        if branch != Some(&entry.branch) {
+            println!("Branch {}:", entry.branch);
+            branch = Some(&entry.branch);
+        }
+
+        let detail = match (&entry.local, &entry.rule) {
+            (Some(local), _) => &local[..10],
+            (None, Some(rule)) => rule.as_str(),
+            (None, None) => "",
+        };
+        println!(
+            "  {}  {:8}  {:12}  {}",
+            &entry.commit[..10],
+            entry.status.as_str(),
+            detail,
+            entry.summary
+        );
+    }
+
+    let count = |status| entries.iter().filter(|e| e.status == status).count();
+    println!(
+        "\nTotal: {} synced, {} exported, {} skipped, {} pending, {} unexplained.",
+        count(Status::Synced),
+        count(Status::Exported),
+        count(Status::Skipped),
+        count(Status::Pending),
+        count(Status::Gap),
+    );
+}
+
+/// Classify every remote commit made after the bootstrap of the synchronized branches
+///
+/// Commits are either synced, exported from the local repository, skipped, or pending.
+/// Commits in none of those categories are missing from the local repository without
+/// explanation, and cause the audit to fail.
+pub fn audit(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &app::Options,
+    since: Option<&str>,
+    json: bool,
+) -> Result<(), Error> {
+    let mut commits_map = CommitsMap::new(repo, tags)?;
+    for branch in &opts.branches {
+        commits_map.fill_from_branch(repo, &branch.refname)?;
+    }
+
+    let mut visited = HashSet::new();
+    let mut entries = Vec::new();
+    for branch in &opts.branches {
+        entries.extend(audit_branch(
+            repo,
+            tags,
+            branch,
+            &commits_map,
+            since,
+            &mut visited,
+        )?);
+    }
+
+    if json {
+        // serialization of these types cannot fail
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+    } else {
+        print_table(&entries);
+    }
+
+    let gaps = entries.iter().filter(|e| e.status == Status::Gap).count();
+    if gaps > 0 {
+        return Err(Error::AuditGaps { count: gaps });
+    }
+    Ok(())
+}
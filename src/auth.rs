@@ -0,0 +1,70 @@
+use crate::app;
+use crate::error::Error;
+use git2::{Cred, CredentialType};
+use std::path::Path;
+
+// environment variables holding the credentials
+const SSH_PASSPHRASE_VAR: &str = "RIPIT_SSH_PASSPHRASE";
+const HTTP_USER_VAR: &str = "RIPIT_HTTP_USER";
+const HTTP_TOKEN_VAR: &str = "RIPIT_HTTP_TOKEN";
+
+/// Build the callbacks providing the credentials to the remotes
+///
+/// The credentials are tried in order: the SSH agent, the configured SSH key, the HTTP
+/// token, then the default credentials of the system. Each one is only tried once, so
+/// that the connection fails with an authentication error once they are exhausted.
+pub fn remote_callbacks(opts: &app::Options) -> git2::RemoteCallbacks<'_> {
+    let mut tried_agent = false;
+    let mut tried_key = false;
+    let mut tried_token = false;
+    let mut tried_default = false;
+
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(move |_url, username_from_url, allowed| {
+        let username = username_from_url.unwrap_or("git");
+
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if opts.auth.use_ssh_agent() && !tried_agent {
+                tried_agent = true;
+                return Cred::ssh_key_from_agent(username);
+            }
+            if let (Some(key), false) = (&opts.auth.ssh_key, tried_key) {
+                tried_key = true;
+                let passphrase = std::env::var(SSH_PASSPHRASE_VAR).ok();
+                return Cred::ssh_key(username, None, Path::new(key), passphrase.as_deref());
+            }
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried_token {
+            if let Ok(token) = std::env::var(HTTP_TOKEN_VAR) {
+                tried_token = true;
+                let user = std::env::var(HTTP_USER_VAR).unwrap_or_else(|_| username.to_owned());
+                return Cred::userpass_plaintext(&user, &token);
+            }
+        }
+        if allowed.contains(CredentialType::DEFAULT) && !tried_default {
+            tried_default = true;
+            return Cred::default();
+        }
+        Err(git2::Error::new(
+            git2::ErrorCode::Auth,
+            git2::ErrorClass::Net,
+            "no more credentials to try",
+        ))
+    });
+    callbacks
+}
+
+/// Convert a fetch or push error, distinguishing authentication failures
+pub fn convert_error(err: git2::Error, remote: &str) -> Error {
+    if err.code() == git2::ErrorCode::Auth {
+        Error::AuthFailed {
+            remote: remote.to_owned(),
+            reason: err.message().to_owned(),
+        }
+    } else {
+        err.into()
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+/// Identity replacing the author or committer of the synchronized commits
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    /// Parse an identity written as `name <email>`
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, email) = value.trim().strip_suffix('>')?.split_once('<')?;
+        let name = name.trim();
+        let email = email.trim();
+        if name.is_empty() || email.is_empty() || email.contains(['<', '>']) {
+            return None;
+        }
+        Some(Self {
+            name: name.to_owned(),
+            email: email.to_owned(),
+        })
+    }
+}
+
+/// Mapping of the identities of the remote commits to the ones used in the local commits
+///
+/// Emails are compared case-insensitively. Unknown identities are replaced by the default
+/// one if set, and kept as is otherwise.
+#[derive(Default)]
+pub struct AuthorsMap {
+    identities: HashMap<String, Identity>,
+    default: Option<Identity>,
+    // add an Original-Author trailer to the commits whose author is replaced
+    keep_original_trailer: bool,
+}
+
+impl AuthorsMap {
+    pub fn new(
+        identities: HashMap<String, Identity>,
+        default: Option<Identity>,
+        keep_original_trailer: bool,
+    ) -> Self {
+        Self {
+            identities: identities
+                .into_iter()
+                .map(|(email, identity)| (email.to_lowercase(), identity))
+                .collect(),
+            default,
+            keep_original_trailer,
+        }
+    }
+
+    fn lookup(&self, sig: &git2::Signature) -> Option<&Identity> {
+        // the email may not be valid UTF-8, it then cannot match any entry of the map
+        let email = String::from_utf8_lossy(sig.email_bytes()).to_lowercase();
+        self.identities.get(&email).or(self.default.as_ref())
+    }
+
+    /// Build the signature to use in the local commit, keeping the original date
+    pub fn map_signature(
+        &self,
+        sig: &git2::Signature,
+    ) -> Result<git2::Signature<'static>, git2::Error> {
+        match self.lookup(sig) {
+            Some(identity) => git2::Signature::new(&identity.name, &identity.email, &sig.when()),
+            None => Ok(sig.to_owned()),
+        }
+    }
+
+    /// Trailer recording the original author of a commit, if it is replaced and asked for
+    pub fn original_author_trailer(&self, commit: &git2::Commit) -> Option<String> {
+        if !self.keep_original_trailer {
+            return None;
+        }
+        let author = commit.author();
+        self.lookup(&author)?;
+        Some(format!(
+            "Original-Author: {} <{}>",
+            String::from_utf8_lossy(author.name_bytes()),
+            String::from_utf8_lossy(author.email_bytes())
+        ))
+    }
+}
@@ -0,0 +1,125 @@
+//! Automatic resolution of conflicts, with the conflict_strategy option
+use crate::app::{ConflictRule, ConflictSide};
+use crate::info;
+use crate::util;
+use serde::Serialize;
+use std::path::Path;
+
+// bits of the flags of an index entry containing its stage
+const STAGE_MASK: u16 = 0x3000;
+
+/// Conflicting path resolved by taking one of its versions
+#[derive(Clone, Serialize)]
+pub struct Resolution {
+    pub path: String,
+    pub side: ConflictSide,
+}
+
+/// Returns whether a path matches a glob pattern of the conflict_strategy option
+///
+/// A pattern without `/` is matched against the name of the file as well, so that `*.po`
+/// matches the files of every directory.
+fn is_match(pattern: &str, path: &str) -> bool {
+    if util::glob_match(pattern, path) {
+        return true;
+    }
+    let name = path.rsplit('/').next().unwrap_or(path);
+    !pattern.contains('/') && util::glob_match(pattern, name)
+}
+
+/// Resolve the conflicts of the paths matched by a rule, taking the version of its side
+///
+/// `ours` is the version of the local branch, and `theirs` the one of the remote commit
+/// being copied. A path deleted on this side is removed. The conflicts of the other paths
+/// are left in the index. Returns the resolved paths.
+pub fn resolve(
+    index: &mut git2::Index,
+    rules: &[ConflictRule],
+) -> Result<Vec<Resolution>, git2::Error> {
+    if rules.is_empty() || !index.has_conflicts() {
+        return Ok(Vec::new());
+    }
+    let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+
+    let mut resolved = Vec::new();
+    for conflict in conflicts {
+        let entry = conflict
+            .our
+            .as_ref()
+            .or(conflict.their.as_ref())
+            .or(conflict.ancestor.as_ref());
+        let path = match entry {
+            Some(entry) => String::from_utf8_lossy(&entry.path).into_owned(),
+            None => continue,
+        };
+        let side = match rules.iter().find(|rule| is_match(&rule.pattern, &path)) {
+            Some(rule) => rule.side,
+            None => continue,
+        };
+
+        // removes every stage of the path
+        index.remove_path(Path::new(&path))?;
+        let kept = match side {
+            ConflictSide::Ours => conflict.our,
+            ConflictSide::Theirs => conflict.their,
+        };
+        if let Some(mut entry) = kept {
+            entry.flags &= !STAGE_MASK;
+            index.add(&entry)?;
+        }
+        info!(
+            "Resolved the conflicts of {} with {} version.",
+            path,
+            match side {
+                ConflictSide::Ours => "the local",
+                ConflictSide::Theirs => "the remote",
+            }
+        );
+        resolved.push(Resolution { path, side });
+    }
+    Ok(resolved)
+}
+
+/// Resolve the conflicts of the index of the repository, updating the working directory
+pub fn resolve_in_workdir(
+    repo: &git2::Repository,
+    rules: &[ConflictRule],
+) -> Result<Vec<Resolution>, git2::Error> {
+    let mut index = repo.index()?;
+    let resolved = resolve(&mut index, rules)?;
+    let paths: Vec<String> = resolved.iter().map(|res| res.path.clone()).collect();
+    checkout_resolved(repo, &mut index, &paths)?;
+    Ok(resolved)
+}
+
+/// Write the index of the repository, and check out the paths whose conflicts it resolved
+///
+/// The paths resolved by their deletion are removed from the working directory.
+pub fn checkout_resolved(
+    repo: &git2::Repository,
+    index: &mut git2::Index,
+    paths: &[String],
+) -> Result<(), git2::Error> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    index.write()?;
+
+    let mut checkout_opts = git2::build::CheckoutBuilder::new();
+    checkout_opts.force().remove_untracked(true);
+    for path in paths {
+        checkout_opts.path(path);
+    }
+    repo.checkout_index(Some(index), Some(&mut checkout_opts))
+}
+
+#[test]
+fn test_is_match() {
+    assert!(is_match("*.po", "fr.po"));
+    assert!(is_match("*.po", "po/fr.po"));
+    assert!(is_match("po/*.po", "po/fr.po"));
+    assert!(!is_match("po/*.po", "src/po/fr.po"));
+    assert!(is_match("CHANGELOG.md", "CHANGELOG.md"));
+    assert!(is_match("CHANGELOG.md", "doc/CHANGELOG.md"));
+    assert!(!is_match("doc/CHANGELOG.md", "CHANGELOG.md"));
+}
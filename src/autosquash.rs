@@ -0,0 +1,153 @@
+use crate::error::Error;
+use crate::warn;
+use std::collections::{HashMap, HashSet};
+
+const FIXUP_PREFIX: &str = "fixup! ";
+const SQUASH_PREFIX: &str = "squash! ";
+
+/// Plan of the fixup and squash commits to fold in the commits they reference
+///
+/// A folded commit is not copied: its changes are added to the copy of its target. It is
+/// then mapped to the copy of its first parent, which lists it in its tag.
+#[derive(Default)]
+pub struct Plan<'a> {
+    // commits to fold, by target commit
+    fixups: HashMap<git2::Oid, Vec<git2::Commit<'a>>>,
+    // folded commits with their first parent, in the order of the synchronization
+    folded: Vec<(git2::Oid, git2::Oid)>,
+    // folded commits whose changes were added to the copy of their target
+    applied: HashSet<git2::Oid>,
+}
+
+/// Returns the summary of the commit referenced by a fixup or squash commit
+fn fixup_target<'c>(commit: &'c git2::Commit) -> Option<&'c str> {
+    let summary = commit.summary()?;
+    summary
+        .strip_prefix(FIXUP_PREFIX)
+        .or_else(|| summary.strip_prefix(SQUASH_PREFIX))
+}
+
+/// Returns the message to append to the target of a folded commit
+///
+/// Only squash commits bring their message body, fixup commits are folded silently.
+pub fn squash_message<'c>(commit: &'c git2::Commit) -> Option<&'c str> {
+    if !commit.summary()?.starts_with(SQUASH_PREFIX) {
+        return None;
+    }
+    let (_, body) = commit.message()?.split_once('\n')?;
+    let body = body.trim();
+    if body.is_empty() {
+        None
+    } else {
+        Some(body)
+    }
+}
+
+impl<'a> Plan<'a> {
+    /// Find the fixup and squash commits to fold in the list of commits to copy
+    ///
+    /// A fixup commit is folded if it references an earlier commit of the list, and if its
+    /// first parent is copied as well. Merge commits are never folded, nor used as targets.
+    /// Other fixup commits are copied as is, with a warning.
+    pub fn new(commits: &[git2::Commit<'a>]) -> Self {
+        let mut plan = Self::default();
+        let pending: HashSet<git2::Oid> = commits.iter().map(|ci| ci.id()).collect();
+        // candidate targets, by summary
+        let mut targets: HashMap<&str, git2::Oid> = HashMap::new();
+
+        for commit in commits {
+            let target = match fixup_target(commit) {
+                Some(target) => target,
+                None => {
+                    if let (1, Some(summary)) = (commit.parent_count(), commit.summary()) {
+                        targets.insert(summary, commit.id());
+                    }
+                    continue;
+                }
+            };
+
+            let target_id = match targets.get(target) {
+                Some(id) if commit.parent_count() == 1 => *id,
+                _ => {
+                    warn!(
+                        "commit {} does not reference a commit being synchronized, \
+                         it is copied as is.",
+                        commit.id()
+                    );
+                    continue;
+                }
+            };
+            // the folded commit is mapped to the copy of its first parent
+            let parent_id = commit.parent_id(0).unwrap();
+            if !pending.contains(&parent_id) {
+                warn!(
+                    "the parent of commit {} is not synchronized with it, it is \
+                     copied as is.",
+                    commit.id()
+                );
+                continue;
+            }
+
+            plan.fixups
+                .entry(target_id)
+                .or_default()
+                .push(commit.clone());
+            plan.folded.push((commit.id(), parent_id));
+        }
+        plan
+    }
+
+    /// Commits to fold in the copy of the given commit
+    pub fn fixups_of(&self, id: git2::Oid) -> Vec<git2::Commit<'a>> {
+        self.fixups.get(&id).cloned().unwrap_or_default()
+    }
+
+    pub fn is_folded(&self, id: git2::Oid) -> bool {
+        self.folded.iter().any(|(folded, _)| *folded == id)
+    }
+
+    /// Record that the changes of a folded commit were added to the copy of its target
+    pub fn set_applied(&mut self, id: git2::Oid) {
+        self.applied.insert(id);
+    }
+
+    /// Cancel the folding of a commit, which will be copied as is
+    pub fn unfold(&mut self, id: git2::Oid) {
+        self.folded.retain(|(folded, _)| *folded != id);
+    }
+
+    /// Folded commits whose changes are contained in the copy of the given commit
+    ///
+    /// Those are the applied commits whose first-parent chain of folded commits leads to
+    /// the given commit.
+    pub fn squashed_into(&self, id: git2::Oid) -> Vec<git2::Oid> {
+        let parents: HashMap<git2::Oid, git2::Oid> = self.folded.iter().copied().collect();
+
+        self.folded
+            .iter()
+            .filter(|(folded, _)| self.applied.contains(folded))
+            .filter(|(_, parent)| {
+                let mut anchor = *parent;
+                while let Some(parent) = parents.get(&anchor) {
+                    anchor = *parent;
+                }
+                anchor == id
+            })
+            .map(|(folded, _)| *folded)
+            .collect()
+    }
+}
+
+/// Apply the changes of a folded commit on a tree
+pub fn fold<'r>(
+    repo: &'r git2::Repository,
+    tree: &git2::Tree,
+    commit: &git2::Commit,
+) -> Result<git2::Tree<'r>, Error> {
+    let parent_tree = commit.parent(0)?.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&commit.tree()?), None)?;
+
+    let mut index = repo.apply_to_tree(tree, &diff, None)?;
+    let tree_oid = index.write_tree_to(repo)?;
+    Ok(repo.find_tree(tree_oid)?)
+}
@@ -0,0 +1,125 @@
+//! Stash of the local changes during a run, with `autostash`
+//!
+//! The changes of the index and of the working directory are stashed before the run,
+//! instead of aborting it, and applied again once it is done. The untracked files are left
+//! in place, unless `autostash_untracked` is set. If the run stopped on conflicts, or if the
+//! changes cannot be applied again, they are left in the stash.
+use crate::app::Options;
+use crate::error::Error;
+use crate::state;
+use crate::{info, warn};
+
+// message of the stashes made by ripit
+const STASH_MSG: &str = "ripit: autostash";
+
+/// Stash the local changes, if any, and return the id of the stash
+pub fn save(repo: &git2::Repository, opts: &Options) -> Result<Option<git2::Oid>, Error> {
+    // nothing can be stashed on an unborn branch
+    if repo.is_bare() || repo.head().is_err() {
+        return Ok(None);
+    }
+    let mut flags = git2::StashFlags::DEFAULT;
+    if opts.autostash_untracked {
+        flags |= git2::StashFlags::INCLUDE_UNTRACKED;
+    }
+    // stashing needs a mutable repository
+    let mut stash_repo = git2::Repository::open(repo.path())?;
+    match stash_repo.stash_save(&repo.signature()?, STASH_MSG, Some(flags)) {
+        Ok(id) => {
+            info!("Stashed the local changes in {}.", id);
+            Ok(Some(id))
+        }
+        // there is nothing to stash
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Position of a stash in the list of the stashes, `stash@{0}` being the last one
+fn find_index(repo: &mut git2::Repository, id: git2::Oid) -> Result<Option<usize>, Error> {
+    let mut found = None;
+    repo.stash_foreach(|index, _, stash_id| {
+        if *stash_id == id {
+            found = Some(index);
+        }
+        found.is_none()
+    })?;
+    Ok(found)
+}
+
+/// Untracked files of a stash which now exist in the working directory
+///
+/// libgit2 would merge them with the existing files, instead of refusing to overwrite them.
+fn untracked_in_the_way(repo: &git2::Repository, id: git2::Oid) -> Result<Vec<String>, Error> {
+    let mut paths = Vec::new();
+    // the untracked files are in the third parent of the stash
+    let untracked = match repo.find_commit(id)?.parent(2) {
+        Ok(untracked) => untracked,
+        Err(_) => return Ok(paths),
+    };
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return Ok(paths),
+    };
+    untracked
+        .tree()?
+        .walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                let path = format!("{}{}", root, entry.name().unwrap_or(""));
+                if workdir.join(&path).exists() {
+                    paths.push(path);
+                }
+            }
+            git2::TreeWalkResult::Ok
+        })?;
+    Ok(paths)
+}
+
+/// Apply the stashed changes again, and drop their stash
+///
+/// The stash is kept if the run stopped on conflicts, or if the changes cannot be applied
+/// without conflicts, which is told with the entry holding them.
+pub fn restore(repo: &git2::Repository, id: git2::Oid) -> Result<(), Error> {
+    let mut stash_repo = git2::Repository::open(repo.path())?;
+    let index = match find_index(&mut stash_repo, id)? {
+        Some(index) => index,
+        // dropped by a hook, for example
+        None => return Ok(()),
+    };
+    let kept = |reason: String| Error::AutostashKept { index, id, reason };
+
+    if state::state_path(repo).exists() {
+        warn!(
+            "{}",
+            kept("the synchronization stopped on conflicts".to_owned())
+        );
+        return Ok(());
+    }
+    let paths = untracked_in_the_way(repo, id)?;
+    if !paths.is_empty() {
+        let reason = format!("the untracked files {} already exist", paths.join(", "));
+        warn!("{}", kept(reason));
+        return Ok(());
+    }
+    let mut apply_opts = git2::StashApplyOptions::new();
+    apply_opts.reinstantiate_index();
+    match stash_repo.stash_apply(index, Some(&mut apply_opts)) {
+        Ok(()) if !stash_repo.index()?.has_conflicts() => {
+            stash_repo.stash_drop(index)?;
+            info!("Applied the stashed local changes again.");
+        }
+        // the conflicts are left in the working directory, as git stash pop does
+        Ok(()) => warn!(
+            "{}",
+            kept("they conflict with the synchronized commits".to_owned())
+        ),
+        Err(err) => warn!(
+            "{}",
+            kept(format!(
+                "they cannot be applied on the synchronized commits: {}",
+                err.message()
+            ))
+        ),
+    }
+    Ok(())
+}
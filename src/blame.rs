@@ -0,0 +1,86 @@
+use crate::error::Error;
+use crate::tag;
+use crate::util;
+use std::path::Path;
+
+// number of characters used to display commit ids
+const SHORT_ID_LEN: usize = 10;
+
+fn short_id(id: &str) -> &str {
+    id.get(..SHORT_ID_LEN).unwrap_or(id)
+}
+
+/// Describe the remote commit matching a local commit
+///
+/// Returns the id, author, date and summary to display for a hunk.
+fn describe_commit(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    local: &git2::Commit,
+) -> (String, String, String, String) {
+    let describe = |id: &str, ci: &git2::Commit, label: &str| {
+        (
+            short_id(id).to_owned(),
+            ci.author().name().unwrap_or("").to_owned(),
+            util::format_date(&ci.author().when()),
+            format!("{}{}", ci.summary().unwrap_or(""), label),
+        )
+    };
+
+    let remote_id = match tags.retrieve_ripit_tag(local) {
+        Some((remote_id, _)) => remote_id,
+        None => return describe("local", local, " (local commit)"),
+    };
+    // lines of the bootstrap commit come from the history of the remote before it
+    let label = if tags.is_bootstrap(local) {
+        " (bootstrap)"
+    } else {
+        ""
+    };
+
+    match git2::Oid::from_str(&remote_id).and_then(|oid| repo.find_commit(oid)) {
+        Ok(remote) => describe(&remote_id, &remote, label),
+        // the remote commit was not fetched, only its id is known
+        Err(_) => describe(&remote_id, local, label),
+    }
+}
+
+/// Print the blame of a file, mapping every line to the remote commit that introduced it
+///
+/// The blame is done in the local repository, then each local commit is translated to the
+/// remote commit it was copied from, thanks to its ripit tag. Lines introduced by local
+/// commits are labeled as such.
+pub fn print_blame(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    path: &str,
+    rev: Option<&str>,
+) -> Result<(), Error> {
+    let mut blame_opts = git2::BlameOptions::new();
+    if let Some(rev) = rev {
+        let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+        blame_opts.newest_commit(commit.id());
+    }
+    let blame = repo.blame_file(Path::new(path), Some(&mut blame_opts))?;
+
+    for hunk in blame.iter() {
+        let local = repo.find_commit(hunk.final_commit_id())?;
+        let (id, author, date, summary) = describe_commit(repo, tags, &local);
+
+        let start = hunk.final_start_line();
+        let lines = match hunk.lines_in_hunk() {
+            1 => format!("{}", start),
+            n => format!("{}-{}", start, start + n - 1),
+        };
+        println!(
+            "{:>9}  {:width$}  {}  {}  {}",
+            lines,
+            id,
+            date,
+            author,
+            summary,
+            width = SHORT_ID_LEN
+        );
+    }
+    Ok(())
+}
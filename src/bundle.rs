@@ -0,0 +1,111 @@
+use crate::app;
+use crate::error::Error;
+use crate::info;
+use crate::log;
+use crate::remote_tags;
+use std::process::Command;
+
+/// Run a git command in the repository, returning its stdout
+///
+/// libgit2 does not handle bundles, git is invoked instead.
+fn run_git(repo: &git2::Repository, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(args)
+        .output()
+        .map_err(|err| format!("cannot run git: {}", err))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_owned())
+    }
+}
+
+/// Fetch the branches to synchronize from a bundle, in the refs used for the remote
+///
+/// The bundle must contain every branch to synchronize, and its prerequisites must be in
+/// the local repository, which is the case if they were synchronized before.
+pub fn fetch_from_bundle(
+    repo: &git2::Repository,
+    opts: &app::Options,
+    path: &str,
+) -> Result<(), Error> {
+    let invalid_bundle = |reason| Error::InvalidBundle {
+        path: path.to_owned(),
+        reason,
+    };
+
+    // checks the prerequisites of the bundle
+    run_git(repo, &["bundle", "verify", "--quiet", path]).map_err(invalid_bundle)?;
+
+    let heads = run_git(repo, &["bundle", "list-heads", path]).map_err(invalid_bundle)?;
+    for branch in &opts.branches {
+        let found = heads
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .any(|(_, refname)| refname == branch.remote_refname);
+        if !found {
+            return Err(Error::BundleMissingRef {
+                path: path.to_owned(),
+                refname: branch.remote_refname.clone(),
+            });
+        }
+    }
+
+    for branch in &opts.branches {
+        info!(
+            "Fetch branch {} from bundle {}...",
+            log::branch(&branch.name),
+            path
+        );
+        let refspec = format!("+{}:{}", branch.remote_refname, branch.tracking_refname);
+        run_git(repo, &["fetch", "--quiet", path, &refspec]).map_err(invalid_bundle)?;
+    }
+    if remote_tags::are_fetched(opts) {
+        let refspec = remote_tags::refspec(opts);
+        run_git(repo, &["fetch", "--quiet", "--no-tags", path, &refspec])
+            .map_err(invalid_bundle)?;
+    }
+    Ok(())
+}
+
+/// Write a bundle containing the commits synchronized on each branch
+///
+/// The heads of the branches before the synchronization are the prerequisites of the
+/// bundle, so that it can be fetched in any copy of the local repository. No bundle is
+/// written if nothing was synchronized.
+pub fn emit_bundle(
+    repo: &git2::Repository,
+    opts: &app::Options,
+    path: &str,
+    old_heads: &[git2::Oid],
+) -> Result<(), Error> {
+    let mut revs = Vec::new();
+    for (branch, old_head) in opts.branches.iter().zip(old_heads) {
+        if repo.refname_to_id(&branch.refname)? != *old_head {
+            revs.push(branch.refname.clone());
+            revs.push(format!("^{}", old_head));
+        }
+    }
+    if revs.is_empty() {
+        info!("No commits synchronized, no bundle written.");
+        return Ok(());
+    }
+
+    let mut args = vec!["bundle", "create", "--quiet", path];
+    args.extend(revs.iter().map(|rev| rev.as_str()));
+    run_git(repo, &args).map_err(|reason| Error::BundleWrite {
+        path: path.to_owned(),
+        reason,
+    })?;
+    info!(
+        "Bundle {} written with the new commits of {} branches.",
+        path,
+        revs.len() / 2
+    );
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use crate::app;
+use crate::bundle;
+use crate::error::Error;
+use crate::sync;
+
+/// Problems found while checking the configuration, printed as they are found
+#[derive(Default)]
+struct Report {
+    nb_problems: usize,
+}
+
+impl Report {
+    fn ok(&self, msg: &str) {
+        println!("ok: {}", msg);
+    }
+
+    fn problem(&mut self, msg: &str) {
+        println!("error: {}", msg);
+        self.nb_problems += 1;
+    }
+}
+
+/// Check that the configuration can be used to synchronize the branches
+///
+/// The configuration is already parsed and its regexes compiled. The local repository, the
+/// remote and the branches are then checked, after fetching them unless asked not to.
+/// Every problem is reported, instead of stopping at the first one.
+pub fn check_config(opts: &app::Options) -> Result<(), Error> {
+    let mut report = Report::default();
+
+    report.ok(&format!(
+        "configuration parsed, with {} filters and {} skip_commits rules",
+        opts.commit_msg_filters.len(),
+        opts.skip_commits.len()
+    ));
+    match git2::Repository::open(&opts.repo) {
+        Ok(repo) => check_repo(&repo, opts, &mut report),
+        Err(err) => report.problem(&format!(
+            "cannot open repository {}: {}",
+            opts.repo,
+            err.message()
+        )),
+    }
+
+    if report.nb_problems == 0 {
+        println!("The configuration is valid.");
+        Ok(())
+    } else {
+        Err(Error::CheckFailed {
+            count: report.nb_problems,
+        })
+    }
+}
+
+fn check_repo(repo: &git2::Repository, opts: &app::Options, report: &mut Report) {
+    report.ok(&format!("repository {} opened", opts.repo));
+
+    let fetched = match &opts.source_bundle {
+        Some(path) if opts.fetch => bundle::fetch_from_bundle(repo, opts, path),
+        Some(_) => Ok(()),
+        // the remote is only used for the remote-tracking refs with mirrors
+        None if opts.remote_urls.is_empty()
+            && opts.remote_url.is_none()
+            && repo.find_remote(&opts.remote).is_err() =>
+        {
+            Err(Error::UnknownRemote {
+                remote: opts.remote.clone(),
+            })
+        }
+        None if opts.fetch => sync::fetch_branches(repo, opts),
+        None => Ok(()),
+    };
+    match fetched {
+        Ok(()) if opts.fetch => report.ok(&format!("remote {} fetched", opts.remote)),
+        Ok(()) => (),
+        Err(err) => report.problem(&err.to_string()),
+    }
+    if !opts.skip_shas.is_empty() || !opts.replacements.is_empty() {
+        match sync::check_option_shas(repo, opts) {
+            Ok(()) => report.ok(&format!(
+                "{} commits of skip_shas and replacements found in remote {}",
+                opts.skip_shas.len() + opts.replacements.len(),
+                opts.remote
+            )),
+            Err(err) => report.problem(&err.to_string()),
+        }
+    }
+
+    for branch in &opts.branches {
+        if repo.find_reference(&branch.tracking_refname).is_ok() {
+            report.ok(&format!(
+                "branch {} found in remote {}",
+                branch.remote_name, opts.remote
+            ));
+        } else {
+            report.problem(
+                &Error::UnknownRemoteBranch {
+                    branch: branch.remote_name.clone(),
+                    remote: opts.remote.clone(),
+                }
+                .to_string(),
+            );
+        }
+        if let Some(push_remote) = &branch.push_remote {
+            if repo.find_remote(push_remote).is_err() {
+                report.problem(
+                    &Error::UnknownRemote {
+                        remote: push_remote.clone(),
+                    }
+                    .to_string(),
+                );
+            }
+        }
+    }
+}
@@ -1,10 +1,14 @@
+use crate::autoresolve::Resolution;
 use crate::error::Error;
 use crate::tag;
+use crate::util;
+use crate::{error, info, warn};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::BufRead;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 pub struct SyncedCommit<'a> {
     pub commit: git2::Commit<'a>,
@@ -13,37 +17,143 @@ pub struct SyncedCommit<'a> {
 
 type Map<'a> = HashMap<git2::Oid, SyncedCommit<'a>>;
 
+// Cache file listing the remote commits and their local copies
+//
+// The mappings are appended to the file as they are inserted, and the whole content is
+// rewritten atomically when flushed, which repairs a line truncated by an interrupted
+// append.
+struct CacheFile {
+    path: PathBuf,
+    file: std::fs::File,
+    content: String,
+    // whether mappings were appended since the last rewrite
+    dirty: bool,
+}
+
+impl CacheFile {
+    fn open(path: PathBuf, content: String) -> Result<Self, Error> {
+        let mut opts = std::fs::OpenOptions::new();
+        opts.create(true).append(true);
+        let res = std::fs::create_dir_all(path.parent().unwrap()).and_then(|_| opts.open(&path));
+        match res {
+            Ok(file) => Ok(Self {
+                path,
+                file,
+                content,
+                dirty: false,
+            }),
+            Err(err) => Err(Error::CacheOpenError {
+                err,
+                filename: path,
+            }),
+        }
+    }
+
+    fn append(&mut self, line: &str) -> Result<(), std::io::Error> {
+        self.content.push_str(line);
+        self.dirty = true;
+        self.file.write_all(line.as_bytes())
+    }
+
+    fn rewrite(&mut self) -> Result<(), std::io::Error> {
+        write_atomically(&self.path, &self.content)?;
+        // the file was replaced, the appends must go to the new one
+        self.file = std::fs::OpenOptions::new().append(true).open(&self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
 pub struct CommitsMap<'a> {
     // map of Oid in remote repo to Commit in local repo
     map: Map<'a>,
+    // tags of the local commits, from which the map is filled
+    tags: &'a tag::Tags,
+
+    // None when the map is read-only, nothing is then written in the state files. It is
+    // shared by the maps of the branches synchronized in parallel.
+    cache_file: Option<Arc<Mutex<CacheFile>>>,
+    // lines appended to the cache file by this map
+    appended: Vec<String>,
 
-    cache_file: std::fs::File,
+    // remote commits that were skipped, with the rule that skipped them
+    skipped: HashMap<git2::Oid, String>,
+    skipped_filename: PathBuf,
+
+    // conflicts resolved automatically when copying remote commits during this run
+    resolved: HashMap<git2::Oid, Vec<Resolution>>,
 }
 
 impl<'a> CommitsMap<'a> {
-    pub fn new(repo: &'a git2::Repository) -> Result<Self, Error> {
-        // FIXME: reject bare repositories
-        let filename = repo.workdir().unwrap().join(".ripit-cache");
+    pub fn new(repo: &'a git2::Repository, tags: &'a tag::Tags) -> Result<Self, Error> {
+        Self::open(repo, tags, false)
+    }
+
+    /// Build the map without creating or updating the cache file
+    pub fn new_read_only(repo: &'a git2::Repository, tags: &'a tag::Tags) -> Result<Self, Error> {
+        Self::open(repo, tags, true)
+    }
+
+    fn open(
+        repo: &'a git2::Repository,
+        tags: &'a tag::Tags,
+        read_only: bool,
+    ) -> Result<Self, Error> {
+        let filename = locate_state_file(repo, cache_path(repo), ".ripit-cache", read_only)?;
         let mut map = Map::new();
 
         // fill map from cache file
-        match std::fs::File::open(&filename) {
-            Ok(f) => fill_map_from_cache_file(&mut map, f, repo, &filename)?,
+        let mut content = match std::fs::read_to_string(&filename) {
+            Ok(content) => content,
             Err(err) => match err.kind() {
-                std::io::ErrorKind::NotFound => (),
-                _ => return Err(Error::CacheOpenError { err, filename }),
+                std::io::ErrorKind::NotFound => String::new(),
+                _ => return Err(Error::CacheReadError { err, filename }),
             },
         };
+        let truncated = drop_truncated_line(&mut content, &filename);
+        fill_map_from_cache_content(&mut map, &content, repo, tags, &filename)?;
 
         // open cache file for writing
-        let mut opts = std::fs::OpenOptions::new();
-        opts.create(true).append(true);
-        let cache_file = match opts.open(&filename) {
-            Ok(f) => f,
-            Err(err) => return Err(Error::CacheOpenError { err, filename }),
+        let cache_file = if read_only {
+            None
+        } else {
+            let mut cache_file = CacheFile::open(filename, content)?;
+            // new mappings must not be appended to the truncated line
+            if truncated {
+                if let Err(err) = cache_file.rewrite() {
+                    return Err(Error::CacheOpenError {
+                        err,
+                        filename: cache_file.path,
+                    });
+                }
+            }
+            Some(Arc::new(Mutex::new(cache_file)))
+        };
+
+        let skipped_filename =
+            locate_state_file(repo, skipped_path(repo), ".ripit-skipped", read_only)?;
+        let skipped = match std::fs::File::open(&skipped_filename) {
+            Ok(f) => read_skipped_file(f, &skipped_filename)?,
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => HashMap::new(),
+                _ => {
+                    return Err(Error::CacheOpenError {
+                        err,
+                        filename: skipped_filename,
+                    })
+                }
+            },
         };
 
-        let mut commits_map = Self { map, cache_file };
+        let mut commits_map = Self {
+            map,
+            tags,
+            cache_file,
+            appended: Vec::new(),
+            skipped,
+            skipped_filename,
+            resolved: HashMap::new(),
+        };
 
         // Fill map from HEAD if it exists
         if let Ok(head) = repo.head() {
@@ -53,6 +163,57 @@ impl<'a> CommitsMap<'a> {
         Ok(commits_map)
     }
 
+    /// Share the map with other threads, which open it on their own repository
+    pub fn share(&self) -> SharedMap {
+        SharedMap {
+            entries: self.entries_of(self.map.keys().copied()),
+            cache_file: self.cache_file.clone(),
+            skipped: self.skipped.clone(),
+            skipped_filename: self.skipped_filename.clone(),
+        }
+    }
+
+    /// Mappings of the given remote commits, to be added to the map of another thread
+    pub fn entries_of(&self, oids: impl IntoIterator<Item = git2::Oid>) -> MapEntries {
+        let mut entries = MapEntries::default();
+        for oid in oids {
+            if let Some(synced) = self.map.get(&oid) {
+                entries
+                    .mapped
+                    .push((oid, synced.commit.id(), synced.uprooted));
+            }
+            if let Some(rule) = self.skipped.get(&oid) {
+                entries.skipped.push((oid, rule.clone()));
+            }
+            if let Some(resolutions) = self.resolved.get(&oid) {
+                entries.resolved.push((oid, resolutions.clone()));
+            }
+        }
+        entries
+    }
+
+    /// Add the mappings made by another thread, which already saved them
+    pub fn add_entries(
+        &mut self,
+        repo: &'a git2::Repository,
+        entries: &MapEntries,
+    ) -> Result<(), Error> {
+        for (oid, id, uprooted) in &entries.mapped {
+            let synced = SyncedCommit {
+                commit: repo.find_commit(*id)?,
+                uprooted: *uprooted,
+            };
+            self.insert_uncached(*oid, synced);
+        }
+        for (oid, rule) in &entries.skipped {
+            self.skipped.entry(*oid).or_insert_with(|| rule.clone());
+        }
+        for (oid, resolutions) in &entries.resolved {
+            self.resolved.insert(*oid, resolutions.clone());
+        }
+        Ok(())
+    }
+
     pub fn fill_from_branch(
         &mut self,
         repo: &'a git2::Repository,
@@ -78,12 +239,24 @@ impl<'a> CommitsMap<'a> {
 
             // a commit missing a tag could be an error too. By ignoring it, it will lead to errors
             // if it is a parent of a commit to sync.
-            let (tag, uprooted) = match tag::retrieve_ripit_tag(&commit) {
+            let (tag, uprooted) = match self.tags.retrieve_ripit_tag(&commit) {
                 Some(tag) => tag,
                 None => continue,
             };
+            // only the last part of a split commit is mapped
+            if self.tags.is_partial(&commit) {
+                continue;
+            }
             let remote_oid = git2::Oid::from_str(&tag)?;
 
+            // commits folded in this one are mapped to it as well
+            for squashed_oid in self.tags.retrieve_squashed(&commit) {
+                let synced = SyncedCommit {
+                    commit: commit.clone(),
+                    uprooted,
+                };
+                self.insert_uncached(squashed_oid, synced);
+            }
             if !self.insert(remote_oid, SyncedCommit { commit, uprooted }) {
                 // entry was already in the map, no need to continue
                 break;
@@ -97,35 +270,416 @@ impl<'a> CommitsMap<'a> {
         self.map.contains_key(&oid)
     }
 
-    pub fn get(&self, oid: git2::Oid) -> Option<&SyncedCommit> {
+    pub fn get(&self, oid: git2::Oid) -> Option<&SyncedCommit<'a>> {
         self.map.get(&oid)
     }
 
+    /// Insert a mapping without saving it in the cache file
+    ///
+    /// This is used for commits that do not have a ripit tag, and thus cannot be reloaded
+    /// from the cache file.
+    pub fn insert_uncached(&mut self, oid: git2::Oid, val: SyncedCommit<'a>) {
+        self.map.entry(oid).or_insert(val);
+    }
+
+    /// Map a remote commit to another local commit, for this run only
+    ///
+    /// The commits copied on top of it are then copied on this local commit.
+    pub fn remap(&mut self, oid: git2::Oid, val: SyncedCommit<'a>) {
+        self.map.insert(oid, val);
+    }
+
+    /// Returns the rule that skipped a remote commit, if it was skipped
+    pub fn skip_rule(&self, oid: git2::Oid) -> Option<&str> {
+        self.skipped.get(&oid).map(|rule| rule.as_str())
+    }
+
+    /// Record that a remote commit was skipped by the given rule
+    pub fn record_skip(&mut self, oid: git2::Oid, rule: &str) {
+        if self.skipped.contains_key(&oid) {
+            return;
+        }
+        self.skipped.insert(oid, rule.to_owned());
+        if self.cache_file.is_none() {
+            return;
+        }
+
+        let mut opts = std::fs::OpenOptions::new();
+        opts.create(true).append(true);
+        let res = opts
+            .open(&self.skipped_filename)
+            .and_then(|mut f| writeln!(f, "{} {}", oid, rule));
+        if let Err(err) = res {
+            error!("error when writing in skipped commits file: {}", err);
+        }
+    }
+
+    /// Conflicts resolved automatically when copying a remote commit during this run
+    pub fn resolutions(&self, oid: git2::Oid) -> &[Resolution] {
+        self.resolved
+            .get(&oid)
+            .map_or(&[], |resolutions| resolutions.as_slice())
+    }
+
+    /// Record the conflicts resolved automatically when copying a remote commit
+    pub fn record_resolutions(&mut self, oid: git2::Oid, resolutions: Vec<Resolution>) {
+        self.resolved.insert(oid, resolutions);
+    }
+
+    /// List a local commit copied from a remote commit in the cache file, without mapping it
+    pub fn cache_commit(&mut self, remote_oid: git2::Oid, id: git2::Oid) {
+        self.write_mapping(remote_oid, id);
+    }
+
+    /// Entries added to the cache file since the map was built, in order
+    pub fn added_entries(&self) -> &[String] {
+        &self.appended
+    }
+
+    /// Make sure every mapping inserted so far is persisted on disk
+    ///
+    /// The cache file is rewritten atomically, so that it is never left truncated.
+    pub fn flush(&mut self) {
+        let cache_file = match &self.cache_file {
+            Some(cache_file) => cache_file,
+            None => return,
+        };
+        let mut cache_file = cache_file.lock().unwrap();
+        if cache_file.dirty {
+            if let Err(err) = cache_file.rewrite() {
+                error!("error when writing in cache file: {}", err);
+            }
+        }
+    }
+
+    /// Write the ids of a remote commit and of its local copy in the cache file
+    fn write_mapping(&mut self, remote_oid: git2::Oid, id: git2::Oid) {
+        let cache_file = match &self.cache_file {
+            Some(cache_file) => cache_file,
+            None => return,
+        };
+        let line = format!("{} {}\n", remote_oid, id);
+        if let Err(err) = cache_file.lock().unwrap().append(&line) {
+            error!("error when writing in cache file: {}", err);
+        }
+        self.appended.push(line.trim_end().to_owned());
+    }
+
     pub fn insert(&mut self, oid: git2::Oid, val: SyncedCommit<'a>) -> bool {
         match self.map.entry(oid) {
             Entry::Occupied(_) => false,
             Entry::Vacant(v) => {
-                write_id_in_cache_file(&mut self.cache_file, val.commit.id());
+                let id = val.commit.id();
                 v.insert(val);
+                self.write_mapping(oid, id);
                 true
             }
         }
     }
 }
 
-fn write_id_in_cache_file(file: &mut std::fs::File, id: git2::Oid) {
-    if let Err(err) = writeln!(file, "{}", id) {
-        eprintln!("error when writing in cache file: {}", err);
+/// Mappings of remote commits, which can be sent to other threads
+#[derive(Default)]
+pub struct MapEntries {
+    // remote commits, their local copies, and whether they are uprooted
+    mapped: Vec<(git2::Oid, git2::Oid, bool)>,
+    // remote commits skipped, with the rule that skipped them
+    skipped: Vec<(git2::Oid, String)>,
+    // conflicts resolved automatically when copying remote commits
+    resolved: Vec<(git2::Oid, Vec<Resolution>)>,
+}
+
+/// Commits map shared by the threads synchronizing branches in parallel
+///
+/// Every thread opens it on its own handle on the repository, the cache file being shared.
+pub struct SharedMap {
+    entries: MapEntries,
+    cache_file: Option<Arc<Mutex<CacheFile>>>,
+    skipped: HashMap<git2::Oid, String>,
+    skipped_filename: PathBuf,
+}
+
+impl SharedMap {
+    pub fn open<'a>(
+        &self,
+        repo: &'a git2::Repository,
+        tags: &'a tag::Tags,
+    ) -> Result<CommitsMap<'a>, Error> {
+        let mut commits_map = CommitsMap {
+            map: Map::new(),
+            tags,
+            cache_file: self.cache_file.clone(),
+            appended: Vec::new(),
+            skipped: self.skipped.clone(),
+            skipped_filename: self.skipped_filename.clone(),
+            resolved: HashMap::new(),
+        };
+        commits_map.add_entries(repo, &self.entries)?;
+        Ok(commits_map)
     }
 }
 
-fn fill_map_from_cache_file<'a>(
-    map: &mut Map<'a>,
+impl Drop for CommitsMap<'_> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Write the content of a file atomically
+///
+/// The content is written in a temporary file of the same directory, synced to the disk,
+/// and renamed over the file.
+pub fn write_atomically(path: &Path, content: &str) -> Result<(), std::io::Error> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+
+    // persist the rename, which is not possible on every platform
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+/// Path to the cache file, in the git directory shared by every worktree
+pub fn cache_path(repo: &git2::Repository) -> PathBuf {
+    util::common_dir(repo).join("ripit").join("cache")
+}
+
+/// Path to the file listing the skipped commits, next to the cache file
+pub fn skipped_path(repo: &git2::Repository) -> PathBuf {
+    util::common_dir(repo).join("ripit").join("skipped")
+}
+
+/// Remove the cache entries of the local commits not reachable from the given commits
+///
+/// Returns the number of removed entries.
+pub fn prune_cache(repo: &git2::Repository, heads: &[git2::Oid]) -> Result<usize, Error> {
+    let filename = cache_path(repo);
+    let content = match std::fs::read_to_string(&filename) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(Error::CacheReadError { err, filename }),
+    };
+
+    let mut revwalk = repo.revwalk()?;
+    for head in heads {
+        revwalk.push(*head)?;
+    }
+    let reachable = revwalk.collect::<Result<HashSet<_>, _>>()?;
+
+    let mut nb_removed = 0;
+    let mut new_content = String::new();
+    for line in content.lines() {
+        let local_id = line.rsplit(' ').next().map(git2::Oid::from_str);
+        if let Some(Ok(id)) = local_id {
+            if !reachable.contains(&id) {
+                nb_removed += 1;
+                continue;
+            }
+        }
+        new_content.push_str(line);
+        new_content.push('\n');
+    }
+
+    if nb_removed > 0 {
+        if let Err(err) = write_atomically(&filename, &new_content) {
+            return Err(Error::CacheOpenError { err, filename });
+        }
+    }
+    Ok(nb_removed)
+}
+
+/// Remove the given entries from the cache file
+///
+/// Each entry removes a single line, the last one matching it. Returns the number of
+/// removed entries.
+pub fn remove_cache_entries(repo: &git2::Repository, entries: &[String]) -> Result<usize, Error> {
+    let filename = cache_path(repo);
+    let content = match std::fs::read_to_string(&filename) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(Error::CacheReadError { err, filename }),
+    };
+
+    let mut to_remove: HashMap<&str, usize> = HashMap::new();
+    for entry in entries {
+        *to_remove.entry(entry.as_str()).or_default() += 1;
+    }
+    // the last lines matching the entries are removed, walking the file backwards
+    let mut lines: Vec<&str> = Vec::new();
+    let mut nb_removed = 0;
+    for line in content.lines().rev() {
+        match to_remove.get_mut(line) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                nb_removed += 1;
+            }
+            _ => lines.push(line),
+        }
+    }
+
+    if nb_removed > 0 {
+        let new_content: String = lines
+            .iter()
+            .rev()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        if let Err(err) = write_atomically(&filename, &new_content) {
+            return Err(Error::CacheOpenError { err, filename });
+        }
+    }
+    Ok(nb_removed)
+}
+
+/// Rebuild the cache file from the ripit tags of the commits of the local branches
+///
+/// The commits reachable from the branches, or from the entries of their reflogs and of the
+/// reflog of HEAD, are listed in topological order. The reflogs recover the copies that are
+/// no longer reachable from the branches, such as the resolutions of conflicts of commits
+/// that were uprooted later on. The existing cache file is backed up first.
+///
+/// Returns the number of entries of the new cache file.
+pub fn rebuild_cache(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    refnames: &[&str],
+) -> Result<usize, Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    for refname in refnames {
+        if repo.find_reference(refname).is_err() {
+            warn!("branch {} does not exist, it is ignored.", refname);
+            continue;
+        }
+        revwalk.push_ref(refname)?;
+    }
+    // the conflicts are resolved on a detached HEAD
+    for refname in refnames.iter().chain(&["HEAD"]) {
+        if let Ok(reflog) = repo.reflog(refname) {
+            for entry in reflog.iter() {
+                // the commits of old entries may have been garbage collected
+                let _ = revwalk.push(entry.id_new());
+            }
+        }
+    }
+
+    let mut content = String::new();
+    let mut copies: HashMap<git2::Oid, git2::Oid> = HashMap::new();
+    let mut nb_entries = 0;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let remote_oid = match tags.retrieve_ripit_tag(&commit) {
+            Some((tag, _)) => git2::Oid::from_str(&tag)?,
+            None => continue,
+        };
+        // the parts of a split commit all carry the tag of the remote commit
+        if !tags.is_partial(&commit) {
+            if let Some(previous) = copies.insert(remote_oid, commit.id()) {
+                warn!(
+                    "commits {} and {} are both copies of remote commit {}, the \
+                     latter is used.",
+                    previous,
+                    commit.id(),
+                    remote_oid
+                );
+            }
+        }
+        content.push_str(&format!("{} {}\n", remote_oid, commit.id()));
+        nb_entries += 1;
+    }
+
+    let filename = cache_path(repo);
+    let res = std::fs::create_dir_all(filename.parent().unwrap()).and_then(|_| {
+        if filename.exists() {
+            let backup = filename.with_file_name("cache.bak");
+            std::fs::rename(&filename, &backup)?;
+            info!("Backed up the cache file to {}.", backup.display());
+        }
+        write_atomically(&filename, &content)
+    });
+    if let Err(err) = res {
+        return Err(Error::CacheOpenError { err, filename });
+    }
+    Ok(nb_entries)
+}
+
+/// Path of a state file, moved from its legacy location if it is only found there
+///
+/// A read-only map does not move the file, it is read where it is.
+fn locate_state_file(
+    repo: &git2::Repository,
+    filename: PathBuf,
+    legacy_name: &str,
+    read_only: bool,
+) -> Result<PathBuf, Error> {
+    let legacy = legacy_state_path(repo, legacy_name);
+    if filename.exists() || !legacy.exists() {
+        return Ok(filename);
+    }
+    if read_only {
+        return Ok(legacy);
+    }
+    migrate_state_file(&legacy, &filename)?;
+    Ok(filename)
+}
+
+/// Move a state file from its legacy location to the git directory
+fn migrate_state_file(legacy: &Path, filename: &Path) -> Result<(), Error> {
+    let res = std::fs::create_dir_all(filename.parent().unwrap())
+        .and_then(|_| std::fs::copy(legacy, filename))
+        .and_then(|_| std::fs::remove_file(legacy));
+    if let Err(err) = res {
+        return Err(Error::CacheOpenError {
+            err,
+            filename: filename.to_path_buf(),
+        });
+    }
+    info!(
+        "Moved the state file {} to {}.",
+        legacy.display(),
+        filename.display()
+    );
+    Ok(())
+}
+
+/// Path to a state file used by previous versions, at the root of the main working directory
+///
+/// The state files of a bare repository were stored in its git directory.
+fn legacy_state_path(repo: &git2::Repository, name: &str) -> PathBuf {
+    if repo.is_worktree() {
+        // the files of the main worktree were shared by every worktree
+        let common_dir = util::common_dir(repo);
+        let main_is_bare = git2::Repository::open(&common_dir)
+            .map(|main| main.is_bare())
+            .unwrap_or(false);
+        if main_is_bare {
+            return common_dir.join(name);
+        }
+        if let Some(root) = common_dir.parent() {
+            return root.join(name);
+        }
+    }
+    match repo.workdir() {
+        Some(workdir) => workdir.join(name),
+        None => repo.path().join(name),
+    }
+}
+
+/// Read the file listing skipped commits
+///
+/// Every line contains the id of a remote commit, followed by the rule that skipped it.
+fn read_skipped_file(
     file: std::fs::File,
-    repo: &'a git2::Repository,
     filename: &PathBuf,
-) -> Result<(), Error> {
+) -> Result<HashMap<git2::Oid, String>, Error> {
     let reader = std::io::BufReader::new(&file);
+    let mut skipped = HashMap::new();
 
     for (line_number, line) in reader.lines().enumerate() {
         let line = match line {
@@ -138,38 +692,103 @@ fn fill_map_from_cache_file<'a>(
             }
         };
 
-        match parse_cache_mapping(&line, repo) {
-            Ok((remote_oid, commit)) => {
-                map.insert(remote_oid, commit);
+        let (oid, rule) = line.split_at(line.find(' ').unwrap_or(line.len()));
+        match git2::Oid::from_str(oid) {
+            Ok(oid) => {
+                skipped.insert(oid, rule.trim().to_owned());
             }
-            Err(desc) => {
+            Err(e) => {
                 return Err(Error::CacheInvalidLine {
-                    desc,
+                    desc: e.message().to_owned(),
                     filename: filename.to_owned(),
                     line,
                     line_number: line_number as _,
                 })
             }
+        }
+    }
+
+    Ok(skipped)
+}
+
+/// Remove the last line of the cache content if it was truncated
+///
+/// Every line is terminated, an unterminated last line was being written when ripit was
+/// interrupted. The commit it maps was then not recorded, and is synchronized again.
+fn drop_truncated_line(content: &mut String, filename: &Path) -> bool {
+    if content.is_empty() || content.ends_with('\n') {
+        return false;
+    }
+    let start = content.rfind('\n').map_or(0, |pos| pos + 1);
+    warn!(
+        "ignoring the truncated last line \"{}\" of the cache file {}.",
+        &content[start..],
+        filename.display()
+    );
+    content.truncate(start);
+    true
+}
+
+fn fill_map_from_cache_content<'a>(
+    map: &mut Map<'a>,
+    content: &str,
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    filename: &Path,
+) -> Result<(), Error> {
+    for (line_number, line) in content.lines().enumerate() {
+        match parse_cache_mapping(line, repo, tags) {
+            Ok((remote_oid, commit)) => {
+                // only the last part of a split commit is mapped
+                if !tags.is_partial(&commit.commit) {
+                    for squashed_oid in tags.retrieve_squashed(&commit.commit) {
+                        let synced = SyncedCommit {
+                            commit: commit.commit.clone(),
+                            uprooted: commit.uprooted,
+                        };
+                        map.insert(squashed_oid, synced);
+                    }
+                    map.insert(remote_oid, commit);
+                }
+            }
+            Err(desc) => {
+                return Err(Error::CacheCorrupted {
+                    desc,
+                    filename: filename.to_owned(),
+                    line: line.to_owned(),
+                    line_number: line_number as u32 + 1,
+                })
+            }
         };
     }
 
     Ok(())
 }
 
+/// Parse a line of the cache file
+///
+/// Lines contain the id of a remote commit, followed by the id of its local copy. Cache
+/// files written by previous versions only contain the local id, the remote id is then
+/// read from the tag of the local commit.
 fn parse_cache_mapping<'a>(
     line: &str,
     repo: &'a git2::Repository,
+    tags: &tag::Tags,
 ) -> Result<(git2::Oid, SyncedCommit<'a>), String> {
-    let commit = match commit_from_mapping(line, repo) {
+    let (remote_id, local_id) = match line.split_once(' ') {
+        Some((remote_id, local_id)) => (Some(remote_id), local_id),
+        None => (None, line),
+    };
+    let commit = match commit_from_mapping(local_id, repo) {
         Ok(ci) => ci,
         Err(e) => return Err(e.message().to_owned()),
     };
-    let (tag, uprooted) = match tag::retrieve_ripit_tag(&commit) {
+    let (tag, uprooted) = match tags.retrieve_ripit_tag(&commit) {
         Some(tag) => tag,
         None => return Err("Commit does not have a ripit tag".to_owned()),
     };
 
-    let remote_oid = match git2::Oid::from_str(&tag) {
+    let remote_oid = match git2::Oid::from_str(remote_id.unwrap_or(&tag)) {
         Ok(oid) => oid,
         Err(e) => return Err(e.message().to_owned()),
     };
@@ -0,0 +1,60 @@
+use crate::error::Error;
+use std::process::Command;
+
+/// Build the content of the file edited by the user
+fn message_template(commit: &git2::Commit, msg: &str) -> String {
+    format!(
+        "{}\n\n\
+         # Edit the message of the commit {} to synchronize.\n\
+         # Lines starting with '#' are ignored, and the ripit tag is added afterwards.\n\
+         # An empty message skips the commit.\n",
+        msg.trim_end(),
+        commit.id()
+    )
+}
+
+/// Let the user edit the message of a commit in an editor
+///
+/// The editor command is run in a shell, with the path of the message file as argument.
+/// Returns the edited message, or None if the user emptied it.
+pub fn edit_message(cmd: &str, commit: &git2::Commit, msg: &str) -> Result<Option<String>, Error> {
+    let editor_failed = |reason: String| Error::EditorFailed {
+        commit_id: commit.id(),
+        reason,
+    };
+
+    let path =
+        std::env::temp_dir().join(format!("ripit-{}-{}.msg", std::process::id(), commit.id()));
+    std::fs::write(&path, message_template(commit, msg))
+        .map_err(|err| editor_failed(err.to_string()))?;
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", cmd))
+        .arg(cmd)
+        .arg(&path)
+        .status();
+    let content = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path),
+        Ok(status) => {
+            let _ = std::fs::remove_file(&path);
+            return Err(editor_failed(status.to_string()));
+        }
+        Err(err) => Err(err),
+    };
+    let _ = std::fs::remove_file(&path);
+    let content = content.map_err(|err| editor_failed(err.to_string()))?;
+
+    let lines: Vec<&str> = content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    let msg = lines.join("\n");
+    let msg = msg.trim();
+
+    if msg.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(format!("{}\n", msg)))
+    }
+}
@@ -1,23 +1,342 @@
 use std::fmt;
 use std::path::PathBuf;
 
+// Operation of a synchronization during which an error happened
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Bootstrap,
+    Synchronize,
+    CherryPick,
+    // copy of a merge commit
+    Merge,
+    // copy of the commits to synchronize as a single commit
+    Squash,
+}
+
+impl Operation {
+    /// Verb describing the operation, and the preposition introducing its branch
+    fn describe(self) -> (&'static str, &'static str) {
+        match self {
+            Operation::Bootstrap => ("bootstrap", ""),
+            Operation::Synchronize => ("synchronize", ""),
+            Operation::CherryPick => ("cherry-pick", "onto"),
+            Operation::Merge => ("copy the merge", "onto"),
+            Operation::Squash => ("squash the commits up to", "onto"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     // generic git error
     Git(git2::Error),
+    // A generic git error raised while processing a branch, or a remote commit
+    Failed {
+        operation: Operation,
+        branch: String,
+        // remote commit being processed, and its summary
+        commit: Option<(git2::Oid, String)>,
+        source: Box<Error>,
+    },
+    // invalid command line, or request for the help or version
+    Cli(clap::Error),
     // a ripit tag is required but was not found
     TagMissing,
     // the local repo has changes
     HasLocalChanges,
+    // untracked files of the local repo would be overwritten by the synchronized files
+    UntrackedFilesInTheWay {
+        paths: Vec<String>,
+    },
+    // the local changes stashed with autostash cannot be applied again
+    AutostashKept {
+        index: usize,
+        id: git2::Oid,
+        reason: String,
+    },
     // the parent of a commit to sync cannot be mapped to a commit in the local repo
     UnknownParent {
         commit_id: git2::Oid,
         parent_id: git2::Oid,
+        branch: String,
+    },
+    // A local branch to synchronize does not exist
+    MissingBranch {
+        branch: String,
+    },
+    // A missing local branch cannot be recreated, as no synchronized commits are known
+    CannotRecreateBranch {
+        branch: String,
+        remote: String,
+    },
+    // The remote to synchronize does not exist in the local repository
+    UnknownRemote {
+        remote: String,
+    },
+    // A branch to synchronize does not exist in the remote
+    UnknownRemoteBranch {
+        branch: String,
+        remote: String,
+    },
+    // The revision given with --until or --at cannot be found in the remote
+    UnknownUntilRev {
+        rev: String,
+        remote: String,
+    },
+    // A commit id of the skip_shas or replacements option cannot be found in the remote
+    UnknownOptionSha {
+        option: &'static str,
+        sha: String,
+        remote: String,
+    },
+    // An abbreviated commit id of the skip_shas or replacements option matches several objects
+    AmbiguousOptionSha {
+        option: &'static str,
+        sha: String,
+        remote: String,
+    },
+    // The revision given with --until or --at is not reachable from the remote branch
+    UntilNotInBranch {
+        rev: String,
+        branch: String,
+        remote: String,
+    },
+    // The tree of a local branch to adopt is not the one of the remote revision
+    AdoptTreeMismatch {
+        branch: String,
+        rev: String,
+        remote: String,
+        diffstat: String,
+    },
+    // The local branch contains commits that were not exported to the remote
+    LocalCommitsNotExported {
+        branch: String,
+        count: usize,
+    },
+    // The remote branch was rewritten, the last synchronized commit is no longer in it
+    UpstreamRewritten {
+        branch: String,
+        remote: String,
+        last_synced: git2::Oid,
+        // synchronized remote commits no longer in the branch, with their summaries
+        vanished: Vec<String>,
+    },
+    // A commit cannot be exported to the remote due to conflicts, in a bare repository
+    ExportConflicts {
+        summary: String,
+    },
+    // I/O error when writing exported patches
+    ExportWrite {
+        path: PathBuf,
+        err: std::io::Error,
+    },
+    // I/O error when writing the mapping of the commits
+    MapWrite {
+        path: PathBuf,
+        err: std::io::Error,
+    },
+    // I/O error when reading the mapping of the commits to import
+    MapRead {
+        path: PathBuf,
+        err: std::io::Error,
+    },
+    // The mapping of the commits to import is invalid
+    InvalidMap {
+        path: PathBuf,
+        reason: String,
+    },
+    // The commit gate command failed, or rejected a commit
+    CommitGateFailed {
+        commit_id: git2::Oid,
+        reason: String,
+    },
+    // The message filter command could not be run, failed, or output invalid UTF-8
+    MessageFilterFailed {
+        summary: String,
+        reason: String,
+    },
+    // A commit could not be signed
+    SigningFailed {
+        key: Option<String>,
+        reason: String,
+    },
+    // A hook command could not be run, or failed
+    HookFailed {
+        hook: &'static str,
+        commit_id: git2::Oid,
+        reason: String,
+    },
+    // The merge tool could not be run on a conflicted file
+    MergetoolFailed {
+        path: String,
+        reason: String,
+    },
+    // Remote commits are missing from the local repository without explanation
+    AuditGaps {
+        count: usize,
+    },
+    // Inconsistencies were found between the local branches, the remote ones and the cache
+    VerifyFailed {
+        count: usize,
+    },
+    // A git bundle cannot be used to fetch the remote commits
+    InvalidBundle {
+        path: String,
+        reason: String,
+    },
+    // A git bundle does not contain a branch to synchronize
+    BundleMissingRef {
+        path: String,
+        refname: String,
+    },
+    // The bundle of the synchronized commits cannot be written
+    BundleWrite {
+        path: String,
+        reason: String,
+    },
+    // The remote could not be fetched, as it cannot be reached for example
+    FetchFailed {
+        remote: String,
+        reason: String,
+    },
+    // The local repository is a shallow clone, missing the history of the remote
+    ShallowRepository {
+        path: String,
+        remote: String,
+    },
+    // The remote is a shallow clone, whose history cannot be fetched
+    ShallowRemote {
+        remote: String,
+        url: String,
+    },
+    // The credentials were refused by a remote, or none could be found
+    AuthFailed {
+        remote: String,
+        reason: String,
+    },
+    // The push remote rejected the update of a branch
+    PushRejected {
+        refname: String,
+        reason: String,
+    },
+    // Some synchronized branches could not be pushed
+    PushFailed {
+        branches: Vec<String>,
+    },
+    // The editor could not be run on the message of a commit, or failed
+    EditorFailed {
+        commit_id: git2::Oid,
+        reason: String,
+    },
+    // An option requiring a terminal is used without one
+    NotInteractive {
+        option: &'static str,
+    },
+    // A confirmation must be asked, but the standard input is not a terminal
+    CannotConfirm {
+        question: String,
+    },
+    // The user did not answer a confirmation
+    ConfirmAborted {
+        question: String,
+    },
+    // Problems were found when checking the configuration
+    CheckFailed {
+        count: usize,
+    },
+    // A dry run found no commits to synchronize on any branch
+    NothingToSync,
+    // --abort or --continue is used while no synchronization is stopped
+    NoSyncInProgress,
+    // --abort finds no branch to synchronize, on which the synchronization could be stopped
+    StoppedBranchUnknown,
+    // A synchronization is stopped on conflicts, a new one cannot be started
+    SyncStopped {
+        commit_id: git2::Oid,
+        refname: String,
+    },
+    // An export is stopped on conflicts, only the export subcommand can resume it
+    ExportStopped {
+        commit_id: git2::Oid,
+        refname: String,
+    },
+    // --continue is used while conflicts remain in the index
+    UnresolvedConflicts {
+        paths: Vec<String>,
+    },
+    // The state of the synchronization stopped on conflicts cannot be read
+    InvalidState {
+        path: PathBuf,
+        reason: String,
+    },
+    // --rollback is used while no synchronization is recorded in the journal
+    NothingToRollback,
+    // A branch cannot be rolled back to its tip before the last synchronization
+    CannotRollback {
+        branch: String,
+        reason: String,
+    },
+    // The journal of the synchronizations cannot be read or written
+    InvalidJournal {
+        path: PathBuf,
+        reason: String,
+    },
+    // Another ripit process holds the lock of the repository
+    Locked {
+        path: PathBuf,
+        pid: u32,
+        since: String,
+    },
+    // The lock of the repository cannot be taken
+    LockFailed {
+        path: PathBuf,
+        err: std::io::Error,
+    },
+    // The file to which the messages are appended cannot be opened
+    LogFileOpen {
+        err: std::io::Error,
+        path: PathBuf,
+    },
+    // The handlers of the signals stopping the watch mode cannot be installed
+    SignalSetup {
+        err: std::io::Error,
     },
     // A synchronization caused conflicts in the index. The user has to solve them
     HasConflicts {
+        branch: String,
+        remote_id: git2::Oid,
         summary: String,
     },
+    // conflicts when copying a commit on a branch that is not checked out
+    ConflictsNotCheckedOut {
+        branch: String,
+        remote_id: git2::Oid,
+        summary: String,
+        paths: Vec<String>,
+    },
+
+    // conflicts when copying a commit in a bare repository
+    ConflictsInBareRepo {
+        branch: String,
+        remote_id: git2::Oid,
+        summary: String,
+        paths: Vec<String>,
+    },
+    // the patch replacing a remote commit does not apply on the local branch
+    ReplacementNotApplied {
+        branch: String,
+        remote_id: git2::Oid,
+        summary: String,
+        patch: String,
+        reason: String,
+        // whether the synchronization stopped in the working directory, for the patch to
+        // be applied by hand
+        checked_out: bool,
+    },
+    // feature needing a working directory, used in a bare repository
+    BareRepository {
+        feature: &'static str,
+    },
 
     // error when opening the config file
     FailedOpenCfg {
@@ -29,13 +348,44 @@ pub enum Error {
         path: String,
         error: serde_yaml::Error,
     },
+    // unknown option in the config file, with the closest known one
+    UnknownCfgKey {
+        path: String,
+        key: String,
+        section: Option<&'static str>,
+        suggestion: Option<&'static str>,
+    },
     // invalid config provided. For the moment, only Regex errors can cause this
     InvalidConfig {
         field: &'static str,
         error: regex::Error,
     },
+    // a required option is missing from the configuration
+    MissingConfig {
+        field: &'static str,
+        // git config key that can be used instead
+        git_key: &'static str,
+    },
+    // invalid value for an option of the configuration
+    InvalidValue {
+        field: &'static str,
+        value: String,
+    },
+    // an environment variable used in the configuration is not set
+    UnsetVariable {
+        field: &'static str,
+        var: String,
+    },
+    // invalid date provided as argument
+    InvalidDate {
+        date: String,
+    },
     // Cannot setup the merge context after conflicts
     CannotSetupMergeCtx,
+    // A branch to synchronize in a temporary worktree is checked out
+    BranchCheckedOut {
+        branch: String,
+    },
     // I/O Error whe opening cache file
     CacheOpenError {
         err: std::io::Error,
@@ -46,6 +396,34 @@ pub enum Error {
         err: std::io::Error,
         filename: PathBuf,
     },
+    // Invalid line in the cache file, which must be repaired
+    CacheCorrupted {
+        desc: String,
+        filename: PathBuf,
+        line: String,
+        line_number: u32,
+    },
+    // The MAC of the ripit tag of a local commit matches no remote commit
+    UnknownMac {
+        local_id: git2::Oid,
+        mac: String,
+    },
+    // The record of a synchronization cannot be read
+    InvalidRunRecord {
+        refname: String,
+        reason: String,
+    },
+    // The tags file of the cache-only provenance is missing, and cannot be rebuilt
+    CacheRequired {
+        path: PathBuf,
+    },
+    // Invalid line in the tags file of the cache-only provenance
+    TagsCorrupted {
+        desc: String,
+        filename: PathBuf,
+        line: String,
+        line_number: u32,
+    },
     // Invalid line in cache file
     CacheInvalidLine {
         desc: String,
@@ -61,10 +439,50 @@ impl From<git2::Error> for Error {
     }
 }
 
+impl Error {
+    /// Tell which operation a generic git error happened in
+    ///
+    /// The other errors already describe what failed, and are kept as is, so that they can
+    /// still be matched.
+    pub fn context(
+        self,
+        operation: Operation,
+        branch: &str,
+        commit: Option<&git2::Commit>,
+    ) -> Self {
+        match self {
+            Error::Git(_) => Error::Failed {
+                operation,
+                branch: branch.to_owned(),
+                commit: commit.map(|ci| (ci.id(), ci.summary().unwrap_or("").to_owned())),
+                source: Box::new(self),
+            },
+            err => err,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Git(e) => write!(f, "{}", e.message()),
+            Error::Failed {
+                operation,
+                branch,
+                commit,
+                ..
+            } => {
+                let (verb, preposition) = operation.describe();
+                write!(f, "failed to {}", verb)?;
+                if let Some((id, summary)) = commit {
+                    write!(f, " {} (\"{}\")", id, summary)?;
+                }
+                if !preposition.is_empty() {
+                    write!(f, " {}", preposition)?;
+                }
+                write!(f, " branch {}", branch)
+            }
+            Error::Cli(e) => write!(f, "{}", e.to_string().trim_end()),
             Error::TagMissing => write!(
                 f,
                 "Cannot find any ripit tag in the local repository.\n\
@@ -74,37 +492,441 @@ impl fmt::Display for Error {
                 f,
                 "The repository contains non committed changes.\nAborted."
             ),
+            Error::UntrackedFilesInTheWay { paths } => write!(
+                f,
+                "The untracked files {} would be overwritten by the synchronized commits.\n\
+                 Move them, or stash them during the run with --autostash-untracked.\n\
+                 Aborted.",
+                paths.join(", ")
+            ),
+            Error::AutostashKept { index, id, reason } => write!(
+                f,
+                "The local changes are kept in stash@{{{}}} ({}), as {}.\n\
+                 Apply them with `git stash pop stash@{{{}}}` once the working directory \
+                 is clean, or drop the stash once the conflicts are solved.",
+                index, id, reason, index
+            ),
             Error::UnknownParent {
                 commit_id,
                 parent_id,
+                branch,
             } => write!(
                 f,
                 "Cannot synchronize commit {}: its parent {} cannot be found in the \
-                 local repository",
-                commit_id, parent_id
+                 local repository.\n\
+                 If this is expected, uprooting can be enabled for branch {} with \
+                 `uproot: true` in its configuration, or with `--uproot`.",
+                commit_id, parent_id, branch
+            ),
+            Error::MissingBranch { branch } => write!(
+                f,
+                "Local branch {} does not exist.\n\
+                 Run with `--create-missing-branch` to recreate it on the last synchronized \
+                 commit, or with `--bootstrap` to bootstrap it.",
+                branch
+            ),
+            Error::CannotRecreateBranch { branch, remote } => write!(
+                f,
+                "Cannot recreate branch {}: no commit from {}/{} was synchronized.",
+                branch, remote, branch
+            ),
+            Error::UnknownUntilRev { rev, remote } => {
+                write!(f, "Cannot find revision {} in the remote {}.", rev, remote)
+            }
+            Error::UnknownOptionSha {
+                option,
+                sha,
+                remote,
+            } => write!(
+                f,
+                "Cannot find commit {} of the {} option in the remote {}.",
+                sha, option, remote
+            ),
+            Error::AmbiguousOptionSha {
+                option,
+                sha,
+                remote,
+            } => write!(
+                f,
+                "Commit id {} of the {} option is ambiguous in the remote {}, use a \
+                 longer abbreviation.",
+                sha, option, remote
+            ),
+            Error::UnknownRemote { remote } => write!(
+                f,
+                "Remote {} does not exist in the repository. Add it with `git remote add`, \
+                 or configure its url in the remote option of the configuration.",
+                remote
+            ),
+            Error::UnknownRemoteBranch { branch, remote } => {
+                write!(f, "Branch {} does not exist in remote {}.", branch, remote)
+            }
+            Error::UntilNotInBranch {
+                rev,
+                branch,
+                remote,
+            } => write!(
+                f,
+                "Revision {} is not reachable from branch {}/{}.",
+                rev, remote, branch
             ),
-            Error::HasConflicts { summary } => write!(
+            Error::AdoptTreeMismatch {
+                branch,
+                rev,
+                remote,
+                diffstat,
+            } => write!(
+                f,
+                "Cannot adopt branch {}: its tree differs from the one of {} in the remote {}.\n{}",
+                branch,
+                rev,
+                remote,
+                diffstat.trim_end()
+            ),
+            Error::LocalCommitsNotExported { branch, count } => write!(
+                f,
+                "Branch {} contains {} commits that do not come from the remote.\n\
+                 Export them with the `export` subcommand, and synchronize once they \
+                 are integrated in the remote.",
+                branch, count
+            ),
+            Error::UpstreamRewritten {
+                branch,
+                remote,
+                last_synced,
+                vanished,
+            } => {
+                write!(
+                    f,
+                    "Branch {}/{} was rewritten: the last synchronized commit {} is no longer \
+                     in its history.",
+                    remote, branch, last_synced
+                )?;
+                if !vanished.is_empty() {
+                    write!(
+                        f,
+                        "\nSynchronized commits no longer in the remote branch:\n  {}",
+                        vanished.join("\n  ")
+                    )?;
+                }
+                write!(
+                    f,
+                    "\nRun the synchronization with --force-resync to synchronize the new \
+                     history, skipping the commits already synchronized."
+                )
+            }
+            Error::ExportConflicts { summary } => write!(
+                f,
+                "Cannot export the following commit due to conflicts with the remote:\n  {}\n\
+                 The conflicts can only be resolved in a repository with a working directory.",
+                summary
+            ),
+            Error::ExportWrite { path, err } => {
+                write!(f, "Cannot write patch {}: {}", path.display(), err)
+            }
+            Error::MapWrite { path, err } => {
+                write!(f, "Cannot write the mapping {}: {}", path.display(), err)
+            }
+            Error::MapRead { path, err } => {
+                write!(f, "Cannot read the mapping {}: {}", path.display(), err)
+            }
+            Error::InvalidMap { path, reason } => write!(
+                f,
+                "The mapping {} cannot be imported: {}",
+                path.display(),
+                reason
+            ),
+            Error::CommitGateFailed { commit_id, reason } => write!(
+                f,
+                "The commit gate aborted the synchronization of commit {}: {}",
+                commit_id, reason
+            ),
+            Error::MessageFilterFailed { summary, reason } => write!(
+                f,
+                "The message filter command failed on message \"{}\": {}",
+                summary, reason
+            ),
+            Error::SigningFailed { key, reason } => write!(
+                f,
+                "Cannot sign the commit with {}: {}",
+                match key {
+                    Some(key) => format!("key {}", key),
+                    None => "the default key".to_owned(),
+                },
+                reason
+            ),
+            Error::HookFailed {
+                hook,
+                commit_id,
+                reason,
+            } => write!(
+                f,
+                "The {} hook failed on commit {}: {}",
+                hook, commit_id, reason
+            ),
+            Error::MergetoolFailed { path, reason } => {
+                write!(f, "Cannot run the merge tool on {}: {}", path, reason)
+            }
+            Error::AuditGaps { count } => write!(
+                f,
+                "{} remote commits are neither synchronized, skipped nor pending: \
+                 they are missing from the local repository.",
+                count
+            ),
+            Error::VerifyFailed { count } => write!(
+                f,
+                "{} inconsistenc{} found between the local and remote branches.",
+                count,
+                if *count > 1 { "ies" } else { "y" }
+            ),
+            Error::InvalidBundle { path, reason } => {
+                write!(f, "Cannot fetch from the bundle {}: {}", path, reason)
+            }
+            Error::BundleMissingRef { path, refname } => write!(
+                f,
+                "The bundle {} does not contain the ref {} to synchronize.",
+                path, refname
+            ),
+            Error::BundleWrite { path, reason } => {
+                write!(f, "Cannot write the bundle {}: {}", path, reason)
+            }
+            Error::FetchFailed { remote, reason } => {
+                write!(f, "Cannot fetch remote {}: {}", remote, reason)
+            }
+            Error::ShallowRepository { path, remote } => write!(
+                f,
+                "The repository {} is a shallow clone, but ripit needs the whole history \
+                 of the remote to synchronize it. Fetch it with \
+                 `git fetch --unshallow {}` first.",
+                path, remote
+            ),
+            Error::ShallowRemote { remote, url } => write!(
+                f,
+                "The remote {} is a shallow clone, whose history cannot be fetched by \
+                 ripit. Fetch its whole history with `git fetch --unshallow` in {} first.",
+                remote, url
+            ),
+            Error::AuthFailed { remote, reason } => write!(
+                f,
+                "Authentication failed on remote {}: {}\n\
+                 Configure the credentials in the auth section of the configuration, or \
+                 fetch the commits yourself and use the -F option.",
+                remote, reason
+            ),
+            Error::PushRejected { refname, reason } => {
+                write!(f, "The update of {} was rejected: {}", refname, reason)
+            }
+            Error::PushFailed { branches } => write!(
+                f,
+                "The following branches were synchronized, but could not be pushed: {}",
+                branches.join(", ")
+            ),
+            Error::EditorFailed { commit_id, reason } => write!(
+                f,
+                "Cannot edit the message of commit {}: {}. The synchronization is aborted.",
+                commit_id, reason
+            ),
+            Error::NotInteractive { option } => {
+                write!(f, "The {} option can only be used in a terminal.", option)
+            }
+            Error::CannotConfirm { question } => write!(
+                f,
+                "Cannot ask \"{}\": the standard input is not a terminal.\n\
+                 Use -y to proceed without confirmation.",
+                question
+            ),
+            Error::ConfirmAborted { question } => {
+                write!(f, "No answer given to \"{}\", aborting.", question)
+            }
+            Error::CheckFailed { count } => write!(
+                f,
+                "{} problem{} found in the configuration.",
+                count,
+                if *count > 1 { "s" } else { "" }
+            ),
+            Error::NothingToSync => {
+                write!(f, "Nothing to synchronize, the branches are up to date.")
+            }
+            Error::NoSyncInProgress => {
+                write!(f, "No synchronization is stopped on conflicts.")
+            }
+            Error::StoppedBranchUnknown => write!(
+                f,
+                "Cannot find the branch whose synchronization stopped: no branch to \
+                 synchronize is configured or matches the branch patterns."
+            ),
+            Error::SyncStopped { commit_id, refname } => write!(
+                f,
+                "The synchronization of {} is stopped on the conflicts of commit {}.\n\
+                 Resolve them and run with `--continue`, or run with `--abort`.",
+                refname, commit_id
+            ),
+            Error::ExportStopped { commit_id, refname } => write!(
+                f,
+                "The export of {} is stopped on the conflicts of commit {}.\n\
+                 Resolve them and run the `export` subcommand with `--continue`, or run \
+                 with `--abort`.",
+                refname, commit_id
+            ),
+            Error::UnresolvedConflicts { paths } => write!(
+                f,
+                "Conflicts remain in the following files:\n  {}\n\
+                 Resolve them, and add them to the index before continuing.",
+                paths.join("\n  ")
+            ),
+            Error::InvalidState { path, reason } => write!(
+                f,
+                "Invalid synchronization state file {}: {}",
+                path.display(),
+                reason
+            ),
+            Error::NothingToRollback => {
+                write!(f, "No synchronization of these branches to roll back.")
+            }
+            Error::CannotRollback { branch, reason } => {
+                write!(f, "Cannot roll back branch {}: {}.", branch, reason)
+            }
+            Error::InvalidJournal { path, reason } => {
+                write!(f, "Invalid journal file {}: {}", path.display(), reason)
+            }
+            Error::Locked { path, pid, since } => write!(
+                f,
+                "Another ripit process (pid {}) is running on this repository since {}.\n\
+                 If it was killed, remove the lock {} with the --break-lock option.",
+                pid,
+                since,
+                path.display()
+            ),
+            Error::LockFailed { path, err } => {
+                write!(f, "Cannot take the lock {}: {}", path.display(), err)
+            }
+            Error::LogFileOpen { err, path } => {
+                write!(f, "Cannot open the log file {}: {}", path.display(), err)
+            }
+            Error::SignalSetup { err } => {
+                write!(f, "Cannot handle the signals stopping the watch: {}", err)
+            }
+            Error::HasConflicts { summary, .. } => write!(
                 f,
                 "Cannot synchronize the following commit due to conflicts:\n  {}\n\
                  Solve the conflicts and commit the resolutions, \
                  then run the synchronization again.",
                 summary
             ),
+            Error::ConflictsNotCheckedOut {
+                branch,
+                summary,
+                paths,
+                ..
+            } => write!(
+                f,
+                "Cannot synchronize the following commit due to conflicts:\n  {}\n\
+                 Conflicting paths:\n  {}\n\
+                 Branch {} is not checked out: check it out and run the synchronization \
+                 again to solve the conflicts.",
+                summary,
+                paths.join("\n  "),
+                branch
+            ),
+            Error::ConflictsInBareRepo { summary, paths, .. } => write!(
+                f,
+                "Cannot synchronize the following commit due to conflicts:\n  {}\n\
+                 Conflicting paths:\n  {}\n\
+                 The repository is bare: run the synchronization again with \
+                 --detached-worktree to solve the conflicts in a temporary worktree.",
+                summary,
+                paths.join("\n  ")
+            ),
+            Error::ReplacementNotApplied {
+                summary,
+                patch,
+                reason,
+                checked_out,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Cannot apply the patch {} replacing the following commit: {}\n  {}\n",
+                    patch, reason, summary
+                )?;
+                if *checked_out {
+                    write!(
+                        f,
+                        "Apply the changes of the patch and commit them, then run the \
+                         synchronization again."
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Run the synchronization again with the branch checked out, or with \
+                         --detached-worktree, to apply the changes by hand."
+                    )
+                }
+            }
+            Error::BareRepository { feature } => write!(
+                f,
+                "{} needs a working directory, which a bare repository does not have: \
+                 use --detached-worktree to run in a temporary worktree.",
+                feature
+            ),
             Error::FailedOpenCfg { path, error } => {
                 write!(f, "Cannot open configuration file {}: {}", path, error)
             }
             Error::FailedParseCfg { path, error } => {
                 write!(f, "Invalid configuration file {}: {}", path, error)
             }
+            Error::UnknownCfgKey {
+                path,
+                key,
+                section,
+                suggestion,
+            } => {
+                write!(
+                    f,
+                    "Invalid configuration file {}: unknown option `{}`",
+                    path, key
+                )?;
+                if let Some(section) = section {
+                    write!(f, " in {}", section)?;
+                }
+                match suggestion {
+                    Some(suggestion) => write!(f, ", did you mean `{}`?", suggestion),
+                    None => write!(f, "."),
+                }
+            }
             Error::InvalidConfig { field, error } => {
                 write!(f, "Invalid {} option: {}", field, error)
             }
+            Error::MissingConfig { field, git_key } => write!(
+                f,
+                "Missing {} option: set it in the configuration file, or with the \
+                 `{}` git config key.",
+                field, git_key
+            ),
+            Error::InvalidValue { field, value } => {
+                write!(f, "Invalid {} option: {}", field, value)
+            }
+            Error::UnsetVariable { field, var } => write!(
+                f,
+                "The environment variable {} used in the {} option is not set.",
+                var, field
+            ),
+            Error::InvalidDate { date } => {
+                write!(f, "Invalid date {}: expected format is YYYY-MM-DD", date)
+            }
             Error::CannotSetupMergeCtx => write!(
                 f,
                 "Cannot setup the environment for the resolution of conflicts.\n\
                  Solve the errors listed above, then abort the current commit \
                  and run the synchronization again."
             ),
+            Error::BranchCheckedOut { branch } => write!(
+                f,
+                "Branch {} is checked out in the working directory, it cannot be \
+                 synchronized in a temporary worktree.\n\
+                 Checkout another branch, or run without `--detached-worktree`.",
+                branch
+            ),
             Error::CacheOpenError { err, filename } => {
                 write!(f, "Cannot open cache file {}: {}", filename.display(), err)
             }
@@ -116,6 +938,56 @@ impl fmt::Display for Error {
                     err
                 )
             }
+            Error::CacheCorrupted {
+                desc,
+                filename,
+                line,
+                line_number,
+            } => write!(
+                f,
+                "The cache file {} is corrupted, line {} \"{}\" is invalid: {}\n\
+                 Rebuild it from the ripit tags of the branches with --rebuild-cache.",
+                filename.display(),
+                line_number,
+                line,
+                desc
+            ),
+            Error::UnknownMac { local_id, mac } => write!(
+                f,
+                "The ripit tag {} of the local commit {} matches no remote commit.\n\
+                 With the hmac provenance, the remote commits are found by computing their \
+                 MACs: check the keys of provenance_key and provenance_previous_key, and that \
+                 the remote commit is still fetched or listed in the cache file.",
+                mac, local_id
+            ),
+            Error::InvalidRunRecord { refname, reason } => write!(
+                f,
+                "The record of the synchronization {} is invalid: {}",
+                refname, reason
+            ),
+            Error::CacheRequired { path } => write!(
+                f,
+                "The synchronized commits are not recorded in {}.\n\
+                 With the cache-only provenance, the commits carry no ripit tags, and this file \
+                 is the only record of the synchronization: --rebuild-cache cannot recreate \
+                 it.\nRestore it from a backup, or bootstrap the branches again.",
+                path.display()
+            ),
+            Error::TagsCorrupted {
+                desc,
+                filename,
+                line,
+                line_number,
+            } => write!(
+                f,
+                "The tags file {} is corrupted, line {} \"{}\" is invalid: {}\n\
+                 With the cache-only provenance, it cannot be rebuilt from the branches: \
+                 repair it, or restore it from a backup.",
+                filename.display(),
+                line_number,
+                line,
+                desc
+            ),
             Error::CacheInvalidLine {
                 desc,
                 filename,
@@ -134,3 +1006,50 @@ impl fmt::Display for Error {
         }
     }
 }
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Git(err) => Some(err),
+            Error::Failed { source, .. } => Some(source.as_ref()),
+            Error::ExportWrite { err, .. }
+            | Error::MapWrite { err, .. }
+            | Error::MapRead { err, .. }
+            | Error::LockFailed { err, .. }
+            | Error::LogFileOpen { err, .. }
+            | Error::SignalSetup { err }
+            | Error::CacheOpenError { err, .. }
+            | Error::CacheReadError { err, .. } => Some(err),
+            Error::FailedOpenCfg { error, .. } => Some(error),
+            Error::FailedParseCfg { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_context() {
+    let id = git2::Oid::from_str("1a2b3c4d5e6f1a2b3c4d5e6f1a2b3c4d5e6f1a2b").unwrap();
+    let err = Error::Failed {
+        operation: Operation::CherryPick,
+        branch: "master".to_owned(),
+        commit: Some((id, "fix foo".to_owned())),
+        source: Box::new(Error::Git(git2::Error::from_str("object not found"))),
+    };
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "failed to cherry-pick {} (\"fix foo\") onto branch master",
+            id
+        )
+    );
+    let source = std::error::Error::source(&err).unwrap();
+    assert_eq!(source.to_string(), "object not found");
+
+    let err = Error::Git(git2::Error::from_str("object not found"));
+    let err = err.context(Operation::Synchronize, "master", None);
+    assert_eq!(err.to_string(), "failed to synchronize branch master");
+    // the other errors are kept as is
+    let err = Error::TagMissing.context(Operation::Synchronize, "master", None);
+    assert!(matches!(err, Error::TagMissing));
+}
@@ -0,0 +1,128 @@
+use crate::app;
+use std::path::Path;
+
+/// Returns whether the trees of the remote commits are filtered before being copied
+pub fn is_filtering(opts: &app::Options) -> bool {
+    !opts.exclude_paths.is_empty() || opts.subtree.is_some() || opts.prefix.is_some()
+}
+
+/// Restrict a tree to the paths copied in the local repository
+///
+/// The paths matching the excluded patterns are removed first. The patterns are git
+/// pathspecs: directories match all the files they contain, and globs are allowed. If a
+/// subtree is extracted, its content then becomes the root of the tree, and a missing
+/// subtree gives an empty tree. Finally, the tree is nested in the prefix directory, if
+/// any.
+pub fn filter_tree<'a>(
+    repo: &'a git2::Repository,
+    tree: &git2::Tree,
+    opts: &app::Options,
+) -> Result<git2::Tree<'a>, git2::Error> {
+    let mut tree = repo.find_tree(tree.id())?;
+
+    // an empty pathspec matches every path
+    if !opts.exclude_paths.is_empty() {
+        let mut index = git2::Index::new()?;
+        index.read_tree(&tree)?;
+        index.remove_all(&opts.exclude_paths, None)?;
+        tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    }
+
+    if let Some(subtree) = &opts.subtree {
+        let subtree_id = match tree.get_path(Path::new(subtree)) {
+            Ok(entry) if entry.kind() == Some(git2::ObjectType::Tree) => entry.id(),
+            _ => repo.treebuilder(None)?.write()?,
+        };
+        tree = repo.find_tree(subtree_id)?;
+    }
+
+    match &opts.prefix {
+        Some(prefix) if !tree.is_empty() => {
+            let mut builder = git2::build::TreeUpdateBuilder::new();
+            builder.upsert(prefix, tree.id(), git2::FileMode::Tree);
+            let empty_tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+            repo.find_tree(builder.create_updated(repo, &empty_tree)?)
+        }
+        _ => Ok(tree),
+    }
+}
+
+/// Replace the content of the prefix directory of a local tree by the one of a filtered tree
+///
+/// The paths outside of the prefix are kept from the local tree. Without prefix, the
+/// filtered tree is returned.
+pub fn graft_tree<'a>(
+    repo: &'a git2::Repository,
+    local_tree: &git2::Tree,
+    filtered_tree: &git2::Tree,
+    opts: &app::Options,
+) -> Result<git2::Tree<'a>, git2::Error> {
+    let prefix = match &opts.prefix {
+        Some(prefix) => prefix,
+        None => return repo.find_tree(filtered_tree.id()),
+    };
+
+    let mut builder = git2::build::TreeUpdateBuilder::new();
+    match filtered_tree.get_path(Path::new(prefix)) {
+        Ok(entry) => builder.upsert(prefix, entry.id(), git2::FileMode::Tree),
+        Err(_) => builder.remove(prefix),
+    };
+    repo.find_tree(builder.create_updated(repo, local_tree)?)
+}
+
+/// Build a copy of a remote commit and of its parents with their filtered trees
+///
+/// Cherry-picking this copy instead of the commit only applies the changes of the
+/// copied paths, which never conflict with the paths missing in the local repository. The
+/// copies are not referenced, and thus garbage collected later on.
+pub fn filter_commit<'a>(
+    repo: &'a git2::Repository,
+    commit: &git2::Commit,
+    opts: &app::Options,
+) -> Result<git2::Commit<'a>, git2::Error> {
+    let mut parents = Vec::new();
+    for parent in commit.parents() {
+        let tree = filter_tree(repo, &parent.tree()?, opts)?;
+        let oid = repo.commit(None, &parent.author(), &parent.committer(), "", &tree, &[])?;
+        parents.push(repo.find_commit(oid)?);
+    }
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+
+    let tree = filter_tree(repo, &commit.tree()?, opts)?;
+    let oid = repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        commit.message().unwrap_or(""),
+        &tree,
+        &parents,
+    )?;
+    repo.find_commit(oid)
+}
+
+/// Returns whether a commit only changes paths that are not copied
+///
+/// Commits that were already empty are not considered emptied. Merges are emptied if
+/// none of their parents bring changes to the copied paths, they then collapse on their
+/// first parent.
+pub fn is_emptied(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+    opts: &app::Options,
+) -> Result<bool, git2::Error> {
+    if commit.parent_count() == 0 {
+        return Ok(false);
+    }
+    let tree = commit.tree()?;
+    if commit.parent_count() == 1 && commit.parent(0)?.tree_id() == tree.id() {
+        return Ok(false);
+    }
+
+    let filtered_id = filter_tree(repo, &tree, opts)?.id();
+    for parent in commit.parents() {
+        if filter_tree(repo, &parent.tree()?, opts)?.id() != filtered_id {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,351 @@
+use crate::app;
+use crate::auth;
+use crate::error::Error;
+use crate::log;
+use crate::state;
+use crate::sync;
+use crate::tag;
+use crate::{error, info, warn};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+
+// reflog message of the references of the exported commits
+const REFLOG_MSG: &str = "ripit: export commits";
+
+/// Reference of the commits exported from a branch
+fn export_refname(branch_name: &str) -> String {
+    format!("refs/ripit/export/{}", branch_name)
+}
+
+/// Make the working directory match HEAD, whatever the prefix
+///
+/// The commits of the remote are checked out to solve the conflicts of an export, and the
+/// local branch once they are solved.
+fn force_checkout_head(repo: &git2::Repository) -> Result<(), git2::Error> {
+    let mut checkout_opts = git2::build::CheckoutBuilder::new();
+    checkout_opts.force();
+    repo.checkout_head(Some(&mut checkout_opts))
+}
+
+/// Build the list of local commits to export
+///
+/// Those are the commits without ripit tags committed on top of the given commit. Merge
+/// commits cannot be exported, and are ignored.
+fn find_commits_to_export<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    head: &git2::Commit,
+    since: &git2::Commit,
+) -> Result<Vec<git2::Commit<'a>>, Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(head.id())?;
+    revwalk.hide(since.id())?;
+
+    let mut commits = vec![];
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+
+        if tags.retrieve_ripit_tag(&commit).is_some() {
+            continue;
+        }
+        if commit.parent_count() > 1 {
+            warn!(
+                "ignoring merge commit {}: it cannot be exported.",
+                commit.id()
+            );
+            continue;
+        }
+        commits.push(commit);
+    }
+
+    Ok(commits)
+}
+
+/// Stop the export on the conflicts of a commit, so that they are solved by the user
+///
+/// The commit is cherry-picked in the working directory, on a detached HEAD, and the
+/// export is saved in the state of the synchronization. `--continue` then commits the
+/// resolution, and `--abort` checks out the branch again.
+fn stop_on_conflicts(
+    repo: &git2::Repository,
+    branch: &app::Branch,
+    commit: &git2::Commit,
+    onto: &git2::Commit,
+    msg: &str,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    if repo.is_bare() {
+        return Err(Error::ExportConflicts {
+            summary: commit.summary().unwrap_or("").to_owned(),
+        });
+    }
+    crate::check_local_diff(repo, opts)?;
+
+    repo.set_head_detached(onto.id())?;
+    force_checkout_head(repo)?;
+    repo.cherrypick(commit, None)?;
+    if let Err(err) = std::fs::write(repo.path().join("MERGE_MSG"), msg) {
+        warn!(
+            "cannot write the message of the export in MERGE_MSG: {}",
+            err
+        );
+    }
+    let state = state::SyncState {
+        remote_id: commit.id(),
+        refname: branch.refname.clone(),
+        uprooted: false,
+        head: Some(onto.id()),
+        message: msg.to_owned(),
+        export: true,
+    };
+    if let Err(err) = state::write(repo, &state) {
+        error!("Cannot save the state of the export: {}", err);
+    }
+    Err(state.stopped())
+}
+
+/// Cherrypick a local commit on top of a remote commit, and add the export tag
+///
+/// On conflicts, the export stops with the commit cherry-picked in the working directory.
+fn export_commit<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    commit: &git2::Commit,
+    onto: &git2::Commit,
+    opts: &app::Options,
+) -> Result<git2::Commit<'a>, Error> {
+    let orig_msg = commit.message().unwrap_or("");
+    let tag = tags.format_export_tag(commit);
+    let msg = if orig_msg.ends_with('\n') {
+        format!("{}\n{}\n", orig_msg, tag)
+    } else {
+        format!("{}\n\n{}\n", orig_msg, tag)
+    };
+
+    let mut index = repo.cherrypick_commit(commit, onto, 0, None)?;
+    if index.has_conflicts() {
+        stop_on_conflicts(repo, branch, commit, onto, &msg, opts)?;
+    }
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+
+    let oid = repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        &msg,
+        &tree,
+        &[onto],
+    )?;
+    Ok(repo.find_commit(oid)?)
+}
+
+/// Write the exported commits as a series of patches in the given directory
+fn write_patches(dir: &Path, commits: &[git2::Commit]) -> Result<(), Error> {
+    if let Err(err) = std::fs::create_dir_all(dir) {
+        return Err(Error::ExportWrite {
+            path: dir.to_path_buf(),
+            err,
+        });
+    }
+
+    for (i, commit) in commits.iter().enumerate() {
+        let mut opts = git2::EmailCreateOptions::new();
+        opts.start_number(i + 1);
+        let email = git2::Email::from_commit(commit, &mut opts)?;
+
+        let summary: String = commit
+            .summary()
+            .unwrap_or("")
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .take(52)
+            .collect();
+        let path = dir.join(format!("{:04}-{}.patch", i + 1, summary));
+
+        let res = std::fs::File::create(&path).and_then(|mut f| f.write_all(email.as_slice()));
+        if let Err(err) = res {
+            return Err(Error::ExportWrite { path, err });
+        }
+        info!("Patch {} written.", path.display());
+    }
+    Ok(())
+}
+
+/// Push the exported commits in the `ripit-export/<branch>` branch of the remote
+///
+/// The credentials are provided by the auth callbacks, as when pushing the branches.
+fn push_export(
+    repo: &git2::Repository,
+    refname: &str,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let mut remote = repo.find_remote(&opts.remote)?;
+    let mut rejection = None;
+    {
+        let mut callbacks = auth::remote_callbacks(opts);
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(reason) = status {
+                rejection = Some((refname.to_owned(), reason.to_owned()));
+            }
+            Ok(())
+        });
+        let mut push_opts = git2::PushOptions::new();
+        push_opts.remote_callbacks(callbacks);
+
+        let refspec = format!("+{}:refs/heads/ripit-export/{}", refname, branch.name);
+        remote
+            .push(&[&refspec], Some(&mut push_opts))
+            .map_err(|err| auth::convert_error(err, &opts.remote))?;
+    }
+    match rejection {
+        Some((refname, reason)) => Err(Error::PushRejected { refname, reason }),
+        None => Ok(()),
+    }
+}
+
+/// Commit the resolution of the conflicts of a stopped export, if any
+///
+/// The copy is committed with the message saved in the state, and the reference of the
+/// exported commits is set on it, before checking out the branch again. Returns the full
+/// ref name of the branch whose export is to be resumed.
+pub fn continue_export(
+    repo: &git2::Repository,
+    opts: &app::Options,
+) -> Result<Option<String>, Error> {
+    let state = match state::read(repo)? {
+        Some(state) if state.export && opts.resume => state,
+        Some(state) => return Err(state.stopped()),
+        None if opts.resume => return Err(Error::NoSyncInProgress),
+        None => return Ok(None),
+    };
+
+    let mut index = repo.index()?;
+    if index.has_conflicts() {
+        return Err(Error::UnresolvedConflicts {
+            paths: sync::conflicting_paths(&index)?,
+        });
+    }
+    let commit = repo.find_commit(state.remote_id)?;
+    let onto = match state.head {
+        Some(id) => repo.find_commit(id)?,
+        None => {
+            return Err(Error::InvalidState {
+                path: state::state_path(repo),
+                reason: "missing head".to_owned(),
+            })
+        }
+    };
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let oid = repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        &state.message,
+        &tree,
+        &[&onto],
+    )?;
+    info!(
+        "Created commit {} with the resolution of the conflicts of {}.",
+        oid,
+        commit.id()
+    );
+
+    let branch_name = match opts.branches.iter().find(|b| b.refname == state.refname) {
+        Some(branch) => branch.name.as_str(),
+        None => state.refname.trim_start_matches("refs/heads/"),
+    };
+    repo.reference(&export_refname(branch_name), oid, true, REFLOG_MSG)?;
+    repo.cleanup_state()?;
+    sync::checkout_ref(repo, &state.refname)?;
+    force_checkout_head(repo)?;
+    state::remove(repo);
+    Ok(Some(state.refname))
+}
+
+/// Export the local commits of a branch to the remote
+///
+/// Commits are cherry-picked on top of the remote commit matching the last synced commit,
+/// and an export tag is added to their messages, so that they can be recognized when they
+/// are synchronized back in the local repository. When resumed after conflicts, the
+/// commits exported before them are kept.
+///
+/// The exported commits are pushed in a `ripit-export/<branch>` branch in the remote, or
+/// written as patches in the given directory.
+pub fn export_branch(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    opts: &app::Options,
+    since: Option<&str>,
+    patches: Option<&Path>,
+    resumed: bool,
+) -> Result<(), Error> {
+    let head = repo.revparse_single(&branch.refname)?.peel_to_commit()?;
+    let (last_synced, _) = sync::find_last_synced(repo, tags, head.id())?;
+    let (remote_id, _) = tags.retrieve_ripit_tag_or_throw(&last_synced)?;
+    let mut onto = repo.find_commit(git2::Oid::from_str(&remote_id)?)?;
+
+    let since = match since {
+        Some(rev) => repo.revparse_single(rev)?.peel_to_commit()?,
+        None => last_synced,
+    };
+    let commits = find_commits_to_export(repo, tags, &head, &since)?;
+    if commits.is_empty() {
+        info!("Nothing to export on branch {}.", log::branch(&branch.name));
+        return Ok(());
+    }
+
+    let refname = export_refname(&branch.name);
+    let mut exported = Vec::new();
+    if resumed {
+        // the commits exported before the conflicts are on top of the remote commit
+        let tip = repo.find_reference(&refname)?.peel_to_commit()?;
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+        revwalk.push(tip.id())?;
+        revwalk.hide(onto.id())?;
+        for oid in revwalk {
+            exported.push(repo.find_commit(oid?)?);
+        }
+        onto = tip;
+    }
+    let done: HashSet<String> = exported
+        .iter()
+        .filter_map(|commit| tags.retrieve_export_tag(commit))
+        .collect();
+
+    for commit in &commits {
+        if done.contains(&commit.id().to_string()) {
+            continue;
+        }
+        info!("Exporting commit {}...", commit.id());
+        let res = export_commit(repo, tags, branch, commit, &onto, opts);
+        if res.is_err() {
+            // the commits exported so far are kept for the resumed export
+            repo.reference(&refname, onto.id(), true, REFLOG_MSG)?;
+        }
+        onto = res?;
+        exported.push(onto.clone());
+    }
+
+    match patches {
+        Some(dir) => write_patches(dir, &exported)?,
+        None => {
+            // push the commits in a new branch of the remote
+            repo.reference(&refname, onto.id(), true, REFLOG_MSG)?;
+            push_export(repo, &refname, branch, opts)?;
+            info!(
+                "{} commits exported in branch ripit-export/{} of remote {}.",
+                exported.len(),
+                branch.name,
+                opts.remote
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use crate::app;
+use crate::error::Error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// exit code of the gate command requesting the commit to be skipped
+const SKIP_EXIT_CODE: i32 = 10;
+
+// name of the rule recorded for commits skipped by the gate
+pub const SKIP_RULE: &str = "commit gate";
+
+/// Decision of the commit gate for a commit
+#[derive(PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Skip,
+}
+
+/// Build the patch of a commit, compared to its first parent
+fn commit_patch(repo: &git2::Repository, commit: &git2::Commit) -> Result<Vec<u8>, Error> {
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+
+    let mut patch = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if let '+' | '-' | ' ' = line.origin() {
+            patch.push(line.origin() as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })?;
+    Ok(patch)
+}
+
+/// Run the commit gate command on a commit to synchronize
+///
+/// The command is run in a shell, with the metadata of the commit in environment
+/// variables, and its patch on stdin. An exit code of 0 allows the synchronization of the
+/// commit, an exit code of 10 skips it, and any other exit code aborts the
+/// synchronization.
+pub fn check_commit(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+    branch: &app::Branch,
+    cmd: &str,
+) -> Result<Decision, Error> {
+    let patch = commit_patch(repo, commit)?;
+    let author = commit.author();
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("RIPIT_COMMIT", commit.id().to_string())
+        .env("RIPIT_BRANCH", &branch.name)
+        .env("RIPIT_AUTHOR_NAME", author.name().unwrap_or(""))
+        .env("RIPIT_AUTHOR_EMAIL", author.email().unwrap_or(""))
+        .env("RIPIT_SUMMARY", commit.summary().unwrap_or(""))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            return Err(Error::CommitGateFailed {
+                commit_id: commit.id(),
+                reason: err.to_string(),
+            })
+        }
+    };
+
+    // Write the patch from another thread, so that the command cannot block on a full
+    // stderr pipe. The command is free not to read its input.
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&patch);
+    });
+    let output = child.wait_with_output();
+    let _ = writer.join();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            return Err(Error::CommitGateFailed {
+                commit_id: commit.id(),
+                reason: err.to_string(),
+            })
+        }
+    };
+
+    match output.status.code() {
+        Some(0) => Ok(Decision::Allow),
+        Some(SKIP_EXIT_CODE) => Ok(Decision::Skip),
+        _ => Err(Error::CommitGateFailed {
+            commit_id: commit.id(),
+            reason: format!(
+                "{}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ),
+        }),
+    }
+}
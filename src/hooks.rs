@@ -0,0 +1,32 @@
+use crate::log;
+use std::process::{Command, Stdio};
+
+/// Run a hook command in the working directory of the repository
+///
+/// The command is run in a shell, with the given environment variables. Its output is
+/// printed with the one of ripit, on stderr if stdout is reserved to a machine-readable
+/// output. Returns the reason of the failure if the command cannot be run, or exits with
+/// a non-zero code.
+pub fn run(repo: &git2::Repository, cmd: &str, env: &[(&str, String)]) -> Result<(), String> {
+    let dir = repo.workdir().unwrap_or_else(|| repo.path());
+    let stdout = if log::is_stdout_reserved() {
+        Stdio::from(std::io::stderr())
+    } else {
+        Stdio::inherit()
+    };
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .current_dir(dir)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdout(stdout)
+        .status()
+        .map_err(|err| err.to_string())?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(status.to_string())
+    }
+}
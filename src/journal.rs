@@ -0,0 +1,195 @@
+use crate::app;
+use crate::commits_map;
+use crate::error::Error;
+use crate::info;
+use crate::log;
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const REFLOG_MSG: &str = "ripit: roll back the last synchronization";
+
+/// Changes made on a branch by a synchronization
+#[derive(Serialize, Deserialize)]
+pub struct BranchEntry {
+    pub branch: String,
+    pub refname: String,
+    // local tips before and after the synchronization
+    pub before: String,
+    pub after: String,
+    // lines added in the cache file
+    pub cache_entries: Vec<String>,
+}
+
+/// Entry of the journal, recording the changes made by a synchronization
+///
+/// The journal lists the entries one per line, in JSON, the last one being the most
+/// recent synchronization.
+#[derive(Serialize, Deserialize)]
+pub struct RunEntry {
+    // seconds since the epoch
+    pub date: u64,
+    pub branches: Vec<BranchEntry>,
+}
+
+impl BranchEntry {
+    pub fn new(
+        branch: &app::Branch,
+        before: git2::Oid,
+        after: git2::Oid,
+        cache_entries: &[String],
+    ) -> Self {
+        Self {
+            branch: branch.name.clone(),
+            refname: branch.refname.clone(),
+            before: before.to_string(),
+            after: after.to_string(),
+            cache_entries: cache_entries.to_vec(),
+        }
+    }
+}
+
+impl RunEntry {
+    pub fn new(branches: Vec<BranchEntry>) -> Self {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self { date, branches }
+    }
+}
+
+/// Path to the journal, in the git directory shared by every worktree
+pub fn journal_path(repo: &git2::Repository) -> PathBuf {
+    util::common_dir(repo).join("ripit").join("journal")
+}
+
+/// Append the changes made by a synchronization to the journal
+pub fn record(repo: &git2::Repository, entry: &RunEntry) -> Result<(), Error> {
+    let path = journal_path(repo);
+    let line = serde_json::to_string(entry).unwrap();
+
+    let mut opts = std::fs::OpenOptions::new();
+    opts.create(true).append(true);
+    std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| opts.open(&path))
+        .and_then(|mut f| writeln!(f, "{}", line))
+        .map_err(|err| Error::InvalidJournal {
+            path: path.clone(),
+            reason: err.to_string(),
+        })
+}
+
+fn read(repo: &git2::Repository) -> Result<Vec<RunEntry>, Error> {
+    let path = journal_path(repo);
+    let invalid_journal = |reason: String| Error::InvalidJournal {
+        path: path.clone(),
+        reason,
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(invalid_journal(err.to_string())),
+    };
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|err| invalid_journal(format!("line {}: {}", i + 1, err)))
+        })
+        .collect()
+}
+
+fn write(repo: &git2::Repository, entries: &[RunEntry]) -> Result<(), Error> {
+    let path = journal_path(repo);
+    let content: String = entries
+        .iter()
+        .map(|entry| format!("{}\n", serde_json::to_string(entry).unwrap()))
+        .collect();
+    commits_map::write_atomically(&path, &content).map_err(|err| Error::InvalidJournal {
+        path: path.clone(),
+        reason: err.to_string(),
+    })
+}
+
+fn parse_oid(repo: &git2::Repository, id: &str) -> Result<git2::Oid, Error> {
+    git2::Oid::from_str(id).map_err(|_| Error::InvalidJournal {
+        path: journal_path(repo),
+        reason: format!("invalid commit id \"{}\"", id),
+    })
+}
+
+/// Undo the last synchronization of the branches of the options
+///
+/// The branches are set back on their tips before the synchronization, and the entries it
+/// added to the cache are removed. Nothing is done if a branch was modified since then.
+/// The other branches of the synchronization can still be rolled back later on.
+pub fn rollback(repo: &git2::Repository, opts: &app::Options) -> Result<(), Error> {
+    let mut entries = read(repo)?;
+    let last = entries.last_mut().ok_or(Error::NothingToRollback)?;
+    let (rolled_back, kept): (Vec<BranchEntry>, Vec<BranchEntry>) = last
+        .branches
+        .drain(..)
+        .partition(|entry| opts.branches.iter().any(|b| b.name == entry.branch));
+    if rolled_back.is_empty() {
+        return Err(Error::NothingToRollback);
+    }
+    // the entry is dropped once all its branches are rolled back
+    let emptied = kept.is_empty();
+    last.branches = kept;
+
+    // every branch is checked before modifying anything
+    let mut tips = Vec::new();
+    for entry in &rolled_back {
+        let after = parse_oid(repo, &entry.after)?;
+        let current = repo.refname_to_id(&entry.refname)?;
+        if current != after {
+            let reason = if repo.graph_descendant_of(current, after)? {
+                format!(
+                    "commits were made on top of {} since its synchronization",
+                    after
+                )
+            } else {
+                format!("it was moved from {} since its synchronization", after)
+            };
+            return Err(Error::CannotRollback {
+                branch: entry.branch.clone(),
+                reason,
+            });
+        }
+        tips.push(parse_oid(repo, &entry.before)?);
+    }
+
+    let head = repo.head().ok();
+    let head_name = head.as_ref().and_then(|head| head.name());
+    for (entry, before) in rolled_back.iter().zip(tips) {
+        repo.reference(&entry.refname, before, true, REFLOG_MSG)?;
+        info!(
+            "Rolled back branch {} from {} to {}.",
+            log::branch(&entry.branch),
+            entry.after,
+            before
+        );
+        if Some(entry.refname.as_str()) == head_name && !repo.is_bare() {
+            let mut checkout_opts = git2::build::CheckoutBuilder::new();
+            repo.checkout_head(Some(checkout_opts.force()))?;
+        }
+    }
+
+    let cache_entries: Vec<String> = rolled_back
+        .into_iter()
+        .flat_map(|entry| entry.cache_entries)
+        .collect();
+    let nb_removed = commits_map::remove_cache_entries(repo, &cache_entries)?;
+    if nb_removed > 0 {
+        info!("Removed {} commits from the cache.", nb_removed);
+    }
+
+    if emptied {
+        entries.pop();
+    }
+    write(repo, &entries)
+}
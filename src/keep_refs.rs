@@ -0,0 +1,124 @@
+use crate::error::Error;
+use crate::info;
+use crate::lock;
+use crate::state;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// namespace of the references protecting the commits created by ripit
+const KEEP_REFS_PREFIX: &str = "refs/ripit/keep/";
+
+// number of references created by the run, shared by the branches synchronized in
+// parallel so that their references do not collide
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// held when updating the references, which are listed and deleted by every branch
+static REFS_LOCK: Mutex<()> = Mutex::new(());
+
+/// References protecting the commits created during a synchronization
+///
+/// Until the branch is set on them, commits created on a detached HEAD are only referenced
+/// by the cache file, and could be pruned by a `git gc`. A reference is thus created for
+/// each of them, under `refs/ripit/keep/<run-id>/<n>`.
+pub struct KeepRefs {
+    run_id: String,
+}
+
+impl KeepRefs {
+    pub fn new() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            run_id: format!("{}-{}", now, std::process::id()),
+        }
+    }
+
+    /// Protect a commit not referenced by the branch
+    pub fn protect(&mut self, repo: &git2::Repository, commit_id: git2::Oid) -> Result<(), Error> {
+        let count = COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        let refname = format!("{}{}/{}", KEEP_REFS_PREFIX, self.run_id, count);
+        let _lock = REFS_LOCK.lock().unwrap();
+        repo.reference(&refname, commit_id, true, "ripit: protect created commit")?;
+        Ok(())
+    }
+}
+
+/// Delete every reference protecting a commit, when the commits are abandoned
+pub fn release_all(repo: &git2::Repository) -> Result<(), Error> {
+    let _lock = REFS_LOCK.lock().unwrap();
+    for reference in repo.references_glob(&format!("{}*", KEEP_REFS_PREFIX))? {
+        reference?.delete()?;
+    }
+    Ok(())
+}
+
+/// Delete the references protecting commits now referenced by the branch
+///
+/// References left by previous runs, for example after conflicts, are deleted as well.
+pub fn release(repo: &git2::Repository, branch_id: git2::Oid) -> Result<(), Error> {
+    let _lock = REFS_LOCK.lock().unwrap();
+    let mut protected: HashMap<git2::Oid, Vec<git2::Reference>> = HashMap::new();
+    for reference in repo.references_glob(&format!("{}*", KEEP_REFS_PREFIX))? {
+        let reference = reference?;
+        if let Some(id) = reference.target() {
+            protected.entry(id).or_default().push(reference);
+        }
+    }
+
+    // a single walk finds the protected commits reachable from the branch, checking them
+    // one by one walks the history once per created commit
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(branch_id)?;
+    for oid in revwalk {
+        if protected.is_empty() {
+            break;
+        }
+        for mut reference in protected.remove(&oid?).unwrap_or_default() {
+            reference.delete()?;
+        }
+    }
+    Ok(())
+}
+
+/// Delete the references left by the runs that were interrupted
+///
+/// A run killed before its branches are set leaves its references behind, and they would
+/// keep its commits alive forever. They are stale once the run is no longer in progress:
+/// its process is not running, and no synchronization is stopped on conflicts.
+pub fn prune_stale(repo: &git2::Repository) -> Result<(), Error> {
+    if state::state_path(repo).exists() {
+        return Ok(());
+    }
+    let _lock = REFS_LOCK.lock().unwrap();
+    let mut stale_runs = HashSet::new();
+    for reference in repo.references_glob(&format!("{}*", KEEP_REFS_PREFIX))? {
+        let mut reference = reference?;
+        let run_id = match reference
+            .name()
+            .and_then(|name| name.strip_prefix(KEEP_REFS_PREFIX))
+            .and_then(|name| name.split_once('/'))
+        {
+            Some((run_id, _)) => run_id.to_owned(),
+            None => continue,
+        };
+        // the run id ends with the pid of its process
+        let pid = run_id
+            .rsplit_once('-')
+            .and_then(|(_, pid)| pid.parse().ok());
+        if !pid.is_some_and(lock::is_running) {
+            reference.delete()?;
+            stale_runs.insert(run_id);
+        }
+    }
+    if !stale_runs.is_empty() {
+        info!(
+            "Removed the references of {} interrupted synchronizations.",
+            stale_runs.len()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,679 @@
+//! Copy commits between git repositories
+//!
+//! This crate exposes the synchronization done by the `ripit` executable. The options are
+//! built with [`Options::new`], or parsed from the command line and the configuration file
+//! with [`app::parse_args`], and the branches are then bootstrapped with [`bootstrap`], or
+//! synchronized with [`sync`].
+//!
+//! Progress is printed as on the command line, and the result of the run is returned as a
+//! [`RunReport`]. Confirmations are not asked, unless `yes` is unset in the options, in
+//! which case the `confirm` callback of the options is called.
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let dir = tempfile::tempdir()?;
+//! let sig = git2::Signature::now("John Doe", "john@doe.com")?;
+//! let commit = |repo: &git2::Repository, msg: &str| -> Result<git2::Oid, git2::Error> {
+//!     let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+//!     let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+//!     let parents: Vec<&git2::Commit> = parent.iter().collect();
+//!     repo.commit(Some("HEAD"), &sig, &sig, msg, &tree, &parents)
+//! };
+//!
+//! // commits of the private repository are copied in the public one
+//! let private = git2::Repository::init(dir.path().join("private"))?;
+//! commit(&private, "Initial commit")?;
+//! let public = git2::Repository::init(dir.path().join("public"))?;
+//! let url = dir.path().join("private");
+//! public.remote("private", url.to_str().unwrap())?;
+//! public.config()?.set_str("user.name", "Publisher")?;
+//! public.config()?.set_str("user.email", "publisher@doe.com")?;
+//!
+//! let opts = ripit::Options::new(
+//!     dir.path().join("public").to_str().unwrap(),
+//!     "private",
+//!     &["master"],
+//! )?;
+//! ripit::bootstrap(&opts)?;
+//!
+//! let copied = commit(&private, "Publish this commit")?;
+//! let report = ripit::sync(&opts)?;
+//! assert_eq!(report.branches[0].commits.len(), 1);
+//! assert_eq!(report.branches[0].commits[0].remote_oid, copied);
+//! # Ok(())
+//! # }
+//! ```
+
+mod abort;
+pub mod app;
+mod audit;
+mod auth;
+mod authors;
+mod autoresolve;
+mod autosquash;
+mod autostash;
+mod blame;
+mod bundle;
+mod check;
+mod commits_map;
+mod editor;
+pub mod error;
+mod exclude;
+mod export;
+mod gate;
+mod hooks;
+mod journal;
+mod keep_refs;
+mod lock;
+pub mod log;
+mod mac;
+mod maintenance;
+mod mapping;
+mod merge_drivers;
+mod mergetool;
+mod msg_filter;
+mod notes;
+mod notify;
+mod parallel;
+mod push;
+mod remote_tags;
+pub mod report;
+mod rerere;
+mod runs;
+mod sign;
+mod split;
+mod state;
+mod stats;
+mod sync;
+mod tag;
+mod util;
+mod verify;
+mod watch;
+mod worktree;
+
+pub use app::{Branch, Options};
+pub use check::check_config;
+pub use error::Error;
+pub use notify::notify;
+pub use report::RunReport;
+
+/// Check that the local repo does not contain any staged or unstaged changes
+///
+/// This basically checks that "git diff HEAD" does not return any deltas. A bare
+/// repository has no changes, but cannot run the pre_commit hook.
+fn check_local_diff(repo: &git2::Repository, opts: &Options) -> Result<(), Error> {
+    if repo.is_bare() {
+        return match opts.hooks.pre_commit {
+            Some(_) => Err(Error::BareRepository {
+                feature: "The pre_commit hook",
+            }),
+            None => Ok(()),
+        };
+    }
+
+    let head = match repo.head() {
+        Ok(tgt) => match tgt.target() {
+            Some(oid) => Some(repo.find_commit(oid)?),
+            None => None,
+        },
+        Err(_) => None,
+    };
+
+    // with a prefix, only the paths synchronized are checked
+    let mut diff_opts = git2::DiffOptions::new();
+    if let Some(prefix) = &opts.prefix {
+        diff_opts.pathspec(prefix);
+    }
+    let diff = match head {
+        Some(ci) => repo.diff_tree_to_workdir_with_index(Some(&ci.tree()?), Some(&mut diff_opts)),
+        None => repo.diff_tree_to_workdir_with_index(None, Some(&mut diff_opts)),
+    }?;
+
+    if diff.deltas().count() > 0 {
+        Err(Error::HasLocalChanges)
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that no untracked file would be overwritten by the checkout of the branches
+///
+/// The tip of the remote branch of the checked out branch is compared to the untracked
+/// files, which are not stashed unless `autostash_untracked` is set.
+fn check_untracked_files(repo: &git2::Repository, opts: &Options) -> Result<(), Error> {
+    if repo.is_bare() || opts.autostash_untracked {
+        return Ok(());
+    }
+    let head = repo.find_reference("HEAD")?;
+    let branch = match opts
+        .branches
+        .iter()
+        .find(|branch| head.symbolic_target() == Some(&branch.refname))
+    {
+        Some(branch) => branch,
+        None => return Ok(()),
+    };
+    let tip = match repo.find_reference(&branch.tracking_refname) {
+        Ok(reference) => reference.peel_to_commit()?,
+        Err(_) => return Ok(()),
+    };
+
+    let mut status_opts = git2::StatusOptions::new();
+    status_opts
+        .include_untracked(true)
+        .recurse_untracked_dirs(true);
+    if let Some(prefix) = &opts.prefix {
+        status_opts.pathspec(prefix);
+    }
+    let tree = exclude::filter_tree(repo, &tip.tree()?, opts)?;
+    let paths: Vec<String> = repo
+        .statuses(Some(&mut status_opts))?
+        .iter()
+        .filter(|entry| entry.status().is_wt_new())
+        .filter_map(|entry| entry.path().map(str::to_owned))
+        .filter(|path| tree.get_path(std::path::Path::new(path)).is_ok())
+        .collect();
+    if paths.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::UntrackedFilesInTheWay { paths })
+    }
+}
+
+/// Fetch the last commits of the remote, or of the source bundle
+fn fetch(repo: &git2::Repository, tags: &tag::Tags, opts: &Options) -> Result<(), Error> {
+    match &opts.source_bundle {
+        // fetch last commits from the bundle, as if fetched from the remote
+        Some(path) => bundle::fetch_from_bundle(repo, opts, path)?,
+        // fetch last commits in remote
+        None => sync::update_remote(repo, opts)?,
+    }
+    // the MACs of the fetched commits are computed as well
+    tags.macs().index(repo, &opts.remote)
+}
+
+/// Synchronize or bootstrap the branches in a repository, and report what was done
+///
+/// The remote is fetched first if `fetch` is set. With `autostash`, the local changes are
+/// stashed during the run.
+fn run(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &Options,
+    bootstrap: bool,
+    fetch: bool,
+) -> Result<RunReport, Error> {
+    let mut skipped_id = None;
+    if opts.resume {
+        sync::commit_resolved_conflicts(repo, tags, opts)?;
+    } else if opts.skip {
+        skipped_id = Some(sync::reset_stopped_commit(repo)?);
+    } else {
+        sync::check_no_stopped_sync(repo, tags)?;
+    }
+    if !bootstrap {
+        check_tags_file(repo, tags, opts)?;
+    }
+    sync::check_not_shallow(repo, opts)?;
+    // the local changes are applied again once the run is done
+    let stash = if opts.autostash {
+        autostash::save(repo, opts)?
+    } else {
+        None
+    };
+    let res = run_clean(repo, tags, opts, bootstrap, fetch, skipped_id);
+    if let Some(id) = stash {
+        // the error of the run prevails
+        let restored = autostash::restore(repo, id);
+        let report = res?;
+        restored?;
+        return Ok(report);
+    }
+    res
+}
+
+/// Synchronize or bootstrap the branches in a repository without local changes
+fn run_clean(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &Options,
+    bootstrap: bool,
+    fetch: bool,
+    skipped_id: Option<git2::Oid>,
+) -> Result<RunReport, Error> {
+    check_local_diff(repo, opts)?;
+
+    let tips_before: Vec<report::Tips> = opts
+        .branches
+        .iter()
+        .map(|branch| report::Tips::read(repo, branch))
+        .collect();
+    let mut run_report = RunReport::default();
+    if fetch {
+        self::fetch(repo, tags, opts)?;
+    }
+    if !opts.dry_run {
+        check_untracked_files(repo, opts)?;
+    }
+    sync::check_option_shas(repo, opts)?;
+
+    let mut commits_map = if opts.dry_run {
+        commits_map::CommitsMap::new_read_only(repo, tags)?
+    } else {
+        commits_map::CommitsMap::new(repo, tags)?
+    };
+    if let Some(id) = skipped_id {
+        // the commit is skipped as any other skipped commit when resuming the synchronization
+        commits_map.record_skip(id, sync::CONFLICTS_SKIP_RULE);
+    }
+
+    if bootstrap {
+        for branch in &opts.branches {
+            match &opts.adopt {
+                // the local branch already has the state of this remote revision
+                Some(rev) => sync::adopt_branch(repo, tags, branch, rev, &mut commits_map, opts)?,
+                // bootstrap the branch in the local repo with the state of the
+                // branch in the remote repo
+                None => {
+                    sync::bootstrap_branch_with_remote(repo, tags, branch, &mut commits_map, opts)?
+                }
+            }
+        }
+        for (branch, before) in opts.branches.iter().zip(&tips_before) {
+            // the commits of the kept history are copied as in a synchronization
+            let mut processed = Vec::new();
+            if opts.keep_history.is_some() {
+                sync::sync_branch_with_remote(
+                    repo,
+                    tags,
+                    branch,
+                    &mut commits_map,
+                    &mut processed,
+                    opts,
+                )?;
+            }
+            run_report.add_branch(repo, branch, before, &processed, &commits_map);
+        }
+    } else {
+        let mut has_synced = false;
+        let mut nb_copied = 0;
+
+        // fill the map from every existing branch first, so that the missing ones can be
+        // recreated from the synchronized commits of the other branches
+        for branch in &opts.branches {
+            if repo.find_reference(&branch.refname).is_ok() {
+                commits_map.fill_from_branch(repo, &branch.refname)?;
+            }
+        }
+        for branch in &opts.branches {
+            sync::ensure_branch_exists(repo, branch, &commits_map, opts)?;
+            commits_map.fill_from_branch(repo, &branch.refname)?;
+        }
+        let old_heads = opts
+            .branches
+            .iter()
+            .map(|branch| repo.refname_to_id(&branch.refname))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // the questions and the editor need the branches to be synchronized one at a time
+        let mut results = if opts.jobs > 1 && opts.yes && opts.edit_messages.is_none() {
+            parallel::sync_branches(repo, tags, &mut commits_map, opts)?
+        } else {
+            Vec::new()
+        };
+        let mut failure = None;
+
+        let mut journal_branches = Vec::new();
+        for (i, ((branch, before), old_head)) in opts
+            .branches
+            .iter()
+            .zip(&tips_before)
+            .zip(&old_heads)
+            .enumerate()
+        {
+            // sync local branch with remote by cherry-picking missing commits
+            let mut processed = Vec::new();
+            let nb_entries = commits_map.added_entries().len();
+            let (synced, cache_entries) = match results.get_mut(i).map(Option::take) {
+                // synchronized in parallel: a failure does not prevent handling the others
+                Some(Some(Ok(sync))) => {
+                    processed = sync.processed;
+                    (sync.nb_copied, sync.cache_entries)
+                }
+                Some(Some(Err(err))) => {
+                    failure.get_or_insert(err);
+                    continue;
+                }
+                Some(None) => continue,
+                // the next branches are not synchronized after a failure, but the journal
+                // must still record the branches already synchronized
+                None => match sync::sync_branch_with_remote(
+                    repo,
+                    tags,
+                    branch,
+                    &mut commits_map,
+                    &mut processed,
+                    opts,
+                ) {
+                    Ok(synced) => (synced, commits_map.added_entries()[nb_entries..].to_vec()),
+                    Err(err) => {
+                        failure.get_or_insert(err);
+                        break;
+                    }
+                },
+            };
+            if let Some(n) = synced {
+                has_synced = true;
+                nb_copied += n;
+            }
+            let new_head = repo.refname_to_id(&branch.refname)?;
+            if new_head != *old_head || !cache_entries.is_empty() {
+                journal_branches.push(journal::BranchEntry::new(
+                    branch,
+                    *old_head,
+                    new_head,
+                    &cache_entries,
+                ));
+            }
+            run_report.add_branch(repo, branch, before, &processed, &commits_map);
+            if opts.sync_tags && !opts.dry_run {
+                if let Err(err) = remote_tags::sync_tags(repo, tags, branch, &commits_map, opts) {
+                    failure.get_or_insert(err);
+                    break;
+                }
+            }
+            // a failed push does not prevent synchronizing and pushing the other branches
+            if !opts.dry_run {
+                if let Err(err) = push::push_branch(repo, branch, opts) {
+                    error!("Cannot push branch {}: {}", log::branch(&branch.name), err);
+                    run_report.push_failures.push(branch.name.clone());
+                }
+            }
+        }
+        if opts.dry_run {
+            return match failure {
+                Some(err) => Err(err),
+                None if has_synced => Ok(run_report),
+                None => Err(Error::NothingToSync),
+            };
+        }
+        // the journal allows rolling back the synchronization
+        if !journal_branches.is_empty() {
+            let entry = journal::RunEntry::new(journal_branches);
+            if let Err(err) = journal::record(repo, &entry) {
+                warn!("{}", err);
+            }
+        }
+        if let Some(err) = failure {
+            return Err(err);
+        }
+        if let Err(err) = keep_refs::prune_stale(repo) {
+            warn!(
+                "cannot remove the references of interrupted synchronizations: {}",
+                err
+            );
+        }
+        if opts.record_runs {
+            if let Err(err) = runs::record(repo, &run_report, opts) {
+                warn!("cannot record the synchronization: {}", err);
+            }
+        }
+        maintenance::after_sync(repo, opts, nb_copied);
+        if let Some(path) = &opts.emit_bundle {
+            bundle::emit_bundle(repo, opts, path, &old_heads)?;
+        }
+        if let Some(cmd) = &opts.hooks.post_run {
+            let names: Vec<&str> = opts.branches.iter().map(|b| b.name.as_str()).collect();
+            let env = [
+                ("RIPIT_BRANCHES", names.join(" ")),
+                ("RIPIT_NB_COPIED", nb_copied.to_string()),
+            ];
+            if let Err(reason) = hooks::run(repo, cmd, &env) {
+                warn!("the post_run hook failed: {}", reason);
+            }
+        }
+        if !opts.fetch && !has_synced {
+            warn!(
+                "No commits to synchronize found. Have you fetched \
+                 the latest commits from the private repository with \
+                 `git fetch {}`?",
+                opts.remote
+            );
+        }
+    }
+    Ok(run_report)
+}
+
+/// Add the branches of the remote matching the branch patterns to the branches to synchronize
+///
+/// The branches of the remote are listed, or taken from its remote-tracking branches when
+/// it is not fetched. The branches matching `exclude_branches` are left out, as well as the
+/// ones already having a local branch when bootstrapping.
+pub fn expand_branches(opts: &mut Options) -> Result<(), Error> {
+    if opts.branch_patterns.is_empty() {
+        return Ok(());
+    }
+    let repo = git2::Repository::open(&opts.repo)?;
+    let mut names = if opts.fetch && opts.source_bundle.is_none() {
+        sync::list_remote_branches(&repo, opts)?
+    } else {
+        sync::list_tracking_branches(&repo, opts)?
+    };
+    names.sort();
+
+    let mut expanded: Vec<Branch> = Vec::new();
+    for pattern in &opts.branch_patterns {
+        let mut matched = Vec::new();
+        for name in names.iter().filter(|name| pattern.matches(name)) {
+            if opts
+                .exclude_branches
+                .iter()
+                .any(|p| util::glob_match(p, name))
+                || opts
+                    .branches
+                    .iter()
+                    .chain(&expanded)
+                    .any(|b| &b.name == name)
+            {
+                continue;
+            }
+            let branch = pattern.branch(name, &opts.remote);
+            if opts.bootstrap && repo.find_reference(&branch.refname).is_ok() {
+                debug!("Branch {} is already bootstrapped.", log::branch(name));
+                continue;
+            }
+            matched.push(log::branch(name).to_string());
+            expanded.push(branch);
+        }
+        if matched.is_empty() {
+            info!(
+                "No new branch of {} matches {}.",
+                opts.remote, pattern.pattern
+            );
+        } else {
+            info!(
+                "Branches matching {}: {}.",
+                pattern.pattern,
+                matched.join(", ")
+            );
+        }
+    }
+    opts.branches.extend(expanded);
+    Ok(())
+}
+
+/// Make sure the synchronized commits are recorded with the cache-only provenance
+///
+/// Without the tags file, nothing tells which remote commits the local ones were copied
+/// from, unless they were synchronized with another provenance and carry their tags.
+fn check_tags_file(repo: &git2::Repository, tags: &tag::Tags, opts: &Options) -> Result<(), Error> {
+    if opts.provenance != app::Provenance::CacheOnly || notes::tags_path(repo).exists() {
+        return Ok(());
+    }
+    for branch in &opts.branches {
+        if let Ok(reference) = repo.find_reference(&branch.refname) {
+            sync::find_last_synced(repo, tags, reference.peel_to_commit()?.id())?;
+        }
+    }
+    Ok(())
+}
+
+/// Open the repository of the options, with the context reading its ripit tags
+fn open_repo(opts: &Options) -> Result<(git2::Repository, tag::Tags), Error> {
+    let repo = git2::Repository::open(&opts.repo)?;
+    let notes = notes::Notes::load(&repo, opts.provenance)?;
+    let macs = mac::Macs::new(&opts.provenance_keys);
+    macs.index(&repo, &opts.remote)?;
+    let tags = tag::Tags::new(&opts.tag_prefix, notes, macs);
+    Ok((repo, tags))
+}
+
+/// Open the repository of the options, and lock it for the duration of the run
+///
+/// The run happens in a temporary worktree if asked for, which is kept if the run stops on
+/// conflicts.
+fn run_locked<F, T>(opts: &Options, run: F) -> Result<T, Error>
+where
+    F: FnOnce(&git2::Repository, &tag::Tags) -> Result<T, Error>,
+{
+    if opts.json {
+        // the caller prints the summary of the run on stdout
+        log::reserve_stdout();
+    }
+    let (repo, tags) = open_repo(opts)?;
+
+    // released when returning
+    let _lock = lock::Lock::acquire(&repo, opts.break_lock)?;
+
+    if opts.detached_worktree {
+        // synchronize in a temporary worktree, leaving the working directory untouched
+        let (mut worktree, wt_repo) = worktree::TmpWorktree::setup(&repo, opts)?;
+        let res = run(&wt_repo, &tags);
+        if let Err(
+            Error::HasConflicts { .. }
+            | Error::ReplacementNotApplied {
+                checked_out: true, ..
+            },
+        ) = res
+        {
+            worktree.keep();
+        }
+        res
+    } else {
+        run(&repo, &tags)
+    }
+}
+
+/// Synchronize the branches of the options with the remote
+///
+/// The synchronization is resumed or skips the conflicting commit if `resume` or `skip` is
+/// set. Failing to push a branch does not fail the synchronization: the branch is listed
+/// in the push failures of the report.
+pub fn sync(opts: &Options) -> Result<RunReport, Error> {
+    run_locked(opts, |repo, tags| run(repo, tags, opts, false, opts.fetch))
+}
+
+/// Bootstrap the branches of the options in the local repository
+///
+/// A commit containing the state of each remote branch is created on the local branch,
+/// from which later synchronizations can start. If `adopt` is set, the local branches
+/// must already have the state of this remote revision, and an empty commit referencing it
+/// is created instead.
+pub fn bootstrap(opts: &Options) -> Result<RunReport, Error> {
+    run_locked(opts, |repo, tags| run(repo, tags, opts, true, opts.fetch))
+}
+
+/// Abort a synchronization stopped on conflicts, restoring the branch and the cache
+pub fn abort(opts: &Options) -> Result<(), Error> {
+    run_locked(opts, |repo, _| abort::abort(repo, opts))
+}
+
+/// Synchronize the branches of the options every `interval` seconds
+///
+/// The loop goes on after the failed synchronizations, and stops on SIGINT or SIGTERM.
+pub fn watch(opts: &Options, interval: u64) -> Result<(), Error> {
+    watch::watch(opts, interval)
+}
+
+/// Undo the last synchronization of the branches of the options
+///
+/// The branches are set back on their tips before the synchronization, as recorded in the
+/// journal, and the commits it copied are removed from the cache.
+pub fn rollback(opts: &Options) -> Result<(), Error> {
+    run_locked(opts, |repo, tags| {
+        sync::check_no_stopped_sync(repo, tags)?;
+        check_local_diff(repo, opts)?;
+        journal::rollback(repo, opts)
+    })
+}
+
+/// Verify the consistency between the local branches, the remote branches and the cache
+///
+/// Every inconsistency found is printed, and the verification fails if there is any.
+pub fn verify(opts: &Options) -> Result<(), Error> {
+    let (repo, tags) = open_repo(opts)?;
+    verify::verify(&repo, &tags, opts)
+}
+
+/// Rebuild the cache file from the ripit tags of the branches
+///
+/// Returns the number of entries of the new cache file.
+pub fn rebuild_cache(opts: &Options) -> Result<usize, Error> {
+    let (repo, tags) = open_repo(opts)?;
+    let _lock = lock::Lock::acquire(&repo, opts.break_lock)?;
+    check_tags_file(&repo, &tags, opts)?;
+
+    let refnames: Vec<&str> = opts.branches.iter().map(|b| b.refname.as_str()).collect();
+    commits_map::rebuild_cache(&repo, &tags, &refnames)
+}
+
+/// List the records of the synchronizations, newest first
+pub fn list_runs(opts: &Options) -> Result<(), Error> {
+    let (repo, _) = open_repo(opts)?;
+    runs::list(&repo)
+}
+
+/// Write the mapping between the remote commits and their copies in a JSON or CSV file
+pub fn export_map(opts: &Options, path: &str) -> Result<(), Error> {
+    let (repo, tags) = open_repo(opts)?;
+    mapping::export_map(&repo, &tags, opts, path)
+}
+
+/// Seed the cache with the mapping between remote commits and their copies of a file
+pub fn import_map(opts: &Options, path: &str) -> Result<(), Error> {
+    let (repo, tags) = open_repo(opts)?;
+    let _lock = lock::Lock::acquire(&repo, opts.break_lock)?;
+    mapping::import_map(&repo, &tags, opts, path)
+}
+
+/// Run a subcommand, printing its result
+pub fn run_subcommand(opts: &Options, subcommand: &app::SubCommand) -> Result<(), Error> {
+    let (repo, tags) = open_repo(opts)?;
+
+    match subcommand {
+        app::SubCommand::Stats { since, json } => {
+            stats::print_stats(&repo, &tags, opts, *since, *json)
+        }
+        app::SubCommand::Export { since, patches } => {
+            let _lock = lock::Lock::acquire(&repo, opts.break_lock)?;
+            let resumed = export::continue_export(&repo, opts)?;
+            for branch in &opts.branches {
+                let patches = patches.as_ref().map(std::path::Path::new);
+                let since = since.as_deref();
+                let resumed = resumed.as_deref() == Some(branch.refname.as_str());
+                export::export_branch(&repo, &tags, branch, opts, since, patches, resumed)?;
+            }
+            Ok(())
+        }
+        app::SubCommand::Audit { since, json } => {
+            audit::audit(&repo, &tags, opts, since.as_deref(), *json)
+        }
+        app::SubCommand::Blame { path, rev } => {
+            blame::print_blame(&repo, &tags, path, rev.as_deref())
+        }
+        app::SubCommand::MigrateNotes => {
+            let _lock = lock::Lock::acquire(&repo, opts.break_lock)?;
+            notes::migrate(&repo, &tags, opts)
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::error::Error;
+use crate::util;
+use crate::{error, warn};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lock preventing several ripit processes from synchronizing the same repository
+///
+/// The lock file is created in the git directory shared by the worktrees, and contains
+/// the pid of the process holding it and the time it was taken. It is removed when the
+/// lock is dropped, which also happens when unwinding a panic.
+pub struct Lock {
+    path: PathBuf,
+}
+
+/// Path to the lock file
+fn lock_path(repo: &git2::Repository) -> PathBuf {
+    util::common_dir(repo).join("ripit").join("lock")
+}
+
+/// Parse the pid and the timestamp of a lock file
+fn parse_lock(content: &str) -> Option<(u32, i64)> {
+    let (pid, timestamp) = content.trim().split_once(' ')?;
+    Some((pid.parse().ok()?, timestamp.parse().ok()?))
+}
+
+/// Returns whether a process is running
+///
+/// Processes are looked up in /proc when it exists, and with kill otherwise.
+pub fn is_running(pid: u32) -> bool {
+    if Path::new("/proc/self").exists() {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+impl Lock {
+    /// Take the lock of the repository
+    ///
+    /// A lock held by a process that is no longer running is stale, and taken over. With
+    /// `break_lock`, an existing lock is removed without checking its process.
+    pub fn acquire(repo: &git2::Repository, break_lock: bool) -> Result<Self, Error> {
+        let path = lock_path(repo);
+        let lock_failed = |err: std::io::Error| Error::LockFailed {
+            path: path.clone(),
+            err,
+        };
+        std::fs::create_dir_all(path.parent().unwrap()).map_err(lock_failed)?;
+
+        if path.exists() {
+            let holder = std::fs::read_to_string(&path)
+                .ok()
+                .as_deref()
+                .and_then(parse_lock);
+            match holder {
+                _ if break_lock => warn!("breaking the lock {}.", path.display()),
+                Some((pid, timestamp)) if is_running(pid) => {
+                    return Err(Error::Locked {
+                        path,
+                        pid,
+                        since: util::format_date(&git2::Time::new(timestamp, 0)),
+                    })
+                }
+                Some((pid, _)) => warn!(
+                    "removing the stale lock of process {}, which is not running.",
+                    pid
+                ),
+                // a process may be writing the lock file
+                None => {
+                    return Err(lock_failed(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid lock file, remove it with --break-lock",
+                    )))
+                }
+            }
+            std::fs::remove_file(&path).map_err(lock_failed)?;
+        }
+
+        // the creation fails if another process took the lock in the meantime
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(lock_failed)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let lock = Self { path };
+        writeln!(file, "{} {}", std::process::id(), timestamp).map_err(|err| {
+            Error::LockFailed {
+                path: lock.path.clone(),
+                err,
+            }
+        })?;
+        Ok(lock)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            error!("Cannot remove the lock {}: {}", self.path.display(), err);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Messages printed during a run, filtered by level and colorized on terminals
+//!
+//! The informational messages are printed with [`info!`](crate::info) and
+//! [`debug!`](crate::debug), on stdout unless it is reserved to a machine-readable output,
+//! and the warnings and errors with [`warn!`](crate::warn) and [`error!`](crate::error), on
+//! stderr. The executable configures the output with [`init`] from the options.
+//!
+//! The messages can be deferred with [`defer`], until the run tells whether they must be
+//! printed with [`flush_deferred`], or dropped with [`discard_deferred`].
+//!
+//! The printed messages are appended without colors to the file opened by [`open_file`].
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+
+// Importance of a message, a message being printed if its level is at most the level of
+// the run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+// When to colorize the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    // only when the output is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+// set when stdout is reserved to the JSON summary of the run
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Reserve stdout to a machine-readable output, the human-readable one going to stderr
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether the human-readable output must be printed on stderr
+pub fn is_stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+// Output on which a message is printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    /// Output of the human-readable messages
+    pub fn human() -> Self {
+        if is_stdout_reserved() {
+            Stream::Stderr
+        } else {
+            Stream::Stdout
+        }
+    }
+}
+
+thread_local! {
+    // messages of the thread, kept to be printed together when buffering
+    static BUFFER: RefCell<Option<Vec<(Stream, String)>>> =
+        const { RefCell::new(None) };
+}
+
+// messages of all the threads, kept until the run tells whether they must be printed
+static DEFERRED: Mutex<Option<Vec<(Stream, String)>>> = Mutex::new(None);
+
+/// Keep the messages instead of printing them, until they are flushed or discarded
+pub fn defer() {
+    *DEFERRED.lock().unwrap() = Some(Vec::new());
+}
+
+/// Print the deferred messages, and print the next ones as they come
+pub fn flush_deferred() {
+    if let Some(messages) = DEFERRED.lock().unwrap().take() {
+        write_all(messages);
+    }
+}
+
+/// Drop the deferred messages, and print the next ones as they come
+pub fn discard_deferred() {
+    DEFERRED.lock().unwrap().take();
+}
+
+fn write_all(messages: Vec<(Stream, String)>) {
+    for (stream, msg) in messages {
+        write(stream, &msg);
+    }
+}
+
+// file to which the printed messages are appended
+static FILE: Mutex<Option<std::fs::File>> = Mutex::new(None);
+
+/// Append the printed messages to a file as well
+pub fn open_file(path: &std::path::Path) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    *FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// Remove the escape sequences of the colors from a message
+fn strip_colors(msg: &str) -> String {
+    let mut stripped = String::with_capacity(msg.len());
+    let mut rest = msg;
+    while let Some(start) = rest.find("\x1b[") {
+        stripped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        rest = match rest.find('m') {
+            Some(end) => &rest[end + 1..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+fn write(stream: Stream, msg: &str) {
+    match stream {
+        Stream::Stdout => print!("{}", msg),
+        Stream::Stderr => eprint!("{}", msg),
+    }
+    if let Some(file) = FILE.lock().unwrap().as_mut() {
+        // the run goes on if the log file cannot be written
+        let _ = file.write_all(strip_colors(msg).as_bytes());
+    }
+}
+
+/// Print a message on an output, or keep it if the messages of the thread are buffered, or
+/// if the messages are deferred
+pub fn print(stream: Stream, args: std::fmt::Arguments) {
+    let msg = BUFFER.with(|buffer| match buffer.borrow_mut().as_mut() {
+        Some(buffer) => {
+            buffer.push((stream, args.to_string()));
+            None
+        }
+        None => Some(args),
+    });
+    let msg = msg.and_then(|args| match DEFERRED.lock().unwrap().as_mut() {
+        Some(deferred) => {
+            deferred.push((stream, args.to_string()));
+            None
+        }
+        None => Some(args),
+    });
+    if let Some(args) = msg {
+        write(stream, &args.to_string());
+    }
+}
+
+/// Run a function, keeping the messages it prints instead of printing them
+///
+/// This groups the messages of a thread, so that they are not interleaved with the
+/// messages of the other threads. They are printed with [`replay`].
+pub fn buffered<T>(f: impl FnOnce() -> T) -> (T, Vec<(Stream, String)>) {
+    BUFFER.with(|buffer| *buffer.borrow_mut() = Some(Vec::new()));
+    let res = f();
+    let messages = BUFFER.with(|buffer| buffer.borrow_mut().take());
+    (res, messages.unwrap_or_default())
+}
+
+/// Print the messages kept by [`buffered`], unless the messages are deferred
+pub fn replay(messages: Vec<(Stream, String)>) {
+    match DEFERRED.lock().unwrap().as_mut() {
+        Some(deferred) => deferred.extend(messages),
+        None => write_all(messages),
+    }
+}
+
+/// Print human-readable output, on stderr if stdout is reserved
+#[macro_export]
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::log::print($crate::log::Stream::human(), format_args!($($arg)*))
+    };
+}
+
+/// Print a line of human-readable output, on stderr if stdout is reserved
+#[macro_export]
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        $crate::log::print(
+            $crate::log::Stream::human(),
+            format_args!("{}\n", format_args!($($arg)*)),
+        )
+    };
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static COLORS: AtomicBool = AtomicBool::new(false);
+
+pub const RED: &str = "31";
+pub const YELLOW: &str = "33";
+pub const MAGENTA: &str = "35";
+pub const CYAN: &str = "36";
+
+impl Level {
+    /// Level of a run, from the number of `-v` and `-q` flags given
+    pub fn from_flags(nb_verbose: u8, nb_quiet: u8) -> Self {
+        match i16::from(nb_verbose) - i16::from(nb_quiet) {
+            i16::MIN..=-2 => Level::Error,
+            -1 => Level::Warn,
+            0 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+}
+
+/// Set the level of the messages printed, and whether they are colorized
+///
+/// In the auto mode, colors are used when stderr is a terminal, and stdout as well unless
+/// it is reserved to a machine-readable output.
+pub fn init(level: Level, color: ColorMode) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    let colors = match color {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            std::io::stderr().is_terminal()
+                && (is_stdout_reserved() || std::io::stdout().is_terminal())
+        }
+    };
+    COLORS.store(colors, Ordering::Relaxed);
+}
+
+/// Returns whether the messages of the given level are printed
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Element of a message, printed in color when colors are enabled
+pub struct Painted<T> {
+    value: T,
+    color: &'static str,
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for Painted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if COLORS.load(Ordering::Relaxed) {
+            write!(f, "\x1b[{}m", self.color)?;
+            self.value.fmt(f)?;
+            write!(f, "\x1b[0m")
+        } else {
+            self.value.fmt(f)
+        }
+    }
+}
+
+pub fn paint<T: std::fmt::Display>(value: T, color: &'static str) -> Painted<T> {
+    Painted { value, color }
+}
+
+/// Commit of a remote repository
+pub fn remote_commit<T: std::fmt::Display>(id: T) -> Painted<T> {
+    paint(id, YELLOW)
+}
+
+/// Name of a branch
+pub fn branch<T: std::fmt::Display>(name: T) -> Painted<T> {
+    paint(name, CYAN)
+}
+
+/// Marker of an uprooted commit
+pub fn uprooted<T: std::fmt::Display>(marker: T) -> Painted<T> {
+    paint(marker, MAGENTA)
+}
+
+/// Print an informational message
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            $crate::outln!($($arg)*)
+        }
+    };
+}
+
+/// Print a detailed message, only shown with `-v`
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            $crate::outln!($($arg)*)
+        }
+    };
+}
+
+/// Print a warning on stderr
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Warn) {
+            $crate::log::print(
+                $crate::log::Stream::Stderr,
+                format_args!(
+                    "{}: {}\n",
+                    $crate::log::paint("warning", $crate::log::YELLOW),
+                    format_args!($($arg)*)
+                ),
+            )
+        }
+    };
+}
+
+/// Print an error on stderr, whatever the level of the run
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::print(
+            $crate::log::Stream::Stderr,
+            format_args!(
+                "{}\n",
+                $crate::log::paint(format_args!($($arg)*), $crate::log::RED)
+            ),
+        )
+    };
+}
+
+#[test]
+fn test_level_from_flags() {
+    assert_eq!(Level::from_flags(0, 0), Level::Info);
+    assert_eq!(Level::from_flags(0, 1), Level::Warn);
+    assert_eq!(Level::from_flags(0, 3), Level::Error);
+    assert_eq!(Level::from_flags(2, 0), Level::Debug);
+    assert_eq!(Level::from_flags(1, 1), Level::Info);
+}
@@ -0,0 +1,136 @@
+//! Ripit tags hiding the ids of the remote commits, with the `hmac` provenance
+//!
+//! The tags contain the HMAC-SHA256 of the id of the remote commit, in hexadecimal, instead
+//! of the id itself, so that only the holders of the key can tell which remote commit a
+//! local commit was copied from. As a MAC cannot be reversed, the MACs of the commits of
+//! the remote and of the cache file are computed with every accepted key, and the tags are
+//! then read as if they contained the ids.
+use crate::commits_map;
+use crate::error::Error;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+// prefix of the MACs written in the tags instead of the ids of the remote commits
+pub const MAC_PREFIX: &str = "hmac-sha256:";
+
+#[derive(Default)]
+struct Index {
+    // ids of the remote commits, by MAC
+    ids: HashMap<String, git2::Oid>,
+    // remote commits whose MACs were computed
+    indexed: HashSet<git2::Oid>,
+}
+
+/// MACs of the remote commits, computed with the keys of a run
+#[derive(Default)]
+pub struct Macs {
+    // keys accepted in the tags, the first one being used to write them
+    keys: Vec<Vec<u8>>,
+    // MACs computed so far, completed once the remote is fetched
+    index: Mutex<Index>,
+}
+
+fn compute(key: &[u8], id: git2::Oid) -> String {
+    // HMAC accepts keys of any size
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(id.to_string().as_bytes());
+    let mac: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}{}", MAC_PREFIX, mac)
+}
+
+/// Returns whether the value of a tag is a MAC
+pub fn is_mac(value: &str) -> bool {
+    value.starts_with(MAC_PREFIX)
+}
+
+impl Macs {
+    /// MACs written with the first of the given keys
+    ///
+    /// Without keys, the tags contain the ids of the remote commits.
+    pub fn new(keys: &[Vec<u8>]) -> Self {
+        Self {
+            keys: keys.to_vec(),
+            index: Mutex::default(),
+        }
+    }
+
+    /// Format the id of a remote commit written in a tag
+    pub fn format(&self, id: git2::Oid) -> String {
+        match self.keys.first() {
+            Some(key) => compute(key, id),
+            None => id.to_string(),
+        }
+    }
+
+    /// Id of the remote commit of the value of a tag
+    ///
+    /// A MAC is replaced with the id of the remote commit it was computed from, if known.
+    /// Otherwise, the value is returned unchanged.
+    pub fn resolve(&self, value: &str) -> String {
+        if !is_mac(value) {
+            return value.to_owned();
+        }
+        match self.index.lock().unwrap().ids.get(value) {
+            Some(id) => id.to_string(),
+            None => value.to_owned(),
+        }
+    }
+
+    /// Compute the MACs of the commits of the remote, and of the remote commits of the
+    /// cache
+    ///
+    /// The commits are only indexed once, so that this can be done again once the remote is
+    /// fetched.
+    pub fn index(&self, repo: &git2::Repository, remote: &str) -> Result<(), Error> {
+        if self.keys.is_empty() {
+            return Ok(());
+        }
+
+        let mut ids = Vec::new();
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_glob(&format!("refs/remotes/{}/*", remote))?;
+        for id in revwalk {
+            ids.push(id?);
+        }
+        // the remote commits may no longer be in the remote, if its history was rewritten
+        if let Ok(content) = std::fs::read_to_string(commits_map::cache_path(repo)) {
+            ids.extend(
+                content
+                    .lines()
+                    .filter_map(|line| line.split_once(' '))
+                    .filter_map(|(remote_id, _)| git2::Oid::from_str(remote_id).ok()),
+            );
+        }
+
+        let mut index = self.index.lock().unwrap();
+        let Index {
+            ids: by_mac,
+            indexed,
+        } = &mut *index;
+        for id in ids {
+            if indexed.insert(id) {
+                for key in &self.keys {
+                    by_mac.insert(compute(key, id), id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_mac() {
+    let id = git2::Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+    // echo -n 0123456789012345678901234567890123456789 | openssl sha256 -hmac key
+    assert_eq!(
+        compute(b"key", id),
+        "hmac-sha256:b6a121a8cde9b6a49efec501498e6fed50ebba5a3f6ce25fafc4b4f09ec46144"
+    );
+}
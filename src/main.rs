@@ -1,83 +1,142 @@
-mod app;
-mod commits_map;
-mod error;
-mod sync;
-mod tag;
-mod util;
+use ripit::{app, error, info, log, Error};
 
-/// Check that the local repo does not contain any staged or unstaged changes
-///
-/// This basically checks that "git diff HEAD" does not return any deltas
-fn check_local_diff(repo: &git2::Repository) -> Result<(), error::Error> {
-    let head = match repo.head() {
-        Ok(tgt) => match tgt.target() {
-            Some(oid) => Some(repo.find_commit(oid)?),
-            None => None,
-        },
-        Err(_) => None,
-    };
-
-    let diff = match head {
-        Some(ci) => repo.diff_tree_to_workdir_with_index(Some(&ci.tree()?), None),
-        None => repo.diff_tree_to_workdir_with_index(None, None),
-    }?;
-
-    if diff.deltas().count() > 0 {
-        Err(error::Error::HasLocalChanges)
-    } else {
-        Ok(())
+fn _main() -> Result<(), Error> {
+    let mut opts = app::parse_args()?;
+    log::init(opts.log_level, opts.color);
+    if let Some(path) = &opts.log_file {
+        log::open_file(path.as_ref()).map_err(|err| Error::LogFileOpen {
+            err,
+            path: path.into(),
+        })?;
     }
-}
-
-fn _main() -> Result<(), error::Error> {
-    let opts = app::parse_args()?;
-
-    let repo = git2::Repository::open(&opts.repo)?;
-    check_local_diff(&repo)?;
-
-    if opts.fetch {
-        // fetch last commits in remote
-        sync::update_remote(&repo, &opts)?;
+    if opts.quiet_if_unchanged.is_some() {
+        log::defer();
     }
+    ripit::expand_branches(&mut opts)?;
 
-    let mut commits_map = commits_map::CommitsMap::new(&repo)?;
+    if opts.check_config {
+        return ripit::check_config(&opts);
+    }
+    if opts.verify {
+        return ripit::verify(&opts);
+    }
+    if let Some(subcommand) = &opts.subcommand {
+        return ripit::run_subcommand(&opts, subcommand);
+    }
+    if opts.rebuild_cache {
+        let nb_entries = ripit::rebuild_cache(&opts)?;
+        info!("Rebuilt the cache file with {} entries.", nb_entries);
+        return Ok(());
+    }
+    if opts.list_runs {
+        return ripit::list_runs(&opts);
+    }
+    if let Some(path) = &opts.export_map {
+        return ripit::export_map(&opts, path);
+    }
+    if let Some(path) = &opts.import_map {
+        return ripit::import_map(&opts, path);
+    }
+    if let Some(interval) = opts.watch {
+        return ripit::watch(&opts, interval);
+    }
+    if opts.abort {
+        return ripit::abort(&opts);
+    }
+    if opts.rollback {
+        return ripit::rollback(&opts);
+    }
 
-    if opts.bootstrap {
-        for branch in &opts.branches {
-            // bootstrap the branch in the local repo with the state of the
-            // branch in the remote repo
-            sync::bootstrap_branch_with_remote(&repo, branch, &mut commits_map, &opts)?
-        }
+    let res = if opts.bootstrap || opts.adopt.is_some() {
+        ripit::bootstrap(&opts)
     } else {
-        let mut has_synced = false;
-
-        for branch in &opts.branches {
-            commits_map.fill_from_branch(&repo, &branch.name)?;
+        ripit::sync(&opts)
+    };
+    ripit::notify(&opts, &res);
+    if let Some(quiet) = opts.quiet_if_unchanged {
+        match &res {
+            // a dry run finding nothing to synchronize fails otherwise
+            Err(Error::NothingToSync) => {
+                log::discard_deferred();
+                return Ok(());
+            }
+            Ok(report) if report.is_unchanged() => {
+                log::discard_deferred();
+                if opts.json && quiet == app::QuietIfUnchanged::Text {
+                    report.print();
+                }
+                return Ok(());
+            }
+            _ => log::flush_deferred(),
         }
+    }
+    let report = res?;
+    if opts.json {
+        report.print();
+    }
+    if !report.push_failures.is_empty() {
+        return Err(Error::PushFailed {
+            branches: report.push_failures,
+        });
+    }
+    Ok(())
+}
 
-        for branch in &opts.branches {
-            // sync local branch with remote by cherry-picking missing commits
-            has_synced |= sync::sync_branch_with_remote(&repo, branch, &mut commits_map, &opts)?
-        }
-        if !opts.fetch && !has_synced {
-            eprintln!(
-                "No commits to synchronize found. Have you fetched \
-                 the latest commits from the private repository with \
-                 `git fetch {}`?",
-                opts.remote
-            );
+/// Print an error, followed by the errors that caused it
+///
+/// A cause whose message is already part of the previous one is left out.
+fn print_error(err: &Error) {
+    error!("{}", err);
+    let mut previous = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        // the other details of libgit2 errors are not meant for the user
+        let msg = match cause.downcast_ref::<git2::Error>() {
+            Some(err) => err.message().to_owned(),
+            None => cause.to_string(),
+        };
+        if !previous.contains(&msg) {
+            error!("  caused by: {}", msg);
         }
+        previous = msg;
+        source = cause.source();
+    }
+}
+
+/// Exit code of the process for an error
+///
+/// Scripts can tell apart a synchronization waiting for the resolution of conflicts (2),
+/// a repository with local changes (3), and a dry run with nothing to synchronize (4),
+/// from the other errors (1).
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::HasConflicts { .. }
+        | Error::ConflictsNotCheckedOut { .. }
+        | Error::ConflictsInBareRepo { .. }
+        | Error::ReplacementNotApplied { .. }
+        | Error::SyncStopped { .. }
+        | Error::ExportStopped { .. }
+        | Error::UnresolvedConflicts { .. } => 2,
+        Error::HasLocalChanges | Error::UntrackedFilesInTheWay { .. } => 3,
+        Error::NothingToSync => 4,
+        _ => 1,
     }
-    Ok(())
 }
 
 fn main() {
-    std::process::exit(match _main() {
+    let res = _main();
+    // the messages are printed before the error they lead to
+    log::flush_deferred();
+    std::process::exit(match res {
         Ok(_) => 0,
+        // clap exits with 2 on usage errors, which is the exit code of conflicts
+        Err(Error::Cli(e)) => {
+            let _ = e.print();
+            i32::from(e.use_stderr())
+        }
         Err(e) => {
-            eprintln!("{}", e);
-            // 1 is for clap, 2 for git errors for the moment
-            2
+            print_error(&e);
+            exit_code(&e)
         }
     })
 }
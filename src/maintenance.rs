@@ -0,0 +1,51 @@
+use crate::app;
+use crate::{info, warn};
+use std::process::Command;
+use std::time::Instant;
+
+/// Write the commit-graph file of the repository, reachable from all its refs
+///
+/// libgit2 cannot write commit-graphs, git is invoked instead.
+fn write_commit_graph(repo: &git2::Repository) -> Result<(), String> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(["commit-graph", "write", "--reachable"])
+        .output()
+        .map_err(|err| format!("cannot run git: {}", err))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ))
+    }
+}
+
+/// Run the configured maintenance step, if enough commits were copied
+///
+/// The synchronization is already done, so failures are only reported as warnings.
+pub fn after_sync(repo: &git2::Repository, opts: &app::Options, nb_copied: usize) {
+    let maintenance = match opts.maintenance {
+        Some(maintenance) => maintenance,
+        None => return,
+    };
+    if nb_copied == 0 || nb_copied < opts.maintenance_min_commits {
+        return;
+    }
+
+    let start = Instant::now();
+    let res = match maintenance {
+        app::Maintenance::CommitGraph => write_commit_graph(repo),
+    };
+    match res {
+        Ok(()) => info!(
+            "Commit-graph written in {:.2}s.",
+            start.elapsed().as_secs_f64()
+        ),
+        Err(err) => warn!("cannot write the commit-graph: {}", err),
+    }
+}
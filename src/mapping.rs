@@ -0,0 +1,464 @@
+//! Export of the mapping between the remote commits and their local copies, with
+//! `--export-map`, and import with `--import-map`
+//!
+//! The mapping is read from the ripit tags of the commits of the local branches, and
+//! checked against the cache file, if any. It is written as the commits are walked, in JSON
+//! or CSV depending on the extension of the file.
+//!
+//! An imported mapping seeds the cache of a mirror that was not synchronized by ripit. As
+//! its commits cannot be amended, their tags are written in the tags file, so that they are
+//! then read as the tags of the commits synchronized by ripit.
+use crate::app;
+use crate::commits_map;
+use crate::error::Error;
+use crate::info;
+use crate::notes;
+use crate::tag;
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+/// Format of the exported mapping
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// Remote commit and its local copy
+#[derive(Serialize)]
+struct Entry<'a> {
+    remote: &'a str,
+    local: String,
+    branch: &'a str,
+    date: String,
+    summary: &'a str,
+    // disagreement between the tag and the cache, if any
+    mismatch: Option<String>,
+}
+
+/// Quote a field of a CSV line if needed
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Split a line of a CSV file in its fields, unquoting them
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Writer of the entries, in the format of the file
+struct MapWriter<W: Write> {
+    out: W,
+    format: Format,
+    nb_entries: usize,
+}
+
+impl<W: Write> MapWriter<W> {
+    fn new(mut out: W, format: Format) -> std::io::Result<Self> {
+        match format {
+            Format::Json => write!(out, "[")?,
+            Format::Csv => writeln!(out, "remote,local,branch,date,summary,mismatch")?,
+        }
+        Ok(Self {
+            out,
+            format,
+            nb_entries: 0,
+        })
+    }
+
+    fn write(&mut self, entry: &Entry) -> std::io::Result<()> {
+        match self.format {
+            Format::Json => {
+                let sep = if self.nb_entries == 0 { "" } else { "," };
+                // serialization of this type cannot fail
+                write!(
+                    self.out,
+                    "{}\n  {}",
+                    sep,
+                    serde_json::to_string(entry).unwrap()
+                )?;
+            }
+            Format::Csv => writeln!(
+                self.out,
+                "{},{},{},{},{},{}",
+                entry.remote,
+                entry.local,
+                csv_field(entry.branch),
+                entry.date,
+                csv_field(entry.summary),
+                csv_field(entry.mismatch.as_deref().unwrap_or(""))
+            )?,
+        }
+        self.nb_entries += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> std::io::Result<()> {
+        if self.format == Format::Json {
+            writeln!(self.out, "\n]")?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Read the local commits of the cache file by remote commit
+///
+/// The entries of old versions only list the local commit, and are ignored.
+fn read_cache(repo: &git2::Repository) -> Result<Option<HashMap<String, String>>, Error> {
+    let filename = commits_map::cache_path(repo);
+    let content = match std::fs::read_to_string(&filename) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(Error::CacheReadError { err, filename }),
+    };
+    Ok(Some(
+        content
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(remote, local)| (remote.to_owned(), local.to_owned()))
+            .collect(),
+    ))
+}
+
+/// Disagreement between the tag of a local commit and the cache, if any
+fn mismatch(
+    cache: Option<&HashMap<String, String>>,
+    tags: &tag::Tags,
+    remote: &str,
+    local: &git2::Commit,
+) -> Option<String> {
+    // only the last part of a split commit is mapped to the remote commit
+    if tags.is_partial(local) {
+        return None;
+    }
+    match cache?.get(remote) {
+        Some(cached) if *cached == local.id().to_string() => None,
+        Some(cached) => Some(format!("the cache maps it to {}", cached)),
+        None => Some("missing from the cache".to_owned()),
+    }
+}
+
+/// Write the mapping of the commits of the local branches in a JSON or CSV file
+///
+/// A commit reachable from several branches is attributed to the first one.
+pub fn export_map(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &app::Options,
+    path: &str,
+) -> Result<(), Error> {
+    let path = Path::new(path);
+    let write_err = |err| Error::MapWrite {
+        path: path.to_owned(),
+        err,
+    };
+    let format = Format::from_path(path).ok_or_else(|| Error::InvalidValue {
+        field: "export-map",
+        value: format!("{}: the file must end with .json or .csv", path.display()),
+    })?;
+    let cache = read_cache(repo)?;
+    if cache.is_none() {
+        info!("No cache file, the mapping is only read from the ripit tags.");
+    }
+
+    let file = std::fs::File::create(path).map_err(write_err)?;
+    let mut writer = MapWriter::new(std::io::BufWriter::new(file), format).map_err(write_err)?;
+    let mut seen = HashSet::new();
+    let mut nb_mismatches = 0;
+    for branch in &opts.branches {
+        let tip = match repo.refname_to_id(&branch.refname) {
+            Ok(tip) => tip,
+            Err(_) => continue,
+        };
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(tip)?;
+        for oid in revwalk {
+            let oid = oid?;
+            if !seen.insert(oid) {
+                continue;
+            }
+            let commit = repo.find_commit(oid)?;
+            let remote = match tags.retrieve_ripit_tag(&commit) {
+                Some((remote, _)) => remote,
+                None => continue,
+            };
+            let date = util::format_date(&commit.author().when());
+            let summary = commit.summary().unwrap_or("");
+
+            // the commits folded in the copy are mapped to it as well
+            let squashed = tags.retrieve_squashed(&commit);
+            let squashed = squashed.iter().map(|id| id.to_string());
+            for remote in squashed.chain(std::iter::once(remote)) {
+                let mismatch = mismatch(cache.as_ref(), tags, &remote, &commit);
+                if mismatch.is_some() {
+                    nb_mismatches += 1;
+                }
+                let entry = Entry {
+                    remote: &remote,
+                    local: oid.to_string(),
+                    branch: &branch.name,
+                    date: date.clone(),
+                    summary,
+                    mismatch,
+                };
+                writer.write(&entry).map_err(write_err)?;
+            }
+        }
+    }
+    let nb_entries = writer.nb_entries;
+    writer.finish().map_err(write_err)?;
+
+    info!(
+        "Exported {} commits in {}, {} disagreeing with the cache.",
+        nb_entries,
+        path.display(),
+        nb_mismatches
+    );
+    Ok(())
+}
+
+/// Remote commit and its local copy, read from an imported mapping
+///
+/// The other fields of the entries, such as those of an exported mapping, are ignored.
+#[derive(Deserialize)]
+struct Pair {
+    remote: String,
+    local: String,
+}
+
+/// Read the pairs of a CSV file
+///
+/// The columns are named by the header, if any, and are otherwise the remote commit followed
+/// by the local one.
+fn parse_csv(content: &str) -> Result<Vec<Pair>, String> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .peekable();
+    let (mut remote_col, mut local_col) = (0, 1);
+    if let Some((_, header)) = lines.peek() {
+        let header = csv_fields(header);
+        if header.iter().any(|field| field.trim() == "remote") {
+            let column = |name| {
+                header
+                    .iter()
+                    .position(|field| field.trim() == name)
+                    .ok_or_else(|| format!("the header has no {} column", name))
+            };
+            remote_col = column("remote")?;
+            local_col = column("local")?;
+            lines.next();
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for (line_number, line) in lines {
+        let fields = csv_fields(line);
+        match (fields.get(remote_col), fields.get(local_col)) {
+            (Some(remote), Some(local)) => pairs.push(Pair {
+                remote: remote.trim().to_owned(),
+                local: local.trim().to_owned(),
+            }),
+            _ => {
+                return Err(format!(
+                    "line {} does not contain both commits",
+                    line_number + 1
+                ))
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+/// Parse the id of a commit of an imported mapping
+fn parse_id(id: &str, index: usize) -> Result<git2::Oid, String> {
+    if id.len() != 40 {
+        return Err(format!(
+            "entry {}: {} is not a full commit id",
+            index + 1,
+            id
+        ));
+    }
+    git2::Oid::from_str(id).map_err(|err| format!("entry {}: {}", index + 1, err.message()))
+}
+
+/// Seed the cache from the mapping of a JSON or CSV file
+///
+/// Every local commit must be in the repository, and every remote commit in the fetched
+/// remote. The local commits without a ripit tag get one in the tags file, the last remote
+/// commit mapped to a local commit being the one it was copied from, and the previous ones
+/// being folded in it. With `--write-trailers`, the local commits whose messages have no tag
+/// are listed, as the synchronization then depends on the files of ripit.
+pub fn import_map(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &app::Options,
+    path: &str,
+) -> Result<(), Error> {
+    let path = Path::new(path);
+    let invalid = |reason| Error::InvalidMap {
+        path: path.to_owned(),
+        reason,
+    };
+    let format = Format::from_path(path).ok_or_else(|| Error::InvalidValue {
+        field: "import-map",
+        value: format!("{}: the file must end with .json or .csv", path.display()),
+    })?;
+    let content = std::fs::read_to_string(path).map_err(|err| Error::MapRead {
+        path: path.to_owned(),
+        err,
+    })?;
+    let pairs = match format {
+        Format::Json => serde_json::from_str(&content).map_err(|err| err.to_string()),
+        Format::Csv => parse_csv(&content),
+    }
+    .map_err(invalid)?;
+
+    let mut remote_commits = HashSet::new();
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_glob(&format!("refs/remotes/{}/*", opts.remote))?;
+    for id in revwalk {
+        remote_commits.insert(id?);
+    }
+    let mut commits_map = commits_map::CommitsMap::new(repo, tags)?;
+
+    // remote commits to add to the cache, and the remote commits of each local commit to tag
+    let mut to_cache = Vec::new();
+    let mut cached = HashSet::new();
+    let mut to_tag: Vec<(git2::Commit, Vec<git2::Oid>)> = Vec::new();
+    let mut tag_index: HashMap<git2::Oid, usize> = HashMap::new();
+    for (index, pair) in pairs.iter().enumerate() {
+        let local_id = parse_id(&pair.local, index).map_err(invalid)?;
+        let remote_id = parse_id(&pair.remote, index).map_err(invalid)?;
+        let local = repo.find_commit(local_id).map_err(|_| {
+            invalid(format!(
+                "entry {}: the local commit {} is not in the repository",
+                index + 1,
+                local_id
+            ))
+        })?;
+        if !remote_commits.contains(&remote_id) {
+            return Err(invalid(format!(
+                "entry {}: the remote commit {} is not in the fetched remote {}",
+                index + 1,
+                remote_id,
+                opts.remote
+            )));
+        }
+
+        match commits_map.get(remote_id) {
+            Some(synced) if synced.commit.id() == local_id => continue,
+            Some(synced) => {
+                return Err(invalid(format!(
+                    "entry {}: the cache maps the remote commit {} to {}",
+                    index + 1,
+                    remote_id,
+                    synced.commit.id()
+                )))
+            }
+            None => (),
+        }
+        if !cached.insert(remote_id) {
+            continue;
+        }
+        to_cache.push((remote_id, local_id));
+
+        match tags.retrieve_ripit_tag(&local) {
+            Some((tagged, _)) => {
+                let squashed = tags.retrieve_squashed(&local);
+                if tagged != remote_id.to_string() && !squashed.contains(&remote_id) {
+                    return Err(invalid(format!(
+                        "entry {}: the local commit {} is a copy of {}",
+                        index + 1,
+                        local_id,
+                        tagged
+                    )));
+                }
+            }
+            None => match tag_index.get(&local_id) {
+                Some(&i) => to_tag[i].1.push(remote_id),
+                None => {
+                    tag_index.insert(local_id, to_tag.len());
+                    to_tag.push((local, vec![remote_id]));
+                }
+            },
+        }
+    }
+
+    for (local, remote_ids) in &to_tag {
+        let (remote_id, squashed) = remote_ids.split_last().unwrap();
+        let remote = repo.find_commit(*remote_id)?;
+        let note = tags.format_squashed_tag(&tags.format_ripit_tag(&remote, false), squashed);
+        tags.notes().import(repo, local.id(), &note)?;
+    }
+    for (remote_id, local_id) in &to_cache {
+        commits_map.cache_commit(*remote_id, *local_id);
+    }
+    commits_map.flush();
+    info!(
+        "Imported {} commits from {}, {} of them tagged in {}.",
+        to_cache.len(),
+        path.display(),
+        to_tag.len(),
+        notes::tags_path(repo).display()
+    );
+
+    if opts.write_trailers {
+        info!("Local commits without a provenance trailer, only known from the files of ripit:");
+        let mut seen = HashSet::new();
+        for (_, local_id) in &to_cache {
+            let commit = repo.find_commit(*local_id)?;
+            let (_, tags) = tags.split_tags(commit.message().unwrap_or(""));
+            if tags.is_empty() && seen.insert(commit.id()) {
+                info!("  {} {}", commit.id(), commit.summary().unwrap_or(""));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_csv_field() {
+    assert_eq!(csv_field("fix bug"), "fix bug");
+    assert_eq!(csv_field("a, b"), "\"a, b\"");
+    assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+}
+
+#[test]
+fn test_csv_fields() {
+    assert_eq!(csv_fields("a,b"), ["a", "b"]);
+    assert_eq!(csv_fields("a,\"b, \"\"c\"\"\",d"), ["a", "b, \"c\"", "d"]);
+    assert_eq!(csv_fields(""), [""]);
+}
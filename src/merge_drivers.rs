@@ -0,0 +1,251 @@
+//! Merge drivers set on the paths with the `merge` attribute
+//!
+//! The attributes are read from the `.gitattributes` files of the local branch. The
+//! `union`, `ours` and `theirs` drivers are built in, the other ones being the external
+//! drivers defined in the `merge.<driver>.driver` git configuration.
+use crate::autoresolve;
+use crate::rerere;
+use crate::util;
+use crate::{info, warn};
+use std::path::Path;
+use std::process::Command;
+
+// bits of the flags of an index entry containing its stage
+const STAGE_MASK: u16 = 0x3000;
+
+/// `merge` attributes of the `.gitattributes` files of a tree
+struct Attributes {
+    // directory of the file, pattern and driver, the rules of the deeper files last
+    rules: Vec<(String, String, String)>,
+}
+
+impl Attributes {
+    fn load(repo: &git2::Repository, tree: &git2::Tree) -> Self {
+        let mut files = Vec::new();
+        let _ = tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.name() == Some(".gitattributes") {
+                files.push((dir.to_owned(), entry.id()));
+            }
+            git2::TreeWalkResult::Ok
+        });
+        files.sort_by_key(|(dir, _)| dir.matches('/').count());
+
+        let mut rules = Vec::new();
+        for (dir, id) in files {
+            let content = match repo.find_blob(id) {
+                Ok(blob) => String::from_utf8_lossy(blob.content()).into_owned(),
+                Err(_) => continue,
+            };
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut fields = line.split_whitespace();
+                let pattern = fields.next().unwrap_or("");
+                for attr in fields {
+                    let driver = match attr {
+                        "merge" => "text",
+                        "-merge" => "binary",
+                        // unspecified, the default driver is used
+                        "!merge" => "",
+                        _ => match attr.strip_prefix("merge=") {
+                            Some(driver) => driver,
+                            None => continue,
+                        },
+                    };
+                    rules.push((dir.clone(), pattern.to_owned(), driver.to_owned()));
+                }
+            }
+        }
+        Self { rules }
+    }
+
+    /// Driver of a path, the last matching rule applying
+    fn driver(&self, path: &str) -> Option<&str> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|(dir, pattern, _)| {
+                let relative = match path.strip_prefix(dir.as_str()) {
+                    Some(relative) => relative,
+                    None => return false,
+                };
+                match pattern.trim_start_matches('/') {
+                    p if pattern.contains('/') => util::glob_match(p, relative),
+                    p => util::glob_match(p, name),
+                }
+            })
+            .map(|(_, _, driver)| driver.as_str())
+            .filter(|driver| !driver.is_empty())
+    }
+}
+
+/// Keep the lines of both sides of the conflicting hunks
+fn union(content: &str) -> String {
+    let mut merged = String::new();
+    let mut in_hunk = false;
+    for line in content.split_inclusive('\n') {
+        if !in_hunk && line.starts_with("<<<<<<<") {
+            in_hunk = true;
+        } else if in_hunk && line.starts_with(">>>>>>>") {
+            in_hunk = false;
+        } else if !(in_hunk && line.starts_with("=======")) {
+            merged.push_str(line);
+        }
+    }
+    merged
+}
+
+/// Run an external merge driver, returning the merged content if it succeeds
+///
+/// Like git, `%O`, `%A` and `%B` are replaced with the paths of the temporary files
+/// containing the ancestor, local and remote versions, `%P` with the path of the file and
+/// `%L` with the size of the conflict markers. The driver writes the result in `%A`.
+fn run_external(
+    repo: &git2::Repository,
+    cmd: &str,
+    path: &str,
+    conflict: &git2::IndexConflict,
+) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let dir = std::env::temp_dir().join(format!("ripit-merge-driver-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let mut files = Vec::new();
+    for (name, entry) in [
+        ("O", &conflict.ancestor),
+        ("A", &conflict.our),
+        ("B", &conflict.their),
+    ] {
+        let content = match entry.as_ref().map(|entry| repo.find_blob(entry.id)) {
+            Some(Ok(blob)) => blob.content().to_vec(),
+            _ => Vec::new(),
+        };
+        let file = dir.join(name);
+        std::fs::write(&file, content)?;
+        files.push(file.display().to_string());
+    }
+    let cmd = cmd
+        .replace("%O", &files[0])
+        .replace("%A", &files[1])
+        .replace("%B", &files[2])
+        .replace("%P", path)
+        .replace("%L", "7");
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(&cmd)
+        .current_dir(repo.workdir().unwrap_or_else(|| repo.path()))
+        .status();
+    let merged = match status {
+        Ok(status) if status.success() => Some(std::fs::read(&files[1])?),
+        Ok(status) => {
+            warn!("merge driver failed on {}: {}", path, status);
+            None
+        }
+        Err(err) => {
+            warn!("cannot run the merge driver of {}: {}", path, err);
+            None
+        }
+    };
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(merged)
+}
+
+/// Resolve the conflicts of an index with the merge drivers of the paths
+///
+/// The attributes are read from `tree`, the tree of the local branch. Returns the resolved
+/// paths, the conflicts of the other paths being left in the index.
+pub fn resolve(
+    repo: &git2::Repository,
+    index: &mut git2::Index,
+    tree: &git2::Tree,
+) -> Result<Vec<String>, git2::Error> {
+    if !index.has_conflicts() {
+        return Ok(Vec::new());
+    }
+    let attributes = Attributes::load(repo, tree);
+    if attributes.rules.is_empty() {
+        return Ok(Vec::new());
+    }
+    let config = repo.config()?;
+
+    let mut resolved = Vec::new();
+    let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+    for conflict in conflicts {
+        // only the files modified on both sides can be merged
+        let (our, their) = match (&conflict.our, &conflict.their) {
+            (Some(our), Some(their)) => (our, their),
+            _ => continue,
+        };
+        let path = String::from_utf8_lossy(&our.path).into_owned();
+        let driver = match attributes.driver(&path) {
+            Some(driver) => driver,
+            None => continue,
+        };
+
+        let content = match driver {
+            "ours" => Some(repo.find_blob(our.id)?.content().to_vec()),
+            "theirs" => Some(repo.find_blob(their.id)?.content().to_vec()),
+            "union" => rerere::conflicted_files(repo, index, std::slice::from_ref(&path))?
+                .pop()
+                .map(|(_, content)| union(&content).into_bytes()),
+            // the conflicts are left to the user, as with the default driver
+            "text" | "binary" => None,
+            _ => match config.get_string(&format!("merge.{}.driver", driver)) {
+                Ok(cmd) => run_external(repo, &cmd, &path, &conflict)
+                    .map_err(|err| git2::Error::from_str(&err.to_string()))?,
+                Err(_) => None,
+            },
+        };
+        let content = match content {
+            Some(content) => content,
+            None => continue,
+        };
+
+        // the mode of the local version is kept
+        let mut entry = conflict.our.unwrap();
+        entry.id = repo.blob(&content)?;
+        entry.file_size = content.len() as u32;
+        entry.flags &= !STAGE_MASK;
+        index.remove_path(Path::new(&path))?;
+        index.add(&entry)?;
+        info!(
+            "Resolved the conflicts of {} with the {} merge driver.",
+            path, driver
+        );
+        resolved.push(path);
+    }
+    Ok(resolved)
+}
+
+/// Resolve the conflicts of the index of the repository, updating the working directory
+pub fn resolve_in_workdir(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+) -> Result<Vec<String>, git2::Error> {
+    let mut index = repo.index()?;
+    let resolved = resolve(repo, &mut index, tree)?;
+    autoresolve::checkout_resolved(repo, &mut index, &resolved)?;
+    Ok(resolved)
+}
+
+#[test]
+fn test_merge_attributes() {
+    let attributes = Attributes {
+        rules: vec![
+            ("".to_owned(), "*.md".to_owned(), "union".to_owned()),
+            ("".to_owned(), "/doc/*.bin".to_owned(), "custom".to_owned()),
+            ("doc/".to_owned(), "NEWS.md".to_owned(), "binary".to_owned()),
+            ("doc/".to_owned(), "*.md".to_owned(), "".to_owned()),
+        ],
+    };
+    assert_eq!(attributes.driver("CHANGELOG.md"), Some("union"));
+    assert_eq!(attributes.driver("src/README.md"), Some("union"));
+    assert_eq!(attributes.driver("doc/NEWS.md"), None);
+    assert_eq!(attributes.driver("doc/a.bin"), Some("custom"));
+    assert_eq!(attributes.driver("src/doc/a.bin"), None);
+    assert_eq!(attributes.driver("main.c"), None);
+
+    let content = "a\n<<<<<<< ours\nb\n=======\nc\n>>>>>>> theirs\nd\n";
+    assert_eq!(union(content), "a\nb\nc\nd\n");
+}
@@ -0,0 +1,130 @@
+use crate::error::Error;
+use crate::{info, warn};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Write the version of a conflicted file in a temporary file
+///
+/// A missing version, for a file added or deleted on one side, is written as an empty file.
+fn write_version(
+    repo: &git2::Repository,
+    dir: &Path,
+    name: &str,
+    entry: &Option<git2::IndexEntry>,
+) -> Result<PathBuf, Error> {
+    let content = match entry {
+        Some(entry) => repo.find_blob(entry.id)?.content().to_vec(),
+        None => Vec::new(),
+    };
+
+    let path = dir.join(name);
+    if let Err(err) = std::fs::write(&path, content) {
+        return Err(Error::MergetoolFailed {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        });
+    }
+    Ok(path)
+}
+
+/// Run the merge tool on a conflicted file
+///
+/// Returns the path of the file if the tool resolved its conflicts.
+fn resolve_file(
+    repo: &git2::Repository,
+    conflict: &git2::IndexConflict,
+    dir: &Path,
+    cmd: &str,
+) -> Result<Option<PathBuf>, Error> {
+    let entry = match conflict
+        .our
+        .as_ref()
+        .or(conflict.their.as_ref())
+        .or(conflict.ancestor.as_ref())
+    {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let path = PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned());
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let merged = repo.workdir().unwrap().join(&path);
+
+    let base = write_version(repo, dir, &format!("BASE_{}", filename), &conflict.ancestor)?;
+    let local = write_version(repo, dir, &format!("LOCAL_{}", filename), &conflict.our)?;
+    let remote = write_version(repo, dir, &format!("REMOTE_{}", filename), &conflict.their)?;
+
+    info!("Running merge tool on {}...", path.display());
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .current_dir(repo.workdir().unwrap())
+        .env("BASE", &base)
+        .env("LOCAL", &local)
+        .env("REMOTE", &remote)
+        .env("MERGED", &merged)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Ok(Some(path)),
+        Ok(status) => {
+            warn!(
+                "merge tool failed on {}: {}, conflicts are left unresolved.",
+                path.display(),
+                status
+            );
+            Ok(None)
+        }
+        Err(err) => Err(Error::MergetoolFailed {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        }),
+    }
+}
+
+/// Resolve the conflicts of the index with an external merge tool
+///
+/// The tool is run in a shell for every conflicted file, with the `BASE`, `LOCAL` and
+/// `REMOTE` environment variables containing the paths of the versions of the file, and
+/// `MERGED` the path of the file in the working directory, like `git mergetool`. Files
+/// for which the tool succeeds are staged.
+///
+/// Returns true if all conflicts were resolved.
+pub fn resolve_conflicts(repo: &git2::Repository, cmd: &str) -> Result<bool, Error> {
+    let mut index = repo.index()?;
+    let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+
+    let dir = std::env::temp_dir().join(format!("ripit-mergetool-{}", std::process::id()));
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        return Err(Error::MergetoolFailed {
+            path: dir.display().to_string(),
+            reason: err.to_string(),
+        });
+    }
+
+    let mut res = Ok(true);
+    for conflict in &conflicts {
+        match resolve_file(repo, conflict, &dir, cmd) {
+            Ok(Some(path)) => {
+                if let Err(err) = index.add_path(&path) {
+                    res = Err(err.into());
+                    break;
+                }
+            }
+            Ok(None) => {
+                res = Ok(false);
+                break;
+            }
+            Err(err) => {
+                res = Err(err);
+                break;
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+
+    index.write()?;
+    Ok(res? && !index.has_conflicts())
+}
@@ -0,0 +1,43 @@
+use crate::error::Error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Filter a commit message through the message filter command
+///
+/// The command is run in a shell, with the message on stdin, and its output is used as
+/// the new message. The command must succeed, and output valid UTF-8.
+pub fn filter_message(cmd: &str, msg: &str) -> Result<String, Error> {
+    let failed = |reason: String| Error::MessageFilterFailed {
+        summary: msg.lines().next().unwrap_or("").to_owned(),
+        reason,
+    };
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| failed(err.to_string()))?;
+
+    // Write the message from another thread, so that the command cannot block on a full
+    // stdout pipe.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = msg.as_bytes().to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output();
+    let _ = writer.join();
+    let output = output.map_err(|err| failed(err.to_string()))?;
+
+    if !output.status.success() {
+        return Err(failed(format!(
+            "{}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+    String::from_utf8(output.stdout).map_err(|_| failed("invalid UTF-8 in its output".to_owned()))
+}
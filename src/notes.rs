@@ -0,0 +1,238 @@
+//! Ripit tags recorded outside of the commit messages, with the `notes`, `both` and
+//! `cache-only` provenances
+//!
+//! The tags of a commit are written in its note under `refs/notes/ripit`, instead of or in
+//! addition to its message. With the `cache-only` provenance, they are written in the
+//! `ripit/tags` file next to the cache file instead, so that nothing in the repository
+//! links the local commits to the remote ones. The notes of the repository are loaded in
+//! the context of each run, and read with the messages of the commits, so that the tags of
+//! the commits synchronized before switching provenance are still found. The tags file is
+//! read with every provenance, as the tags of the commits imported with `--import-map` are
+//! written in it.
+use crate::app;
+use crate::error::Error;
+use crate::info;
+use crate::tag;
+use crate::util;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// reference of the notes containing the ripit tags
+pub const NOTES_REF: &str = "refs/notes/ripit";
+
+/// Notes of the commits of a repository, loaded for a run
+#[derive(Default)]
+pub struct Notes {
+    // notes of the commits, by commit id, shared by the branches synchronized in parallel,
+    // which also serialize their writes with its lock
+    notes: Mutex<HashMap<git2::Oid, String>>,
+    // file in which the notes are written with the cache-only provenance
+    tags_file: Option<PathBuf>,
+}
+
+/// Path to the file of the tags written with the cache-only provenance
+pub fn tags_path(repo: &git2::Repository) -> PathBuf {
+    util::common_dir(repo).join("ripit").join("tags")
+}
+
+/// Write the tags of a commit on a single line of the tags file
+fn escape(tags: &str) -> String {
+    tags.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut tags = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => tags.push('\n'),
+            ('\\', Some('\\')) => tags.push('\\'),
+            _ => {
+                tags.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    tags
+}
+
+/// Read the tags file, listing the id of each local commit followed by its tags
+fn read_tags_file(path: &PathBuf, notes: &mut HashMap<git2::Oid, String>) -> Result<(), Error> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(Error::CacheReadError {
+                err,
+                filename: path.clone(),
+            })
+        }
+    };
+    for (line_number, line) in content.lines().enumerate() {
+        let (id, tags) = line.split_once(' ').unwrap_or((line, ""));
+        match git2::Oid::from_str(id) {
+            Ok(id) => {
+                notes.insert(id, unescape(tags));
+            }
+            Err(err) => {
+                return Err(Error::TagsCorrupted {
+                    desc: err.message().to_owned(),
+                    filename: path.clone(),
+                    line: line.to_owned(),
+                    line_number: line_number as u32 + 1,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Append a line of tags to the tags file
+fn append_tags(path: &PathBuf, commit_id: git2::Oid, tags: &str) -> Result<(), Error> {
+    let mut opts = std::fs::OpenOptions::new();
+    opts.create(true).append(true);
+    let res = std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| opts.open(path))
+        .and_then(|mut file| writeln!(file, "{} {}", commit_id, escape(tags)));
+    res.map_err(|err| Error::CacheOpenError {
+        err,
+        filename: path.clone(),
+    })
+}
+
+impl Notes {
+    /// Load the notes of the repository, if any
+    ///
+    /// The tags file is read as well, and with the cache-only provenance, the notes are
+    /// then written in it.
+    pub fn load(repo: &git2::Repository, provenance: app::Provenance) -> Result<Self, Error> {
+        let mut notes = HashMap::new();
+        match repo.notes(Some(NOTES_REF)) {
+            Ok(iter) => {
+                for item in iter {
+                    let (note_id, commit_id) = item?;
+                    let blob = repo.find_blob(note_id)?;
+                    let note = String::from_utf8_lossy(blob.content()).into_owned();
+                    notes.insert(commit_id, note);
+                }
+            }
+            Err(err) if err.code() == git2::ErrorCode::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        let path = tags_path(repo);
+        read_tags_file(&path, &mut notes)?;
+        Ok(Self {
+            notes: Mutex::new(notes),
+            tags_file: (provenance == app::Provenance::CacheOnly).then_some(path),
+        })
+    }
+
+    /// Note of a commit, if any
+    pub fn get(&self, commit_id: git2::Oid) -> Option<String> {
+        self.notes.lock().unwrap().get(&commit_id).cloned()
+    }
+
+    /// Record the tags of a commit in its note, replacing the previous note
+    ///
+    /// With the cache-only provenance, they are appended to the tags file instead.
+    pub fn write(
+        &self,
+        repo: &git2::Repository,
+        signature: &git2::Signature,
+        commit_id: git2::Oid,
+        tags: &str,
+    ) -> Result<(), Error> {
+        let mut notes = self.notes.lock().unwrap();
+        match &self.tags_file {
+            Some(path) => append_tags(path, commit_id, tags)?,
+            None => {
+                repo.note(signature, signature, Some(NOTES_REF), commit_id, tags, true)?;
+            }
+        }
+        notes.insert(commit_id, tags.to_owned());
+        Ok(())
+    }
+
+    /// Record the tags of a commit of a mirror that was not synchronized by ripit
+    ///
+    /// The history of the mirror is published, and its commits cannot be amended: their
+    /// tags are written in the tags file whatever the provenance.
+    pub fn import(
+        &self,
+        repo: &git2::Repository,
+        commit_id: git2::Oid,
+        tags: &str,
+    ) -> Result<(), Error> {
+        let mut notes = self.notes.lock().unwrap();
+        append_tags(&tags_path(repo), commit_id, tags)?;
+        notes.insert(commit_id, tags.to_owned());
+        Ok(())
+    }
+
+    /// Error raised when no tag is found in the history of a branch
+    ///
+    /// With the cache-only provenance, the tags can only be found in the tags file.
+    pub fn tag_missing(&self) -> Error {
+        match &self.tags_file {
+            Some(path) => Error::CacheRequired { path: path.clone() },
+            None => Error::TagMissing,
+        }
+    }
+}
+
+/// Record in notes the tags of the messages of the commits of the branches
+///
+/// The commits keep their messages: the branches are published, and rewriting them would
+/// break their clones. The commits synchronized afterwards only carry their tags in notes
+/// with the `notes` provenance.
+pub fn migrate(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let mut revwalk = repo.revwalk()?;
+    for branch in &opts.branches {
+        revwalk.push_ref(&branch.refname)?;
+    }
+    let notes = tags.notes();
+    if notes.tags_file.is_some() {
+        return Err(Error::InvalidValue {
+            field: "provenance",
+            value: "the notes cannot be written with the cache-only provenance".to_owned(),
+        });
+    }
+    let signature = repo.signature()?;
+
+    let mut nb_notes = 0;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        if notes.get(commit.id()).is_some() {
+            continue;
+        }
+        let (_, commit_tags) = tags.split_tags(commit.message().unwrap_or(""));
+        if commit_tags.is_empty() {
+            continue;
+        }
+        notes.write(repo, &signature, commit.id(), &commit_tags)?;
+        nb_notes += 1;
+    }
+    info!(
+        "Recorded the ripit tags of {} commits in {}.",
+        nb_notes, NOTES_REF
+    );
+    Ok(())
+}
+
+#[test]
+fn test_escape() {
+    let tags = "rip-it-squashed: 0123\nrip-it: 4567 part 1/2\\n";
+    assert_eq!(
+        escape(tags),
+        "rip-it-squashed: 0123\\nrip-it: 4567 part 1/2\\\\n"
+    );
+    assert_eq!(unescape(&escape(tags)), tags);
+}
@@ -0,0 +1,201 @@
+use crate::app;
+use crate::error::Error;
+use crate::report::RunReport;
+use crate::warn;
+use serde::Serialize;
+
+/// Synchronization of a branch, as notified
+#[derive(Serialize)]
+struct BranchPayload<'a> {
+    branch: &'a str,
+    synced_commits: usize,
+}
+
+/// Commit whose copy stopped the synchronization on conflicts
+#[derive(Serialize)]
+struct ConflictPayload<'a> {
+    branch: &'a str,
+    remote_oid: String,
+    summary: &'a str,
+}
+
+/// Outcome of a run, posted to the `notify_url` of the configuration
+#[derive(Serialize)]
+struct Payload<'a> {
+    remote: &'a str,
+    outcome: app::NotifyEvent,
+    branches: Vec<BranchPayload<'a>>,
+    conflict: Option<ConflictPayload<'a>>,
+    error: Option<String>,
+}
+
+fn build_payload<'a>(opts: &'a app::Options, result: &'a Result<RunReport, Error>) -> Payload<'a> {
+    let mut payload = Payload {
+        remote: &opts.remote,
+        outcome: app::NotifyEvent::Success,
+        branches: Vec::new(),
+        conflict: None,
+        error: None,
+    };
+    match result {
+        Ok(report) => {
+            payload.branches = report
+                .branches
+                .iter()
+                .map(|branch| BranchPayload {
+                    branch: &branch.branch,
+                    synced_commits: branch.commits.iter().filter(|ci| !ci.skipped).count(),
+                })
+                .collect();
+            // the branches that could not be pushed are synchronized, but the run fails
+            if !report.push_failures.is_empty() {
+                payload.outcome = app::NotifyEvent::Error;
+                let err = Error::PushFailed {
+                    branches: report.push_failures.clone(),
+                };
+                payload.error = Some(err.to_string());
+            }
+        }
+        Err(
+            err @ (Error::HasConflicts {
+                branch,
+                remote_id,
+                summary,
+            }
+            | Error::ConflictsNotCheckedOut {
+                branch,
+                remote_id,
+                summary,
+                ..
+            }
+            | Error::ConflictsInBareRepo {
+                branch,
+                remote_id,
+                summary,
+                ..
+            }
+            | Error::ReplacementNotApplied {
+                branch,
+                remote_id,
+                summary,
+                ..
+            }),
+        ) => {
+            payload.outcome = app::NotifyEvent::Conflict;
+            payload.conflict = Some(ConflictPayload {
+                branch,
+                remote_oid: remote_id.to_string(),
+                summary,
+            });
+            payload.error = Some(err.to_string());
+        }
+        Err(err) => {
+            payload.outcome = app::NotifyEvent::Error;
+            payload.error = Some(err.to_string());
+        }
+    }
+    payload
+}
+
+/// Post a JSON body to an http URL, waiting at most `timeout` for each step
+///
+/// This is a minimal HTTP/1.1 client, only supporting plain http, and considering any 2xx
+/// status as a success.
+#[cfg(feature = "notify")]
+fn post(url: &str, body: &str, timeout: std::time::Duration) -> Result<(), String> {
+    use std::io::{Read, Write};
+    use std::net::{TcpStream, ToSocketAddrs};
+
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| "only http URLs are supported".to_owned())?;
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    let addr = if authority.contains(':') {
+        authority.to_owned()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let addrs = addr.to_socket_addrs().map_err(|err| err.to_string())?;
+    let mut last_err = format!("cannot resolve {}", authority);
+    let mut stream = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            }
+            Err(err) => last_err = err.to_string(),
+        }
+    }
+    let mut stream = stream.ok_or(last_err)?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|err| err.to_string())?;
+
+    let request = format!(
+        "POST {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         User-Agent: ripit/{}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        path,
+        authority,
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+        body
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|err| err.to_string())?;
+
+    // only the status line matters
+    let mut response = Vec::new();
+    let mut buf = [0; 512];
+    while !response.contains(&b'\n') {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or("");
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some(_) => Err(format!("the server answered \"{}\"", status.trim())),
+        None => Err("invalid response from the server".to_owned()),
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+fn post(_url: &str, _body: &str, _timeout: std::time::Duration) -> Result<(), String> {
+    Err("ripit was built without the notify feature".to_owned())
+}
+
+/// Notify the outcome of a synchronization to the `notify_url` of the configuration
+///
+/// Nothing is sent if the outcome is not one of the `notify_on` events. A failure to notify
+/// does not change the result of the run, and is only reported as a warning.
+pub fn notify(opts: &app::Options, result: &Result<RunReport, Error>) {
+    let notify = match &opts.notify {
+        Some(notify) => notify,
+        None => return,
+    };
+    let payload = build_payload(opts, result);
+    if !notify.on.contains(&payload.outcome) {
+        return;
+    }
+
+    // serialization of these types cannot fail
+    let body = serde_json::to_string(&payload).unwrap();
+    let timeout = std::time::Duration::from_secs(notify.timeout);
+    if let Err(reason) = post(&notify.url, &body, timeout) {
+        warn!("cannot notify {}: {}", notify.url, reason);
+    }
+}
@@ -0,0 +1,194 @@
+//! Synchronization of several branches in parallel
+//!
+//! The branches sharing commits to synchronize are grouped, and synchronized one after the
+//! other in the order of the configuration. The groups are independent, and are
+//! synchronized by up to `jobs` threads, each one working on its own handle on the
+//! repository. The groups of a branch synchronized in the working directory are
+//! synchronized afterwards, on the calling thread.
+use crate::app;
+use crate::commits_map::{CommitsMap, MapEntries, SharedMap};
+use crate::error::Error;
+use crate::log;
+use crate::sync;
+use crate::tag;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+/// Result of the synchronization of a branch
+pub struct BranchSync {
+    // number of commits created, None if there was nothing to synchronize
+    pub nb_copied: Option<usize>,
+    // remote commits processed
+    pub processed: Vec<git2::Oid>,
+    // entries added to the cache file
+    pub cache_entries: Vec<String>,
+    // mappings of the processed commits
+    entries: MapEntries,
+}
+
+/// Group the branches sharing commits to synchronize
+///
+/// The groups list the indexes of their branches, in order.
+fn group_branches(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    commits_map: &CommitsMap,
+    opts: &app::Options,
+) -> Vec<Vec<usize>> {
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let mut parents: Vec<usize> = (0..opts.branches.len()).collect();
+    let mut owners: HashMap<git2::Oid, usize> = HashMap::new();
+    for (i, branch) in opts.branches.iter().enumerate() {
+        // a branch that cannot be synchronized fails on its own
+        let pending =
+            sync::pending_commits(repo, tags, branch, commits_map, opts).unwrap_or_default();
+        for oid in pending {
+            match owners.get(&oid) {
+                Some(&j) => {
+                    let (ri, rj) = (root(&mut parents, i), root(&mut parents, j));
+                    parents[ri.max(rj)] = ri.min(rj);
+                }
+                None => {
+                    owners.insert(oid, i);
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of_root = HashMap::new();
+    for i in 0..opts.branches.len() {
+        let r = root(&mut parents, i);
+        let group = *group_of_root.entry(r).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(i);
+    }
+    groups
+}
+
+/// Synchronize the branches of a group, stopping on the first failure
+///
+/// The branches after a failed one are not synchronized, as they depend on its commits.
+fn sync_group<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    commits_map: &mut CommitsMap<'a>,
+    group: &[usize],
+    opts: &app::Options,
+) -> Vec<(usize, Result<BranchSync, Error>)> {
+    let mut results = Vec::new();
+    for &i in group {
+        let mut processed = Vec::new();
+        let nb_entries = commits_map.added_entries().len();
+        let branch = &opts.branches[i];
+        match sync::sync_branch_with_remote(repo, tags, branch, commits_map, &mut processed, opts) {
+            Ok(nb_copied) => {
+                let sync = BranchSync {
+                    nb_copied,
+                    cache_entries: commits_map.added_entries()[nb_entries..].to_vec(),
+                    entries: commits_map.entries_of(processed.iter().copied()),
+                    processed,
+                };
+                results.push((i, Ok(sync)));
+            }
+            Err(err) => {
+                results.push((i, Err(err)));
+                break;
+            }
+        }
+    }
+    results
+}
+
+/// Synchronize a group in a thread, on its own handle on the repository
+fn sync_group_in_thread(
+    path: &std::path::Path,
+    shared_map: &SharedMap,
+    group: &[usize],
+    tags: &tag::Tags,
+    opts: &app::Options,
+) -> Vec<(usize, Result<BranchSync, Error>)> {
+    let res = git2::Repository::open(path)
+        .map_err(Error::from)
+        .and_then(|repo| {
+            let mut commits_map = shared_map.open(&repo, tags)?;
+            Ok(sync_group(&repo, tags, &mut commits_map, group, opts))
+        });
+    match res {
+        Ok(results) => results,
+        Err(err) => vec![(group[0], Err(err))],
+    }
+}
+
+/// Synchronize the branches, up to `jobs` of them at a time
+///
+/// The results are listed in the order of the branches, a branch that was not
+/// synchronized because of the failure of a branch of its group having no result. The
+/// mappings made by the other threads are added to the commits map.
+pub fn sync_branches<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    commits_map: &mut CommitsMap<'a>,
+    opts: &app::Options,
+) -> Result<Vec<Option<Result<BranchSync, Error>>>, Error> {
+    let (parallel, sequential): (Vec<_>, Vec<_>) = group_branches(repo, tags, commits_map, opts)
+        .into_iter()
+        .partition(|group| {
+            group
+                .iter()
+                .all(|&i| sync::keeps_workdir(repo, &opts.branches[i], opts))
+        });
+    let mut results: Vec<Option<Result<BranchSync, Error>>> =
+        opts.branches.iter().map(|_| None).collect();
+
+    // the mappings made so far must be on disk, and are shared with the threads
+    commits_map.flush();
+    let shared_map = commits_map.share();
+    let path = repo.workdir().unwrap_or_else(|| repo.path()).to_path_buf();
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..opts.jobs.min(parallel.len()) {
+            let sender = sender.clone();
+            let (path, shared_map, next, parallel) = (&path, &shared_map, &next, &parallel);
+            scope.spawn(move || {
+                while let Some(group) = parallel.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    // the messages of a group are printed together, once it is synchronized
+                    let res =
+                        log::buffered(|| sync_group_in_thread(path, shared_map, group, tags, opts));
+                    if sender.send(res).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        for (group_results, messages) in receiver {
+            log::replay(messages);
+            for (i, res) in group_results {
+                results[i] = Some(res);
+            }
+        }
+    });
+    for sync in results.iter().flatten().flatten() {
+        commits_map.add_entries(repo, &sync.entries)?;
+    }
+
+    // the branches using the working directory are synchronized once the others are done
+    for group in &sequential {
+        for (i, res) in sync_group(repo, tags, commits_map, group, opts) {
+            results[i] = Some(res);
+        }
+    }
+    Ok(results)
+}
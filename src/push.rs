@@ -0,0 +1,85 @@
+use crate::app;
+use crate::auth;
+use crate::error::Error;
+use crate::info;
+use crate::log;
+use crate::notes;
+
+/// Push a synchronized branch to its push remote, if any
+///
+/// The branch is pushed under the same ref name, and is never forced: the remote branch
+/// must only be updated by ripit. The notes containing the ripit tags are pushed with it,
+/// unless the tags are only recorded in the messages.
+pub fn push_branch(
+    repo: &git2::Repository,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let remote_name = match &branch.push_remote {
+        Some(remote_name) => remote_name,
+        None => return Ok(()),
+    };
+    let mut remote = repo.find_remote(remote_name)?;
+    let local_id = repo.refname_to_id(&branch.refname)?;
+
+    // the remote-tracking branch is updated by the push, it gives the tip of the branch in
+    // the push remote as of the last push or fetch
+    let old_id = branch.refname.strip_prefix("refs/heads/").and_then(|name| {
+        repo.refname_to_id(&format!("refs/remotes/{}/{}", remote_name, name))
+            .ok()
+    });
+    if old_id == Some(local_id) {
+        info!(
+            "Branch {} is up to date in {}.",
+            log::branch(&branch.name),
+            remote_name
+        );
+        return Ok(());
+    }
+    if !opts.yes {
+        let question = format!("Push branch {} to {}?", branch.name, remote_name);
+        match (opts.confirm)(&question)? {
+            app::Answer::Yes => (),
+            app::Answer::No => return Ok(()),
+            app::Answer::Abort => return Err(Error::ConfirmAborted { question }),
+        }
+    }
+
+    let mut rejection = None;
+    {
+        let mut callbacks = auth::remote_callbacks(opts);
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(reason) = status {
+                rejection = Some((refname.to_owned(), reason.to_owned()));
+            }
+            Ok(())
+        });
+        let mut push_opts = git2::PushOptions::new();
+        push_opts.remote_callbacks(callbacks);
+
+        let mut refspecs = vec![format!("{}:{}", branch.refname, branch.refname)];
+        // the notes are never published with the cache-only provenance
+        if matches!(
+            opts.provenance,
+            app::Provenance::Notes | app::Provenance::Both
+        ) && repo.find_reference(notes::NOTES_REF).is_ok()
+        {
+            refspecs.push(format!("{}:{}", notes::NOTES_REF, notes::NOTES_REF));
+        }
+        remote
+            .push(&refspecs, Some(&mut push_opts))
+            .map_err(|err| auth::convert_error(err, remote_name))?;
+    }
+    if let Some((refname, reason)) = rejection {
+        return Err(Error::PushRejected { refname, reason });
+    }
+
+    info!(
+        "Pushed branch {} to {}: {} -> {}.",
+        log::branch(&branch.name),
+        remote_name,
+        old_id.map_or_else(|| "(unknown)".to_owned(), |id| id.to_string()),
+        local_id
+    );
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use crate::app;
+use crate::commits_map::CommitsMap;
+use crate::error::Error;
+use crate::sync;
+use crate::tag;
+use crate::{info, warn};
+
+/// Namespace in which the tags of the remote are fetched
+pub fn tracking_namespace(opts: &app::Options) -> String {
+    format!("refs/ripit/remotes/{}/tags/", opts.remote)
+}
+
+/// Refspec fetching the tags of the remote in their namespace
+pub fn refspec(opts: &app::Options) -> String {
+    format!("+refs/tags/*:{}*", tracking_namespace(opts))
+}
+
+/// Whether the tags of the remote must be fetched
+///
+/// They are needed to synchronize them, and to find the revisions given with --until, --at
+/// or --adopt.
+pub fn are_fetched(opts: &app::Options) -> bool {
+    opts.sync_tags
+        || opts.until.is_some()
+        || opts.adopt.is_some()
+        || opts.branches.iter().any(|b| b.until.is_some())
+}
+
+/// Create the local tags of the remote tags reachable from the synchronized branch
+///
+/// The tags are created on the copies of their targets, with the annotation filtered like
+/// the commit messages. Tags whose target was not synchronized are skipped, and existing
+/// tags are left untouched.
+pub fn sync_tags(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    commits_map: &CommitsMap,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let tip = sync::find_sync_target(repo, branch, opts)?.id();
+    let namespace = tracking_namespace(opts);
+
+    for reference in repo.references_glob(&format!("{}*", namespace))? {
+        let reference = reference?;
+        let name = match reference.name().and_then(|n| n.strip_prefix(&namespace)) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let target = match reference.peel_to_commit() {
+            Ok(commit) => commit,
+            Err(_) => {
+                warn!("tag {} does not point to a commit, it is skipped.", name);
+                continue;
+            }
+        };
+        if target.id() != tip && !repo.graph_descendant_of(tip, target.id())? {
+            continue;
+        }
+        let local_ci = match commits_map.get(target.id()) {
+            Some(synced) => &synced.commit,
+            None => {
+                warn!(
+                    "commit {} of tag {} was not synchronized, the tag is skipped.",
+                    target.id(),
+                    name
+                );
+                continue;
+            }
+        };
+
+        let refname = format!("refs/tags/{}", name);
+        if let Ok(existing) = repo.find_reference(&refname) {
+            if existing.peel_to_commit()?.id() != local_ci.id() {
+                warn!(
+                    "tag {} already exists on another commit, it is not updated.",
+                    name
+                );
+            }
+            continue;
+        }
+
+        match reference.peel_to_tag() {
+            Ok(remote_tag) => {
+                let tagger = match remote_tag.tagger() {
+                    Some(tagger) => opts.authors.map_signature(&tagger)?,
+                    None => repo.signature()?,
+                };
+                let msg = sync::filter_commit_msg(remote_tag.message().unwrap_or(""), tags, opts)?;
+                repo.tag(&name, local_ci.as_object(), &tagger, &msg, false)?;
+            }
+            Err(_) => {
+                repo.tag_lightweight(&name, local_ci.as_object(), false)?;
+            }
+        }
+        info!("Created tag {} on commit {}.", name, local_ci.id());
+    }
+    Ok(())
+}
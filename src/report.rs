@@ -0,0 +1,131 @@
+use crate::app;
+use crate::autoresolve::Resolution;
+use crate::commits_map::CommitsMap;
+use serde::{Serialize, Serializer};
+
+fn serialize_oid<S: Serializer>(oid: &git2::Oid, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(oid)
+}
+
+fn serialize_opt_oid<S: Serializer>(
+    oid: &Option<git2::Oid>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match oid {
+        Some(oid) => serializer.collect_str(oid),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Result of the synchronization of a remote commit
+#[derive(Serialize)]
+pub struct CommitReport {
+    #[serde(serialize_with = "serialize_oid")]
+    pub remote_oid: git2::Oid,
+    /// Copy of the commit, or the local commit containing its changes if it was folded
+    #[serde(serialize_with = "serialize_opt_oid")]
+    pub local_oid: Option<git2::Oid>,
+    pub summary: String,
+    pub uprooted: bool,
+    pub skipped: bool,
+    /// Conflicting paths resolved automatically by the conflict_strategy option
+    pub auto_resolved: Vec<Resolution>,
+}
+
+/// Result of the synchronization of a branch
+#[derive(Serialize)]
+pub struct BranchReport {
+    pub branch: String,
+    #[serde(serialize_with = "serialize_opt_oid")]
+    pub remote_tip_before: Option<git2::Oid>,
+    #[serde(serialize_with = "serialize_opt_oid")]
+    pub remote_tip_after: Option<git2::Oid>,
+    #[serde(serialize_with = "serialize_opt_oid")]
+    pub local_tip_before: Option<git2::Oid>,
+    #[serde(serialize_with = "serialize_opt_oid")]
+    pub local_tip_after: Option<git2::Oid>,
+    /// Remote commits handled by the synchronization, in order
+    pub commits: Vec<CommitReport>,
+}
+
+/// Summary of a synchronization, printed as JSON at the end of the run with `--json`
+#[derive(Default, Serialize)]
+pub struct RunReport {
+    pub branches: Vec<BranchReport>,
+    /// Branches whose push failed, which does not prevent the other ones from being pushed
+    #[serde(skip)]
+    pub push_failures: Vec<String>,
+}
+
+/// Tips of the remote-tracking and local refs of a branch
+pub struct Tips {
+    remote: Option<git2::Oid>,
+    local: Option<git2::Oid>,
+}
+
+impl Tips {
+    pub fn read(repo: &git2::Repository, branch: &app::Branch) -> Self {
+        Self {
+            remote: repo.refname_to_id(&branch.tracking_refname).ok(),
+            local: repo.refname_to_id(&branch.refname).ok(),
+        }
+    }
+}
+
+impl RunReport {
+    /// Add the result of the synchronization of a branch
+    ///
+    /// `processed` lists the remote commits handled by the synchronization, in order.
+    pub fn add_branch(
+        &mut self,
+        repo: &git2::Repository,
+        branch: &app::Branch,
+        before: &Tips,
+        processed: &[git2::Oid],
+        commits_map: &CommitsMap,
+    ) {
+        let after = Tips::read(repo, branch);
+        let commits = processed
+            .iter()
+            .map(|&oid| {
+                let skipped = commits_map.skip_rule(oid).is_some();
+                let synced = commits_map.get(oid).filter(|_| !skipped);
+                CommitReport {
+                    remote_oid: oid,
+                    local_oid: synced.map(|synced| synced.commit.id()),
+                    summary: repo
+                        .find_commit(oid)
+                        .ok()
+                        .and_then(|ci| ci.summary().map(str::to_owned))
+                        .unwrap_or_default(),
+                    uprooted: synced.is_some_and(|synced| synced.uprooted),
+                    skipped,
+                    auto_resolved: commits_map.resolutions(oid).to_vec(),
+                }
+            })
+            .collect();
+
+        self.branches.push(BranchReport {
+            branch: branch.name.clone(),
+            remote_tip_before: before.remote,
+            remote_tip_after: after.remote,
+            local_tip_before: before.local,
+            local_tip_after: after.local,
+            commits,
+        });
+    }
+
+    /// Returns whether no branch was changed, nor had commits to synchronize
+    pub fn is_unchanged(&self) -> bool {
+        self.push_failures.is_empty()
+            && self.branches.iter().all(|branch| {
+                branch.commits.is_empty() && branch.local_tip_before == branch.local_tip_after
+            })
+    }
+
+    /// Print the summary as JSON on stdout
+    pub fn print(&self) {
+        // serialization of these types cannot fail
+        println!("{}", serde_json::to_string_pretty(self).unwrap());
+    }
+}
@@ -0,0 +1,335 @@
+//! Reuse of the resolutions of conflicts recorded by previous synchronizations
+//!
+//! Like `git rerere`, the conflicting hunks of the files are hashed when a synchronization
+//! stops on conflicts, and the files resolved by the user are recorded once it continues.
+//! The later conflicts with the same hunks are resolved with the recorded resolutions.
+//!
+//! The resolutions are stored in the `ripit/rr-cache` directory of the git directory, in a
+//! directory per hash containing the conflicting file, `preimage`, and its resolution,
+//! `postimage`. The files in conflict when the synchronization stopped are listed in the
+//! `ripit/MERGE_RR` file.
+use crate::autoresolve;
+use crate::util;
+use crate::{error, info};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// bits of the flags of an index entry containing its stage
+const STAGE_MASK: u16 = 0x3000;
+
+fn store_path(repo: &git2::Repository) -> PathBuf {
+    util::common_dir(repo).join("ripit").join("rr-cache")
+}
+
+fn pending_path(repo: &git2::Repository) -> PathBuf {
+    repo.path().join("ripit").join("MERGE_RR")
+}
+
+/// File containing conflict markers, split around its conflicting hunks
+///
+/// `texts` has one more element than `hunks`, the hunks being between the texts.
+struct Conflicted<'a> {
+    texts: Vec<&'a str>,
+    hunks: Vec<&'a str>,
+}
+
+impl<'a> Conflicted<'a> {
+    /// Split a file around its conflicting hunks, None if it has none
+    fn parse(content: &'a str) -> Option<Self> {
+        let mut texts = Vec::new();
+        let mut hunks = Vec::new();
+        let mut start = 0;
+        let mut hunk_start = None;
+        let mut pos = 0;
+        for line in content.split_inclusive('\n') {
+            match hunk_start {
+                None if line.starts_with("<<<<<<<") => {
+                    texts.push(&content[start..pos]);
+                    hunk_start = Some(pos);
+                }
+                Some(hunk) if line.starts_with(">>>>>>>") => {
+                    hunks.push(&content[hunk..(pos + line.len())]);
+                    start = pos + line.len();
+                    hunk_start = None;
+                }
+                _ => (),
+            }
+            pos += line.len();
+        }
+        if hunks.is_empty() || hunk_start.is_some() {
+            return None;
+        }
+        texts.push(&content[start..]);
+        Some(Self { texts, hunks })
+    }
+
+    /// Hash of the conflicting hunks, without the labels of their markers
+    fn hash(&self) -> String {
+        let mut normalized = String::new();
+        for hunk in &self.hunks {
+            for line in hunk.split_inclusive('\n') {
+                match ["<<<<<<<", "|||||||", ">>>>>>>"]
+                    .iter()
+                    .find(|marker| line.starts_with(*marker))
+                {
+                    Some(marker) => {
+                        normalized.push_str(marker);
+                        normalized.push('\n');
+                    }
+                    None => normalized.push_str(line),
+                }
+            }
+        }
+        // hashing a buffer cannot fail
+        git2::Oid::hash_object(git2::ObjectType::Blob, normalized.as_bytes())
+            .unwrap()
+            .to_string()
+    }
+
+    /// Resolve the hunks with the resolution of a file having the same hunks
+    ///
+    /// The resolution of each hunk is found between the texts around it in `postimage`, so
+    /// the texts of the files may differ. None if the resolution cannot be split that way.
+    fn resolve(&self, preimage: &Conflicted, postimage: &str) -> Option<String> {
+        let nb_hunks = preimage.hunks.len();
+        if self.hunks.len() != nb_hunks {
+            return None;
+        }
+        let mut rest = postimage.strip_prefix(preimage.texts[0])?;
+        let mut resolved = self.texts[0].to_owned();
+        for i in 1..nb_hunks {
+            let end = rest.find(preimage.texts[i])?;
+            resolved.push_str(&rest[..end]);
+            resolved.push_str(self.texts[i]);
+            rest = &rest[(end + preimage.texts[i].len())..];
+        }
+        resolved.push_str(rest.strip_suffix(preimage.texts[nb_hunks])?);
+        resolved.push_str(self.texts[nb_hunks]);
+        Some(resolved)
+    }
+}
+
+/// Contents of the conflicting files of an index, with conflict markers
+///
+/// The files are checked out in a temporary directory of the store, so that the working
+/// directory is left untouched. The files that are not text are omitted.
+pub fn conflicted_files(
+    repo: &git2::Repository,
+    index: &mut git2::Index,
+    paths: &[String],
+) -> Result<Vec<(String, String)>, git2::Error> {
+    let dir = store_path(repo).join("tmp");
+    // leftovers of an interrupted run
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).map_err(|err| git2::Error::from_str(&err.to_string()))?;
+
+    let mut checkout_opts = git2::build::CheckoutBuilder::new();
+    checkout_opts
+        .force()
+        .target_dir(&dir)
+        .update_index(false)
+        .conflict_style_merge(true);
+    for path in paths {
+        checkout_opts.path(path);
+    }
+    repo.checkout_index(Some(index), Some(&mut checkout_opts))?;
+
+    let contents = paths
+        .iter()
+        .filter_map(|path| {
+            let content = std::fs::read_to_string(dir.join(path)).ok()?;
+            Some((path.clone(), content))
+        })
+        .collect();
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(contents)
+}
+
+/// Conflicts of an index modifying the file on both sides, with the local version
+fn both_modified(index: &git2::Index) -> Result<Vec<(String, git2::IndexEntry)>, git2::Error> {
+    let mut conflicts = Vec::new();
+    for conflict in index.conflicts()? {
+        if let git2::IndexConflict {
+            our: Some(our),
+            their: Some(_),
+            ..
+        } = conflict?
+        {
+            conflicts.push((String::from_utf8_lossy(&our.path).into_owned(), our));
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Find the recorded resolution of a conflicting file
+///
+/// The resolution is marked as used, so that it is not pruned.
+fn lookup(repo: &git2::Repository, content: &str) -> Option<String> {
+    let conflicted = Conflicted::parse(content)?;
+    let dir = store_path(repo).join(conflicted.hash());
+    let postimage_path = dir.join("postimage");
+    let postimage = std::fs::read_to_string(&postimage_path).ok()?;
+    let preimage = std::fs::read_to_string(dir.join("preimage")).ok()?;
+
+    // the resolution may also change the texts around the hunks, it is then only reused
+    // for the same file
+    let resolved = if content == preimage {
+        postimage
+    } else {
+        conflicted.resolve(&Conflicted::parse(&preimage)?, &postimage)?
+    };
+    if let Ok(file) = std::fs::File::options().append(true).open(&postimage_path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    Some(resolved)
+}
+
+/// Resolve the conflicts of an index whose resolution was recorded
+///
+/// Returns the resolved paths, the conflicts of the other paths being left in the index.
+pub fn resolve(
+    repo: &git2::Repository,
+    index: &mut git2::Index,
+) -> Result<Vec<String>, git2::Error> {
+    if !index.has_conflicts() || !store_path(repo).is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut conflicts: HashMap<String, git2::IndexEntry> =
+        both_modified(index)?.into_iter().collect();
+    let paths: Vec<String> = conflicts.keys().cloned().collect();
+
+    let mut resolved = Vec::new();
+    for (path, content) in conflicted_files(repo, index, &paths)? {
+        let resolution = match lookup(repo, &content) {
+            Some(resolution) => resolution,
+            None => continue,
+        };
+        // the mode of the local version is kept
+        let mut entry = conflicts.remove(&path).unwrap();
+        entry.id = repo.blob(resolution.as_bytes())?;
+        entry.file_size = resolution.len() as u32;
+        entry.flags &= !STAGE_MASK;
+        index.remove_path(Path::new(&path))?;
+        index.add(&entry)?;
+        info!(
+            "Resolved the conflicts of {} with the recorded resolution.",
+            path
+        );
+        resolved.push(path);
+    }
+    Ok(resolved)
+}
+
+/// Resolve the conflicts of the index of the repository, updating the working directory
+pub fn resolve_in_workdir(repo: &git2::Repository) -> Result<Vec<String>, git2::Error> {
+    let mut index = repo.index()?;
+    let resolved = resolve(repo, &mut index)?;
+    autoresolve::checkout_resolved(repo, &mut index, &resolved)?;
+    Ok(resolved)
+}
+
+/// Record the conflicts of the index, whose resolutions are saved once committed
+///
+/// The resolutions unused for `max_age` days are pruned. Failures are only logged, the
+/// conflicts being solved by the user anyway.
+pub fn save_preimages(repo: &git2::Repository, max_age: u64) {
+    let res = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut index = repo.index()?;
+        let paths: Vec<String> = both_modified(&index)?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        let mut pending = String::new();
+        for (path, content) in conflicted_files(repo, &mut index, &paths)? {
+            if let Some(conflicted) = Conflicted::parse(&content) {
+                let hash = conflicted.hash();
+                let dir = store_path(repo).join(&hash);
+                std::fs::create_dir_all(&dir)?;
+                std::fs::write(dir.join("preimage"), &content)?;
+                pending.push_str(&format!("{}\t{}\n", hash, path));
+            }
+        }
+        if !pending.is_empty() {
+            std::fs::write(pending_path(repo), pending)?;
+        }
+        prune(repo, max_age)?;
+        Ok(())
+    })();
+    if let Err(err) = res {
+        error!("Cannot save the conflicts for later resolutions: {}", err);
+    }
+}
+
+/// Record the resolutions of the conflicts saved when the synchronization stopped
+///
+/// `tree` is the tree of the commit containing the resolutions.
+pub fn record_resolutions(repo: &git2::Repository, tree: &git2::Tree) {
+    let path = pending_path(repo);
+    let pending = match std::fs::read_to_string(&path) {
+        Ok(pending) => pending,
+        Err(_) => return,
+    };
+    for (hash, file) in pending.lines().filter_map(|line| line.split_once('\t')) {
+        // a deleted file is not a resolution that can be reused
+        let blob = match tree
+            .get_path(Path::new(file))
+            .and_then(|entry| repo.find_blob(entry.id()))
+        {
+            Ok(blob) => blob,
+            Err(_) => continue,
+        };
+        let postimage = store_path(repo).join(hash).join("postimage");
+        match std::fs::write(&postimage, blob.content()) {
+            Ok(()) => info!("Recorded the resolution of the conflicts of {}.", file),
+            Err(err) => error!("Cannot record the resolution of {}: {}", file, err),
+        }
+    }
+    forget_preimages(repo);
+}
+
+/// Forget the conflicts saved when the synchronization stopped, as it was not continued
+pub fn forget_preimages(repo: &git2::Repository) {
+    let path = pending_path(repo);
+    if let Err(err) = std::fs::remove_file(&path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            error!("Cannot remove {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Remove the resolutions unused for `max_age` days, and the conflicts never resolved
+fn prune(repo: &git2::Repository, max_age: u64) -> Result<(), std::io::Error> {
+    let max_age = Duration::from_secs(max_age * 24 * 3600);
+    let now = SystemTime::now();
+    for entry in std::fs::read_dir(store_path(repo))? {
+        let dir = entry?.path();
+        let last_used = ["postimage", "preimage"]
+            .iter()
+            .find_map(|name| dir.join(name).metadata().ok()?.modified().ok());
+        let stale = last_used
+            .and_then(|last_used| now.duration_since(last_used).ok())
+            .is_some_and(|age| age > max_age);
+        if stale {
+            std::fs::remove_dir_all(&dir)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_reuse_resolution() {
+    let preimage = "a\n<<<<<<< ours\nb\n=======\nc\n>>>>>>> theirs\nd\n";
+    let postimage = "a\nbc\nd\n";
+    let pre = Conflicted::parse(preimage).unwrap();
+
+    // the texts around the hunks may change, not the labels of the markers
+    let content = "a2\n<<<<<<< HEAD\nb\n=======\nc\n>>>>>>> commit\nd\ne\n";
+    let cur = Conflicted::parse(content).unwrap();
+    assert_eq!(cur.hash(), pre.hash());
+    assert_eq!(cur.resolve(&pre, postimage).unwrap(), "a2\nbc\nd\ne\n");
+
+    let other = Conflicted::parse("<<<<<<< ours\nb\n=======\nc2\n>>>>>>> theirs\n").unwrap();
+    assert_ne!(other.hash(), pre.hash());
+    assert!(Conflicted::parse("a\n<<<<<<< ours\nb\n").is_none());
+}
@@ -0,0 +1,195 @@
+//! Records of the synchronizations, with the `record_runs` option
+//!
+//! Each successful run creates a reference under `refs/ripit/runs/`, named after its date,
+//! pointing to a blob containing its summary in JSON. With `tag_runs`, the reference is an
+//! annotated tag of the new tip of the first synchronized branch instead, the summary being
+//! its message. The records older than `runs_retention_days` are pruned after each run.
+use crate::app;
+use crate::error::Error;
+use crate::report::RunReport;
+use crate::util;
+use crate::{info, outln};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// prefix of the references of the records
+pub const RUNS_REF_PREFIX: &str = "refs/ripit/runs/";
+
+const REFLOG_MSG: &str = "ripit: record the synchronization";
+
+/// Remote commit synchronized by a run, and its local copy
+#[derive(Serialize, Deserialize)]
+struct CommitPair {
+    remote: String,
+    local: String,
+}
+
+/// Changes made on a branch by a run
+#[derive(Serialize, Deserialize)]
+struct BranchRecord {
+    branch: String,
+    remote_tip: Option<String>,
+    local_tip_before: Option<String>,
+    local_tip_after: Option<String>,
+    commits: Vec<CommitPair>,
+}
+
+/// Summary of a run, recorded in its reference
+#[derive(Serialize, Deserialize)]
+struct RunRecord {
+    // seconds since the epoch
+    date: u64,
+    remote: String,
+    remote_url: String,
+    branches: Vec<BranchRecord>,
+}
+
+impl RunRecord {
+    /// Build the record of a run from its report, with the branches it changed
+    fn new(repo: &git2::Repository, report: &RunReport, opts: &app::Options) -> Self {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let remote = repo.find_remote(&opts.remote).ok();
+        let remote_url = remote
+            .as_ref()
+            .and_then(|remote| remote.url())
+            .or_else(|| opts.remote_urls.first().map(String::as_str))
+            .unwrap_or("");
+        let branches = report
+            .branches
+            .iter()
+            .filter(|branch| branch.local_tip_before != branch.local_tip_after)
+            .map(|branch| BranchRecord {
+                branch: branch.branch.clone(),
+                remote_tip: branch.remote_tip_after.map(|id| id.to_string()),
+                local_tip_before: branch.local_tip_before.map(|id| id.to_string()),
+                local_tip_after: branch.local_tip_after.map(|id| id.to_string()),
+                commits: branch
+                    .commits
+                    .iter()
+                    .filter(|commit| !commit.skipped)
+                    .filter_map(|commit| {
+                        Some(CommitPair {
+                            remote: commit.remote_oid.to_string(),
+                            local: commit.local_oid?.to_string(),
+                        })
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            date,
+            remote: opts.remote.clone(),
+            remote_url: remote_url.to_owned(),
+            branches,
+        }
+    }
+}
+
+/// Record a successful run, if it changed any branch, and prune the old records
+pub fn record(
+    repo: &git2::Repository,
+    report: &RunReport,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let record = RunRecord::new(repo, report, opts);
+    let target = match record
+        .branches
+        .first()
+        .and_then(|branch| branch.local_tip_after.as_deref())
+    {
+        Some(target) => git2::Oid::from_str(target)?,
+        None => return Ok(()),
+    };
+    // serialization of these types cannot fail
+    let summary = serde_json::to_string_pretty(&record).unwrap();
+
+    // runs may happen in the same second
+    let mut refname = format!("{}{}", RUNS_REF_PREFIX, record.date);
+    let mut n = 1;
+    while repo.find_reference(&refname).is_ok() {
+        n += 1;
+        refname = format!("{}{}-{}", RUNS_REF_PREFIX, record.date, n);
+    }
+
+    let id = if opts.tag_runs {
+        let target = repo.find_object(target, None)?;
+        let name = refname.trim_start_matches("refs/");
+        repo.tag_annotation_create(name, &target, &repo.signature()?, &summary)?
+    } else {
+        repo.blob(summary.as_bytes())?
+    };
+    repo.reference(&refname, id, false, REFLOG_MSG)?;
+    info!("Recorded the synchronization in {}.", refname);
+
+    if let Some(days) = opts.runs_retention_days {
+        prune(repo, record.date.saturating_sub(days * 24 * 3600))?;
+    }
+    Ok(())
+}
+
+/// Read the records of the runs, newest first
+fn read_records(repo: &git2::Repository) -> Result<Vec<(String, RunRecord)>, Error> {
+    let mut records = Vec::new();
+    for reference in repo.references_glob(&format!("{}*", RUNS_REF_PREFIX))? {
+        let reference = reference?;
+        let refname = reference.name().unwrap_or("").to_owned();
+        let object = reference.peel(git2::ObjectType::Any)?;
+        let content = match (reference.peel_to_tag(), object.as_blob()) {
+            (Ok(tag), _) => tag.message().unwrap_or("").to_owned(),
+            (_, Some(blob)) => String::from_utf8_lossy(blob.content()).into_owned(),
+            _ => String::new(),
+        };
+        let record: RunRecord =
+            serde_json::from_str(&content).map_err(|err| Error::InvalidRunRecord {
+                refname: refname.clone(),
+                reason: err.to_string(),
+            })?;
+        records.push((refname, record));
+    }
+    records.sort_by(|(a_name, a), (b_name, b)| (b.date, b_name).cmp(&(a.date, a_name)));
+    Ok(records)
+}
+
+/// Delete the records of the runs older than a date
+fn prune(repo: &git2::Repository, before: u64) -> Result<(), Error> {
+    let mut nb_pruned = 0;
+    for (refname, record) in read_records(repo)? {
+        if record.date < before {
+            repo.find_reference(&refname)?.delete()?;
+            nb_pruned += 1;
+        }
+    }
+    if nb_pruned > 0 {
+        info!("Pruned {} records of old synchronizations.", nb_pruned);
+    }
+    Ok(())
+}
+
+/// Print the records of the runs, newest first
+pub fn list(repo: &git2::Repository) -> Result<(), Error> {
+    for (refname, record) in read_records(repo)? {
+        outln!(
+            "{} on {} from {} {}",
+            refname.trim_start_matches(RUNS_REF_PREFIX),
+            util::format_date(&git2::Time::new(record.date as i64, 0)),
+            record.remote,
+            record.remote_url
+        );
+        for branch in &record.branches {
+            outln!(
+                "  {}: {}..{}, remote tip {}",
+                branch.branch,
+                branch.local_tip_before.as_deref().unwrap_or("(none)"),
+                branch.local_tip_after.as_deref().unwrap_or("(none)"),
+                branch.remote_tip.as_deref().unwrap_or("(none)")
+            );
+            for pair in &branch.commits {
+                outln!("    {} -> {}", pair.remote, pair.local);
+            }
+        }
+    }
+    Ok(())
+}
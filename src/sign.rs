@@ -0,0 +1,152 @@
+use crate::app;
+use crate::error::Error;
+use crate::tag;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Run the signing program on the content of a commit, and return its signature
+fn sign_buffer(signing: &app::Signing, buffer: &str) -> Result<String, Error> {
+    let failed = |reason: String| Error::SigningFailed {
+        key: signing.key.clone(),
+        reason,
+    };
+
+    let mut cmd = Command::new(&signing.program);
+    match (signing.format, &signing.key) {
+        (app::SigningFormat::OpenPgp, Some(key)) => cmd.args(["--status-fd=2", "-bsau", key]),
+        (app::SigningFormat::OpenPgp, None) => cmd.args(["--status-fd=2", "-bsa"]),
+        // the data to sign is read on stdin, and its signature written on stdout
+        (app::SigningFormat::Ssh, Some(key)) => cmd.args(["-Y", "sign", "-n", "git", "-f", key]),
+        (app::SigningFormat::Ssh, None) => return Err(failed("no SSH key configured".to_owned())),
+    };
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| failed(format!("cannot run {}: {}", signing.program, err)))?;
+
+    // Write the content from another thread, so that the program cannot block on a full
+    // stdout pipe.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = buffer.as_bytes().to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output();
+    let _ = writer.join();
+    let output = output.map_err(|err| failed(err.to_string()))?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(failed(format!(
+            "{}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+    String::from_utf8(output.stdout).map_err(|_| failed("invalid signature".to_owned()))
+}
+
+/// Point a reference on a commit, following symbolic references such as HEAD
+fn update_reference(
+    repo: &git2::Repository,
+    refname: &str,
+    oid: git2::Oid,
+    message: &str,
+) -> Result<(), git2::Error> {
+    let mut refname = refname.to_owned();
+    while let Ok(reference) = repo.find_reference(&refname) {
+        match reference.symbolic_target() {
+            Some(target) => refname = target.to_owned(),
+            None => break,
+        }
+    }
+    let summary = message.lines().next().unwrap_or("");
+    repo.reference(&refname, oid, true, &format!("commit: {}", summary))?;
+    Ok(())
+}
+
+/// Creator of the commits, signing them if asked for in the options
+pub struct Committer<'a> {
+    repo: &'a git2::Repository,
+    tags: &'a tag::Tags,
+    signing: Option<&'a app::Signing>,
+    provenance: app::Provenance,
+}
+
+impl<'a> Committer<'a> {
+    pub fn new(repo: &'a git2::Repository, tags: &'a tag::Tags, opts: &'a app::Options) -> Self {
+        Self {
+            repo,
+            tags,
+            signing: opts.signing.as_ref(),
+            provenance: opts.provenance,
+        }
+    }
+
+    /// Create a commit, signed if asked for in the options
+    ///
+    /// As with `Repository::commit`, the given reference is updated to point on the new
+    /// commit. The ripit tags of the message are moved to the note of the commit, or copied
+    /// there, depending on the provenance of the options.
+    pub fn commit(
+        &self,
+        update_ref: Option<&str>,
+        author: &git2::Signature,
+        committer: &git2::Signature,
+        message: &str,
+        tree: &git2::Tree,
+        parents: &[&git2::Commit],
+    ) -> Result<git2::Oid, Error> {
+        let (stripped, note) = match self.provenance {
+            app::Provenance::Trailers | app::Provenance::Hmac => (None, String::new()),
+            app::Provenance::Notes | app::Provenance::CacheOnly => {
+                let (stripped, note) = self.tags.split_tags(message);
+                (Some(stripped), note)
+            }
+            app::Provenance::Both => (None, self.tags.split_tags(message).1),
+        };
+        let message = stripped.as_deref().unwrap_or(message);
+
+        let oid = self.create(update_ref, author, committer, message, tree, parents)?;
+        if !note.is_empty() {
+            self.tags.notes().write(self.repo, committer, oid, &note)?;
+        }
+        Ok(oid)
+    }
+
+    /// Create a commit, signing it if asked for in the options
+    fn create(
+        &self,
+        update_ref: Option<&str>,
+        author: &git2::Signature,
+        committer: &git2::Signature,
+        message: &str,
+        tree: &git2::Tree,
+        parents: &[&git2::Commit],
+    ) -> Result<git2::Oid, Error> {
+        let repo = self.repo;
+        let signing = match self.signing {
+            Some(signing) => signing,
+            None => return Ok(repo.commit(update_ref, author, committer, message, tree, parents)?),
+        };
+
+        let buffer = repo.commit_create_buffer(author, committer, message, tree, parents)?;
+        let buffer = match std::str::from_utf8(&buffer) {
+            Ok(buffer) => buffer,
+            Err(_) => {
+                return Err(Error::SigningFailed {
+                    key: signing.key.clone(),
+                    reason: "the author or committer is not valid UTF-8".to_owned(),
+                })
+            }
+        };
+        let signature = sign_buffer(signing, buffer)?;
+        let oid = repo.commit_signed(buffer, &signature, None)?;
+
+        if let Some(refname) = update_ref {
+            update_reference(repo, refname, oid, message)?;
+        }
+        Ok(oid)
+    }
+}
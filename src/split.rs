@@ -0,0 +1,90 @@
+use crate::app;
+use crate::error::Error;
+use std::path::{Path, PathBuf};
+
+// name of the group containing the paths not matched by any rule
+const DEFAULT_GROUP: &str = "other";
+
+/// Part of a commit, containing the changes of a group of paths
+pub struct Part {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Group the changes between two trees according to the split rules
+///
+/// Groups are returned in the order of the rules, followed by the group of paths matched
+/// by no rule. Groups without changes are omitted.
+pub fn split_changes(
+    repo: &git2::Repository,
+    old_tree: &git2::Tree,
+    new_tree: &git2::Tree,
+    rules: &[app::SplitRule],
+) -> Result<Vec<Part>, Error> {
+    let diff = repo.diff_tree_to_tree(Some(old_tree), Some(new_tree), None)?;
+
+    let mut parts: Vec<Part> = rules
+        .iter()
+        .map(|rule| Part {
+            name: rule.name.clone(),
+            paths: Vec::new(),
+        })
+        .chain(std::iter::once(Part {
+            name: DEFAULT_GROUP.to_owned(),
+            paths: Vec::new(),
+        }))
+        .collect();
+
+    for delta in diff.deltas() {
+        let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+            Some(path) => path,
+            None => continue,
+        };
+        let group = rules
+            .iter()
+            .position(|rule| rule.paths.iter().any(|prefix| path.starts_with(prefix)))
+            .unwrap_or(rules.len());
+        parts[group].paths.push(path.to_path_buf());
+    }
+
+    parts.retain(|part| !part.paths.is_empty());
+    Ok(parts)
+}
+
+/// Build a tree containing the base tree, with the given paths taken from the full tree
+pub fn build_partial_tree<'a>(
+    repo: &'a git2::Repository,
+    base: &git2::Tree,
+    full: &git2::Tree,
+    paths: &[PathBuf],
+) -> Result<git2::Tree<'a>, Error> {
+    let mut index = git2::Index::new()?;
+    index.read_tree(base)?;
+
+    for path in paths {
+        match full.get_path(path) {
+            Ok(entry) => index.add(&index_entry(path, &entry))?,
+            Err(_) => index.remove_path(path)?,
+        }
+    }
+
+    let tree_oid = index.write_tree_to(repo)?;
+    Ok(repo.find_tree(tree_oid)?)
+}
+
+fn index_entry(path: &Path, entry: &git2::TreeEntry) -> git2::IndexEntry {
+    git2::IndexEntry {
+        ctime: git2::IndexTime::new(0, 0),
+        mtime: git2::IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode: entry.filemode() as u32,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id: entry.id(),
+        flags: 0,
+        flags_extended: 0,
+        path: path.to_string_lossy().into_owned().into_bytes(),
+    }
+}
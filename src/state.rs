@@ -0,0 +1,134 @@
+use crate::error;
+use crate::error::Error;
+use std::path::PathBuf;
+
+/// State of a synchronization stopped on conflicts
+///
+/// It is saved in the git directory of the working directory containing the conflicts, so
+/// that the synchronization can be aborted or resumed by a later run. The file lists the
+/// fields, one per line, followed by an empty line and the message. The local commit on
+/// which the copy is made is missing from the files of older versions.
+///
+/// The export of local commits to the remote stops on conflicts the same way: the state
+/// then holds the local commit being exported, and the commit of the remote it is copied
+/// on.
+pub struct SyncState {
+    // remote commit whose copy caused the conflicts, or local commit when exporting
+    pub remote_id: git2::Oid,
+    // full ref name of the local branch being synchronized
+    pub refname: String,
+    // whether the copy is uprooted
+    pub uprooted: bool,
+    // local commit on which the copy is made
+    pub head: Option<git2::Oid>,
+    // message of the copy, filtered and tagged
+    pub message: String,
+    // whether a local commit is exported to the remote
+    pub export: bool,
+}
+
+impl SyncState {
+    /// Error telling that the synchronization, or the export, is stopped on conflicts
+    pub fn stopped(self) -> Error {
+        if self.export {
+            Error::ExportStopped {
+                commit_id: self.remote_id,
+                refname: self.refname,
+            }
+        } else {
+            Error::SyncStopped {
+                commit_id: self.remote_id,
+                refname: self.refname,
+            }
+        }
+    }
+}
+
+/// Path to the state file
+pub fn state_path(repo: &git2::Repository) -> PathBuf {
+    repo.path().join("ripit").join("STATE")
+}
+
+/// Save the state of a synchronization stopped on conflicts
+pub fn write(repo: &git2::Repository, state: &SyncState) -> Result<(), std::io::Error> {
+    let path = state_path(repo);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let mut head = match state.head {
+        Some(head) => format!("head {}\n", head),
+        None => String::new(),
+    };
+    if state.export {
+        head.push_str("export true\n");
+    }
+    std::fs::write(
+        &path,
+        format!(
+            "commit {}\nbranch {}\nuprooted {}\n{}\n{}",
+            state.remote_id, state.refname, state.uprooted, head, state.message
+        ),
+    )
+}
+
+/// Read the state of the synchronization stopped on conflicts, if any
+pub fn read(repo: &git2::Repository) -> Result<Option<SyncState>, Error> {
+    let path = state_path(repo);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(Error::InvalidState {
+                path,
+                reason: err.to_string(),
+            })
+        }
+    };
+    let invalid_state = |reason: &str| Error::InvalidState {
+        path: path.clone(),
+        reason: reason.to_owned(),
+    };
+
+    let (fields, message) = content.split_once("\n\n").unwrap_or((content.as_str(), ""));
+    let mut remote_id = None;
+    let mut refname = None;
+    let mut uprooted = false;
+    let mut head = None;
+    let mut export = false;
+    for line in fields.lines() {
+        match line.split_once(' ') {
+            Some(("commit", id)) => {
+                remote_id = Some(git2::Oid::from_str(id).map_err(|_| invalid_state(line))?)
+            }
+            Some(("branch", name)) => refname = Some(name.to_owned()),
+            Some(("uprooted", value)) => {
+                uprooted = value.parse().map_err(|_| invalid_state(line))?
+            }
+            Some(("head", id)) => {
+                head = Some(git2::Oid::from_str(id).map_err(|_| invalid_state(line))?)
+            }
+            Some(("export", value)) => export = value.parse().map_err(|_| invalid_state(line))?,
+            _ => return Err(invalid_state(line)),
+        }
+    }
+
+    match (remote_id, refname) {
+        (Some(remote_id), Some(refname)) => Ok(Some(SyncState {
+            remote_id,
+            refname,
+            uprooted,
+            head,
+            message: message.to_owned(),
+            export,
+        })),
+        _ => Err(invalid_state("missing commit or branch")),
+    }
+}
+
+/// Remove the state file, once the synchronization is no longer stopped
+pub fn remove(repo: &git2::Repository) {
+    let path = state_path(repo);
+    if let Err(err) = std::fs::remove_file(&path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            error!("Cannot remove {}: {}", path.display(), err);
+        }
+    }
+}
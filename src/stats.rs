@@ -0,0 +1,188 @@
+use crate::app;
+use crate::error::Error;
+use crate::tag;
+use crate::util;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Default, Serialize)]
+struct Counters {
+    // number of synchronized commits, including uprooted ones
+    synced: u32,
+    // number of uprooted commits
+    uprooted: u32,
+    // number of commits created by the user after resolving conflicts
+    conflicts: u32,
+}
+
+impl Counters {
+    fn add(&mut self, uprooted: bool, conflict: bool) {
+        self.synced += 1;
+        if uprooted {
+            self.uprooted += 1;
+        }
+        if conflict {
+            self.conflicts += 1;
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Group<'a> {
+    key: &'a str,
+    #[serde(flatten)]
+    counters: &'a Counters,
+}
+
+#[derive(Default)]
+struct Stats {
+    months: BTreeMap<String, Counters>,
+    authors: BTreeMap<String, Counters>,
+    total: Counters,
+}
+
+/// Returns whether the commit was created by the user after a conflict
+///
+/// When copying a commit, ripit keeps the committer of the original commit. A different
+/// committer thus means the commit was done by the user when resolving conflicts.
+fn is_conflict_resolution(repo: &git2::Repository, commit: &git2::Commit, remote_id: &str) -> bool {
+    let remote_commit = match git2::Oid::from_str(remote_id).and_then(|oid| repo.find_commit(oid)) {
+        Ok(ci) => ci,
+        Err(_) => return false,
+    };
+    let (local, remote) = (commit.committer(), remote_commit.committer());
+
+    local.name_bytes() != remote.name_bytes() || local.email_bytes() != remote.email_bytes()
+}
+
+/// Gather statistics on the synchronized commits of every configured branch
+fn compute_stats(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &app::Options,
+    since: Option<i64>,
+) -> Result<Stats, Error> {
+    let mut stats = Stats::default();
+    let mut visited = HashSet::new();
+
+    for branch in &opts.branches {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_ref(&branch.refname)?;
+
+        for oid in revwalk {
+            let oid = oid?;
+            // commits shared between branches are only counted once
+            if !visited.insert(oid) {
+                continue;
+            }
+
+            let commit = repo.find_commit(oid)?;
+            if let Some(since) = since {
+                if commit.committer().when().seconds() < since {
+                    continue;
+                }
+            }
+            let (remote_id, uprooted) = match tags.retrieve_ripit_tag(&commit) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            // the parts of a split commit are counted once, with the last one
+            if tags.is_bootstrap(&commit) || tags.is_partial(&commit) {
+                continue;
+            }
+
+            let conflict = is_conflict_resolution(repo, &commit, &remote_id);
+            let (year, month) = util::year_month(&commit.committer().when());
+
+            stats
+                .months
+                .entry(format!("{:04}-{:02}", year, month))
+                .or_default()
+                .add(uprooted, conflict);
+            stats
+                .authors
+                .entry(commit.author().to_string())
+                .or_default()
+                .add(uprooted, conflict);
+            stats.total.add(uprooted, conflict);
+        }
+    }
+
+    Ok(stats)
+}
+
+fn print_table(title: &str, groups: &BTreeMap<String, Counters>) {
+    let width = groups
+        .keys()
+        .map(|key| key.chars().count())
+        .chain(std::iter::once(title.len()))
+        .max()
+        .unwrap_or(0);
+
+    println!(
+        "{:width$}  {:>8}  {:>8}  {:>9}",
+        title,
+        "Synced",
+        "Uprooted",
+        "Conflicts",
+        width = width
+    );
+    for (key, counters) in groups {
+        println!(
+            "{:width$}  {:>8}  {:>8}  {:>9}",
+            key,
+            counters.synced,
+            counters.uprooted,
+            counters.conflicts,
+            width = width
+        );
+    }
+}
+
+fn to_groups(map: &BTreeMap<String, Counters>) -> Vec<Group<'_>> {
+    map.iter()
+        .map(|(key, counters)| Group { key, counters })
+        .collect()
+}
+
+fn print_json(stats: &Stats) {
+    #[derive(Serialize)]
+    struct JsonStats<'a> {
+        months: Vec<Group<'a>>,
+        authors: Vec<Group<'a>>,
+        total: &'a Counters,
+    }
+
+    let json = JsonStats {
+        months: to_groups(&stats.months),
+        authors: to_groups(&stats.authors),
+        total: &stats.total,
+    };
+    // serialization of these types cannot fail
+    println!("{}", serde_json::to_string_pretty(&json).unwrap());
+}
+
+/// Print statistics about the synchronized commits
+pub fn print_stats(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &app::Options,
+    since: Option<i64>,
+    json: bool,
+) -> Result<(), Error> {
+    let stats = compute_stats(repo, tags, opts, since)?;
+
+    if json {
+        print_json(&stats);
+    } else {
+        print_table("Month", &stats.months);
+        println!();
+        print_table("Author", &stats.authors);
+        println!(
+            "\nTotal: {} commits synchronized, {} uprooted, {} after conflicts.",
+            stats.total.synced, stats.total.uprooted, stats.total.conflicts
+        );
+    }
+    Ok(())
+}
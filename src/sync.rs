@@ -1,429 +1,2756 @@
 use crate::app;
+use crate::auth;
+use crate::autoresolve;
+use crate::autosquash;
 use crate::commits_map::{CommitsMap, SyncedCommit};
-use crate::error::Error;
+use crate::editor;
+use crate::error::{Error, Operation};
+use crate::exclude;
+use crate::gate;
+use crate::hooks;
+use crate::keep_refs::{self, KeepRefs};
+use crate::log;
+use crate::mac;
+use crate::merge_drivers;
+use crate::mergetool;
+use crate::msg_filter;
+use crate::remote_tags;
+use crate::rerere;
+use crate::sign;
+use crate::split;
+use crate::state;
 use crate::tag;
 use crate::util;
-use std::io::Write;
+use crate::{debug, error, info, out, outln, warn};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal, Write};
 use std::path::Path;
 
+// name of the rule recorded for commits whose edited message was emptied
+const EMPTY_MESSAGE_RULE: &str = "empty message";
+// rule recorded for the commits whose message matches the skip_commits option
+const SKIP_COMMITS_RULE: &str = "skip_commits";
+// rule recorded for the commits listed in the skip_shas option
+const SKIP_SHAS_RULE: &str = "skip_shas";
+// rule recorded for the commits only changing excluded paths
+const EXCLUDED_PATHS_RULE: &str = "excluded paths";
+// rule recorded for the commits skipped with --skip after conflicts
+pub const CONFLICTS_SKIP_RULE: &str = "conflicts";
+// rule recorded for the commits of a rewritten remote branch already synchronized
+const PATCH_ID_RULE: &str = "patch-id";
+
+// message of the reflog entries of the synchronized branches
+const REFLOG_MSG: &str = "ripit: synchronize with remote";
+
 // {{{ Fetch remote
 
-pub fn update_remote(repo: &git2::Repository, opts: &app::Options) -> Result<(), git2::Error> {
-    let mut remote = repo.find_remote(&opts.remote)?;
+/// Returns whether a fetch error is caused by an unreachable remote
+///
+/// Authentication errors, HTTP errors and missing repositories are not: the server
+/// answered, and the other mirrors would fail the same way.
+fn is_connection_error(err: &git2::Error) -> bool {
+    use git2::ErrorClass;
 
-    for branch in &opts.branches {
-        if opts.verbose {
-            println!("Fetch branch {} in remote {}...", branch.name, opts.remote);
-        }
-        if let Err(e) = remote.fetch(&[&branch.name], None, None) {
-            eprintln!(
-                "Fetch failed. Consider running `git fetch {}` \
-                      yourself, and use the -F option to avoid the fetch \
-                      in ripit.",
-                opts.remote
+    if err.code() == git2::ErrorCode::Auth || err.code() == git2::ErrorCode::NotFound {
+        return false;
+    }
+    match err.class() {
+        // libgit2 reports missing local repositories and invalid URLs in those classes
+        ErrorClass::Os | ErrorClass::Net => {
+            !err.message().starts_with("failed to resolve path")
+                && !err.message().starts_with("unsupported URL protocol")
+        }
+        ErrorClass::Ssh | ErrorClass::Ssl => true,
+        _ => false,
+    }
+}
+
+/// Fetch refspecs from a remote, reporting the progress of the transfer
+///
+/// The credentials are provided by the auth callbacks. The progress is only shown with
+/// the informational messages, when stderr is a terminal. The messages of the remote are
+/// kept in any case, as they explain the failures of the fetch better than libgit2 errors.
+///
+/// When the tags are synchronized, they are fetched in their own namespace, and must not
+/// be created in the local tags namespace by the automatic tag following.
+fn fetch_refspecs(
+    remote: &mut git2::Remote,
+    refspecs: &[String],
+    opts: &app::Options,
+) -> Result<(), git2::Error> {
+    let show_progress = log::enabled(log::Level::Info) && std::io::stderr().is_terminal();
+    let remote_msg = RefCell::new(String::new());
+    let shown = Cell::new(false);
+
+    let mut callbacks = auth::remote_callbacks(opts);
+    callbacks.sideband_progress(|data| {
+        let text = String::from_utf8_lossy(data);
+        if show_progress {
+            if let Some(line) = text.split(['\r', '\n']).rfind(|l| !l.is_empty()) {
+                eprint!("\r\x1b[Kremote: {}", line);
+                shown.set(true);
+            }
+        }
+        remote_msg.borrow_mut().push_str(&text);
+        true
+    });
+    if show_progress {
+        callbacks.transfer_progress(|stats| {
+            eprint!(
+                "\r\x1b[KReceiving objects: {}/{}, {} KiB",
+                stats.received_objects(),
+                stats.total_objects(),
+                stats.received_bytes() / 1024
             );
-            return Err(e);
+            shown.set(true);
+            true
+        });
+    }
+
+    let mut fetch_opts = git2::FetchOptions::new();
+    fetch_opts.remote_callbacks(callbacks);
+    // the tags are only followed when fetching the whole remote
+    if remote_tags::are_fetched(opts) || !opts.fetch_all {
+        fetch_opts.download_tags(git2::AutotagOption::None);
+    }
+    let res = remote.fetch(refspecs, Some(&mut fetch_opts), None);
+
+    if shown.get() {
+        eprintln!();
+    }
+    res.map_err(|err| {
+        let remote_msg = remote_msg.borrow();
+        match remote_msg
+            .split(['\r', '\n'])
+            .map(str::trim)
+            .rfind(|l| !l.is_empty())
+        {
+            Some(line) => git2::Error::new(
+                err.code(),
+                err.class(),
+                format!("{} ({})", line, err.message()),
+            ),
+            None => err,
+        }
+    })
+}
+
+/// Build the refspecs fetching the branches to synchronize, and the tags if needed
+///
+/// With the fetch_all option, the refspecs configured for the remote are fetched as well.
+/// The refspecs of the branches are still given, as the configured ones may not update
+/// their remote-tracking refs.
+fn build_refspecs(
+    repo: &git2::Repository,
+    opts: &app::Options,
+) -> Result<Vec<String>, git2::Error> {
+    let mut refspecs = Vec::new();
+    if opts.fetch_all {
+        // the mirrors use the remote-tracking refs of the remote, which may not exist
+        if let Ok(remote) = repo.find_remote(&opts.remote) {
+            for refspec in remote.fetch_refspecs()?.iter().flatten() {
+                refspecs.push(refspec.to_owned());
+            }
+        }
+    }
+    refspecs.extend(
+        opts.branches
+            .iter()
+            .map(|branch| format!("+{}:{}", branch.remote_refname, branch.tracking_refname)),
+    );
+    if remote_tags::are_fetched(opts) {
+        refspecs.push(remote_tags::refspec(opts));
+    }
+    Ok(refspecs)
+}
+
+/// Fetch the branches from the first reachable mirror of the remote
+fn fetch_from_mirrors(repo: &git2::Repository, opts: &app::Options) -> Result<(), git2::Error> {
+    let refspecs = build_refspecs(repo, opts)?;
+
+    let mut last_err = None;
+    for url in &opts.remote_urls {
+        info!("Fetch branches from mirror {}...", url);
+        let mut remote = repo.remote_anonymous(url)?;
+        match fetch_refspecs(&mut remote, &refspecs, opts) {
+            Ok(()) => {
+                info!("Fetched from mirror {}.", url);
+                return Ok(());
+            }
+            Err(err) if is_connection_error(&err) => {
+                warn!("cannot reach mirror {}: {}", url, err.message());
+                last_err = Some(err);
+            }
+            Err(err) => {
+                last_err = Some(err);
+                break;
+            }
+        }
+    }
+    error!(
+        "Fetch failed. Consider fetching the commits in the remote-tracking \
+         branches of {} yourself, and use the -F option to avoid the fetch in ripit.",
+        opts.remote
+    );
+    // the list of mirrors is not empty
+    Err(last_err.unwrap())
+}
+
+/// List the names of the branches of the remote, or of its first reachable mirror
+pub fn list_remote_branches(
+    repo: &git2::Repository,
+    opts: &app::Options,
+) -> Result<Vec<String>, Error> {
+    setup_remote(repo, opts)?;
+    let mut remotes = Vec::new();
+    if opts.remote_urls.is_empty() {
+        match repo.find_remote(&opts.remote) {
+            Ok(remote) => remotes.push(remote),
+            Err(_) => {
+                return Err(Error::UnknownRemote {
+                    remote: opts.remote.clone(),
+                })
+            }
+        }
+    } else {
+        for url in &opts.remote_urls {
+            remotes.push(repo.remote_anonymous(url)?);
+        }
+    }
+
+    let mut last_err = None;
+    for mut remote in remotes {
+        let callbacks = auth::remote_callbacks(opts);
+        match remote.connect_auth(git2::Direction::Fetch, Some(callbacks), None) {
+            Ok(connection) => {
+                let mut names = Vec::new();
+                for head in connection.list()? {
+                    if let Some(name) = head.name().strip_prefix("refs/heads/") {
+                        names.push(name.to_owned());
+                    }
+                }
+                return Ok(names);
+            }
+            Err(err) if is_connection_error(&err) => last_err = Some(err),
+            Err(err) => {
+                last_err = Some(err);
+                break;
+            }
+        }
+    }
+    // there is at least one remote
+    Err(match auth::convert_error(last_err.unwrap(), &opts.remote) {
+        Error::Git(err) => Error::FetchFailed {
+            remote: opts.remote.clone(),
+            reason: err.message().to_owned(),
+        },
+        err => err,
+    })
+}
+
+/// List the names of the remote-tracking branches of the remote
+pub fn list_tracking_branches(
+    repo: &git2::Repository,
+    opts: &app::Options,
+) -> Result<Vec<String>, Error> {
+    let prefix = format!("refs/remotes/{}/", opts.remote);
+    let mut names = Vec::new();
+    for reference in repo.references_glob(&format!("{}*", prefix))? {
+        let reference = reference?;
+        match reference.name().and_then(|name| name.strip_prefix(&prefix)) {
+            Some("HEAD") | None => (),
+            Some(name) => names.push(name.to_owned()),
+        }
+    }
+    Ok(names)
+}
+
+/// Fetch the last commits of the branches to synchronize
+///
+/// The branches missing from the remote after the fetch are reported as such, instead of
+/// failing later on their lookup.
+pub fn update_remote(repo: &git2::Repository, opts: &app::Options) -> Result<(), Error> {
+    fetch_branches(repo, opts)?;
+
+    for branch in &opts.branches {
+        if repo.find_reference(&branch.tracking_refname).is_err() {
+            return Err(Error::UnknownRemoteBranch {
+                branch: branch.remote_name.clone(),
+                remote: opts.remote.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Create the remote with its configured url if missing, or update its url if it differs
+///
+/// Updating the url of an existing remote is confirmed, unless `yes` is set.
+fn setup_remote(repo: &git2::Repository, opts: &app::Options) -> Result<(), Error> {
+    let url = match &opts.remote_url {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+    let remote = match repo.find_remote(&opts.remote) {
+        Ok(remote) => remote,
+        Err(_) => {
+            repo.remote(&opts.remote, url)?;
+            info!("Created remote {} with url {}.", opts.remote, url);
+            return Ok(());
+        }
+    };
+    let current_url = remote.url().unwrap_or("");
+    if current_url == url {
+        return Ok(());
+    }
+
+    if !opts.yes {
+        let question = format!(
+            "Update the url of remote {} from {} to {}?",
+            opts.remote, current_url, url
+        );
+        match (opts.confirm)(&question)? {
+            app::Answer::Yes => (),
+            app::Answer::No => {
+                warn!("keeping the url {} of remote {}", current_url, opts.remote);
+                return Ok(());
+            }
+            app::Answer::Abort => return Err(Error::ConfirmAborted { question }),
+        }
+    }
+    repo.remote_set_url(&opts.remote, url)?;
+    info!("Updated the url of remote {} to {}.", opts.remote, url);
+    Ok(())
+}
+
+/// Fetch the branches to synchronize, missing ones being silently ignored
+///
+/// The remote is created first if its url is configured. Failing to reach it is reported
+/// as such, distinctly from a missing remote.
+pub fn fetch_branches(repo: &git2::Repository, opts: &app::Options) -> Result<(), Error> {
+    setup_remote(repo, opts)?;
+    // the mirrors are fetched with anonymous remotes
+    if opts.remote_urls.is_empty() && repo.find_remote(&opts.remote).is_err() {
+        return Err(Error::UnknownRemote {
+            remote: opts.remote.clone(),
+        });
+    }
+    fetch_remote(repo, opts).map_err(|err| match auth::convert_error(err, &opts.remote) {
+        // the parents of the commits at the boundary of a shallow clone are missing
+        Error::Git(err) if err.code() == git2::ErrorCode::NotFound => {
+            match find_shallow_remote(repo, opts) {
+                Some(url) => Error::ShallowRemote {
+                    remote: opts.remote.clone(),
+                    url,
+                },
+                None => Error::FetchFailed {
+                    remote: opts.remote.clone(),
+                    reason: err.message().to_owned(),
+                },
+            }
         }
+        Error::Git(err) => Error::FetchFailed {
+            remote: opts.remote.clone(),
+            reason: err.message().to_owned(),
+        },
+        err => err,
+    })
+}
+
+/// Url of the remote, or of one of its mirrors, which is a shallow clone
+///
+/// Only the remotes in the local filesystem can be checked.
+fn find_shallow_remote(repo: &git2::Repository, opts: &app::Options) -> Option<String> {
+    let urls = if opts.remote_urls.is_empty() {
+        vec![repo.find_remote(&opts.remote).ok()?.url()?.to_owned()]
+    } else {
+        opts.remote_urls.clone()
+    };
+    urls.into_iter().find(|url| {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        git2::Repository::open(path).is_ok_and(|remote| remote.is_shallow())
+    })
+}
+
+/// Make sure the local repository has the whole history of the remote
+///
+/// The commits at the boundary of a shallow clone have no parents, and ripit would take
+/// them for root commits, or fail to find the commits it synchronized before them.
+pub fn check_not_shallow(repo: &git2::Repository, opts: &app::Options) -> Result<(), Error> {
+    if repo.is_shallow() {
+        return Err(Error::ShallowRepository {
+            path: opts.repo.clone(),
+            remote: opts.remote.clone(),
+        });
+    }
+    Ok(())
+}
+
+fn fetch_remote(repo: &git2::Repository, opts: &app::Options) -> Result<(), git2::Error> {
+    if !opts.remote_urls.is_empty() {
+        return fetch_from_mirrors(repo, opts);
+    }
+    let mut remote = repo.find_remote(&opts.remote)?;
+    let refspecs = build_refspecs(repo, opts)?;
+
+    if opts.fetch_all {
+        info!("Fetch remote {}...", opts.remote);
+    } else {
+        let names: Vec<String> = opts
+            .branches
+            .iter()
+            .map(|b| log::branch(&b.name).to_string())
+            .collect();
+        info!(
+            "Fetch {} {} in remote {}...",
+            if names.len() > 1 {
+                "branches"
+            } else {
+                "branch"
+            },
+            names.join(", "),
+            opts.remote
+        );
+    }
+    if let Err(e) = fetch_refspecs(&mut remote, &refspecs, opts) {
+        error!(
+            "Fetch failed. Consider running `git fetch {}` \
+                  yourself, and use the -F option to avoid the fetch \
+                  in ripit.",
+            opts.remote
+        );
+        return Err(e);
     }
     Ok(())
 }
 
 // }}}
 // {{{ Find commits to sync */
-/// Build a revwalk to iterate from a commit (excluded), up to the branch's last commit
-fn build_revwalk<'a>(
-    repo: &'a git2::Repository,
+/// List the commits from a commit (excluded), up to the branch's last commit
+///
+/// The walk does not go past the commits already synchronized with their parents: their
+/// ancestors are synchronized as well, or precede the bootstrap. The uprooted commits and
+/// the bootstrap commits are walked through, as their ancestors may not be synchronized.
+/// Without this, the whole history of the remote would be walked when the synchronization
+/// does not start from the last remote commit, e.g. when merges are synchronized.
+///
+/// If linearized, only the first parents of the merges are followed.
+fn walk_commits(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
     commit: &git2::Commit,
     branch: &git2::Object,
-) -> Result<git2::Revwalk<'a>, git2::Error> {
+    commits_map: &CommitsMap,
+    linearize: bool,
+) -> Result<Vec<git2::Oid>, git2::Error> {
     let mut revwalk = repo.revwalk()?;
     revwalk
         .set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)
         .unwrap();
+    if linearize {
+        revwalk.simplify_first_parent()?;
+    }
     revwalk.push(branch.id())?;
     revwalk.hide(commit.id())?;
-    Ok(revwalk)
+
+    let is_known = |oid| {
+        commits_map
+            .get(oid)
+            .is_some_and(|synced| !synced.uprooted && !tags.is_bootstrap(&synced.commit))
+    };
+    let revwalk = revwalk.with_hide_callback(&is_known)?;
+    revwalk.collect()
 }
 
-/// Build a list of the commits to synchronize
+/// Find the commits brought by the merges of a list, and not synchronized yet
 ///
-/// In most situations, the commits to synchronize are simply the difference set
-/// between the local repo (up to local_commit) and the remote one
-/// (up to remote_commit). This is trivially buildable with a revwalk.
+/// When linearizing the history, those are folded in the copies of the merges.
+fn find_merged_commits(
+    repo: &git2::Repository,
+    commits: &[git2::Commit],
+    commits_map: &CommitsMap,
+) -> Result<HashMap<git2::Oid, Vec<git2::Oid>>, git2::Error> {
+    let mut merged = HashMap::new();
+    for ci in commits.iter().filter(|ci| ci.parent_count() > 1) {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+        for parent_id in ci.parent_ids().skip(1) {
+            revwalk.push(parent_id)?;
+        }
+        revwalk.hide(ci.parent_id(0)?)?;
+
+        let mut ids = Vec::new();
+        for oid in revwalk {
+            let oid = oid?;
+            if !commits_map.contains_key(oid) {
+                ids.push(oid);
+            }
+        }
+        merged.insert(ci.id(), ids);
+    }
+    Ok(merged)
+}
+
+/// Returns whether a commit is the merge grouping the commits copied by a run
 ///
-/// However, if we are in the process of syncing unknown commits, and the
-/// local head contains uprooted commits, we must:
-/// * rewind to the last non-uprooted commit, so that a sensical revwalk
-///   can be built.
-/// * ignore the already uprooted commits from the revwalk.
-fn find_commits_to_sync<'a>(
+/// Created with the group_runs option, it has no ripit tag, and its second parent is the
+/// last commit copied by the run.
+fn is_run_group(commit: &git2::Commit, tags: &tag::Tags) -> bool {
+    commit.parent_count() == 2
+        && tags.retrieve_ripit_tag(commit).is_none()
+        && commit
+            .parent(1)
+            .is_ok_and(|parent| tags.retrieve_ripit_tag(&parent).is_some())
+}
+
+/// Walk backwards from a local commit, until a commit with a ripit tag is found
+///
+/// Commits without ripit tags were committed locally, their ids are returned, with the
+/// last synced commit. The merges grouping the commits copied by a run are walked through
+/// their second parent.
+pub fn find_last_synced<'a>(
     repo: &'a git2::Repository,
+    tags: &tag::Tags,
     local_commit: git2::Oid,
-    remote_commit: &git2::Object,
-    commits_map: &CommitsMap,
-    opts: &app::Options,
-) -> Result<Vec<git2::Commit<'a>>, Error> {
+) -> Result<(git2::Commit<'a>, Vec<git2::Oid>), Error> {
     let mut start = local_commit;
+    let mut local_commits = Vec::new();
+
+    loop {
+        let ci = repo.find_commit(start)?;
+        if tags.retrieve_ripit_tag(&ci).is_some() {
+            return Ok((ci, local_commits));
+        }
+        if ci.parent_count() == 0 {
+            return Err(tags.notes().tag_missing());
+        }
+        if is_run_group(&ci, tags) {
+            start = ci.parent_id(1)?;
+            continue;
+        }
+        local_commits.push(ci.id());
+        start = ci.parent_id(0)?;
+    }
+}
+
+/// Find the remote commit from which the synchronization starts
+///
+/// This is the remote commit of the last synced commit, ignoring uprooted commits. The
+/// local commits made on top of the last synced commit are returned as well.
+pub fn find_sync_start<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    local_commit: git2::Oid,
+) -> Result<(git2::Commit<'a>, Vec<git2::Oid>), Error> {
+    let (mut ci, local_commits) = find_last_synced(repo, tags, local_commit)?;
     let mut last_tag;
     let mut cnt = 0;
 
     // walk backwards until a non-uprooted commit is reached
     loop {
-        let ci = repo.find_commit(start)?;
-        let (tag, uprooted) = tag::retrieve_ripit_tag_or_throw(&ci)?;
+        let (tag, uprooted) = tags.retrieve_ripit_tag_or_throw(&ci)?;
         last_tag = tag;
         if !uprooted {
             // The bootstrap is not uprooted, the loop cannot be infinite
             break;
         }
         cnt += 1;
-        start = ci.parent_id(0)?;
+        // the parent may be the merge grouping the commits of the previous run
+        ci = find_last_synced(repo, tags, ci.parent_id(0)?)?.0;
     }
-    if opts.verbose {
-        if cnt > 0 {
-            println!("Rewinding {} commits to ignore uprooted ones.", cnt);
-        }
-        println!("Found ripit tag, last synced commit was {}.", last_tag);
+    if cnt > 0 {
+        debug!("Rewinding {} commits to ignore uprooted ones.", cnt);
+    }
+    debug!(
+        "Found ripit tag, last synced commit was {}.",
+        log::remote_commit(&last_tag)
+    );
+
+    // the MAC of the hmac provenance matches none of the known remote commits
+    if mac::is_mac(&last_tag) {
+        return Err(Error::UnknownMac {
+            local_id: ci.id(),
+            mac: last_tag,
+        });
     }
 
     // Get the commit related to this SHA-1
     let remote_start = repo.find_commit(git2::Oid::from_str(&last_tag)?)?;
+    Ok((remote_start, local_commits))
+}
 
-    let revwalk = build_revwalk(repo, &remote_start, remote_commit)?;
-    let mut commits = vec![];
-    for oid in revwalk {
-        let oid = oid?;
-        if !commits_map.contains_key(oid) {
-            commits.push(repo.find_commit(oid)?);
-        } else if opts.verbose {
-            println!("Ignoring {}: commit already synchronized.", oid);
+/// Find the remote commit up to which a branch is synchronized
+///
+/// This is the head of the remote branch, unless a revision is given with --until, or with
+/// --at for this branch. It is looked up in the tags and branches of the remote first, and
+/// must be reachable from the head of the remote branch.
+pub fn find_sync_target<'a>(
+    repo: &'a git2::Repository,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<git2::Commit<'a>, Error> {
+    let head = match repo.revparse_single(&branch.tracking_refname) {
+        Ok(head) => head.peel_to_commit()?,
+        // without the fetch, the branch may also never have been fetched
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            return Err(Error::UnknownRemoteBranch {
+                branch: branch.remote_name.clone(),
+                remote: opts.remote.clone(),
+            })
         }
+        Err(err) => return Err(err.into()),
+    };
+    match branch.until.as_ref().or(opts.until.as_ref()) {
+        Some(rev) => resolve_remote_rev(repo, &head, rev, branch, opts),
+        None => Ok(head),
     }
-
-    Ok(commits)
 }
 
-// }}}
-// {{{ Sync branch
+/// Remote commits of a branch that are not synchronized yet
+///
+/// Unlike the commits listed by the synchronization, they are not filtered: this is only
+/// used to find the branches sharing commits to synchronize.
+pub fn pending_commits(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    commits_map: &CommitsMap,
+    opts: &app::Options,
+) -> Result<HashSet<git2::Oid>, Error> {
+    let local_commit = repo.refname_to_id(&branch.refname)?;
+    let remote_target = find_sync_target(repo, branch, opts)?;
+    let (remote_start, _) = find_sync_start(repo, tags, local_commit)?;
+
+    let oids = walk_commits(
+        repo,
+        tags,
+        &remote_start,
+        remote_target.as_object(),
+        commits_map,
+        opts.linearize,
+    )?;
+    Ok(oids
+        .into_iter()
+        .filter(|oid| !commits_map.contains_key(*oid))
+        .collect())
+}
 
-fn force_checkout_head(repo: &git2::Repository) -> Result<(), git2::Error> {
-    let mut opts = git2::build::CheckoutBuilder::new();
-    opts.force();
-    repo.checkout_head(Some(&mut opts))
+/// Make sure every commit id of the skip_shas and replacements options designates a single
+/// remote commit
+///
+/// The commits are matched by prefix when synchronizing, so an ambiguous abbreviation
+/// would skip or replace the wrong commits.
+pub fn check_option_shas(repo: &git2::Repository, opts: &app::Options) -> Result<(), Error> {
+    let odb = repo.odb()?;
+    let shas = opts
+        .skip_shas
+        .iter()
+        .map(|sha| ("skip_shas", sha))
+        .chain(opts.replacements.iter().map(|r| ("replacements", &r.sha)));
+    for (option, sha) in shas {
+        let unknown = || Error::UnknownOptionSha {
+            option,
+            sha: sha.clone(),
+            remote: opts.remote.clone(),
+        };
+        let id = match odb.exists_prefix(git2::Oid::from_str(sha)?, sha.len()) {
+            Ok(id) => id,
+            Err(err) if err.code() == git2::ErrorCode::Ambiguous => {
+                return Err(Error::AmbiguousOptionSha {
+                    option,
+                    sha: sha.clone(),
+                    remote: opts.remote.clone(),
+                })
+            }
+            Err(_) => return Err(unknown()),
+        };
+        repo.find_commit(id).map_err(|_| unknown())?;
+    }
+    Ok(())
 }
 
-fn filter_commit_msg(msg: &str, opts: &app::Options) -> String {
-    if opts.commit_msg_filters.is_empty() {
-        return msg.to_owned();
+/// Returns whether a remote commit is listed in the skip_shas option
+fn is_skipped_sha(commit: &git2::Commit, opts: &app::Options) -> bool {
+    if opts.skip_shas.is_empty() {
+        return false;
     }
+    let id = commit.id().to_string();
+    opts.skip_shas
+        .iter()
+        .any(|sha| id.starts_with(sha.as_str()))
+}
 
-    let new_lines: Vec<&str> = msg
-        .lines()
-        .filter(|line| {
-            if opts.commit_msg_filters.is_match(line) {
-                if opts.verbose {
-                    println!("  Filtering out line '{}'", line);
-                }
-                false
-            } else {
-                true
-            }
-        })
-        .collect();
+/// Replacement of a remote commit, listed in the replacements option
+fn replacement_of<'o>(
+    commit: &git2::Commit,
+    opts: &'o app::Options,
+) -> Option<&'o app::Replacement> {
+    let id = commit.id().to_string();
+    opts.replacements
+        .iter()
+        .find(|replacement| id.starts_with(replacement.sha.as_str()))
+}
 
-    new_lines.join("\n")
+/// Add to the message of a remote commit the note telling it was replaced with a patch
+fn replaced_message(msg: &str, replacement: &app::Replacement) -> String {
+    format!(
+        "{}\n\nReplaced with the local patch {}.\n",
+        msg.trim_end(),
+        replacement.path
+    )
 }
 
-// TODO: use a string builder, to avoid the double alloc
-fn update_commit_msg(orig_msg: &str, tag: &str, opts: &app::Options) -> String {
-    let orig_msg = filter_commit_msg(orig_msg, opts);
-    if orig_msg.ends_with('\n') {
-        format!("{}\n{}\n", orig_msg, tag)
-    } else {
-        format!("{}\n\n{}\n", orig_msg, tag)
+/// Find a revision of the remote, which must be reachable from the head of the remote branch
+///
+/// The revision is looked up in the tags and branches of the remote first.
+fn resolve_remote_rev<'a>(
+    repo: &'a git2::Repository,
+    head: &git2::Commit,
+    rev: &str,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<git2::Commit<'a>, Error> {
+    let candidates = [
+        format!("{}{}", remote_tags::tracking_namespace(opts), rev),
+        format!("refs/remotes/{}/{}", opts.remote, rev),
+    ];
+    let target = candidates
+        .iter()
+        .find_map(|refname| repo.find_reference(refname).ok())
+        .and_then(|reference| reference.peel_to_commit().ok())
+        .or_else(|| repo.revparse_single(rev).ok()?.peel_to_commit().ok())
+        .ok_or_else(|| Error::UnknownUntilRev {
+            rev: rev.to_owned(),
+            remote: opts.remote.clone(),
+        })?;
+
+    if target.id() != head.id() && !repo.graph_descendant_of(head.id(), target.id())? {
+        return Err(Error::UntilNotInBranch {
+            rev: rev.to_owned(),
+            branch: branch.remote_name.clone(),
+            remote: opts.remote.clone(),
+        });
     }
+    Ok(target)
 }
 
-/// Append the tag to .git/MERGE_MSG, if it exists
-fn update_merge_msg(repo: &git2::Repository, tag: &str, opts: &app::Options) {
-    let path = Path::new(repo.path()).join("MERGE_MSG");
-    let msg = match std::fs::read_to_string(&path) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Error when reading the MERGE_MSG file: {}", e);
-            "".to_owned()
+/// Build a list of the commits to synchronize
+///
+/// In most situations, the commits to synchronize are simply the difference set
+/// between the local repo (up to local_commit) and the remote one
+/// (up to remote_commit). This is trivially buildable with a revwalk.
+///
+/// However, if we are in the process of syncing unknown commits, and the
+/// local head contains uprooted commits, we must:
+/// * rewind to the last non-uprooted commit, so that a sensical revwalk
+///   can be built.
+/// * ignore the already uprooted commits from the revwalk.
+///
+/// Commits exported from the local repository are mapped to the local commits they
+/// originate from, and are not synchronized. Any other commit made locally on top of the
+/// last synced commit must have been exported, otherwise it would be lost.
+fn find_commits_to_sync<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    local_commit: git2::Oid,
+    remote_commit: &git2::Object,
+    commits_map: &mut CommitsMap<'a>,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<Vec<git2::Commit<'a>>, Error> {
+    let (remote_start, local_commits) = find_sync_start(repo, tags, local_commit)?;
+    let rewritten = match check_not_rewritten(repo, &remote_start, commits_map, branch, opts) {
+        Err(err @ Error::UpstreamRewritten { .. }) if opts.force_resync => {
+            let vanished = reanchor(repo, local_commit, &remote_start, commits_map, branch)?;
+            Some(vanished.ok_or(err)?)
+        }
+        res => {
+            res?;
+            None
         }
     };
 
-    // TODO: use a string builder
-    let msg = update_commit_msg(&msg, tag, opts);
-
-    if let Ok(mut file) = std::fs::File::create(&path) {
-        if let Err(e) = write!(file, "{}", &msg) {
-            eprintln!("Error when adding rip-it tag to MERGE_MSG: {}", e);
+    let oids = walk_commits(
+        repo,
+        tags,
+        &remote_start,
+        remote_commit,
+        commits_map,
+        opts.linearize,
+    )?;
+    let mut commits = vec![];
+    let mut exported = vec![];
+    for oid in oids {
+        let commit = repo.find_commit(oid)?;
+
+        if commits_map.contains_key(oid) {
+            debug!(
+                "Ignoring {}: commit already synchronized.",
+                log::remote_commit(oid)
+            );
+        } else if let Some(local_ci) = find_exported_commit(repo, tags, &commit) {
+            info!(
+                "Ignoring {}: commit exported from local commit {}.",
+                log::remote_commit(oid),
+                local_ci.id()
+            );
+            exported.push(local_ci.id());
+            commits_map.insert_uncached(
+                oid,
+                SyncedCommit {
+                    commit: local_ci,
+                    uprooted: false,
+                },
+            );
+        } else {
+            commits.push(commit);
         }
     }
-}
 
-/// Fix the context saved in the .git directory, to indicate the commit is a merge
+    if let Some(vanished) = rewritten {
+        let nb_matched = match_patch_ids(repo, &commits, vanished, commits_map)?;
+        info!(
+            "Branch {} was rewritten in the remote {}: {} commits matched by patch-id, {} \
+             new or modified commits to synchronize.",
+            log::branch(&branch.name),
+            opts.remote,
+            nb_matched,
+            commits.len() - nb_matched
+        );
+    }
+
+    // with a prefix, the local commits are expected to change the paths outside of it
+    if !commits.is_empty()
+        && opts.prefix.is_none()
+        && local_commits.iter().any(|id| !exported.contains(id))
+    {
+        return Err(Error::LocalCommitsNotExported {
+            branch: branch.name.clone(),
+            count: local_commits.len(),
+        });
+    }
+
+    Ok(commits)
+}
+
+/// Make sure the remote commit from which the synchronization starts is still in the remote
+/// branch
+///
+/// When the remote branch was rewritten, e.g. rebased, the commits to synchronize cannot be
+/// found. The synchronized commits that are no longer in the branch are listed in the error.
+fn check_not_rewritten(
+    repo: &git2::Repository,
+    remote_start: &git2::Commit,
+    commits_map: &CommitsMap,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let remote_tip = repo.refname_to_id(&branch.tracking_refname)?;
+    if remote_tip == remote_start.id() || repo.graph_descendant_of(remote_tip, remote_start.id())? {
+        return Ok(());
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(remote_start.id())?;
+    revwalk.hide(remote_tip)?;
+    let mut vanished = Vec::new();
+    for oid in revwalk {
+        let oid = oid?;
+        if commits_map.contains_key(oid) {
+            let ci = repo.find_commit(oid)?;
+            vanished.push(format!(
+                "{} {}",
+                &oid.to_string()[..10],
+                ci.summary().unwrap_or("")
+            ));
+        }
+    }
+    Err(Error::UpstreamRewritten {
+        branch: branch.remote_name.clone(),
+        remote: opts.remote.clone(),
+        last_synced: remote_start.id(),
+        vanished,
+    })
+}
+
+/// Re-anchor the synchronization of a rewritten remote branch on its new history
+///
+/// The commit from which the remote branch was rewritten is mapped to the local branch for
+/// this run, so that the commits of the new history are copied on top of it. Returns the
+/// synchronized remote commits that are no longer in the remote branch, or None if the
+/// new history has nothing in common with the synchronized one.
+fn reanchor<'a>(
+    repo: &'a git2::Repository,
+    local_commit: git2::Oid,
+    remote_start: &git2::Commit,
+    commits_map: &mut CommitsMap<'a>,
+    branch: &app::Branch,
+) -> Result<Option<Vec<git2::Oid>>, Error> {
+    let remote_tip = repo.refname_to_id(&branch.tracking_refname)?;
+    let base = match repo.merge_base(remote_start.id(), remote_tip) {
+        Ok(base) => base,
+        Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(remote_start.id())?;
+    revwalk.hide(base)?;
+    let mut vanished = Vec::new();
+    for oid in revwalk {
+        let oid = oid?;
+        if commits_map.contains_key(oid) {
+            vanished.push(oid);
+        }
+    }
+
+    let synced = SyncedCommit {
+        commit: repo.find_commit(local_commit)?,
+        uprooted: false,
+    };
+    commits_map.remap(base, synced);
+    Ok(Some(vanished))
+}
+
+/// Compute the patch-id of a commit, None for the merges
+fn patch_id(repo: &git2::Repository, commit: &git2::Commit) -> Result<Option<git2::Oid>, Error> {
+    let parent_tree = match commit.parent_count() {
+        0 => None,
+        1 => Some(commit.parent(0)?.tree()?),
+        _ => return Ok(None),
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+    Ok(Some(diff.patchid(None)?))
+}
+
+/// Record the commits with the patch-id of a vanished synchronized commit as skipped
+///
+/// Their changes are already in the local branch. Returns the number of matched commits.
+fn match_patch_ids(
+    repo: &git2::Repository,
+    commits: &[git2::Commit],
+    vanished: Vec<git2::Oid>,
+    commits_map: &mut CommitsMap,
+) -> Result<usize, Error> {
+    let mut synced_ids: HashMap<git2::Oid, usize> = HashMap::new();
+    for oid in vanished {
+        // the vanished commits may have been pruned from the repository
+        if let Ok(ci) = repo.find_commit(oid) {
+            if let Some(id) = patch_id(repo, &ci)? {
+                *synced_ids.entry(id).or_default() += 1;
+            }
+        }
+    }
+
+    let mut nb_matched = 0;
+    for ci in commits {
+        let count = match patch_id(repo, ci)? {
+            Some(id) => synced_ids.get_mut(&id),
+            None => None,
+        };
+        if let Some(count) = count.filter(|count| **count > 0) {
+            *count -= 1;
+            commits_map.record_skip(ci.id(), PATCH_ID_RULE);
+            nb_matched += 1;
+        }
+    }
+    Ok(nb_matched)
+}
+
+/// Find the local commit a remote commit was exported from, if any
+pub fn find_exported_commit<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    commit: &git2::Commit,
+) -> Option<git2::Commit<'a>> {
+    let local_id = tags.retrieve_export_tag(commit)?;
+    let local_id = git2::Oid::from_str(&local_id).ok()?;
+
+    repo.find_commit(local_id).ok()
+}
+
+// }}}
+// {{{ Sync branch
+
+/// Returns whether a branch is synchronized without touching the working directory
+///
+/// This is the case when another branch is checked out, unless the working directory is
+/// needed by the pre_commit hook or to solve the conflicts. A bare repository has no
+/// working directory.
+pub fn keeps_workdir(repo: &git2::Repository, branch: &app::Branch, opts: &app::Options) -> bool {
+    repo.is_bare()
+        || (opts.hooks.pre_commit.is_none()
+            && opts.mergetool.is_none()
+            && !opts.detached_worktree
+            && head_is_other_branch(repo, &branch.refname))
+}
+
+/// Make the working directory match HEAD
+///
+/// With a prefix, the paths outside of it are left untouched. Nothing is done in a bare
+/// repository.
+fn force_checkout_head(repo: &git2::Repository, opts: &app::Options) -> Result<(), git2::Error> {
+    if repo.is_bare() {
+        return Ok(());
+    }
+    let mut checkout_opts = git2::build::CheckoutBuilder::new();
+    checkout_opts.force();
+    if let Some(prefix) = &opts.prefix {
+        checkout_opts.path(prefix);
+    }
+    repo.checkout_head(Some(&mut checkout_opts))
+}
+
+/// Filter the message of a remote commit or tag
+///
+/// The message is piped through the message filter command first, if any. The ripit tags
+/// of the remote are then handled, and the lines matching the filters are removed.
+pub fn filter_commit_msg(
+    msg: &str,
+    tags: &tag::Tags,
+    opts: &app::Options,
+) -> Result<String, Error> {
+    let piped;
+    let msg = match &opts.message_filter_cmd {
+        Some(cmd) => {
+            piped = msg_filter::filter_message(cmd, msg)?;
+            piped.as_str()
+        }
+        None => msg,
+    };
+    if opts.commit_msg_filters.is_empty() && opts.foreign_tags == app::ForeignTags::Keep {
+        return Ok(msg.to_owned());
+    }
+
+    let new_lines: Vec<Cow<str>> = msg
+        .lines()
+        .filter_map(|line| {
+            if tags.is_ripit_tag(line) {
+                // tag of the remote commit, pointing to the remote of the remote
+                match opts.foreign_tags {
+                    app::ForeignTags::Keep => (),
+                    app::ForeignTags::Strip => return None,
+                    app::ForeignTags::Neutralize => {
+                        return Some(Cow::Owned(tags.neutralize_ripit_tag(line)))
+                    }
+                }
+            }
+            if opts.commit_msg_filters.is_match(line) {
+                debug!("  Filtering out line '{}'", line);
+                None
+            } else {
+                Some(Cow::Borrowed(line))
+            }
+        })
+        .collect();
+
+    Ok(new_lines.join("\n"))
+}
+
+// TODO: use a string builder, to avoid the double alloc
+fn update_commit_msg(
+    orig_msg: &str,
+    tag: &str,
+    tags: &tag::Tags,
+    opts: &app::Options,
+) -> Result<String, Error> {
+    let orig_msg = filter_commit_msg(orig_msg, tags, opts)?;
+    // the trailers already in the message, such as a sign-off, are not repeated
+    let tag: Vec<&str> = tag
+        .lines()
+        .filter(|line| !orig_msg.lines().any(|orig_line| orig_line == *line))
+        .collect();
+    let tag = tag.join("\n");
+    if orig_msg.ends_with('\n') {
+        Ok(format!("{}\n{}\n", orig_msg, tag))
+    } else {
+        Ok(format!("{}\n\n{}\n", orig_msg, tag))
+    }
+}
+
+/// Format the tags added to the message of the copy of a remote commit
+///
+/// The trailer of the original author, the configured trailers and the sign-off, if any,
+/// precede the tags, so that the ripit tag stays last.
+fn format_tag(
+    commit: &git2::Commit,
+    uprooted: bool,
+    squashed: &[git2::Oid],
+    branch_name: &str,
+    tags: &tag::Tags,
+    opts: &app::Options,
+) -> String {
+    let mut lines = Vec::new();
+    if let Some(trailer) = opts.authors.original_author_trailer(commit) {
+        lines.push(trailer);
+    }
+    for (key, value) in &opts.trailers {
+        let value = value
+            .replace("{remote_oid}", &commit.id().to_string())
+            .replace("{remote}", &opts.remote)
+            .replace("{branch}", branch_name);
+        lines.push(format!("{}: {}", key, value));
+    }
+    if let Some(identity) = &opts.signoff {
+        lines.push(format!(
+            "Signed-off-by: {} <{}>",
+            identity.name, identity.email
+        ));
+    }
+    lines.push(tags.format_squashed_tag(&tags.format_ripit_tag(commit, uprooted), squashed));
+    lines.join("\n")
+}
+
+/// Append the tag to .git/MERGE_MSG, if it exists
+fn update_merge_msg(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    tag: &str,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let path = Path::new(repo.path()).join("MERGE_MSG");
+    let msg = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Error when reading the MERGE_MSG file: {}", e);
+            "".to_owned()
+        }
+    };
+
+    // TODO: use a string builder
+    let mut msg = update_commit_msg(&msg, tag, tags, opts)?;
+    if opts.provenance == app::Provenance::Notes {
+        // the tags are recorded in a note once the user committed, see check_no_stopped_sync
+        msg = tags.split_tags(&msg).0;
+    }
+
+    if let Ok(mut file) = std::fs::File::create(&path) {
+        if let Err(e) = write!(file, "{}", &msg) {
+            error!("Error when adding rip-it tag to MERGE_MSG: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Fix the context saved in the .git directory, to indicate the commit is a merge
 ///
 /// As we use a cherry-pick to bring changes without bringing the whole tree of the uprooted
 /// commit, git saves a cherry-pick context in the .git directory, which means that when the user
 /// commits the change, it will create a merge with a single parent, instead of the proper merge
 /// commit.
 /// To fix this, the context is modified directly in the .git directory. Yes, this is very ugly :/
-fn fix_merge_ctx(repo: &git2::Repository, commit_id: git2::Oid) -> bool {
+fn fix_merge_ctx(repo: &git2::Repository, merged: &[&git2::Commit]) -> bool {
     // Remove CHERRY_PICK_HEAD
     let path = repo.path().join("CHERRY_PICK_HEAD");
     if let Err(err) = std::fs::remove_file(&path) {
-        eprintln!("Cannot remove {}: {}", path.display(), err);
+        error!("Cannot remove {}: {}", path.display(), err);
         return false;
     }
 
-    // Create MERGE_HEAD, containing the id of the commit brought by the merge
+    // Create MERGE_HEAD, containing the ids of the commits brought by the merge, one per
+    // line as for an octopus merge
     let path = repo.path().join("MERGE_HEAD");
     let mut file = match std::fs::File::create(&path) {
         Ok(f) => f,
         Err(err) => {
-            eprintln!("Cannot create {}: {}", path.display(), err);
+            error!("Cannot create {}: {}", path.display(), err);
             return false;
         }
     };
 
-    if let Err(err) = writeln!(file, "{}", commit_id) {
-        eprintln!("Cannot write in {}: {}", path.display(), err);
-        return false;
+    for commit in merged {
+        if let Err(err) = writeln!(file, "{}", commit.id()) {
+            error!("Cannot write in {}: {}", path.display(), err);
+            return false;
+        }
     }
 
     true
 }
 
+/// Changes made on the remote commits when copying them
+struct Rewrites<'a> {
+    // fixup and squash commits folded in the commits they reference
+    plan: autosquash::Plan<'a>,
+    // messages edited by the user, replacing the messages of the remote commits
+    messages: HashMap<git2::Oid, String>,
+    // commits brought by the merges, folded in their copies when linearizing the history
+    merged: HashMap<git2::Oid, Vec<git2::Oid>>,
+    // the branch is not checked out, and the working directory must be left untouched
+    keep_workdir: bool,
+    // local commit on which the last copy was made, where the commits without parents
+    // are uprooted
+    head: Option<git2::Oid>,
+    // conflicts resolved automatically when copying the remote commits
+    resolved: HashMap<git2::Oid, Vec<autoresolve::Resolution>>,
+    // context formatting the ripit tags added to the messages of the copies
+    tags: &'a tag::Tags,
+}
+
+impl Rewrites<'_> {
+    /// Remote commits whose changes are contained in the copy of the given commit
+    fn folded_into(&self, id: git2::Oid) -> Vec<git2::Oid> {
+        let mut folded = self.plan.squashed_into(id);
+        folded.extend(self.merged.get(&id).into_iter().flatten());
+        folded
+    }
+}
+
+/// Drop the changes of a commit being copied, leaving the repository as before its copy
+fn drop_cherrypick(repo: &git2::Repository, parent: &git2::Commit) -> Result<(), git2::Error> {
+    repo.cleanup_state()?;
+    repo.reset(parent.as_object(), git2::ResetType::Hard, None)
+}
+
+/// Paths of the conflicts of an index
+pub fn conflicting_paths(index: &git2::Index) -> Result<Vec<String>, git2::Error> {
+    let mut paths: Vec<String> = index
+        .conflicts()?
+        .filter_map(|conflict| {
+            let conflict = conflict.ok()?;
+            let entry = conflict.our.or(conflict.their).or(conflict.ancestor)?;
+            Some(String::from_utf8_lossy(&entry.path).into_owned())
+        })
+        .collect();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// How a remote commit is applied on the copy of its first known parent
+#[derive(Clone, Copy)]
+struct Pick {
+    // whether the commit is uprooted
+    uprooted: bool,
+    // number of the remote parent of a merge whose copy is the first local parent, the
+    // changes of the merge being taken relative to it
+    mainline: u32,
+}
+
 fn do_cherrypick<'a, 'b>(
     repo: &'a git2::Repository,
     commit: &'b git2::Commit,
     local_parents: &Vec<&'b git2::Commit>,
-    is_merge: bool,
-    uprooted: bool,
+    pick_opts: Pick,
     branch: &app::Branch,
+    rewrites: &mut Rewrites,
     opts: &app::Options,
-) -> Result<git2::Commit<'a>, Error> {
+) -> Result<Option<git2::Commit<'a>>, Error> {
+    let is_merge = commit.parent_count() > 1;
     let branch_id = repo.refname_to_id(&branch.refname)?;
     let update_branch = local_parents[0].id() == branch_id;
+    let keep_workdir = rewrites.keep_workdir;
+    let tags = rewrites.tags;
+    rewrites.head = Some(local_parents[0].id());
+
+    let uprooted = pick_opts.uprooted;
+    let tag = format_tag(
+        commit,
+        uprooted,
+        &rewrites.folded_into(commit.id()),
+        &branch.name,
+        tags,
+        opts,
+    );
+    let edited_msg = rewrites.messages.get(&commit.id());
+    // the changes of a replaced commit are the ones of its patch, with a note in its message
+    let replacement = replacement_of(commit, opts);
+    let patch = match replacement {
+        Some(replacement) => Some(git2::Diff::from_buffer(&replacement.patch)?),
+        None => None,
+    };
+    let replaced_msg = replacement.map(|replacement| {
+        let msg = edited_msg
+            .map(|msg| msg.as_str())
+            .or_else(|| commit.message());
+        replaced_message(msg.unwrap_or(""), replacement)
+    });
+    // message of the copy, when it is not the one of the remote commit
+    let own_msg = replaced_msg.as_ref().or(edited_msg);
+    let not_applied = |err: git2::Error, checked_out| Error::ReplacementNotApplied {
+        branch: branch.name.clone(),
+        remote_id: commit.id(),
+        summary: commit.summary().unwrap_or("").to_owned(),
+        patch: replacement.map(|r| r.path.clone()).unwrap_or_default(),
+        reason: err.message().to_owned(),
+        checked_out,
+    };
+
+    // cherrypick changes on top of HEAD, the changes of a merge being the ones it brings to
+    // the parent on which it is copied
+    let mainline = if is_merge { pick_opts.mainline } else { 0 };
+    let mut cherrypick_opts = git2::CherrypickOptions::new();
+    cherrypick_opts.mainline(mainline);
+    // only the changes of the copied paths are applied
+    let filtered;
+    let pick = if !exclude::is_filtering(opts) {
+        commit
+    } else {
+        filtered = exclude::filter_commit(repo, commit, opts)?;
+        &filtered
+    };
+    // the changes are merged in memory, the working directory being checked out once all
+    // the commits are copied. It is only used by the pre_commit hook, and to solve the
+    // conflicts.
+    let mut in_memory = None;
+    let mut resolved = Vec::new();
+    if let (Some(patch), None) = (&patch, &opts.hooks.pre_commit) {
+        match repo.apply_to_tree(&local_parents[0].tree()?, patch, None) {
+            Ok(index) => in_memory = Some(index),
+            Err(err) if repo.is_bare() || keep_workdir => return Err(not_applied(err, false)),
+            // applied again in the working directory, where the synchronization stops
+            Err(_) => (),
+        }
+    } else if opts.hooks.pre_commit.is_none() {
+        let mut index = repo.cherrypick_commit(pick, local_parents[0], mainline, None)?;
+        merge_drivers::resolve(repo, &mut index, &local_parents[0].tree()?)?;
+        let resolutions = autoresolve::resolve(&mut index, &opts.conflict_strategy)?;
+        if opts.rerere {
+            rerere::resolve(repo, &mut index)?;
+        }
+        if !index.has_conflicts() {
+            in_memory = Some(index);
+            resolved = resolutions;
+        } else if repo.is_bare() {
+            return Err(Error::ConflictsInBareRepo {
+                branch: branch.name.clone(),
+                remote_id: commit.id(),
+                summary: commit.summary().unwrap_or("").to_owned(),
+                paths: conflicting_paths(&index)?,
+            });
+        } else if keep_workdir {
+            // there is no working directory in which the conflicts could be solved
+            return Err(Error::ConflictsNotCheckedOut {
+                branch: branch.name.clone(),
+                remote_id: commit.id(),
+                summary: commit.summary().unwrap_or("").to_owned(),
+                paths: conflicting_paths(&index)?,
+            });
+        }
+    }
+    let use_workdir = in_memory.is_none();
+    // nothing to drop when the working directory is not used
+    let drop_cherrypick = |repo, parent| {
+        if use_workdir {
+            drop_cherrypick(repo, parent)
+        } else {
+            Ok(())
+        }
+    };
+
+    // the patch of a replaced commit does not apply, the user applies it by hand
+    let mut failed_patch = None;
+    if use_workdir {
+        // checkout parent, then cherrypick on top of it
+        if update_branch {
+            checkout_branch(repo, branch)?;
+        } else {
+            repo.set_head_detached(local_parents[0].id())?;
+        }
+        force_checkout_head(repo, opts)?;
+        if let Some(patch) = &patch {
+            if let Err(err) = repo.apply(patch, git2::ApplyLocation::Both, None) {
+                failed_patch = Some(err);
+            }
+        } else {
+            repo.cherrypick(pick, Some(&mut cherrypick_opts))?;
+            merge_drivers::resolve_in_workdir(repo, &local_parents[0].tree()?)?;
+            resolved = autoresolve::resolve_in_workdir(repo, &opts.conflict_strategy)?;
+            if opts.rerere {
+                rerere::resolve_in_workdir(repo)?;
+            }
+        }
+    } else if !keep_workdir && head_is_branch(repo, &branch.refname)? {
+        // HEAD is detached until the branch is checked out again, so that an interrupted
+        // synchronization leaves it matching the working directory
+        repo.set_head_detached(branch_id)?;
+    }
+    let mut has_conflicts = failed_patch.is_some() || use_workdir && repo.index()?.has_conflicts();
+    if has_conflicts && failed_patch.is_none() {
+        if let Some(cmd) = &opts.mergetool {
+            has_conflicts = !mergetool::resolve_conflicts(repo, cmd)?;
+        }
+    }
+    if has_conflicts {
+        // The commit message is written in .git/MERGE_MSG, and will be
+        // used when the user commits the changes.
+        // It must thus be updated to:
+        //  - apply the filters
+        //  - add the ripit-tag
+        if let Some(msg) = own_msg {
+            if let Err(e) = std::fs::write(repo.path().join("MERGE_MSG"), msg) {
+                error!("Error when writing the edited message in MERGE_MSG: {}", e);
+            }
+        }
+        if let Err(err) = update_merge_msg(repo, tags, &tag, opts) {
+            drop_cherrypick(repo, local_parents[0])?;
+            return Err(err);
+        }
+
+        if is_merge && local_parents.len() > 1 && !fix_merge_ctx(repo, &local_parents[1..]) {
+            return Err(Error::CannotSetupMergeCtx);
+        }
+
+        // saved so that the synchronization can be aborted or continued
+        let orig_msg = own_msg.map(|msg| msg.as_str()).or_else(|| commit.message());
+        let message = match update_commit_msg(orig_msg.unwrap_or(""), &tag, tags, opts) {
+            Ok(message) => message,
+            Err(err) => {
+                drop_cherrypick(repo, local_parents[0])?;
+                return Err(err);
+            }
+        };
+        let state = state::SyncState {
+            remote_id: commit.id(),
+            refname: branch.refname.clone(),
+            uprooted,
+            head: Some(local_parents[0].id()),
+            message,
+            export: false,
+        };
+        if let Err(err) = state::write(repo, &state) {
+            error!("Cannot save the synchronization state: {}", err);
+        }
+        if opts.rerere {
+            rerere::save_preimages(repo, opts.rerere_max_age);
+        }
+
+        if let Some(err) = failed_patch {
+            return Err(not_applied(err, true));
+        }
+        return Err(Error::HasConflicts {
+            branch: branch.name.clone(),
+            remote_id: commit.id(),
+            summary: commit.summary().unwrap_or("").to_owned(),
+        });
+    }
+    if !resolved.is_empty() {
+        rewrites.resolved.insert(commit.id(), resolved);
+    }
+
+    if let Some(cmd) = &opts.hooks.pre_commit {
+        let env = [
+            ("RIPIT_REMOTE_OID", commit.id().to_string()),
+            ("RIPIT_BRANCH", branch.name.clone()),
+        ];
+        if let Err(reason) = hooks::run(repo, cmd, &env) {
+            drop_cherrypick(repo, local_parents[0])?;
+            return Err(Error::HookFailed {
+                hook: "pre_commit",
+                commit_id: commit.id(),
+                reason,
+            });
+        }
+    }
 
-    // checkout parent, then cherrypick on top of it
-    if update_branch {
-        repo.set_head(&branch.refname)?;
+    let tree_oid = match &mut in_memory {
+        Some(index) => index.write_tree_to(repo)?,
+        None => {
+            let mut index = repo.index()?;
+            // the pre_commit hook may have staged changes, which are included in the copy
+            index.read(false)?;
+            index.write_tree()?
+        }
+    };
+    let mut tree = repo.find_tree(tree_oid)?;
+
+    // fold the fixup commits referencing this commit, if they apply cleanly
+    let mut orig_msg = own_msg
+        .map(|msg| msg.as_str())
+        .or_else(|| commit.message())
+        .map(|msg| msg.to_owned());
+    let plan = &mut rewrites.plan;
+    for fixup in plan.fixups_of(commit.id()) {
+        match autosquash::fold(repo, &tree, &fixup) {
+            Ok(new_tree) => {
+                info!(
+                    "Folded commit {} in {}.",
+                    log::remote_commit(fixup.id()),
+                    log::remote_commit(commit.id())
+                );
+                tree = new_tree;
+                plan.set_applied(fixup.id());
+                if let (Some(msg), Some(body)) = (&mut orig_msg, autosquash::squash_message(&fixup))
+                {
+                    *msg = format!("{}\n\n{}\n", msg.trim_end(), body);
+                }
+            }
+            Err(err) => {
+                warn!(
+                    "cannot fold commit {} in {}, it is copied as is: {}",
+                    fixup.id(),
+                    commit.id(),
+                    err
+                );
+                plan.unfold(fixup.id());
+            }
+        }
+    }
+    // the changes of the commit may already exist locally. Merges are kept, as they record
+    // the history of the branch, unless it is linearized
+    if opts.skip_empty && (!is_merge || opts.linearize) && tree.id() == local_parents[0].tree_id() {
+        drop_cherrypick(repo, local_parents[0])?;
+        return Ok(None);
+    }
+
+    // commits folded in this one were applied before, or just above
+    let tag = format_tag(
+        commit,
+        uprooted,
+        &rewrites.folded_into(commit.id()),
+        &branch.name,
+        tags,
+        opts,
+    );
+    let (author, committer) = build_signatures(
+        opts.authors.map_signature(&commit.author())?,
+        opts.authors.map_signature(&commit.committer())?,
+        commit,
+        opts,
+    )?;
+
+    let build_msg = |tag: &str| {
+        let msg = match &orig_msg {
+            Some(orig_msg) => update_commit_msg(orig_msg, tag, tags, opts),
+            None => Ok(tag.to_owned()),
+        };
+        if msg.is_err() {
+            drop_cherrypick(repo, local_parents[0])?;
+        }
+        msg
+    };
+    // if the first parent is the branch's head, then directly
+    // update the branch when committing
+    let update_ref = if update_branch {
+        Some(branch.refname.as_str())
+    } else if use_workdir {
+        Some("HEAD")
     } else {
-        repo.set_head_detached(local_parents[0].id())?;
+        None
+    };
+
+    // the changes of the commit are dropped if it cannot be created, e.g. if it cannot be
+    // signed
+    let create_commit = |msg: &str, tree: &git2::Tree, parents: &[&git2::Commit]| {
+        let res = sign::Committer::new(repo, tags, opts)
+            .commit(update_ref, &author, &committer, msg, tree, parents);
+        if res.is_err() {
+            drop_cherrypick(repo, local_parents[0])?;
+        }
+        res
+    };
+
+    // if its changes span several groups of paths, the commit is split in parts, the last
+    // one containing all the changes
+    let parts = if is_merge || opts.split_rules.is_empty() {
+        vec![]
+    } else {
+        split::split_changes(repo, &local_parents[0].tree()?, &tree, &opts.split_rules)?
+    };
+    let mut new_msg = build_msg(&tag)?;
+    let mut prev_part = None;
+    if parts.len() > 1 {
+        let base_tree = local_parents[0].tree()?;
+        let mut paths = Vec::new();
+        for (i, part) in parts[..(parts.len() - 1)].iter().enumerate() {
+            paths.extend_from_slice(&part.paths);
+            let part_tree = split::build_partial_tree(repo, &base_tree, &tree, &paths)?;
+            let part_tag = tag::format_part_tag(&tag, &part.name, i + 1, parts.len());
+            let parent = prev_part.as_ref().unwrap_or(local_parents[0]);
+
+            let part_oid = create_commit(&build_msg(&part_tag)?, &part_tree, &[parent])?;
+            info!("Created commit {} for part {}.", part_oid, part.name);
+            prev_part = Some(repo.find_commit(part_oid)?);
+        }
+        let last = &parts[parts.len() - 1];
+        new_msg = build_msg(&tag::format_part_tag(
+            &tag,
+            &last.name,
+            parts.len(),
+            parts.len(),
+        ))?;
+    }
+    let parents = match &prev_part {
+        Some(part) => vec![part],
+        None => local_parents.clone(),
+    };
+
+    // commit the changes
+    let ci_oid = create_commit(&new_msg, &tree, &parents)?;
+
+    let new_commit = repo.find_commit(ci_oid)?;
+    if uprooted {
+        info!("{} commit {}.", log::uprooted("Uprooted"), new_commit.id());
+    } else {
+        info!("Created commit {}.", new_commit.id());
     }
-    force_checkout_head(repo)?;
 
-    let tag = tag::format_ripit_tag(commit, uprooted);
+    // if one of the following parents was the local branch, then update it.
+    //
+    // This can happen when syncing merge commits, as we will first synchronize the second
+    // branch, and update the local branch, then synchronize the merge commit. We need to
+    // fix the local branch back to the merge commit.
+    if !update_branch && local_parents.iter().any(|p| p.id() == branch_id) {
+        repo.reference(&branch.refname, new_commit.id(), true, REFLOG_MSG)?;
+        if use_workdir {
+            checkout_branch(repo, branch)?;
+        }
+    } else if use_workdir && update_branch && !is_heads_ref(&branch.refname) {
+        // HEAD is detached on refs outside of the heads namespace, and must follow them
+        checkout_branch(repo, branch)?;
+    }
+
+    // make the working directory match HEAD
+    if use_workdir {
+        force_checkout_head(repo, opts)?;
+        repo.cleanup_state()?;
+    }
+    rewrites.head = Some(new_commit.id());
+
+    Ok(Some(new_commit))
+}
+
+/// Result of the copy of a remote commit
+enum Copy<'a> {
+    // the local commit created
+    Created(SyncedCommit<'a>),
+    // the commit was empty once copied, and was not created. The local commit on which
+    // it would have been created is kept.
+    Empty(SyncedCommit<'a>),
+}
+
+/// Set the date of a signature, or the current time if none is given
+fn with_date(
+    sig: &git2::Signature,
+    time: Option<git2::Time>,
+) -> Result<git2::Signature<'static>, git2::Error> {
+    let name = String::from_utf8_lossy(sig.name_bytes());
+    let email = String::from_utf8_lossy(sig.email_bytes());
+    match time {
+        Some(time) if time == sig.when() => Ok(sig.to_owned()),
+        Some(time) => git2::Signature::new(&name, &email, &time),
+        None => git2::Signature::now(&name, &email),
+    }
+}
+
+/// Author and committer of a local commit created from a remote commit
+///
+/// Their dates are copied from the remote commit, or set to the current time, according
+/// to the `dates` option.
+fn build_signatures(
+    author: git2::Signature<'static>,
+    committer: git2::Signature<'static>,
+    remote: &git2::Commit,
+    opts: &app::Options,
+) -> Result<(git2::Signature<'static>, git2::Signature<'static>), Error> {
+    let (author_time, committer_time) = match opts.dates {
+        app::Dates::Preserve => (
+            Some(remote.author().when()),
+            Some(remote.committer().when()),
+        ),
+        app::Dates::AuthorOnly => (Some(remote.author().when()), None),
+        app::Dates::Now => (None, None),
+    };
+    let committer = match &opts.committer_email {
+        Some(email) => {
+            git2::Signature::new(committer.name().unwrap_or(""), email, &committer.when())?
+        }
+        None => committer,
+    };
+    Ok((
+        with_date(&author, author_time)?,
+        with_date(&committer, committer_time)?,
+    ))
+}
+
+/// Cherrypick a given commit on top of HEAD, and add the ripit tag
+fn copy_commit<'a, 'b>(
+    repo: &'a git2::Repository,
+    commit: &'b git2::Commit,
+    commits_map: &'b CommitsMap,
+    branch: &app::Branch,
+    rewrites: &mut Rewrites,
+    opts: &app::Options,
+) -> Result<Copy<'a>, Error> {
+    let head;
+    let branch_head;
+
+    info!("Copying commit {}...", log::remote_commit(commit.id()));
+
+    // Find parent of the commit in local repo
+    let mut local_parents: Vec<&git2::Commit> = Vec::new();
+    let mut uprooted = true;
+    let mut mainline = 1;
+    // when linearizing the history, merges are copied as commits with a single parent
+    let nb_parents = if opts.linearize {
+        1
+    } else {
+        commit.parent_count()
+    };
+    for (i, parent_id) in commit.parent_ids().take(nb_parents).enumerate() {
+        match commits_map.get(parent_id) {
+            Some(parent_ci) => {
+                // the changes of a merge are applied on the copy of its first known parent
+                if local_parents.is_empty() {
+                    mainline = i as u32 + 1;
+                }
+                // parents can be mapped to the same commit if some commits were skipped
+                if !local_parents
+                    .iter()
+                    .any(|ci| ci.id() == parent_ci.commit.id())
+                {
+                    local_parents.push(&parent_ci.commit);
+                }
+                // A commit with uprooted parents is uprooted
+                if !parent_ci.uprooted {
+                    uprooted = false;
+                }
+            }
+            None => {
+                if !branch.uproot {
+                    return Err(Error::UnknownParent {
+                        commit_id: commit.id(),
+                        parent_id,
+                        branch: branch.name.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    // with a prefix, the local commits made on top of the copy of the parent are kept, as
+    // well as the merges grouping the commits of the previous runs
+    if (opts.prefix.is_some() || opts.group_runs) && !local_parents.is_empty() {
+        let branch_id = repo.refname_to_id(&branch.refname)?;
+        if branch_id != local_parents[0].id()
+            && repo.graph_descendant_of(branch_id, local_parents[0].id())?
+        {
+            branch_head = repo.find_commit(branch_id)?;
+            local_parents[0] = &branch_head;
+        }
+    }
+
+    if local_parents.is_empty() {
+        assert!(branch.uproot);
+        // uproot the commit on the last copy, or on HEAD
+        // XXX: head *has* a target, because we have at least the bootstrap
+        // commit.
+        head = repo.find_commit(rewrites.head.unwrap())?;
+        local_parents.push(&head);
+    }
+
+    let copy = do_cherrypick(
+        repo,
+        commit,
+        &local_parents,
+        Pick { uprooted, mainline },
+        branch,
+        rewrites,
+        opts,
+    )?;
+    Ok(match copy {
+        Some(commit) => Copy::Created(SyncedCommit { commit, uprooted }),
+        None => Copy::Empty(SyncedCommit {
+            commit: repo.find_commit(local_parents[0].id())?,
+            uprooted,
+        }),
+    })
+}
+
+/// Truncate the list of commits to sync before the first commit matching the regex
+///
+/// As the list is sorted topologically, the kept prefix contains all the ancestors of its
+/// commits, so merges are either fully synchronized or not at all. A matching merge commit
+/// is handled as any other commit: it is not synchronized, nor are the commits after it.
+///
+/// Returns true if the list was truncated.
+fn truncate_before_match(
+    commits: &mut Vec<git2::Commit>,
+    re: &regex::Regex,
+    branch: &app::Branch,
+) -> bool {
+    let pos = commits
+        .iter()
+        .position(|ci| re.is_match(ci.message().unwrap_or("")));
+
+    if let Some(pos) = pos {
+        let ci = &commits[pos];
+        info!(
+            "Stopping before commit {} on branch {}: its message matches \"{}\".\n  {}",
+            log::remote_commit(ci.id()),
+            log::branch(&branch.name),
+            re.as_str(),
+            ci.summary().unwrap_or("")
+        );
+        commits.truncate(pos);
+        true
+    } else {
+        false
+    }
+}
+
+/// Truncate the list of commits to sync to its oldest commits
+///
+/// As in truncate_before_match, the kept prefix contains all the ancestors of its commits,
+/// so no merge is synchronized without its parents.
+fn truncate_to_max_commits(commits: &mut Vec<git2::Commit>, max: usize, branch: &app::Branch) {
+    if commits.len() > max {
+        info!(
+            "Synchronizing the {} oldest commits on branch {}, {} commits remain.",
+            max,
+            log::branch(&branch.name),
+            commits.len() - max
+        );
+        commits.truncate(max);
+    }
+}
+
+/// Sync the local repository with the new changes from the given remote
+/// Returns the number of copied commits, or None if there was no commits to synchronize.
+pub fn sync_branch_with_remote<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    commits_map: &mut CommitsMap<'a>,
+    processed: &mut Vec<git2::Oid>,
+    opts: &app::Options,
+) -> Result<Option<usize>, Error> {
+    sync_branch(repo, tags, branch, commits_map, processed, opts)
+        .map_err(|err| err.context(Operation::Synchronize, &branch.name, None))
+}
+
+fn sync_branch<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    commits_map: &mut CommitsMap<'a>,
+    processed: &mut Vec<git2::Oid>,
+    opts: &app::Options,
+) -> Result<Option<usize>, Error> {
+    let local_commit = repo.revparse_single(&branch.refname)?.peel_to_commit()?;
+
+    // Get the branch last commit in the remote
+    let remote_target = find_sync_target(repo, branch, opts)?;
+
+    // Build revwalk from specified commit up to last commit in branch in remote
+    let mut commits = find_commits_to_sync(
+        repo,
+        tags,
+        local_commit.id(),
+        remote_target.as_object(),
+        commits_map,
+        branch,
+        opts,
+    )?;
+
+    let stopped = match &opts.stop_before {
+        Some(stop_before) => truncate_before_match(&mut commits, stop_before, branch),
+        None => false,
+    };
+    if let Some(max) = opts.max_commits {
+        truncate_to_max_commits(&mut commits, max, branch);
+    }
+
+    if commits.is_empty() && stopped {
+        info!(
+            "Nothing to synchronize on branch {} before the stop commit.",
+            log::branch(&branch.name)
+        );
+        return Ok(None);
+    } else if commits.is_empty() {
+        info!(
+            "Nothing to synchronize on branch {}, already up to date with {}.",
+            log::branch(&branch.name),
+            opts.remote
+        );
+        return Ok(None);
+    }
+    // the messages deferred by --quiet-if-unchanged are printed once commits are found
+    log::flush_deferred();
+
+    // Commits skipped by a previous synchronization are skipped again, as well as the
+    // commits listed in skip_shas, matching skip_commits or emptied by exclude_paths and
+    // subtree. The commit gate is consulted for the others before copying anything. When
+    // squashing, the remote tree is taken as is, and no commit can be skipped.
+    let mut skipped = HashMap::new();
+    if !opts.squash {
+        for ci in &commits {
+            if let Some(rule) = commits_map.skip_rule(ci.id()) {
+                skipped.insert(ci.id(), rule.to_owned());
+            } else if is_skipped_sha(ci, opts) {
+                skipped.insert(ci.id(), SKIP_SHAS_RULE.to_owned());
+            } else if opts.skip_commits.is_match(ci.message().unwrap_or("")) {
+                skipped.insert(ci.id(), SKIP_COMMITS_RULE.to_owned());
+            } else if exclude::is_filtering(opts) && exclude::is_emptied(repo, ci, opts)? {
+                info!(
+                    "Skipping {}: it only changes paths that are not copied.",
+                    log::remote_commit(ci.id())
+                );
+                skipped.insert(ci.id(), EXCLUDED_PATHS_RULE.to_owned());
+            } else if opts.dry_run {
+                continue;
+            } else if let Some(cmd) = &opts.commit_gate_cmd {
+                if gate::check_commit(repo, ci, branch, cmd)? == gate::Decision::Skip {
+                    skipped.insert(ci.id(), gate::SKIP_RULE.to_owned());
+                }
+            }
+        }
+    }
+
+    // fixup and squash commits are folded in the copied commits they reference
+    let plan = if opts.autosquash {
+        let to_copy: Vec<git2::Commit> = commits
+            .iter()
+            .filter(|ci| !skipped.contains_key(&ci.id()))
+            .cloned()
+            .collect();
+        autosquash::Plan::new(&to_copy)
+    } else {
+        autosquash::Plan::default()
+    };
+    let merged = if opts.linearize {
+        find_merged_commits(repo, &commits, commits_map)?
+    } else {
+        HashMap::new()
+    };
+    let keep_workdir = keeps_workdir(repo, branch, opts);
+    let mut rewrites = Rewrites {
+        plan,
+        messages: HashMap::new(),
+        merged,
+        keep_workdir,
+        head: if keep_workdir {
+            Some(local_commit.id())
+        } else {
+            repo.head().ok().and_then(|head| head.target())
+        },
+        resolved: HashMap::new(),
+        tags,
+    };
+
+    if opts.dry_run {
+        print_dry_run(
+            &commits,
+            &skipped,
+            &rewrites.plan,
+            commits_map,
+            branch,
+            tags,
+            opts,
+        )?;
+        return Ok(Some(0));
+    }
+
+    if !confirm_sync(repo, &commits, &skipped, &rewrites.plan, branch, opts)? {
+        return Ok(Some(0));
+    }
+
+    if opts.squash {
+        squash_commits(repo, tags, &commits, commits_map, branch, opts)
+            .map_err(|err| err.context(Operation::Squash, &branch.name, commits.last()))?;
+        processed.extend(commits.iter().map(|ci| ci.id()));
+        return Ok(Some(1));
+    }
+
+    // the messages are all edited before copying anything, so that aborting the editor
+    // leaves the repository untouched
+    if let Some(cmd) = &opts.edit_messages {
+        for ci in &commits {
+            if skipped.contains_key(&ci.id()) || rewrites.plan.is_folded(ci.id()) {
+                continue;
+            }
+            match edit_commit_message(ci, cmd, tags, opts)? {
+                Some(msg) => {
+                    rewrites.messages.insert(ci.id(), msg);
+                }
+                None => {
+                    // the fixups of the commit can no longer be folded in it
+                    for fixup in rewrites.plan.fixups_of(ci.id()) {
+                        rewrites.plan.unfold(fixup.id());
+                    }
+                    skipped.insert(ci.id(), EMPTY_MESSAGE_RULE.to_owned());
+                }
+            }
+        }
+    }
+
+    // cherry-pick every commit, and add the rip-it tag in the commits messages
+    let mut last_commit_id = None;
+    let mut nb_copied = 0;
+    let mut keep_refs = KeepRefs::new();
+    for ci in &commits {
+        processed.push(ci.id());
+        if let Some(rule) = skipped.get(&ci.id()) {
+            skip_commit(ci, rule, commits_map);
+            continue;
+        }
+        if rewrites.plan.is_folded(ci.id()) {
+            // its changes are in the copy of its target, and thus in the copy of its parent
+            map_to_parent(ci, commits_map);
+            continue;
+        }
+        let copy = copy_commit(repo, ci, commits_map, branch, &mut rewrites, opts);
+        if let (Ok(_), Some(resolutions)) = (&copy, rewrites.resolved.remove(&ci.id())) {
+            commits_map.record_resolutions(ci.id(), resolutions);
+        }
+        let copied_ci = match copy {
+            Ok(Copy::Created(copied_ci)) => copied_ci,
+            Ok(Copy::Empty(parent)) => {
+                info!(
+                    "Skipped commit {}: it is empty once copied.",
+                    log::remote_commit(ci.id())
+                );
+                // cached, so that the merges of this commit still find their parents
+                map_merged_commits(ci, &parent, &rewrites, commits_map);
+                commits_map.insert(ci.id(), parent);
+                continue;
+            }
+            Err(err) => {
+                // only keep the references of the commits not reachable from the branch
+                keep_refs::release(repo, repo.refname_to_id(&branch.refname)?)?;
+                update_workdir(repo, branch, &rewrites, opts)?;
+                let operation = if ci.parent_count() > 1 && !opts.linearize {
+                    Operation::Merge
+                } else {
+                    Operation::CherryPick
+                };
+                return Err(err.context(operation, &branch.name, Some(ci)));
+            }
+        };
+        nb_copied += 1;
+
+        // add mapping for this new pair
+        let copied_id = copied_ci.commit.id();
+        last_commit_id = Some(copied_id);
+        // the previous parts of a split commit are listed in the cache, but only the last
+        // part is mapped to the remote commit
+        let mut part = copied_ci.commit.parent(0).ok();
+        while let Some(part_ci) = part.filter(|ci| tags.is_partial(ci)) {
+            commits_map.cache_commit(ci.id(), part_ci.id());
+            keep_refs.protect(repo, part_ci.id())?;
+            part = part_ci.parent(0).ok();
+        }
+        map_merged_commits(ci, &copied_ci, &rewrites, commits_map);
+        commits_map.insert(ci.id(), copied_ci);
+        keep_refs.protect(repo, copied_id)?;
+
+        if let Some(cmd) = &opts.hooks.post_commit {
+            let env = [
+                ("RIPIT_REMOTE_OID", ci.id().to_string()),
+                ("RIPIT_LOCAL_OID", copied_id.to_string()),
+                ("RIPIT_BRANCH", branch.name.clone()),
+            ];
+            if let Err(reason) = hooks::run(repo, cmd, &env) {
+                let err = Error::HookFailed {
+                    hook: "post_commit",
+                    commit_id: ci.id(),
+                    reason,
+                };
+                if opts.hooks.post_commit_fatal {
+                    // the commits copied so far are kept
+                    checkpoint(repo, branch, commits_map, copied_id)?;
+                    update_workdir(repo, branch, &rewrites, opts)?;
+                    return Err(err);
+                }
+                warn!("{}", err);
+            }
+        }
+
+        if opts.checkpoint_every > 0 && nb_copied % opts.checkpoint_every == 0 {
+            checkpoint(repo, branch, commits_map, copied_id)?;
+        }
+        util::test_abort_hook(nb_copied);
+    }
+
+    // Set the branch on the last copied commit
+    if let Some(ci_id) = last_commit_id {
+        setup_branch(repo, &branch.refname, &repo.find_commit(ci_id).unwrap())?;
+        if opts.group_runs {
+            group_run(repo, tags, branch, &local_commit, &commits, nb_copied, opts)?;
+        }
+    }
+    keep_refs::release(repo, repo.refname_to_id(&branch.refname)?)?;
+    update_workdir(repo, branch, &rewrites, opts)?;
+
+    Ok(Some(nb_copied))
+}
+
+/// Group the commits copied by a run under a merge commit, with the group_runs option
+///
+/// The first parent of the merge is the tip of the branch before the run, and its second
+/// parent the last copied commit. Its message summarizes the run, and it has no ripit tag.
+fn group_run(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    local_tip: &git2::Commit,
+    commits: &[git2::Commit],
+    nb_copied: usize,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let last_copied = repo.find_commit(repo.refname_to_id(&branch.refname)?)?;
+    let sig = repo.signature()?;
+
+    let mut msg = format!(
+        "Sync {} {} {} ({} commit{})\n",
+        opts.remote,
+        branch.remote_name,
+        util::format_date(&sig.when()),
+        nb_copied,
+        if nb_copied > 1 { "s" } else { "" }
+    );
+    // the remote commits are not named with the cache-only and hmac provenances
+    if let (Some(first), Some(last)) = (commits.first(), commits.last()) {
+        if !opts.provenance.hides_remote_ids() {
+            let start = first.parent_id(0).unwrap_or_else(|_| first.id());
+            msg.push_str(&format!(
+                "\nCopied {} {}..{}\n",
+                opts.remote,
+                start,
+                last.id()
+            ));
+        }
+    }
+
+    let merge_id = sign::Committer::new(repo, tags, opts).commit(
+        None,
+        &sig,
+        &sig,
+        &msg,
+        &last_copied.tree()?,
+        &[local_tip, &last_copied],
+    )?;
+    repo.reference(&branch.refname, merge_id, true, REFLOG_MSG)?;
+    info!(
+        "Grouped the {} copied commits under merge commit {}.",
+        nb_copied, merge_id
+    );
+    Ok(())
+}
+
+/// Checkout the branch, once its commits were copied in memory
+///
+/// The conflicts of a commit left in the working directory to be solved are kept, as well
+/// as a replacement patch left to be applied by hand.
+fn update_workdir(
+    repo: &git2::Repository,
+    branch: &app::Branch,
+    rewrites: &Rewrites,
+    opts: &app::Options,
+) -> Result<(), git2::Error> {
+    if rewrites.keep_workdir || repo.index()?.has_conflicts() || state::state_path(repo).exists() {
+        return Ok(());
+    }
+    checkout_branch(repo, branch)?;
+    force_checkout_head(repo, opts)?;
+    repo.cleanup_state()
+}
+
+/// Number of files changed, and of lines inserted and deleted by commits
+#[derive(Default)]
+struct DiffStat {
+    files: usize,
+    insertions: usize,
+    deletions: usize,
+}
+
+impl DiffStat {
+    /// Changes brought by a commit to its first parent, on the copied paths
+    fn of_commit(
+        repo: &git2::Repository,
+        commit: &git2::Commit,
+        opts: &app::Options,
+    ) -> Result<Self, git2::Error> {
+        let mut tree = commit.tree()?;
+        let mut parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        if exclude::is_filtering(opts) {
+            tree = exclude::filter_tree(repo, &tree, opts)?;
+            parent_tree = match parent_tree {
+                Some(parent_tree) => Some(exclude::filter_tree(repo, &parent_tree, opts)?),
+                None => None,
+            };
+        }
+
+        let stats = repo
+            .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?
+            .stats()?;
+        Ok(Self {
+            files: stats.files_changed(),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+        })
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.files += other.files;
+        self.insertions += other.insertions;
+        self.deletions += other.deletions;
+    }
+}
+
+impl std::fmt::Display for DiffStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+            self.files,
+            plural(self.files),
+            self.insertions,
+            plural(self.insertions),
+            self.deletions,
+            plural(self.deletions)
+        )
+    }
+}
+
+/// Describe the commits to synchronize
+///
+/// When a confirmation is asked, the diffstat of each copied commit is given, and their
+/// total. Past `diffstat_max_commits` commits, only the total is given.
+fn format_pending(
+    repo: &git2::Repository,
+    commits: &[git2::Commit],
+    skipped: &HashMap<git2::Oid, String>,
+    plan: &autosquash::Plan,
+    opts: &app::Options,
+) -> Result<String, Error> {
+    let with_stats = !opts.yes;
+    let per_commit = commits.len() <= opts.diffstat_max_commits;
+    let mut total = DiffStat::default();
+    let mut desc = String::new();
+
+    for ci in commits {
+        desc.push_str(&format!(
+            "  Commit {id}{skipped}\n    {author}\n    {summary}\n",
+            id = log::remote_commit(ci.id()),
+            skipped = match skipped.get(&ci.id()) {
+                Some(rule) => format!(" (skipped: {})", rule),
+                None if plan.is_folded(ci.id()) => " (folded)".to_owned(),
+                None => String::new(),
+            },
+            author = ci.author(),
+            summary = ci.summary().unwrap_or("")
+        ));
+        if with_stats && !skipped.contains_key(&ci.id()) {
+            let stat = DiffStat::of_commit(repo, ci, opts)?;
+            if per_commit {
+                desc.push_str(&format!("    {}\n", stat));
+            }
+            total.add(&stat);
+        }
+        desc.push('\n');
+    }
+    if with_stats {
+        desc.push_str(&format!("Total: {}\n\n", total));
+    }
+    Ok(desc)
+}
+
+/// List the commits to synchronize on a branch, and ask for a confirmation unless `yes`
+///
+/// Returns whether the commits must be synchronized.
+fn confirm_sync(
+    repo: &git2::Repository,
+    commits: &[git2::Commit],
+    skipped: &HashMap<git2::Oid, String>,
+    plan: &autosquash::Plan,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<bool, Error> {
+    // the history kept when bootstrapping is copied without confirmation, as the bootstrap
+    let ask = !opts.yes && !opts.bootstrap;
+    // without a question to ask, the list is an informational message
+    if !ask && !log::enabled(log::Level::Info) {
+        return Ok(true);
+    }
+    if opts.repo == "." {
+        outln!(
+            "Commits to synchronize on {} from {}:",
+            log::branch(&branch.name),
+            opts.remote
+        );
+    } else {
+        outln!(
+            "Commits to synchronize on {} from {} in {}:",
+            log::branch(&branch.name),
+            opts.remote,
+            opts.repo
+        );
+    }
+    out!("{}", format_pending(repo, commits, skipped, plan, opts)?);
+
+    if !ask {
+        return Ok(true);
+    }
+    match (opts.confirm)("Is this ok?")? {
+        app::Answer::Yes => Ok(true),
+        app::Answer::No => Ok(false),
+        app::Answer::Abort => Err(Error::ConfirmAborted {
+            question: "Is this ok?".to_owned(),
+        }),
+    }
+}
+
+/// Copy the changes of all the commits to synchronize in a single local commit
+///
+/// The filtered tree of the last commit is taken as is, so that no conflicts can happen.
+/// The other commits are folded in the copy, and listed in its tag. The summaries of all
+/// the commits are listed in its message.
+fn squash_commits<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    commits: &[git2::Commit],
+    commits_map: &mut CommitsMap<'a>,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let (tip, folded) = commits.split_last().unwrap();
+    let folded: Vec<git2::Oid> = folded.iter().map(|ci| ci.id()).collect();
 
-    // cherrypick changes on top of HEAD
-    let mut cherrypick_opts = git2::CherrypickOptions::new();
-    if is_merge {
-        // TODO: find the right mainline
-        cherrypick_opts.mainline(1);
+    // HEAD is not moved in a bare repository, where it designates the default branch
+    if !repo.is_bare() {
+        checkout_branch(repo, branch)?;
+        force_checkout_head(repo, opts)?;
     }
-    repo.cherrypick(commit, Some(&mut cherrypick_opts))?;
-
-    if repo.index()?.has_conflicts() {
-        // The commit message is written in .git/MERGE_MSG, and will be
-        // used when the user commits the changes.
-        // It must thus be updated to:
-        //  - apply the filters
-        //  - add the ripit-tag
-        update_merge_msg(repo, &tag, opts);
+    let head = repo.find_commit(repo.refname_to_id(&branch.refname)?)?;
 
-        if is_merge && local_parents.len() > 1 && !fix_merge_ctx(repo, local_parents[1].id()) {
-            return Err(Error::CannotSetupMergeCtx);
-        }
+    // with a prefix, the content of the local repository outside of it is kept
+    let tree = exclude::filter_tree(repo, &tip.tree()?, opts)?;
+    let tree = exclude::graft_tree(repo, &head.tree()?, &tree, opts)?;
 
-        return Err(Error::HasConflicts {
-            summary: commit.summary().unwrap_or("").to_owned(),
-        });
+    let mut msg = format!(
+        "Squash {} commits from remote {}\n\n",
+        commits.len(),
+        opts.remote
+    );
+    for ci in commits {
+        msg.push_str(&format!("* {}\n", ci.summary().unwrap_or("")));
     }
+    let tag = format_tag(tip, false, &folded, &branch.name, tags, opts);
+    let msg = update_commit_msg(&msg, &tag, tags, opts)?;
 
-    let new_msg = match commit.message() {
-        Some(orig_msg) => update_commit_msg(orig_msg, &tag, opts),
-        None => tag,
-    };
-    // if the first parent is the branch's head, then directly
-    // update the branch when committing
-    let update_ref = if update_branch {
-        &branch.refname
-    } else {
-        "HEAD"
-    };
-
-    // commit the changes
-    let tree_oid = repo.index()?.write_tree()?;
-    let tree = repo.find_tree(tree_oid)?;
-    let ci_oid = repo.commit(
-        Some(update_ref),
-        &commit.author(),
-        &commit.committer(),
-        &new_msg,
+    let sig = repo.signature()?;
+    let (author, committer) = build_signatures(sig.clone(), sig, tip, opts)?;
+    let commit_oid = sign::Committer::new(repo, tags, opts).commit(
+        Some(&branch.refname),
+        &author,
+        &committer,
+        &msg,
         &tree,
-        local_parents,
+        &[&head],
     )?;
-
-    let new_commit = repo.find_commit(ci_oid)?;
-    if uprooted {
-        println!("Uprooted commit {}.", new_commit.id());
-    } else {
-        println!("Created commit {}.", new_commit.id());
+    // HEAD is detached on refs outside of the heads namespace, and must follow them
+    if !repo.is_bare() {
+        checkout_branch(repo, branch)?;
+        force_checkout_head(repo, opts)?;
     }
+    info!(
+        "Created commit {} squashing {} commits.",
+        commit_oid,
+        commits.len()
+    );
 
-    // if one of the following parents was the local branch, then update it.
-    //
-    // This can happen when syncing merge commits, as we will first synchronize the second
-    // branch, and update the local branch, then synchronize the merge commit. We need to
-    // fix the local branch back to the merge commit.
-    if !update_branch && local_parents.iter().any(|p| p.id() == branch_id) {
-        repo.branch(&branch.name, &new_commit, true)?;
-        repo.set_head(&branch.refname)?;
+    let commit = repo.find_commit(commit_oid)?;
+    for id in folded {
+        let synced = SyncedCommit {
+            commit: commit.clone(),
+            uprooted: false,
+        };
+        commits_map.insert(id, synced);
     }
+    commits_map.insert(
+        tip.id(),
+        SyncedCommit {
+            commit,
+            uprooted: false,
+        },
+    );
+    Ok(())
+}
 
-    // make the working directory match HEAD
-    force_checkout_head(repo)?;
-    repo.cleanup_state()?;
+/// List the commits that would be synchronized, with their filtered summary
+///
+/// Commits with a parent that is neither synchronized nor in the list would be uprooted,
+/// which fails without the uproot option.
+fn print_dry_run(
+    commits: &[git2::Commit],
+    skipped: &HashMap<git2::Oid, String>,
+    plan: &autosquash::Plan,
+    commits_map: &CommitsMap,
+    branch: &app::Branch,
+    tags: &tag::Tags,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let pending: HashSet<git2::Oid> = commits.iter().map(|ci| ci.id()).collect();
 
-    Ok(new_commit)
+    outln!(
+        "Commits that would be synchronized on {}:",
+        log::branch(&branch.name)
+    );
+    for ci in commits {
+        let msg = filter_commit_msg(ci.message().unwrap_or(""), tags, opts)?;
+        // the merged parents are irrelevant when linearizing the history
+        let nb_parents = if opts.linearize { 1 } else { ci.parent_count() };
+        let has_unknown_parent = ci
+            .parent_ids()
+            .take(nb_parents)
+            .any(|id| !commits_map.contains_key(id) && !pending.contains(&id));
+
+        outln!(
+            "  {} {}{}",
+            log::remote_commit(&ci.id().to_string()[..10]),
+            msg.lines().next().unwrap_or(""),
+            match skipped.get(&ci.id()) {
+                Some(rule) => format!(" (skipped: {})", rule),
+                None if plan.is_folded(ci.id()) => " (folded)".to_owned(),
+                None if has_unknown_parent && branch.uproot => " (uprooted)".to_owned(),
+                None if has_unknown_parent => " (unknown parent, needs --uproot)".to_owned(),
+                None => String::new(),
+            }
+        );
+    }
+    Ok(())
 }
 
-/// Cherrypick a given commit on top of HEAD, and add the ripit tag
-fn copy_commit<'a, 'b>(
-    repo: &'a git2::Repository,
-    commit: &'b git2::Commit,
-    commits_map: &'b CommitsMap,
-    branch: &app::Branch,
+/// Let the user edit the filtered message of a commit to synchronize
+///
+/// Returns None if the user emptied the message, and confirmed the commit must be skipped.
+fn edit_commit_message(
+    commit: &git2::Commit,
+    cmd: &str,
+    tags: &tag::Tags,
     opts: &app::Options,
-) -> Result<SyncedCommit<'a>, Error> {
-    let head;
+) -> Result<Option<String>, Error> {
+    let msg = filter_commit_msg(commit.message().unwrap_or(""), tags, opts)?;
 
-    if opts.verbose {
-        println!("Copying commit {}...", commit.id());
+    if let Some(msg) = editor::edit_message(cmd, commit, &msg)? {
+        return Ok(Some(msg));
+    }
+    let question = format!("Empty message, skip commit {}?", commit.id());
+    match (opts.confirm)(&question)? {
+        app::Answer::Yes => Ok(None),
+        app::Answer::No => Err(Error::EditorFailed {
+            commit_id: commit.id(),
+            reason: "empty message".to_owned(),
+        }),
+        app::Answer::Abort => Err(Error::ConfirmAborted { question }),
     }
+}
 
-    // Find parent of the commit in local repo
-    let mut local_parents = Vec::new();
-    let mut uprooted = true;
-    let is_merge = commit.parent_count() > 1;
-    for parent_id in commit.parent_ids() {
-        match commits_map.get(parent_id) {
-            Some(parent_ci) => {
-                local_parents.push(&parent_ci.commit);
-                // A commit with uprooted parents is uprooted
-                if !parent_ci.uprooted {
-                    uprooted = false;
-                }
-            }
-            None => {
-                if !opts.uproot {
-                    return Err(Error::UnknownParent {
-                        commit_id: commit.id(),
-                        parent_id,
-                    });
-                }
-            }
-        }
+/// Skip the synchronization of a commit
+///
+/// The commit is mapped to the local commit of its first known parent, so that its
+/// descendants are copied on top of it. The skip is recorded with the rule that caused it.
+fn skip_commit<'a>(commit: &git2::Commit, rule: &str, commits_map: &mut CommitsMap<'a>) {
+    info!("Skipped commit {}.", log::remote_commit(commit.id()));
+    commits_map.record_skip(commit.id(), rule);
+    map_to_parent(commit, commits_map);
+}
+
+/// Map the commits folded in the copy of a linearized merge to this copy
+fn map_merged_commits<'a>(
+    commit: &git2::Commit,
+    copy: &SyncedCommit<'a>,
+    rewrites: &Rewrites,
+    commits_map: &mut CommitsMap<'a>,
+) {
+    for id in rewrites.merged.get(&commit.id()).into_iter().flatten() {
+        let synced = SyncedCommit {
+            commit: copy.commit.clone(),
+            uprooted: copy.uprooted,
+        };
+        commits_map.insert(*id, synced);
     }
+}
 
-    if local_parents.is_empty() {
-        assert!(opts.uproot);
-        // uproot the commit on HEAD
-        // XXX: head *has* a target, because we have at least the bootstrap
-        // commit.
-        head = repo.find_commit(repo.head().unwrap().target().unwrap())?;
-        local_parents.push(&head);
+/// Map a commit that is not copied to the local commit of its first known parent
+fn map_to_parent<'a>(commit: &git2::Commit, commits_map: &mut CommitsMap<'a>) {
+    let parent = commit
+        .parent_ids()
+        .find_map(|id| commits_map.get(id))
+        .map(|parent| SyncedCommit {
+            commit: parent.commit.clone(),
+            uprooted: parent.uprooted,
+        });
+    if let Some(parent) = parent {
+        commits_map.insert_uncached(commit.id(), parent);
     }
+}
 
-    Ok(SyncedCommit {
-        commit: do_cherrypick(
-            repo,
-            commit,
-            &local_parents,
-            is_merge,
-            uprooted,
-            branch,
-            opts,
-        )?,
-        uprooted,
-    })
+/// Persist the progress of the synchronization on the given commit
+///
+/// The branch is set on the last copied commit, and the cache is flushed, so that an
+/// interrupted synchronization can resume from this point.
+fn checkpoint(
+    repo: &git2::Repository,
+    branch: &app::Branch,
+    commits_map: &mut CommitsMap,
+    commit_id: git2::Oid,
+) -> Result<(), Error> {
+    setup_branch(repo, &branch.refname, &repo.find_commit(commit_id)?)?;
+    keep_refs::release(repo, commit_id)?;
+    commits_map.flush();
+    info!(
+        "Checkpoint: branch {} set on {}.",
+        log::branch(&branch.name),
+        commit_id
+    );
+    Ok(())
 }
 
-/// Sync the local repository with the new changes from the given remote
-/// false is returned if there was no commits to synchronize.
-pub fn sync_branch_with_remote<'a>(
-    repo: &'a git2::Repository,
+/// Make sure the local branch exists, recreating it if allowed
+///
+/// A missing branch is recreated on the local commit matching the most recent commit of
+/// the remote branch that was synchronized.
+pub fn ensure_branch_exists(
+    repo: &git2::Repository,
     branch: &app::Branch,
-    commits_map: &mut CommitsMap<'a>,
+    commits_map: &CommitsMap,
     opts: &app::Options,
-) -> Result<bool, Error> {
-    let local_commit = repo.revparse_single(&branch.name)?.peel_to_commit()?;
-
-    // Get the branch last commit in the remote
-    let remote_branch = repo.revparse_single(&format!("{}/{}", opts.remote, branch.name))?;
+) -> Result<(), Error> {
+    if repo.find_reference(&branch.refname).is_ok() {
+        return Ok(());
+    }
+    // a dry run cannot recreate the branch
+    if !branch.create_missing || opts.dry_run {
+        return Err(Error::MissingBranch {
+            branch: branch.name.clone(),
+        });
+    }
 
-    // Build revwalk from specified commit up to last commit in branch in remote
-    let commits = find_commits_to_sync(repo, local_commit.id(), &remote_branch, commits_map, opts)?;
+    let remote_branch = repo.revparse_single(&branch.tracking_refname)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL)?;
+    revwalk.push(remote_branch.id())?;
 
-    if commits.is_empty() {
-        println!(
-            "Nothing to synchronize on branch {}, already up to date with {}.",
-            branch.name, opts.remote
-        );
-        return Ok(false);
+    for oid in revwalk {
+        let oid = oid?;
+        if let Some(synced) = commits_map.get(oid) {
+            repo.reference(&branch.refname, synced.commit.id(), false, REFLOG_MSG)?;
+            info!(
+                "Recreated branch {} on commit {}, copy of {}.",
+                log::branch(&branch.name),
+                synced.commit.id(),
+                log::remote_commit(oid)
+            );
+            return Ok(());
+        }
     }
 
-    println!("Commits to synchronize on {}:", branch.name);
-    for ci in &commits {
-        print!(
-            "  Commit {id}\n    {author}\n    {summary}\n\n",
-            id = ci.id(),
-            author = ci.author(),
-            summary = ci.summary().unwrap_or("")
-        );
+    Err(Error::CannotRecreateBranch {
+        branch: branch.name.clone(),
+        remote: opts.remote.clone(),
+    })
+}
+
+/// Make sure no synchronization is stopped on conflicts before starting a new one
+///
+/// If the user committed the resolution of the conflicts, the synchronization is no longer
+/// stopped, and its state is removed.
+pub fn check_no_stopped_sync(repo: &git2::Repository, tags: &tag::Tags) -> Result<(), Error> {
+    let state = match state::read(repo)? {
+        Some(state) if state.export => return Err(state.stopped()),
+        Some(state) => state,
+        None => return Ok(()),
+    };
+
+    let head = repo.head().and_then(|head| head.peel_to_commit()).ok();
+    let head_tag = head.as_ref().and_then(|head| tags.retrieve_ripit_tag(head));
+    match (head_tag, &head) {
+        (Some((remote_id, _)), _) if remote_id == state.remote_id.to_string() => {
+            if let Ok(tree) = repo.head().and_then(|head| head.peel_to_tree()) {
+                rerere::record_resolutions(repo, &tree);
+            }
+            state::remove(repo);
+            Ok(())
+        }
+        // without its tags, the message of the commit of the user cannot tell it is the
+        // resolution. It is when made on the commit on which the copy was stopped, and the
+        // tags saved in the state are then recorded in its note.
+        (None, Some(head)) if state.head.is_some() && head.parent_id(0).ok() == state.head => {
+            let (_, note) = tags.split_tags(&state.message);
+            tags.notes()
+                .write(repo, &head.committer(), head.id(), &note)?;
+            rerere::record_resolutions(repo, &head.tree()?);
+            state::remove(repo);
+            Ok(())
+        }
+        _ => Err(state.stopped()),
     }
+}
 
-    if !opts.yes && !util::confirm_action() {
-        return Ok(true);
+/// Reset the conflicts of a stopped synchronization, to skip the commit that caused them
+///
+/// Returns the id of the remote commit to skip.
+pub fn reset_stopped_commit(repo: &git2::Repository) -> Result<git2::Oid, Error> {
+    let state = match state::read(repo)? {
+        // the export is resumed by the export subcommand
+        Some(state) if state.export => return Err(state.stopped()),
+        Some(state) => state,
+        None => return Err(Error::NoSyncInProgress),
+    };
+
+    repo.cleanup_state()?;
+    repo.reset(
+        repo.head()?.peel_to_commit()?.as_object(),
+        git2::ResetType::Hard,
+        None,
+    )?;
+    rerere::forget_preimages(repo);
+    state::remove(repo);
+    info!(
+        "Reset the conflicts of commit {}.",
+        log::remote_commit(state.remote_id)
+    );
+
+    Ok(state.remote_id)
+}
+
+/// Commit the resolution of the conflicts of a stopped synchronization
+///
+/// The message saved in the state is used, or built from the remote commit if missing. The
+/// copy is then mapped to the remote commit when the commits map is filled from HEAD.
+pub fn commit_resolved_conflicts(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let state = match state::read(repo)? {
+        // the export is resumed by the export subcommand
+        Some(state) if state.export => return Err(state.stopped()),
+        Some(state) => state,
+        None => return Err(Error::NoSyncInProgress),
+    };
+
+    let mut index = repo.index()?;
+    if index.has_conflicts() {
+        return Err(Error::UnresolvedConflicts {
+            paths: conflicting_paths(&index)?,
+        });
     }
 
-    // cherry-pick every commit, and add the rip-it tag in the commits messages
-    let mut last_commit_id = None;
-    for ci in &commits {
-        let copied_ci = copy_commit(repo, ci, commits_map, branch, opts)?;
+    let commit = repo.find_commit(state.remote_id)?;
+    let msg = if state.message.is_empty() {
+        let branch_name = match opts.branches.iter().find(|b| b.refname == state.refname) {
+            Some(branch) => branch.name.as_str(),
+            None => state.refname.trim_start_matches("refs/heads/"),
+        };
+        update_commit_msg(
+            commit.message().unwrap_or(""),
+            &format_tag(&commit, state.uprooted, &[], branch_name, tags, opts),
+            tags,
+            opts,
+        )?
+    } else {
+        state.message
+    };
 
-        // add mapping for this new pair
-        last_commit_id = Some(copied_ci.commit.id());
-        commits_map.insert(ci.id(), copied_ci);
+    // the other parents of a merge are saved in MERGE_HEAD, see fix_merge_ctx
+    let mut parents = vec![repo.head()?.peel_to_commit()?];
+    if let Ok(merge_head) = std::fs::read_to_string(repo.path().join("MERGE_HEAD")) {
+        for line in merge_head.lines() {
+            parents.push(repo.find_commit(git2::Oid::from_str(line.trim())?)?);
+        }
     }
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let (author, committer) = build_signatures(
+        opts.authors.map_signature(&commit.author())?,
+        opts.authors.map_signature(&commit.committer())?,
+        &commit,
+        opts,
+    )?;
+    let ci_oid = sign::Committer::new(repo, tags, opts).commit(
+        Some("HEAD"),
+        &author,
+        &committer,
+        &msg,
+        &tree,
+        &parents,
+    )?;
+    info!(
+        "Created commit {} with the resolution of the conflicts of {}.",
+        ci_oid,
+        log::remote_commit(commit.id())
+    );
 
-    // Set the branch on the last copied commit
-    if let Some(ci_id) = last_commit_id {
-        setup_branch(repo, &branch.name, &repo.find_commit(ci_id).unwrap())?;
+    // the branch follows the commit if HEAD was detached on it
+    let branch_id = repo.refname_to_id(&state.refname)?;
+    if !head_is_branch(repo, &state.refname)? && parents.iter().any(|p| p.id() == branch_id) {
+        repo.reference(&state.refname, ci_oid, true, REFLOG_MSG)?;
+        checkout_ref(repo, &state.refname)?;
     }
+    rerere::record_resolutions(repo, &tree);
+    force_checkout_head(repo, opts)?;
+    repo.cleanup_state()?;
+    state::remove(repo);
 
-    Ok(true)
+    Ok(())
 }
 
 // }}}
 // {{{ Bootstrap branch
 
-/// Cherrypick a given commit on top of HEAD, and add the ripit tag
+const DEFAULT_BOOTSTRAP_MESSAGE: &str =
+    "Bootstrap repository from {remote} {branch} at {remote_oid}";
+
+// the remote commit is not named with the cache-only and hmac provenances
+const HIDDEN_BOOTSTRAP_MESSAGE: &str = "Bootstrap repository from {remote} {branch}";
+
+/// Build the message of a bootstrap commit from the `bootstrap_message` template
+///
+/// The ripit tag is always added, as it is used to find the bootstrap commit.
+fn format_bootstrap_msg(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    remote_commit: &git2::Commit,
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<String, Error> {
+    // the remote may be replaced by its mirrors
+    let remote = repo.find_remote(&opts.remote).ok();
+    let remote_url = remote
+        .as_ref()
+        .and_then(|remote| remote.url())
+        .or_else(|| opts.remote_urls.first().map(String::as_str))
+        .unwrap_or("");
+    let template =
+        opts.bootstrap_message
+            .as_deref()
+            .unwrap_or(if opts.provenance.hides_remote_ids() {
+                HIDDEN_BOOTSTRAP_MESSAGE
+            } else {
+                DEFAULT_BOOTSTRAP_MESSAGE
+            });
+    let msg = template
+        .replace("{remote_oid}", &remote_commit.id().to_string())
+        .replace("{remote_url}", remote_url)
+        .replace("{remote}", &opts.remote)
+        .replace("{branch}", &branch.remote_name)
+        .replace("{date}", &util::format_date(&remote_commit.time()));
+
+    Ok(format!(
+        "{}\n\n{}\n",
+        msg.trim_end(),
+        tags.format_ripit_tag(remote_commit, false)
+    ))
+}
+
+/// Create the bootstrap commit of a branch, with the content of the given remote commit
 fn commit_bootstrap<'a>(
     repo: &'a git2::Repository,
+    tags: &tag::Tags,
     remote_commit: &git2::Commit,
-    remote: &str,
-) -> Result<git2::Commit<'a>, git2::Error> {
-    let msg = format!(
-        "Bootstrap repository from remote {}\n\nrip-it: {}\n",
-        remote,
-        remote_commit.id()
-    );
+    branch: &app::Branch,
+    opts: &app::Options,
+) -> Result<git2::Commit<'a>, Error> {
+    let msg = format_bootstrap_msg(repo, tags, remote_commit, branch, opts)?;
 
     // commit the whole index
     let head = match repo.head() {
@@ -439,82 +2766,142 @@ fn commit_bootstrap<'a>(
         parents.push(h);
     }
 
+    // with a prefix, the content of the local repository outside of it is kept
+    let mut tree = exclude::filter_tree(repo, &remote_commit.tree()?, opts)?;
+    if let Some(h) = head.as_ref() {
+        tree = exclude::graft_tree(repo, &h.tree()?, &tree, opts)?;
+    }
+
     let sig = repo.signature()?;
-    let commit_oid = repo.commit(
+    let (author, committer) = build_signatures(sig.clone(), sig, remote_commit, opts)?;
+    let commit_oid = sign::Committer::new(repo, tags, opts).commit(
         Some("HEAD"),
-        &sig,
-        &sig,
+        &author,
+        &committer,
         &msg,
-        &remote_commit.tree()?,
+        &tree,
         &parents,
     )?;
 
-    force_checkout_head(repo)?;
+    force_checkout_head(repo, opts)?;
 
-    repo.find_commit(commit_oid)
+    Ok(repo.find_commit(commit_oid)?)
 }
 
 /// Returns whether HEAD is currently tracking the given branch
-fn head_is_branch(repo: &git2::Repository, branch: &str) -> Result<bool, git2::Error> {
+fn head_is_branch(repo: &git2::Repository, refname: &str) -> Result<bool, git2::Error> {
     let head = repo.head()?;
 
-    Ok(if !head.is_branch() {
-        false
-    } else if let Some(name) = head.name() {
-        name == format!("refs/heads/{}", branch)
+    Ok(head.is_branch() && head.name() == Some(refname))
+}
+
+/// Returns whether HEAD is tracking a branch other than the given one
+///
+/// A detached HEAD is not on another branch, e.g. when solving the conflicts of a merge.
+fn head_is_other_branch(repo: &git2::Repository, refname: &str) -> bool {
+    match repo.head() {
+        Ok(head) => head.is_branch() && head.name() != Some(refname),
+        Err(_) => false,
+    }
+}
+
+fn is_heads_ref(refname: &str) -> bool {
+    refname.starts_with("refs/heads/")
+}
+
+/// Checkout the local branch
+///
+/// HEAD can only track branches of the heads namespace, it is detached on other refs.
+fn checkout_branch(repo: &git2::Repository, branch: &app::Branch) -> Result<(), git2::Error> {
+    checkout_ref(repo, &branch.refname)
+}
+
+/// Checkout a local ref, detaching HEAD if it is not in the heads namespace
+pub fn checkout_ref(repo: &git2::Repository, refname: &str) -> Result<(), git2::Error> {
+    if is_heads_ref(refname) {
+        repo.set_head(refname)
     } else {
-        false
-    })
+        repo.set_head_detached(repo.refname_to_id(refname)?)
+    }
 }
 
 /// Create or set the branch to this commit
 fn setup_branch(
     repo: &git2::Repository,
-    branch: &str,
+    refname: &str,
     commit: &git2::Commit,
 ) -> Result<(), git2::Error> {
-    if !head_is_branch(repo, branch)? {
-        repo.branch(branch, commit, true)?;
+    if !head_is_branch(repo, refname)? {
+        repo.reference(refname, commit.id(), true, REFLOG_MSG)?;
     }
     Ok(())
 }
 
+/// Find the commit `n` first-parent commits before a remote commit
+///
+/// The root commit is returned if the history is shorter.
+fn find_history_start(commit: git2::Commit, n: usize) -> git2::Commit {
+    let mut start = commit;
+    for _ in 0..n {
+        match start.parent(0) {
+            Ok(parent) => start = parent,
+            Err(_) => break,
+        }
+    }
+    start
+}
+
 /// Bootstrap the branch in the local repo with the state of the branch in the remote repo
 ///
 /// Create a commit that will contain the whole index of the remote's branch HEAD, with the
-/// appropriate ripit tag.
+/// appropriate ripit tag. With keep_history, the commit is created from an older remote
+/// commit, the following ones being then synchronized.
 /// Following this bootstrap, synchronisation between the two repos will be possible.
 pub fn bootstrap_branch_with_remote<'a>(
     repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    commits_map: &mut CommitsMap<'a>,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    bootstrap_branch(repo, tags, branch, commits_map, opts)
+        .map_err(|err| err.context(Operation::Bootstrap, &branch.name, None))
+}
+
+fn bootstrap_branch<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
     branch: &app::Branch,
     commits_map: &mut CommitsMap<'a>,
     opts: &app::Options,
 ) -> Result<(), Error> {
     // Get the branch last commit in the remote
-    let remote_branch = repo.revparse_single(&format!("{}/{}", opts.remote, branch.name))?;
-    let remote_commit = remote_branch.peel_to_commit()?;
+    let mut remote_commit = find_sync_target(repo, branch, opts)?;
+    if let Some(n) = opts.keep_history {
+        remote_commit = find_history_start(remote_commit, n);
+    }
 
     match commits_map.get(remote_commit.id()) {
         Some(ci) => {
             // If the commit exists in the CommitsMap, it means it was created
             // when boostrapping another branch: we can re-use this commit.
-            println!(
+            info!(
                 "Re-use commit {} to bootstrap branch {}.",
                 ci.commit.id(),
-                branch.name
+                log::branch(&branch.name)
             );
-            setup_branch(repo, &branch.name, &ci.commit)?;
+            setup_branch(repo, &branch.refname, &ci.commit)?;
         }
         None => {
             // build the bootstrap commit from the state of this commit
-            let commit = commit_bootstrap(repo, &remote_commit, &opts.remote)?;
-            println!(
+            let commit = commit_bootstrap(repo, tags, &remote_commit, branch, opts)?;
+            info!(
                 "Bootstrap commit {} created for branch {}.",
                 commit.id(),
-                branch.name
+                log::branch(&branch.name)
             );
 
-            setup_branch(repo, &branch.name, &commit)?;
+            setup_branch(repo, &branch.refname, &commit)?;
             commits_map.insert(
                 remote_commit.id(),
                 SyncedCommit {
@@ -528,4 +2915,143 @@ pub fn bootstrap_branch_with_remote<'a>(
     Ok(())
 }
 
+/// Adopt a local branch whose tree is the one of a remote revision
+///
+/// The local branch was maintained without ripit: instead of a bootstrap commit, an empty
+/// commit with the ripit tag of the remote revision is added on top of it, from which
+/// synchronizations can start. The trees are compared once the remote one is filtered as a
+/// bootstrap would be.
+pub fn adopt_branch<'a>(
+    repo: &'a git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    rev: &str,
+    commits_map: &mut CommitsMap<'a>,
+    opts: &app::Options,
+) -> Result<(), Error> {
+    let head = find_sync_target(repo, branch, opts)?;
+    let remote_commit = resolve_remote_rev(repo, &head, rev, branch, opts)?;
+    let local_commit = match repo.find_reference(&branch.refname) {
+        Ok(reference) => reference.peel_to_commit()?,
+        Err(_) => {
+            return Err(Error::MissingBranch {
+                branch: branch.name.clone(),
+            })
+        }
+    };
+
+    let local_tree = local_commit.tree()?;
+    let remote_tree = exclude::filter_tree(repo, &remote_commit.tree()?, opts)?;
+    let remote_tree = exclude::graft_tree(repo, &local_tree, &remote_tree, opts)?;
+    if remote_tree.id() != local_tree.id() {
+        let diff = repo.diff_tree_to_tree(Some(&local_tree), Some(&remote_tree), None)?;
+        let diffstat = diff.stats()?.to_buf(git2::DiffStatsFormat::FULL, 80)?;
+        return Err(Error::AdoptTreeMismatch {
+            branch: branch.name.clone(),
+            rev: rev.to_owned(),
+            remote: opts.remote.clone(),
+            diffstat: diffstat.as_str().unwrap_or("").to_owned(),
+        });
+    }
+
+    let adopted = if opts.provenance.hides_remote_ids() {
+        opts.remote.clone()
+    } else {
+        format!("{} {}", opts.remote, remote_commit.id())
+    };
+    let msg = format!(
+        "Adopt branch {} as {}\n\n{}\n",
+        branch.name,
+        adopted,
+        tags.format_ripit_tag(&remote_commit, false)
+    );
+    let sig = repo.signature()?;
+    let (author, committer) = build_signatures(sig.clone(), sig, &remote_commit, opts)?;
+    let commit_oid = sign::Committer::new(repo, tags, opts).commit(
+        Some(&branch.refname),
+        &author,
+        &committer,
+        &msg,
+        &local_tree,
+        &[&local_commit],
+    )?;
+    let commit = repo.find_commit(commit_oid)?;
+    info!(
+        "Branch {} adopted as {} with commit {}.",
+        log::branch(&branch.name),
+        log::remote_commit(remote_commit.id()),
+        commit_oid
+    );
+
+    commits_map.insert(
+        remote_commit.id(),
+        SyncedCommit {
+            commit,
+            uprooted: false,
+        },
+    );
+    Ok(())
+}
+
 // }}}
+
+#[test]
+fn test_confirm_sync() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = git2::Repository::init(dir.path()).unwrap();
+    let sig = git2::Signature::now("John Doe", "john@doe.com").unwrap();
+    let commit = |files: &[(&str, &str)]| {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (name, content) in files {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let oid = repo
+            .commit(Some("HEAD"), &sig, &sig, "msg", &tree, &parents)
+            .unwrap();
+        repo.find_commit(oid).unwrap()
+    };
+    let commits = vec![
+        commit(&[("a", "1\n2\n")]),
+        commit(&[("a", "1\n3\n"), ("b", "1\n")]),
+    ];
+    let skipped = HashMap::new();
+    let plan = autosquash::Plan::default();
+
+    let new_opts = || app::Options::new(dir.path().to_str().unwrap(), "origin", &["master"]);
+    let mut opts = new_opts().unwrap();
+    let branch_opts = new_opts().unwrap();
+    let branch = &branch_opts.branches[0];
+    // no diffstat without confirmation
+    let desc = format_pending(&repo, &commits, &skipped, &plan, &opts).unwrap();
+    assert!(!desc.contains("changed"));
+
+    opts.yes = false;
+    let desc = format_pending(&repo, &commits, &skipped, &plan, &opts).unwrap();
+    assert!(desc.contains("    1 file changed, 2 insertions(+), 0 deletions(-)\n"));
+    assert!(desc.contains("    2 files changed, 2 insertions(+), 1 deletion(-)\n"));
+    assert!(desc.ends_with("Total: 3 files changed, 4 insertions(+), 1 deletion(-)\n\n"));
+
+    // only the total is given for many commits
+    opts.diffstat_max_commits = 1;
+    let desc = format_pending(&repo, &commits, &skipped, &plan, &opts).unwrap();
+    assert_eq!(desc.matches("changed").count(), 1);
+
+    opts.confirm = Box::new(|question| {
+        assert_eq!(question, "Is this ok?");
+        Ok(app::Answer::Yes)
+    });
+    assert!(confirm_sync(&repo, &commits, &skipped, &plan, branch, &opts).unwrap());
+    opts.confirm = Box::new(|_| Ok(app::Answer::No));
+    assert!(!confirm_sync(&repo, &commits, &skipped, &plan, branch, &opts).unwrap());
+    opts.confirm = Box::new(|_| Ok(app::Answer::Abort));
+    assert!(matches!(
+        confirm_sync(&repo, &commits, &skipped, &plan, branch, &opts),
+        Err(Error::ConfirmAborted { .. })
+    ));
+    opts.yes = true;
+    assert!(confirm_sync(&repo, &commits, &skipped, &plan, branch, &opts).unwrap());
+}
@@ -1,35 +1,283 @@
 use crate::error::Error;
+use crate::mac;
+use crate::notes;
+use std::borrow::Cow;
 
-/// Parse the commit message to retrieve the SHA-1 stored as a ripit tag
-///
-/// If the commit message contains the string `rip-it: <sha-1>`, the sha-1 is returned
-pub fn retrieve_ripit_tag(commit: &git2::Commit) -> Option<(String, bool)> {
-    let msg = commit.message()?;
-    let tag_index = msg.find("rip-it: ")?;
-    let sha1_start = tag_index + 8;
+// prefix of the tags, still recognized when another prefix is configured
+pub const DEFAULT_PREFIX: &str = "rip-it";
+// suffix of the prefix of the tags replacing the ripit tags of the remote commits, when
+// neutralized
+const FOREIGN_TAG: &str = "-upstream";
+// suffix of the prefix of the tags listing the remote commits folded in a synchronized
+// commit
+const SQUASHED_TAG: &str = "-squashed";
+// suffix of the prefix of the tags of the commits exported to the remote
+const EXPORT_TAG: &str = "-export";
 
-    if msg.len() >= sha1_start + 40 {
-        let sha1 = msg[(sha1_start)..(sha1_start + 40)].to_owned();
-        let sha1_end = &msg[(sha1_start + 40)..];
+/// Ripit tags of the commits, read and written with the settings of a run
+pub struct Tags {
+    // prefix of the tags written in the commit messages
+    prefix: String,
+    // notes of the repository, holding the tags not written in the commit messages
+    notes: notes::Notes,
+    // MACs written in the tags instead of the ids of the remote commits
+    macs: mac::Macs,
+}
 
-        Some((sha1, sha1_end.starts_with(" uprooted")))
-    } else {
-        None
+impl Default for Tags {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_PREFIX,
+            notes::Notes::default(),
+            mac::Macs::default(),
+        )
     }
 }
 
-pub fn retrieve_ripit_tag_or_throw(commit: &git2::Commit) -> Result<(String, bool), Error> {
-    match retrieve_ripit_tag(commit) {
-        Some(v) => Ok(v),
-        // FIXME: this error should mention the commit oid
-        None => Err(Error::TagMissing),
+impl Tags {
+    pub fn new(prefix: &str, notes: notes::Notes, macs: mac::Macs) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            notes,
+            macs,
+        }
+    }
+
+    /// Notes of the repository, in which the tags of the commits are recorded
+    pub fn notes(&self) -> &notes::Notes {
+        &self.notes
+    }
+
+    /// MACs of the remote commits, with the `hmac` provenance
+    pub fn macs(&self) -> &mac::Macs {
+        &self.macs
+    }
+
+    /// Strip a tag of the given kind from a line, returning the value of the tag
+    ///
+    /// Tags written with the default prefix are recognized as well, so that changing the
+    /// prefix does not lose the history of the synchronization.
+    fn strip_tag<'l>(&self, line: &'l str, kind: &str) -> Option<&'l str> {
+        [self.prefix.as_str(), DEFAULT_PREFIX]
+            .iter()
+            .find_map(|prefix| {
+                line.strip_prefix(prefix)?
+                    .strip_prefix(kind)?
+                    .strip_prefix(": ")
+            })
+    }
+
+    /// Message of a commit, followed by its note containing its tags, if any
+    fn tagged_message<'c>(&self, commit: &'c git2::Commit) -> Option<Cow<'c, str>> {
+        let msg = commit.message()?;
+        match self.notes.get(commit.id()) {
+            Some(note) => Some(Cow::Owned(format!("{}\n{}", msg, note))),
+            None => Some(Cow::Borrowed(msg)),
+        }
+    }
+
+    /// Parse the commit message to retrieve the SHA-1 stored as a ripit tag
+    ///
+    /// If the commit message contains a line starting with `rip-it: <sha-1>`, the sha-1 is
+    /// returned. As the tag is appended to the message, only the last such line is
+    /// considered: previous ones come from the remote commit, if the remote is itself a
+    /// mirror. The tags recorded in the note of the commit come after its message.
+    ///
+    /// With the hmac provenance, the tag contains the MAC of the sha-1, which is returned
+    /// as is if no known remote commit matches it.
+    pub fn retrieve_ripit_tag(&self, commit: &git2::Commit) -> Option<(String, bool)> {
+        let msg = self.tagged_message(commit)?;
+        let tag = msg
+            .lines()
+            .rev()
+            .find_map(|line| self.strip_tag(line, ""))?;
+
+        let len = if mac::is_mac(tag) {
+            tag.find(' ').unwrap_or(tag.len())
+        } else {
+            40
+        };
+        let sha1 = self.macs.resolve(tag.get(..len)?);
+        Some((sha1, tag[len..].starts_with(" uprooted")))
+    }
+
+    /// Parse the ripit tag of a commit split in parts, to retrieve the part index and count
+    ///
+    /// The tag of each part of a split commit is suffixed with ` part <index>/<count>`.
+    pub fn retrieve_part(&self, commit: &git2::Commit) -> Option<(u32, u32)> {
+        let msg = self.tagged_message(commit)?;
+        let line = msg.lines().rev().find(|line| self.is_ripit_tag(line))?;
+        let (_, part) = line.split_once(" part ")?;
+        let (index, count) = part.trim().split_once('/')?;
+
+        Some((index.parse().ok()?, count.parse().ok()?))
+    }
+
+    /// Returns whether the commit is a part of a split commit, other than the last one
+    ///
+    /// Only the last part is mapped to the remote commit.
+    pub fn is_partial(&self, commit: &git2::Commit) -> bool {
+        matches!(self.retrieve_part(commit), Some((index, count)) if index < count)
+    }
+
+    /// Parse the commit message to retrieve the remote commits folded in the commit
+    ///
+    /// Those are listed in `rip-it-squashed: <sha-1>` lines, just before the ripit tag.
+    pub fn retrieve_squashed(&self, commit: &git2::Commit) -> Vec<git2::Oid> {
+        let msg = self.tagged_message(commit).unwrap_or_default();
+        let mut lines = msg
+            .lines()
+            .rev()
+            .skip_while(|line| !self.is_ripit_tag(line));
+        // skip the ripit tag itself
+        lines.next();
+
+        let mut ids: Vec<git2::Oid> = lines
+            .map_while(|line| self.strip_tag(line, SQUASHED_TAG))
+            .filter_map(|sha1| git2::Oid::from_str(&self.macs.resolve(sha1.trim())).ok())
+            .collect();
+        ids.reverse();
+        ids
+    }
+
+    /// Returns whether the line of a commit message is a ripit tag
+    pub fn is_ripit_tag(&self, line: &str) -> bool {
+        self.strip_tag(line, "").is_some()
+    }
+
+    /// Split the tags added by ripit from a message, returning the message and the tags
+    ///
+    /// The tags are the last ripit tag, and the squashed tags preceding it. The other
+    /// lines, such as the trailers, are kept in the message.
+    pub fn split_tags(&self, msg: &str) -> (String, String) {
+        let lines: Vec<&str> = msg.lines().collect();
+        let end = match lines.iter().rposition(|line| self.is_ripit_tag(line)) {
+            Some(pos) => pos + 1,
+            None => return (msg.to_owned(), String::new()),
+        };
+        let start = lines[..end - 1]
+            .iter()
+            .rposition(|line| self.strip_tag(line, SQUASHED_TAG).is_none())
+            .map_or(0, |pos| pos + 1);
+
+        let mut kept = lines[..start].to_vec();
+        kept.extend(&lines[end..]);
+        let msg = kept.join("\n");
+        (
+            format!("{}\n", msg.trim_end()),
+            lines[start..end].join("\n"),
+        )
+    }
+
+    /// Rename a ripit tag, so that it is no longer recognized as one
+    pub fn neutralize_ripit_tag(&self, line: &str) -> String {
+        format!(
+            "{}{}: {}",
+            self.prefix,
+            FOREIGN_TAG,
+            self.strip_tag(line, "").unwrap_or(line)
+        )
+    }
+
+    /// Returns whether the commit is a bootstrap commit
+    ///
+    /// A bootstrap commit is the only commit with a ripit tag without any parent with a
+    /// ripit tag.
+    pub fn is_bootstrap(&self, commit: &git2::Commit) -> bool {
+        self.retrieve_ripit_tag(commit).is_some()
+            && !commit
+                .parents()
+                .any(|parent| self.retrieve_ripit_tag(&parent).is_some())
+    }
+
+    pub fn retrieve_ripit_tag_or_throw(
+        &self,
+        commit: &git2::Commit,
+    ) -> Result<(String, bool), Error> {
+        match self.retrieve_ripit_tag(commit) {
+            Some(v) => Ok(v),
+            // FIXME: this error should mention the commit oid
+            None => Err(self.notes.tag_missing()),
+        }
+    }
+
+    pub fn format_ripit_tag(&self, commit: &git2::Commit, uprooted: bool) -> String {
+        format!(
+            "{}: {}{}",
+            self.prefix,
+            self.macs.format(commit.id()),
+            if uprooted { " uprooted" } else { "" }
+        )
+    }
+
+    /// Format the tag of a commit in which other remote commits were folded
+    pub fn format_squashed_tag(&self, tag: &str, squashed: &[git2::Oid]) -> String {
+        let mut new_tag = String::new();
+        for id in squashed {
+            new_tag.push_str(&format!(
+                "{}{}: {}\n",
+                self.prefix,
+                SQUASHED_TAG,
+                self.macs.format(*id)
+            ));
+        }
+        new_tag.push_str(tag);
+        new_tag
+    }
+
+    /// Parse the commit message to retrieve the SHA-1 stored as a ripit export tag
+    ///
+    /// If the commit message contains the string `rip-it-export: <sha-1>`, the commit was
+    /// exported from the local repository, and the sha-1 of the local commit is returned.
+    pub fn retrieve_export_tag(&self, commit: &git2::Commit) -> Option<String> {
+        let msg = commit.message()?;
+
+        [self.prefix.as_str(), DEFAULT_PREFIX]
+            .iter()
+            .find_map(|prefix| {
+                let tag = format!("{}{}: ", prefix, EXPORT_TAG);
+                let sha1_start = msg.find(&tag)? + tag.len();
+
+                msg.get(sha1_start..(sha1_start + 40)).map(|s| s.to_owned())
+            })
+    }
+
+    pub fn format_export_tag(&self, commit: &git2::Commit) -> String {
+        format!("{}{}: {}", self.prefix, EXPORT_TAG, commit.id())
     }
 }
 
-pub fn format_ripit_tag(commit: &git2::Commit, uprooted: bool) -> String {
-    format!(
-        "rip-it: {}{}",
-        commit.id(),
-        if uprooted { " uprooted" } else { "" }
-    )
+/// Format the tag of a part of a split commit, from the tag of the commit
+pub fn format_part_tag(tag: &str, name: &str, index: usize, count: usize) -> String {
+    format!("[part: {}]\n{} part {}/{}", name, tag, index, count)
+}
+
+#[test]
+fn test_split_tags() {
+    let id = "0123456789012345678901234567890123456789";
+    let msg = format!(
+        "title\n\nrip-it-upstream: {id}\n\nSigned-off-by: Foo <foo@bar>\n\
+         rip-it-squashed: {id}\nrip-it: {id} uprooted\n",
+        id = id
+    );
+    let (stripped, tags) = Tags::default().split_tags(&msg);
+    assert_eq!(
+        stripped,
+        format!(
+            "title\n\nrip-it-upstream: {}\n\nSigned-off-by: Foo <foo@bar>\n",
+            id
+        )
+    );
+    assert_eq!(
+        tags,
+        format!("rip-it-squashed: {id}\nrip-it: {id} uprooted", id = id)
+    );
+
+    let (stripped, tags) = Tags::default().split_tags("[part: doc]\nrip-it: 0123 part 1/2");
+    assert_eq!(stripped, "[part: doc]\n");
+    assert_eq!(tags, "rip-it: 0123 part 1/2");
+    assert_eq!(
+        Tags::default().split_tags("title\n"),
+        ("title\n".to_owned(), String::new())
+    );
 }
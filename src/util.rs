@@ -1,25 +1,434 @@
+use crate::app;
+use crate::error::Error;
+use crate::log;
+use std::io::{BufRead, IsTerminal};
 // for stdout().flush
 use std::io::Write;
+use std::path::PathBuf;
 
-/// Display a prompt asking for confirmation by the user
+// invalid answers after which the question is abandoned
+const MAX_INVALID_ANSWERS: usize = 5;
+
+/// Display the given question, and read a yes or no answer from the input
 ///
-/// Returns true if the user confirmed, false in all other cases
-pub fn confirm_action() -> bool {
-    let mut input = String::new();
+/// `y`, `yes`, `n` and `no` are accepted in any case, and an empty answer is the default
+/// one. The question is asked again on invalid answers, and abandoned if the input is
+/// closed, or after too many invalid answers.
+pub fn read_answer<R: BufRead>(question: &str, default_yes: bool, input: &mut R) -> app::Answer {
+    let mut line = String::new();
 
-    loop {
-        print!("Is this ok? [yN] ");
+    for _ in 0..MAX_INVALID_ANSWERS {
+        crate::out!("{} [{}] ", question, if default_yes { "Yn" } else { "yN" });
         std::io::stdout().flush().unwrap();
 
-        if std::io::stdin().read_line(&mut input).is_err() {
-            return false;
+        line.clear();
+        match input.read_line(&mut line) {
+            // no answer can be read once the input is closed
+            Ok(0) | Err(_) => return app::Answer::Abort,
+            Ok(_) => (),
+        }
+
+        match line.trim().to_lowercase().as_str() {
+            "" if default_yes => return app::Answer::Yes,
+            "" => return app::Answer::No,
+            "y" | "yes" => return app::Answer::Yes,
+            "n" | "no" => return app::Answer::No,
+            _ => crate::outln!("Please answer yes or no."),
+        }
+    }
+    app::Answer::Abort
+}
+
+/// Ask the given question to the user on the terminal
+///
+/// Fails if the standard input is not a terminal, as in cron jobs or CI, instead of
+/// waiting for an answer that will never come.
+pub fn confirm(question: &str, default_yes: bool) -> Result<app::Answer, Error> {
+    // the question must come after the messages leading to it
+    log::flush_deferred();
+    if !test_interactive() && !std::io::stdin().is_terminal() {
+        return Err(Error::CannotConfirm {
+            question: question.to_owned(),
+        });
+    }
+    Ok(read_answer(
+        question,
+        default_yes,
+        &mut std::io::stdin().lock(),
+    ))
+}
+
+/// Returns whether ripit runs in a terminal, and can thus interact with the user
+pub fn is_interactive() -> bool {
+    test_interactive() || (std::io::stdin().is_terminal() && std::io::stdout().is_terminal())
+}
+
+/// Returns whether tests force the interaction with the user, without a terminal
+///
+/// This is set by the `RIPIT_TEST_INTERACTIVE` environment variable, which release builds
+/// ignore.
+#[cfg(debug_assertions)]
+fn test_interactive() -> bool {
+    std::env::var_os("RIPIT_TEST_INTERACTIVE").is_some()
+}
+
+#[cfg(not(debug_assertions))]
+fn test_interactive() -> bool {
+    false
+}
+
+/// Abort the process once the given number of commits have been copied
+///
+/// This is only used by tests, to simulate a synchronization killed midway, when the
+/// `RIPIT_TEST_ABORT_AFTER` environment variable is set. Release builds ignore it.
+#[cfg(debug_assertions)]
+pub fn test_abort_hook(nb_copied: usize) {
+    if let Ok(limit) = std::env::var("RIPIT_TEST_ABORT_AFTER") {
+        if limit.parse() == Ok(nb_copied) {
+            crate::error!("Aborting after {} copied commits.", nb_copied);
+            // killed without unwinding, as an interrupted run would be
+            let pid = std::process::id().to_string();
+            let _ = std::process::Command::new("kill")
+                .args(["-KILL", &pid])
+                .status();
+            std::process::abort();
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub fn test_abort_hook(_nb_copied: usize) {}
+
+/// Return the path to the git directory shared by every worktree of the repository
+///
+/// For a linked worktree, `Repository::path` returns the private git directory of the
+/// worktree, which contains a `commondir` file pointing to the shared one.
+pub fn common_dir(repo: &git2::Repository) -> PathBuf {
+    let path = repo.path();
+
+    if repo.is_worktree() {
+        if let Ok(commondir) = std::fs::read_to_string(path.join("commondir")) {
+            let commondir = path.join(commondir.trim());
+            return commondir.canonicalize().unwrap_or(commondir);
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Expand the `${VAR}` and `$VAR` references to variables in a string
+///
+/// Variables are resolved with the given function. `$$` is an escaped `$`, and a `$` not
+/// followed by a variable name is kept as is. On error, the name of the first unresolved
+/// variable is returned.
+pub fn expand_vars<F>(value: &str, lookup: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut res = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find('$') {
+        res.push_str(&rest[..pos]);
+        rest = &rest[(pos + 1)..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            res.push('$');
+            rest = after;
+            continue;
+        }
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[(end + 1)..]),
+                None => ("", rest),
+            },
+            None => {
+                let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(is_name_char);
+        if !valid {
+            res.push('$');
+            continue;
+        }
+
+        match lookup(name) {
+            Some(value) => res.push_str(&value),
+            None => return Err(name.to_owned()),
+        }
+        rest = after;
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+/// Expand the references to environment variables in a string, see `expand_vars`
+pub fn expand_env(value: &str) -> Result<String, String> {
+    expand_vars(value, |name| std::env::var(name).ok())
+}
+
+/// Number of days since 1970-01-01 of a date in the proleptic gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+/// Parse a date in the format YYYY-MM-DD, and return the matching UTC timestamp
+pub fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86400)
+}
+
+/// Parse an interval in seconds, optionally suffixed with `s`, `m` or `h`
+pub fn parse_interval(value: &str) -> Option<u64> {
+    let (number, unit) = match value.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &value[number.len()..]),
+        None => (value, "s"),
+    };
+    let number: u64 = number.parse().ok()?;
+    let factor = match unit {
+        "m" => 60,
+        "h" => 3600,
+        _ => 1,
+    };
+    number.checked_mul(factor).filter(|&secs| secs > 0)
+}
+
+/// Return the year, month and day of a git time, in the timezone of the time
+fn civil_date(time: &git2::Time) -> (i64, u32, u32) {
+    let secs = time.seconds() + i64::from(time.offset_minutes()) * 60;
+    let days = secs.div_euclid(86400);
+
+    // inverse of days_from_civil
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month as u32, day as u32)
+}
+
+/// Return the year and month of a git time, in the timezone of the time
+pub fn year_month(time: &git2::Time) -> (i64, u32) {
+    let (year, month, _) = civil_date(time);
+
+    (year, month)
+}
+
+/// Format a git time as YYYY-MM-DD, in the timezone of the time
+pub fn format_date(time: &git2::Time) -> String {
+    let (year, month, day) = civil_date(time);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Levenshtein distance between two strings, counted in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { diag } else { diag + 1 };
+            diag = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(diag + 1);
         }
+    }
+    row[b.len()]
+}
+
+/// Match a name against a glob pattern
+///
+/// `*` matches any sequence of characters and `?` any single character, both without
+/// matching `/`, so that `release/*` does not match `release/1.0/hotfix`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
 
-        match input.trim() {
-            "y" | "Y" => return true,
-            "n" | "N" => return false,
-            _ => (),
+    // position after the last star, and the position in the name it currently matches up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some('?') if name[n] != '/' => {
+                p += 1;
+                n += 1;
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            // backtrack to the last star, making it match one more character
+            _ => match star {
+                Some((star_p, star_n)) if name[star_n] != '/' => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                }
+                _ => return false,
+            },
         }
-        input.clear();
     }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Find the candidate closest to a misspelled word, if it is close enough to be a typo
+pub fn closest_match<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max_distance = std::cmp::max(1, word.chars().count() / 3);
+
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(word, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("", ""), 0);
+    assert_eq!(edit_distance("filters", "filters"), 0);
+    assert_eq!(edit_distance("filter", "filters"), 1);
+    assert_eq!(edit_distance("remtoe", "remote"), 2);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "repo"), 4);
+
+    let candidates = ["repo", "remote", "branch", "branches"];
+    assert_eq!(closest_match("filter", &candidates), None);
+    assert_eq!(closest_match("remot", &candidates), Some("remote"));
+    assert_eq!(closest_match("branchs", &candidates), Some("branch"));
+    assert_eq!(closest_match("branchess", &candidates), Some("branches"));
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("master", "master"));
+    assert!(!glob_match("master", "master2"));
+    assert!(glob_match("release/*", "release/1.0"));
+    assert!(glob_match("release/*", "release/"));
+    assert!(!glob_match("release/*", "release/1.0/hotfix"));
+    assert!(!glob_match("release/*", "releases/1.0"));
+    assert!(glob_match("release/*-rc", "release/1.0-rc"));
+    assert!(!glob_match("release/*-rc", "release/1.0-rc2"));
+    assert!(glob_match("*/*-rc*", "release/1.0-rc2"));
+    assert!(glob_match("v?.*", "v1.2"));
+    assert!(!glob_match("v?.*", "v10.2"));
+    assert!(!glob_match("a?b", "a/b"));
+}
+
+#[test]
+fn test_dates() {
+    assert_eq!(parse_date("1970-01-01"), Some(0));
+    assert_eq!(parse_date("2019-08-16"), Some(1565913600));
+    assert_eq!(parse_date("2019-13-16"), None);
+    assert_eq!(parse_date("2019-08"), None);
+    assert_eq!(parse_date("foo"), None);
+
+    assert_eq!(year_month(&git2::Time::new(0, 0)), (1970, 1));
+    assert_eq!(year_month(&git2::Time::new(1565913600, 0)), (2019, 8));
+    // timezone is taken into account
+    assert_eq!(year_month(&git2::Time::new(1564617599, 0)), (2019, 7));
+    assert_eq!(year_month(&git2::Time::new(1564617599, 60)), (2019, 8));
+    assert_eq!(year_month(&git2::Time::new(951782400, 0)), (2000, 2));
+
+    assert_eq!(format_date(&git2::Time::new(0, 0)), "1970-01-01");
+    assert_eq!(format_date(&git2::Time::new(1565913600, 0)), "2019-08-16");
+    assert_eq!(format_date(&git2::Time::new(951782400, 0)), "2000-02-29");
+    assert_eq!(format_date(&git2::Time::new(1564617599, 60)), "2019-08-01");
+}
+
+#[test]
+fn test_parse_interval() {
+    assert_eq!(parse_interval("90"), Some(90));
+    assert_eq!(parse_interval("30s"), Some(30));
+    assert_eq!(parse_interval("5m"), Some(300));
+    assert_eq!(parse_interval("2h"), Some(7200));
+    assert_eq!(parse_interval("0"), None);
+    assert_eq!(parse_interval("m"), None);
+    assert_eq!(parse_interval("1d"), None);
+}
+
+#[test]
+fn test_expand_vars() {
+    let lookup = |name: &str| match name {
+        "ROOT" => Some("/srv".to_owned()),
+        "TOKEN_2" => Some("s3cr3t".to_owned()),
+        _ => None,
+    };
+
+    assert_eq!(expand_vars("no vars", lookup).unwrap(), "no vars");
+    assert_eq!(
+        expand_vars("${ROOT}/public", lookup).unwrap(),
+        "/srv/public"
+    );
+    assert_eq!(expand_vars("$ROOT/public", lookup).unwrap(), "/srv/public");
+    assert_eq!(
+        expand_vars("^token: $TOKEN_2$", lookup).unwrap(),
+        "^token: s3cr3t$"
+    );
+
+    // missing variables are reported by name
+    assert_eq!(
+        expand_vars("${MISSING}/a", lookup),
+        Err("MISSING".to_owned())
+    );
+    assert_eq!(
+        expand_vars("$ROOT/$MISSING", lookup),
+        Err("MISSING".to_owned())
+    );
+
+    // escaping, and dollars not followed by a variable name
+    assert_eq!(expand_vars("$$ROOT", lookup).unwrap(), "$ROOT");
+    assert_eq!(expand_vars("a$$$ROOT", lookup).unwrap(), "a$/srv");
+    assert_eq!(expand_vars("^Refs$", lookup).unwrap(), "^Refs$");
+    assert_eq!(
+        expand_vars("$1 ${} ${ROOT", lookup).unwrap(),
+        "$1 ${} ${ROOT"
+    );
+}
+
+#[test]
+fn test_read_answer() {
+    let answer = |input: &str, default_yes| {
+        read_answer("Continue?", default_yes, &mut std::io::Cursor::new(input))
+    };
+
+    assert_eq!(answer("y\n", false), app::Answer::Yes);
+    assert_eq!(answer("YES\n", false), app::Answer::Yes);
+    assert_eq!(answer("No\n", true), app::Answer::No);
+    assert_eq!(answer(" n \n", true), app::Answer::No);
+    // the default answer is used for empty answers
+    assert_eq!(answer("\n", false), app::Answer::No);
+    assert_eq!(answer("\n", true), app::Answer::Yes);
+    // invalid answers are asked again
+    assert_eq!(answer("maybe\nyes\n", false), app::Answer::Yes);
+    // the question is abandoned on EOF, or after too many invalid answers
+    assert_eq!(answer("", true), app::Answer::Abort);
+    assert_eq!(answer("maybe", false), app::Answer::Abort);
+    assert_eq!(answer(&"what\n".repeat(10), false), app::Answer::Abort);
 }
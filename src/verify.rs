@@ -0,0 +1,252 @@
+use crate::app;
+use crate::commits_map;
+use crate::error::Error;
+use crate::exclude;
+use crate::mac;
+use crate::tag;
+use std::collections::{HashMap, HashSet};
+
+/// Inconsistencies found while verifying the branches, printed as they are found
+#[derive(Default)]
+struct Report {
+    nb_problems: usize,
+}
+
+impl Report {
+    fn problem(&mut self, msg: &str) {
+        println!("inconsistency: {}", msg);
+        self.nb_problems += 1;
+    }
+}
+
+/// Remote commits referenced by the ripit tags of the local commits
+#[derive(Default)]
+struct Copies {
+    // remote commit -> local commits whose ripit tag references it, or in which it was
+    // folded
+    copies: HashMap<git2::Oid, Vec<git2::Oid>>,
+    // local commits reachable from the branches
+    reachable: HashSet<git2::Oid>,
+}
+
+/// Check the ripit tags of the commits of a local branch
+///
+/// Every remote commit referenced by a tag must exist, be reachable from the remote
+/// branch, and be copied by a single local commit.
+fn verify_branch(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    branch: &app::Branch,
+    copies: &mut Copies,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let local_tip = match repo.refname_to_id(&branch.refname) {
+        Ok(id) => id,
+        Err(_) => {
+            report.problem(&format!("local branch {} does not exist", branch.name));
+            return Ok(());
+        }
+    };
+    let mut remote_commits = HashSet::new();
+    match repo.refname_to_id(&branch.tracking_refname) {
+        Ok(remote_tip) => {
+            let mut revwalk = repo.revwalk()?;
+            revwalk.push(remote_tip)?;
+            for oid in revwalk {
+                remote_commits.insert(oid?);
+            }
+        }
+        Err(_) => report.problem(&format!(
+            "remote branch {} was not fetched",
+            branch.tracking_refname
+        )),
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(local_tip)?;
+    for oid in revwalk {
+        let oid = oid?;
+        // commits shared between branches are only verified once
+        if !copies.reachable.insert(oid) {
+            continue;
+        }
+        let commit = repo.find_commit(oid)?;
+        let remote_id = match tags.retrieve_ripit_tag(&commit) {
+            Some((tag, _)) => tag,
+            None => continue,
+        };
+        let remote_id = match git2::Oid::from_str(&remote_id) {
+            Ok(id) => id,
+            Err(_) if mac::is_mac(&remote_id) => {
+                report.problem(&format!(
+                    "the MAC of the ripit tag of local commit {} matches no remote commit \
+                     with the provenance keys",
+                    oid
+                ));
+                continue;
+            }
+            Err(_) => {
+                report.problem(&format!(
+                    "local commit {} has an invalid ripit tag {}",
+                    oid, remote_id
+                ));
+                continue;
+            }
+        };
+
+        let mut referenced = tags.retrieve_squashed(&commit);
+        referenced.push(remote_id);
+        for id in referenced {
+            if repo.find_commit(id).is_err() {
+                report.problem(&format!(
+                    "remote commit {} of local commit {} does not exist",
+                    id, oid
+                ));
+            } else if !remote_commits.is_empty() && !remote_commits.contains(&id) {
+                report.problem(&format!(
+                    "remote commit {} of local commit {} is not in remote branch {}",
+                    id, oid, branch.name
+                ));
+            }
+            // the parts of a split commit all reference the remote commit
+            if !tags.is_partial(&commit) {
+                copies.copies.entry(id).or_default().push(oid);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the entries of the cache agree with the ripit tags of the local commits
+fn verify_cache(
+    repo: &git2::Repository,
+    tags: &tag::Tags,
+    copies: &Copies,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let filename = commits_map::cache_path(repo);
+    let content = match std::fs::read_to_string(&filename) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(Error::CacheReadError { err, filename }),
+    };
+
+    for line in content.lines() {
+        // entries of old versions only list the local commit
+        let (remote, local) = line.split_once(' ').unwrap_or(("", line));
+        let local_commit = match git2::Oid::from_str(local) {
+            Ok(id) if copies.reachable.contains(&id) => repo.find_commit(id)?,
+            _ => {
+                report.problem(&format!(
+                    "cache entry \"{}\" points to a commit not reachable from the branches",
+                    line
+                ));
+                continue;
+            }
+        };
+        let tagged = tags
+            .retrieve_ripit_tag(&local_commit)
+            .and_then(|(tag, _)| git2::Oid::from_str(&tag).ok());
+        let remote_id = match git2::Oid::from_str(remote).ok().or(tagged) {
+            Some(id) => id,
+            None => {
+                report.problem(&format!("cache entry \"{}\" is invalid", line));
+                continue;
+            }
+        };
+
+        // the remote commits not referenced by any tag, such as the commits empty once
+        // copied, are mapped to the local commits they would have been copied on
+        let locals = match copies.copies.get(&remote_id) {
+            Some(locals) => locals,
+            None => continue,
+        };
+        // the parts of a split commit are listed in the cache as well
+        let is_part = tags.is_partial(&local_commit) && tagged == Some(remote_id);
+        if !is_part && !locals.contains(&local_commit.id()) {
+            report.problem(&format!(
+                "cache entry maps remote commit {} to {}, but its copy is {}",
+                remote_id,
+                local_commit.id(),
+                locals[locals.len() - 1]
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that the tree of a local branch matches the tree of the remote branch
+///
+/// The remote tree is filtered as when copying the commits, and only the prefix directory
+/// of the local tree is compared if any.
+fn verify_tree(
+    repo: &git2::Repository,
+    branch: &app::Branch,
+    opts: &app::Options,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let (local_tip, remote_tip) = match (
+        repo.revparse_single(&branch.refname),
+        repo.revparse_single(&branch.tracking_refname),
+    ) {
+        (Ok(local), Ok(remote)) => (local.peel_to_commit()?, remote.peel_to_commit()?),
+        // already reported
+        _ => return Ok(()),
+    };
+    let local_tree = local_tip.tree()?;
+    let filtered = exclude::filter_tree(repo, &remote_tip.tree()?, opts)?;
+    let expected = exclude::graft_tree(repo, &local_tree, &filtered, opts)?;
+    if expected.id() != local_tree.id() {
+        report.problem(&format!(
+            "the tree of branch {} differs from the tree of remote commit {}",
+            branch.name,
+            remote_tip.id()
+        ));
+    }
+    Ok(())
+}
+
+/// Verify the consistency between the local branches, the remote branches and the cache
+///
+/// The remote commits referenced by the ripit tags of the local branches must exist and be
+/// in the remote branches, and be copied only once. The cache entries must point to
+/// commits of the local branches copied from their remote commits. With `verify_trees`,
+/// the trees of the local branches must match the trees of the remote branches. Every
+/// inconsistency is reported, instead of stopping at the first one.
+pub fn verify(repo: &git2::Repository, tags: &tag::Tags, opts: &app::Options) -> Result<(), Error> {
+    let mut report = Report::default();
+    let mut copies = Copies::default();
+
+    for branch in &opts.branches {
+        verify_branch(repo, tags, branch, &mut copies, &mut report)?;
+        if opts.verify_trees {
+            verify_tree(repo, branch, opts, &mut report)?;
+        }
+    }
+
+    let mut duplicates: Vec<_> = copies
+        .copies
+        .iter()
+        .filter(|(_, locals)| locals.len() > 1)
+        .collect();
+    duplicates.sort();
+    for (remote_id, locals) in duplicates {
+        let locals: Vec<String> = locals.iter().map(|id| id.to_string()).collect();
+        report.problem(&format!(
+            "remote commit {} is copied by several local commits: {}",
+            remote_id,
+            locals.join(", ")
+        ));
+    }
+
+    verify_cache(repo, tags, &copies, &mut report)?;
+
+    if report.nb_problems == 0 {
+        println!("No inconsistencies found.");
+        Ok(())
+    } else {
+        Err(Error::VerifyFailed {
+            count: report.nb_problems,
+        })
+    }
+}
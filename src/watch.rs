@@ -0,0 +1,169 @@
+//! Synchronization in a loop, with `--watch`
+//!
+//! Each cycle fetches the remote, and synchronizes the branches if the remote or local
+//! tips changed since the last synchronization. The fetch is retried less and less often
+//! while it fails. A synchronization stopped on conflicts suspends the cycles until it is
+//! resumed or aborted. SIGINT and SIGTERM stop the loop once the current cycle is done,
+//! and a second signal stops ripit at once.
+use crate::app::Options;
+use crate::error::Error;
+use crate::state;
+use crate::worktree;
+use crate::{debug, error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// longest delay between two fetches while they fail, unless the interval is longer
+const MAX_BACKOFF: u64 = 3600;
+
+/// Remote and local tips of the branches
+type Tips = Vec<(Option<git2::Oid>, Option<git2::Oid>)>;
+
+fn read_tips(repo: &git2::Repository, opts: &Options) -> Tips {
+    opts.branches
+        .iter()
+        .map(|branch| {
+            (
+                repo.refname_to_id(&branch.tracking_refname).ok(),
+                repo.refname_to_id(&branch.refname).ok(),
+            )
+        })
+        .collect()
+}
+
+/// Outcome of a cycle
+enum Cycle {
+    // the tips did not change, or the branches were synchronized
+    Done,
+    // the fetch failed
+    FetchFailed(Error),
+}
+
+/// Print a reminder if a synchronization is stopped on conflicts
+///
+/// Returns whether it is.
+fn remind_stopped_sync(repo: &git2::Repository, opts: &Options) -> Result<bool, Error> {
+    let state = match (opts.detached_worktree, worktree::open_kept(repo)) {
+        (true, Some(wt_repo)) => state::read(&wt_repo)?,
+        _ => state::read(repo)?,
+    };
+    match state {
+        Some(state) => {
+            warn!("{}", state.stopped());
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Fetch the remote, and synchronize the branches if their tips changed since the last
+/// synchronization
+fn cycle(opts: &Options, seen: &mut Option<Tips>) -> Result<Cycle, Error> {
+    let tips = {
+        let (repo, tags) = crate::open_repo(opts)?;
+        if remind_stopped_sync(&repo, opts)? {
+            return Ok(Cycle::Done);
+        }
+        let _lock = crate::lock::Lock::acquire(&repo, opts.break_lock)?;
+        if opts.fetch {
+            if let Err(err) = crate::fetch(&repo, &tags, opts) {
+                return Ok(Cycle::FetchFailed(err));
+            }
+        }
+        read_tips(&repo, opts)
+    };
+    if seen.as_ref() == Some(&tips) {
+        debug!("Nothing changed since the last synchronization.");
+        return Ok(Cycle::Done);
+    }
+
+    *seen = None;
+    let res = crate::run_locked(opts, |repo, tags| {
+        let report = crate::run(repo, tags, opts, false, false)?;
+        Ok((report, read_tips(repo, opts)))
+    });
+    let res = res.map(|(report, tips)| {
+        // the branches that failed to be pushed are pushed again by the next cycle
+        if report.push_failures.is_empty() {
+            *seen = Some(tips);
+        } else {
+            warn!(
+                "cannot push branches {}, they will be pushed again.",
+                report.push_failures.join(", ")
+            );
+        }
+        report
+    });
+    crate::notify(opts, &res);
+    // the conflicts are reminded by the next cycles
+    if let Err(err) = res {
+        error!("{}", err);
+    }
+    Ok(Cycle::Done)
+}
+
+/// Wait for a number of seconds, or until the loop is stopped
+fn wait(secs: u64, stop: &AtomicBool) {
+    let end = Instant::now() + Duration::from_secs(secs);
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= end {
+            break;
+        }
+        std::thread::sleep(std::cmp::min(end - now, Duration::from_millis(200)));
+    }
+}
+
+/// Synchronize the branches every `interval` seconds, until SIGINT or SIGTERM is received
+pub fn watch(opts: &Options, interval: u64) -> Result<(), Error> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        // a second signal stops ripit at once, as the flag is then already set
+        let res = flag::register_conditional_shutdown(signal, 1, Arc::clone(&stop))
+            .and_then(|_| flag::register(signal, Arc::clone(&stop)));
+        if let Err(err) = res {
+            return Err(Error::SignalSetup { err });
+        }
+    }
+
+    info!(
+        "Watching remote {}, synchronizing every {} seconds.",
+        opts.remote, interval
+    );
+    let mut seen = None;
+    let mut nb_failures = 0;
+    while !stop.load(Ordering::Relaxed) {
+        let delay = match cycle(opts, &mut seen) {
+            Ok(Cycle::Done) => {
+                nb_failures = 0;
+                interval
+            }
+            // the delay doubles with each failure, which is only notified once
+            Ok(Cycle::FetchFailed(err)) => {
+                let res = Err(err);
+                if nb_failures == 0 {
+                    crate::notify(opts, &res);
+                }
+                if let Err(err) = res {
+                    error!("{}", err);
+                }
+                nb_failures += 1;
+                let delay = interval.saturating_mul(1 << std::cmp::min(nb_failures, 16));
+                let delay = std::cmp::min(delay, std::cmp::max(interval, MAX_BACKOFF));
+                warn!("fetching again in {} seconds.", delay);
+                delay
+            }
+            // the repository may be locked by another ripit process
+            Err(err) => {
+                error!("{}", err);
+                interval
+            }
+        };
+        wait(delay, &stop);
+    }
+    info!("Stopped watching remote {}.", opts.remote);
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use crate::app;
+use crate::error::Error;
+use crate::{debug, error};
+use std::path::PathBuf;
+
+// name of the temporary worktree, fixed so that it can be reused after conflicts
+const WORKTREE_NAME: &str = "ripit";
+
+/// Temporary linked worktree in which the synchronization is done
+///
+/// The worktree is removed when dropped, unless it must be kept for the user to resolve
+/// conflicts in it.
+pub struct TmpWorktree {
+    worktree: git2::Worktree,
+    keep: bool,
+}
+
+impl TmpWorktree {
+    /// Open the temporary worktree, creating it if needed
+    ///
+    /// If a previous synchronization stopped on conflicts, the worktree still exists, and
+    /// is reused so that the synchronization can be resumed.
+    pub fn setup(
+        repo: &git2::Repository,
+        opts: &app::Options,
+    ) -> Result<(Self, git2::Repository), Error> {
+        // The branches are updated without touching the working directory of the user:
+        // if one of them is checked out, the working directory would no longer match it.
+        let head = repo.head()?;
+        for branch in &opts.branches {
+            if !repo.is_bare() && head.is_branch() && head.name() == Some(&branch.refname) {
+                return Err(Error::BranchCheckedOut {
+                    branch: branch.name.clone(),
+                });
+            }
+        }
+
+        let worktree = match repo.find_worktree(WORKTREE_NAME) {
+            Ok(worktree) if worktree.validate().is_ok() => {
+                debug!("Reusing temporary worktree {}.", worktree.path().display());
+                worktree
+            }
+            _ => create_worktree(repo, &head.peel_to_commit()?)?,
+        };
+        let wt_repo = git2::Repository::open_from_worktree(&worktree)?;
+
+        Ok((
+            Self {
+                worktree,
+                keep: false,
+            },
+            wt_repo,
+        ))
+    }
+
+    /// Keep the worktree on disk, so that the user can resolve conflicts in it
+    pub fn keep(&mut self) {
+        self.keep = true;
+        error!(
+            "The synchronization was done in the temporary worktree {path}.\n\
+             Solve the conflicts and commit the resolutions in this worktree, then \
+             run the synchronization again. Once done, the worktree will be removed.",
+            path = self.worktree.path().display(),
+        );
+    }
+}
+
+impl Drop for TmpWorktree {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        let path = self.worktree.path().to_path_buf();
+        let mut opts = git2::WorktreePruneOptions::new();
+        opts.valid(true).working_tree(true);
+        if let Err(e) = self.worktree.prune(Some(&mut opts)) {
+            error!(
+                "Cannot remove temporary worktree {}: {}",
+                path.display(),
+                e.message()
+            );
+        }
+        if path.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&path) {
+                error!("Cannot remove temporary worktree {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Open the temporary worktree kept after conflicts, if any
+pub fn open_kept(repo: &git2::Repository) -> Option<git2::Repository> {
+    let worktree = repo.find_worktree(WORKTREE_NAME).ok()?;
+    worktree.validate().ok()?;
+    git2::Repository::open_from_worktree(&worktree).ok()
+}
+
+/// Create a new worktree in a temporary directory, with a detached HEAD on the given commit
+fn create_worktree(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+) -> Result<git2::Worktree, Error> {
+    // remove leftovers from a previous execution
+    if let Ok(worktree) = repo.find_worktree(WORKTREE_NAME) {
+        let mut prune_opts = git2::WorktreePruneOptions::new();
+        prune_opts.valid(true).working_tree(true);
+        worktree.prune(Some(&mut prune_opts))?;
+    }
+
+    let name = format!("ripit-worktree-{}", std::process::id());
+    let path: PathBuf = std::env::temp_dir().join(&name);
+
+    // A worktree must be created on a branch. Use a temporary one, removed once the
+    // worktree HEAD is detached.
+    let mut branch = repo.branch(&name, commit, false)?;
+
+    let worktree = {
+        let reference = branch.get();
+        let mut add_opts = git2::WorktreeAddOptions::new();
+        add_opts.reference(Some(reference));
+        repo.worktree(WORKTREE_NAME, &path, Some(&add_opts))
+    };
+    let worktree = match worktree {
+        Ok(worktree) => worktree,
+        Err(e) => {
+            branch.delete()?;
+            return Err(e.into());
+        }
+    };
+
+    let wt_repo = git2::Repository::open_from_worktree(&worktree)?;
+    wt_repo.set_head_detached(commit.id())?;
+    branch.delete()?;
+
+    debug!("Created temporary worktree {}.", path.display());
+    Ok(worktree)
+}
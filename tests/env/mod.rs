@@ -32,6 +32,22 @@ fn find_ripit_exec() -> PathBuf {
     root.join("ripit")
 }
 
+// }}}
+// {{{ git exec handling
+
+/// Run a git command in a directory, which must succeed
+///
+/// To set up what libgit2 cannot, such as shallow clones.
+pub fn run_git(dir: &Path, args: &[&str]) {
+    let status = process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .status()
+        .expect("git command");
+    assert!(status.success());
+}
+
 // }}}
 // {{{ Test repo
 
@@ -51,7 +67,7 @@ impl TestRepo {
         self.checkout_head(Some(&mut opts.force())).unwrap();
     }
 
-    pub fn do_commit(&self, msg: &str) -> git2::Commit {
+    pub fn do_commit(&self, msg: &str) -> git2::Commit<'_> {
         let mut index = self.index().unwrap();
         let tree = self.find_tree(index.write_tree().unwrap()).unwrap();
 
@@ -72,8 +88,9 @@ impl TestRepo {
         self.find_commit(commit_oid).unwrap()
     }
 
-    fn write_and_add_file(&self, filename: &str, content: &str) {
+    pub fn write_and_add_file(&self, filename: &str, content: &str) {
         let path = Path::new(self.workdir().unwrap()).join(filename);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
         fs::File::create(path)
             .unwrap()
             .write_all(content.as_bytes())
@@ -83,12 +100,22 @@ impl TestRepo {
         index.add_path(Path::new(filename)).unwrap();
     }
 
-    pub fn commit_file(&self, filename: &str, commit_msg: &str) -> git2::Commit {
+    pub fn commit_file(&self, filename: &str, commit_msg: &str) -> git2::Commit<'_> {
         self.write_and_add_file(filename, commit_msg);
         self.do_commit(commit_msg)
     }
 
-    pub fn resolve_conflict_and_commit(&self, filename: &str) -> git2::Commit {
+    pub fn commit_file_with_content(
+        &self,
+        filename: &str,
+        content: &str,
+        commit_msg: &str,
+    ) -> git2::Commit<'_> {
+        self.write_and_add_file(filename, content);
+        self.do_commit(commit_msg)
+    }
+
+    pub fn resolve_conflict_and_commit(&self, filename: &str) -> git2::Commit<'_> {
         // overwrite file containing conflicts, and add it to the index
         self.write_and_add_file(filename, "resolved conflict");
 
@@ -114,12 +141,35 @@ impl TestRepo {
     }
 
     /// Commit a file, and tag the commit (the tag name and the files content are the same)
-    fn commit_file_and_tag(&self, filename: &str, tag: &str) -> git2::Commit {
+    fn commit_file_and_tag(&self, filename: &str, tag: &str) -> git2::Commit<'_> {
         let ci = self.commit_file(filename, &format!("{}\n\nline test filtered\ndetails", tag));
         self.tag_lightweight(tag, ci.as_object(), true).unwrap();
         ci
     }
 
+    /// Create a long history on HEAD, each commit changing one of a few files
+    ///
+    /// The commits are created without touching the working directory, which is checked out
+    /// once at the end.
+    pub fn commit_history(&self, nb_commits: usize, prefix: &str) {
+        let sig = self.signature().unwrap();
+        let mut parent = self.head().unwrap().peel_to_commit().unwrap();
+        for i in 0..nb_commits {
+            let name = format!("{}{}", prefix, i);
+            let blob = self.blob(name.as_bytes()).unwrap();
+            let mut builder = self.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            builder
+                .insert(format!("{}{}", prefix, i % 10), blob, 0o100644)
+                .unwrap();
+            let tree = self.find_tree(builder.write().unwrap()).unwrap();
+            let id = self
+                .commit(Some("HEAD"), &sig, &sig, &name, &tree, &[&parent])
+                .unwrap();
+            parent = self.find_commit(id).unwrap();
+        }
+        self.force_checkout_head();
+    }
+
     pub fn check_file(&self, filename: &str, file_present: bool, file_in_index: bool) {
         let path = Path::new(self.workdir().unwrap()).join(filename);
         assert_eq!(path.exists(), file_present);
@@ -137,7 +187,7 @@ impl TestRepo {
     }
 
     /// Do a commit-merge of the given commit in HEAD
-    fn do_merge_commit(&self, theirs: &git2::Commit, content: &str) -> git2::Commit {
+    fn do_merge_commit(&self, theirs: &git2::Commit, content: &str) -> git2::Commit<'_> {
         let annotated_theirs = self.find_annotated_commit(theirs.id()).unwrap();
         self.merge(&[&annotated_theirs], None, None).unwrap();
 
@@ -189,12 +239,45 @@ impl TestRepo {
         ci
     }
 
-    fn do_merge(&self, theirs: &git2::Commit, content: &str) -> git2::Commit {
+    pub fn count_stashes(&self) -> usize {
+        let mut nb_stashes = 0;
+        // listing the stashes needs a mutable repository
+        git2::Repository::open(self.path())
+            .unwrap()
+            .stash_foreach(|_, _, _| {
+                nb_stashes += 1;
+                true
+            })
+            .unwrap();
+        nb_stashes
+    }
+
+    pub fn do_merge(&self, theirs: &git2::Commit, content: &str) -> git2::Commit<'_> {
         let ci = self.do_merge_commit(theirs, content);
         self.tag_lightweight(content, ci.as_object(), true).unwrap();
         ci
     }
 
+    /// Commit the index as a merge of HEAD with all the given commits, and tag it
+    ///
+    /// The merged content must be staged beforehand.
+    pub fn commit_octopus(&self, theirs: &[&git2::Commit], tag: &str) -> git2::Commit<'_> {
+        let mut index = self.index().unwrap();
+        let tree = self.find_tree(index.write_tree().unwrap()).unwrap();
+        let head = self.head().unwrap().peel_to_commit().unwrap();
+        let mut parents = vec![&head];
+        parents.extend_from_slice(theirs);
+        let sig = self.signature().unwrap();
+
+        let id = self
+            .commit(Some("HEAD"), &sig, &sig, tag, &tree, &parents)
+            .unwrap();
+        let ci = self.find_commit(id).unwrap();
+        self.tag_lightweight(tag, ci.as_object(), true).unwrap();
+        self.force_checkout_head();
+        ci
+    }
+
     pub fn reset_hard(&self, commit: &git2::Object) {
         self.reset(commit, git2::ResetType::Hard, None).unwrap();
     }
@@ -256,7 +339,7 @@ impl TestEnv {
         let cfg_path = local_dir.path().join("cfg.yml");
         let cfg = format!(
             "\
-path: {}
+repo: {}
 remote: private
 filters:
   - ^Refs
@@ -362,6 +445,45 @@ filters:
         self.remote_repo.do_merge(&c4, "c5");
     }
 
+    /// Setup an octopus merge
+    ///
+    ///     --> C1 --
+    ///    /         \
+    ///   ---> C2 -----\
+    ///  /              \
+    /// C0 ---> C3 -------> C4
+    ///
+    /// If first_parent_unknown, C4 is instead the merge of C0 with C2 and C3, which are made
+    /// on top of C1, and it changes the file modified by C1. Once bootstrapped on C1, the
+    /// first parent of C4 is unknown.
+    ///
+    pub fn setup_octopus_merge(&self, first_parent_unknown: bool) {
+        let c0 = self.remote_repo.commit_file_and_tag("f", "c0");
+        let c1 = self.remote_repo.commit_file_and_tag("f", "c1");
+        if !first_parent_unknown {
+            self.remote_repo.reset_hard(c0.as_object());
+        }
+        let c2 = self.remote_repo.commit_file_and_tag("c2", "c2");
+        self.remote_repo.reset_hard(if first_parent_unknown {
+            c1.as_object()
+        } else {
+            c0.as_object()
+        });
+        let c3 = self.remote_repo.commit_file_and_tag("c3", "c3");
+
+        if first_parent_unknown {
+            self.remote_repo.reset_hard(c0.as_object());
+            self.remote_repo.write_and_add_file("f", "c4");
+            self.remote_repo.write_and_add_file("c2", "c2");
+            self.remote_repo.write_and_add_file("c3", "c3");
+            self.remote_repo.commit_octopus(&[&c2, &c3], "c4");
+        } else {
+            self.remote_repo.write_and_add_file("f", "c1");
+            self.remote_repo.write_and_add_file("c2", "c2");
+            self.remote_repo.commit_octopus(&[&c1, &c2], "c4");
+        }
+    }
+
     /// Setup merge commit resolving conflitcs
     ///
     ///      -> C1 --
@@ -415,16 +537,34 @@ filters:
         self.remote_repo.do_merge(&c4, "c6");
     }
 
-    fn run_ripit(&self, successful: bool, args: &[&str], err_msg: Option<&str>) {
+    fn run_ripit(&self, successful: bool, args: &[&str], err_msg: Option<&str>) -> String {
         let mut args = args.to_vec();
         args.push(&self.cfg_path);
 
-        let mut cmd = process::Command::new(&self.ripit_exec);
-        cmd.current_dir(self.local_dir.path());
-        cmd.args(args);
+        self.exec_ripit(successful, &args, err_msg, &[])
+    }
+
+    fn exec_ripit(
+        &self,
+        successful: bool,
+        args: &[&str],
+        err_msg: Option<&str>,
+        envs: &[(&str, &str)],
+    ) -> String {
+        self.exec_ripit_with_input(successful, args, err_msg, envs, "")
+    }
 
-        let output = cmd.output().expect("ripit command");
-        println!("stdout: {}", str::from_utf8(&output.stdout).unwrap());
+    fn exec_ripit_with_input(
+        &self,
+        successful: bool,
+        args: &[&str],
+        err_msg: Option<&str>,
+        envs: &[(&str, &str)],
+        input: &str,
+    ) -> String {
+        let output = self.spawn_ripit(args, envs, input);
+        let stdout = str::from_utf8(&output.stdout).unwrap();
+        println!("stdout: {}", stdout);
 
         let stderr = str::from_utf8(&output.stderr).unwrap();
         if let Some(msg) = err_msg {
@@ -438,10 +578,144 @@ filters:
         // repo
         self.local_repo.index().unwrap().read(true).unwrap();
         self.remote_repo.index().unwrap().read(true).unwrap();
+
+        stdout.to_owned()
+    }
+
+    fn spawn_ripit(&self, args: &[&str], envs: &[(&str, &str)], input: &str) -> process::Output {
+        let mut cmd = process::Command::new(&self.ripit_exec);
+        cmd.current_dir(self.local_dir.path());
+        cmd.args(args);
+        cmd.envs(envs.iter().copied());
+        cmd.stdin(process::Stdio::piped());
+        cmd.stdout(process::Stdio::piped());
+        cmd.stderr(process::Stdio::piped());
+
+        let mut child = cmd.spawn().expect("ripit command");
+        // stdin is closed once the input is written
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().expect("ripit command")
+    }
+
+    /// Start ripit without waiting for it to exit
+    pub fn spawn_ripit_background(&self, args: &[&str]) -> process::Child {
+        let mut cmd = process::Command::new(&self.ripit_exec);
+        cmd.current_dir(self.local_dir.path());
+        cmd.args(args);
+        cmd.arg(&self.cfg_path);
+        cmd.stdout(process::Stdio::piped());
+        cmd.stderr(process::Stdio::piped());
+        cmd.spawn().expect("ripit command")
+    }
+
+    /// Run a ripit subcommand, and return its output
+    pub fn run_ripit_subcommand(&self, args: &[&str]) -> String {
+        let mut all_args = vec![self.cfg_path.as_str()];
+        all_args.extend_from_slice(args);
+
+        self.exec_ripit(true, &all_args, None, &[])
+    }
+
+    /// Run a failing ripit subcommand, and return its output
+    pub fn run_ripit_subcommand_failure(&self, args: &[&str], err_msg: Option<&str>) -> String {
+        let mut all_args = vec![self.cfg_path.as_str()];
+        all_args.extend_from_slice(args);
+
+        self.exec_ripit(false, &all_args, err_msg, &[])
+    }
+
+    /// Run ripit, killing it once the given number of commits have been copied
+    ///
+    /// Returns the output of the execution.
+    pub fn run_ripit_aborted(&self, args: &[&str], abort_after: usize) -> String {
+        let mut args = args.to_vec();
+        args.push(&self.cfg_path);
+        let abort_after = abort_after.to_string();
+
+        self.exec_ripit(
+            false,
+            &args,
+            Some("Aborting after"),
+            &[("RIPIT_TEST_ABORT_AFTER", &abort_after)],
+        )
+    }
+
+    /// Run ripit with the given environment variables, and return its output
+    pub fn run_ripit_with_env(
+        &self,
+        successful: bool,
+        args: &[&str],
+        err_msg: Option<&str>,
+        envs: &[(&str, &str)],
+    ) -> String {
+        let mut args = args.to_vec();
+        args.push(&self.cfg_path);
+
+        self.exec_ripit(successful, &args, err_msg, envs)
+    }
+
+    /// Run ripit, and return its exit code and output
+    pub fn run_ripit_status(&self, args: &[&str]) -> (Option<i32>, String) {
+        let mut args = args.to_vec();
+        args.push(&self.cfg_path);
+
+        let output = self.spawn_ripit(&args, &[], "");
+        let stdout = str::from_utf8(&output.stdout).unwrap();
+        println!("stdout: {}", stdout);
+        println!("stderr: {}", str::from_utf8(&output.stderr).unwrap());
+
+        (output.status.code(), stdout.to_owned())
+    }
+
+    /// Run ripit interactively, answering its prompts with the given input
+    ///
+    /// Returns the output of the execution.
+    pub fn run_ripit_interactive(
+        &self,
+        successful: bool,
+        args: &[&str],
+        input: &str,
+        envs: &[(&str, &str)],
+    ) -> String {
+        let mut args = args.to_vec();
+        args.push(&self.cfg_path);
+        let mut envs = envs.to_vec();
+        envs.push(("RIPIT_TEST_INTERACTIVE", "1"));
+
+        self.exec_ripit_with_input(successful, &args, None, &envs, input)
+    }
+
+    /// Path to the cache file of the local repository
+    pub fn cache_path(&self) -> PathBuf {
+        self.local_repo.path().join("ripit").join("cache")
+    }
+
+    /// Path to the file listing the skipped commits
+    pub fn skipped_path(&self) -> PathBuf {
+        self.local_repo.path().join("ripit").join("skipped")
+    }
+
+    /// Replace the configuration file
+    pub fn set_cfg(&self, cfg: &str) {
+        fs::write(&self.cfg_path, cfg).unwrap();
+    }
+
+    /// Append options to the configuration file
+    pub fn add_cfg(&self, cfg: &str) {
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.cfg_path)
+            .unwrap();
+        writeln!(file, "{}", cfg).unwrap();
     }
 
     pub fn run_ripit_failure(&self, args: &[&str], err_msg: Option<&str>) {
-        self.run_ripit(false, args, err_msg)
+        self.run_ripit(false, args, err_msg);
     }
 
     pub fn run_ripit_success(&self, args: &[&str]) {
@@ -84,16 +84,328 @@ fn test_basic_sync() {
 
         assert!(local_msg.contains(&pattern));
 
-        // cache will contain the ids of copied commits
-        expected_cache.push_str(&format!("{}\n", local_commit.id()));
+        // cache will map the remote commits to their copies
+        expected_cache.push_str(&format!("{} {}\n", remote_commit.id(), local_commit.id()));
     }
 
     // check the cache file contains the synced ids
-    let path = env.local_repo.workdir().unwrap().join(".ripit-cache");
-    let contents = std::fs::read_to_string(path).unwrap();
+    let contents = std::fs::read_to_string(env.cache_path()).unwrap();
     assert_eq!(contents, expected_cache);
 }
 
+/// Test the cache and skipped files are moved from the working directory to the git directory
+#[test]
+fn test_cache_migration() {
+    let env = env::TestEnv::new(None);
+
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["-y"]);
+
+    // put the cache where previous versions wrote it, with only the local ids
+    let cache: String = fs::read_to_string(env.cache_path())
+        .unwrap()
+        .lines()
+        .map(|line| format!("{}\n", line.split_once(' ').unwrap().1))
+        .collect();
+    let legacy_path = env.local_repo.workdir().unwrap().join(".ripit-cache");
+    fs::write(&legacy_path, &cache).unwrap();
+    fs::remove_file(env.cache_path()).unwrap();
+    let skipped = format!("{} conflicts\n", git2::Oid::zero());
+    let legacy_skipped_path = env.local_repo.workdir().unwrap().join(".ripit-skipped");
+    fs::write(&legacy_skipped_path, &skipped).unwrap();
+
+    // the legacy cache is loaded: a is not synchronized again
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+
+    assert!(!legacy_path.exists());
+    let head = env.local_repo.head().unwrap().target().unwrap();
+    assert_eq!(
+        fs::read_to_string(env.cache_path()).unwrap(),
+        format!("{}{} {}\n", cache, b.id(), head)
+    );
+    assert!(!legacy_skipped_path.exists());
+    assert_eq!(fs::read_to_string(env.skipped_path()).unwrap(), skipped);
+}
+
+/// Test the synchronization of a long history
+///
+/// The lookups done for every commit must not depend on the number of commits already
+/// synchronized, or this test becomes very slow.
+#[test]
+fn test_long_history() {
+    let env = env::TestEnv::new(None);
+
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.commit_history(2000, "a");
+    let start = std::time::Instant::now();
+    env.run_ripit_success(&["-y"]);
+    let elapsed = start.elapsed();
+    assert_eq!(env.local_repo.count_commits(), 2001);
+    assert!(
+        elapsed < std::time::Duration::from_secs(30),
+        "synchronizing 2000 commits took {:?}",
+        elapsed
+    );
+
+    // every reference protecting the copies is released
+    let keep_refs = env.local_repo.references_glob("refs/ripit/keep/*").unwrap();
+    assert_eq!(keep_refs.count(), 0);
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    assert_eq!(cache.lines().count(), 2001);
+
+    // without the cache, the synchronized commits are found from the ripit tags
+    fs::remove_file(env.cache_path()).unwrap();
+    env.remote_repo.commit_history(3, "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2004);
+}
+
+/// Test the detection of a rewritten remote branch
+#[test]
+fn test_upstream_rewritten() {
+    let env = env::TestEnv::new(None);
+
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.remote_repo.head().unwrap().peel_to_commit().unwrap();
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+
+    // the remote branch is rebased: a and b are replaced
+    env.remote_repo.reset_hard(bootstrap.as_object());
+    env.remote_repo.commit_file("c.txt", "c");
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_failure(
+        &["-y"],
+        Some(&format!(
+            "Branch private/master was rewritten: the last synchronized commit {} is no \
+             longer in its history.",
+            b.id()
+        )),
+    );
+    env.run_ripit_failure(
+        &["-y"],
+        Some(&format!("\n  {} a\n", &a.id().to_string()[..10])),
+    );
+    env.run_ripit_failure(
+        &["-y"],
+        Some(&format!("\n  {} b", &b.id().to_string()[..10])),
+    );
+    assert_eq!(
+        env.local_repo.refname_to_id("refs/heads/master").unwrap(),
+        head
+    );
+}
+
+/// Test the resynchronization of a rewritten remote branch
+#[test]
+fn test_force_resync() {
+    let env = env::TestEnv::new(None);
+
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.remote_repo.head().unwrap().peel_to_commit().unwrap();
+    env.remote_repo.commit_file("a.txt", "a");
+    env.remote_repo.commit_file("b.txt", "b");
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 4);
+
+    // the remote branch is rebased on a new commit, c is dropped and d is added
+    env.remote_repo.reset_hard(bootstrap.as_object());
+    env.remote_repo.commit_file("x.txt", "x");
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    env.remote_repo.commit_file("b.txt", "b");
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_failure(&["-y"], Some("was rewritten"));
+
+    // a and b are already synchronized, x and d are copied on top of the local branch
+    let output = env.run_ripit_subcommand(&["-y", "--force-resync"]);
+    assert!(output.contains("2 commits matched by patch-id, 2 new or modified commits"));
+    assert_eq!(env.local_repo.count_commits(), 6);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.summary(), Some("d"));
+    assert_eq!(head.parent(0).unwrap().summary(), Some("x"));
+    for file in &["a.txt", "b.txt", "c.txt", "d.txt", "x.txt"] {
+        env.local_repo.check_file(file, true, true);
+    }
+    let skipped = env.skipped_path();
+    assert!(fs::read_to_string(skipped)
+        .unwrap()
+        .contains(&format!("{} patch-id", a.id())));
+
+    // the synchronization then goes on from the new history
+    env.remote_repo.commit_file("e.txt", "e");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 7);
+}
+
+#[test]
+fn test_cache_truncated() {
+    let env = env::TestEnv::new(None);
+
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["-y"]);
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+
+    // the last line is ignored and removed
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    fs::write(env.cache_path(), format!("{}{} 12ab", cache, b.id())).unwrap();
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    let head = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+    assert_eq!(
+        fs::read_to_string(env.cache_path()).unwrap(),
+        format!("{}{} {}\n", cache, b.id(), head)
+    );
+    assert!(!env.cache_path().with_file_name("cache.tmp").exists());
+
+    // other invalid lines are corruptions
+    fs::write(env.cache_path(), format!("{} 12ab\n{}", b.id(), cache)).unwrap();
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_failure(&["-y"], Some("is corrupted, line 1"));
+    assert_eq!(env.local_repo.count_commits(), 3);
+}
+
+/// Test aborting a synchronization stopped on conflicts
+#[test]
+fn test_abort() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+
+    let c9 = env.remote_repo.revparse_single("c9").unwrap();
+    env.remote_repo.reset_hard(&c9);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // nothing to abort
+    env.run_ripit_failure(&["--abort"], Some("No synchronization is stopped"));
+
+    // c11 is uprooted, then c12 conflicts
+    let c10 = env.remote_repo.revparse_single("c10").unwrap();
+    env.remote_repo.reset_hard(&c10);
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c12"));
+    let state_path = env.local_repo.path().join("ripit").join("STATE");
+    assert!(state_path.exists());
+
+    env.run_ripit_success(&["--abort"]);
+    assert!(!state_path.exists());
+    assert_eq!(env.local_repo.state(), git2::RepositoryState::Clean);
+    assert!(!env.local_repo.index().unwrap().has_conflicts());
+
+    // the branch is on the copy of c11, the last copied commit
+    let head = env.local_repo.head().unwrap();
+    assert_eq!(head.shorthand().unwrap(), "master");
+    let head_ci = head.peel_to_commit().unwrap();
+    assert!(head_ci.summary().unwrap().contains("c11"));
+    assert_eq!(env.local_repo.count_commits(), 2);
+
+    env.run_ripit_failure(&["--abort"], Some("No synchronization is stopped"));
+
+    // the synchronization resumes on c12
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c12"));
+}
+
+/// Test aborting a stopped synchronization when no branch matches the branch patterns
+#[test]
+fn test_abort_without_branches() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("remote: private\nbranches:\n  - release/*\n");
+    let a = env.remote_repo.commit_file("a.txt", "a");
+
+    // a merge is in progress, without a state telling the stopped branch
+    fs::write(
+        env.local_repo.path().join("MERGE_HEAD"),
+        format!("{}\n", a.id()),
+    )
+    .unwrap();
+    env.run_ripit_failure(
+        &["--abort"],
+        Some("Cannot find the branch whose synchronization stopped"),
+    );
+}
+
+/// Test committing the resolution of conflicts, and resuming the synchronization
+#[test]
+fn test_continue() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+
+    let c9 = env.remote_repo.revparse_single("c9").unwrap();
+    env.remote_repo.reset_hard(&c9);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let c10 = env.remote_repo.revparse_single("c10").unwrap();
+    env.remote_repo.reset_hard(&c10);
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c12"));
+
+    // the conflicts must be resolved first
+    env.run_ripit_failure(
+        &["-yu", "--continue"],
+        Some("Conflicts remain in the following files:\n  c12\n"),
+    );
+
+    env.local_repo
+        .write_and_add_file("c12", "resolved conflict");
+    env.local_repo.index().unwrap().write().unwrap();
+    env.run_ripit_success(&["-yu", "--continue"]);
+    assert!(!env.local_repo.path().join("ripit").join("STATE").exists());
+
+    // the resolution was committed, then c10 was synchronized
+    let head_ci = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head_ci.summary().unwrap().contains("c10"));
+    let c12 = env.remote_repo.revparse_single("c12").unwrap();
+    let resolved = head_ci
+        .parents()
+        .find(|ci| ci.summary() == Some("c12"))
+        .unwrap();
+    let msg = resolved.message().unwrap();
+    assert!(msg.ends_with(&format!("\nrip-it: {} uprooted\n", c12.id())));
+    assert!(!msg.contains("test"));
+    assert!(!msg.contains('#'));
+
+    env.run_ripit_failure(&["--continue"], Some("No synchronization is stopped"));
+}
+
+/// Test skipping the commit that caused conflicts
+#[test]
+fn test_skip_on_conflicts() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("commit_gate_cmd: '[ \"$RIPIT_SUMMARY\" != a ] || exit 10'");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // b conflicts, as a is skipped by the gate
+    let init = env.remote_repo.head().unwrap().peel_to_commit().unwrap();
+    env.remote_repo.commit_file_with_content("f", "a", "a");
+    let b = env.remote_repo.commit_file_with_content("f", "b", "b");
+    env.remote_repo.reset_hard(init.as_object());
+    let s = env.remote_repo.commit_file("s.txt", "s");
+    env.remote_repo.reset_hard(b.as_object());
+    env.remote_repo.do_merge(&s, "m");
+
+    env.run_ripit_failure(&["-y"], Some("due to conflicts:\n  b"));
+    env.run_ripit_success(&["-y", "--skip"]);
+
+    // the merge is copied on top of the parent of b
+    let head_ci = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head_ci.summary().unwrap(), "m");
+    let parents: Vec<git2::Commit> = head_ci.parents().collect();
+    assert_eq!(parents.len(), 2);
+    assert!(parents[0].summary().unwrap().contains("Bootstrap"));
+    assert_eq!(parents[1].summary().unwrap(), "s");
+    env.local_repo.check_file("s.txt", true, true);
+    env.local_repo.check_file("f", false, false);
+
+    // b is recorded as skipped
+    let skipped = fs::read_to_string(env.skipped_path()).unwrap();
+    assert!(skipped.contains(&format!("{} conflicts\n", b.id())));
+
+    env.run_ripit_failure(&["-y", "--skip"], Some("No synchronization is stopped"));
+}
+
 /// Test that exec is aborted if local changes are present
 #[test]
 fn test_abort_on_local_changes() {
@@ -119,6 +431,40 @@ fn test_abort_on_local_changes() {
 
     env.local_repo.force_checkout_head();
     env.run_ripit_success(&["-y"]);
+
+    // the local changes are stashed during the run, and applied again
+    let workdir = Path::new(env.local_repo.workdir().unwrap());
+    fs::write(workdir.join("a.txt"), "wip").unwrap();
+    fs::write(workdir.join("notes.swp"), "editor").unwrap();
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_failure(&["-y"], Some("Aborted"));
+    env.run_ripit_success(&["-y", "--autostash"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    assert_eq!(fs::read_to_string(workdir.join("a.txt")).unwrap(), "wip");
+    assert!(workdir.join("b.txt").exists());
+    assert!(workdir.join("notes.swp").exists());
+    assert_eq!(env.local_repo.count_stashes(), 0);
+
+    // untracked files are not stashed, and must not be overwritten
+    fs::write(workdir.join("c.txt"), "untracked").unwrap();
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_failure(
+        &["-y", "--autostash"],
+        Some("The untracked files c.txt would be overwritten"),
+    );
+    assert_eq!(fs::read_to_string(workdir.join("a.txt")).unwrap(), "wip");
+    assert_eq!(env.local_repo.count_stashes(), 0);
+
+    // the stash is kept if the changes cannot be applied again
+    env.run_ripit_with_env(
+        true,
+        &["-y", "--autostash-untracked"],
+        Some("The local changes are kept in stash@{0}"),
+        &[],
+    );
+    assert_eq!(env.local_repo.count_commits(), 4);
+    assert_eq!(env.local_repo.count_stashes(), 1);
+    assert_eq!(fs::read_to_string(workdir.join("c.txt")).unwrap(), "c");
 }
 
 /// Test filtering of commits
@@ -264,6 +610,48 @@ fn test_uproot_sync() {
     let parents: Vec<git2::Commit> = parents[0].parents().collect();
     assert_eq!(parents.len(), 1);
     assert!(parents[0].summary().unwrap().contains("Bootstrap"));
+
+    // the cache maps every remote commit to its copy, uprooted or not
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    assert!(cache.ends_with(&format!("{} {}\n", c8.id(), head_ci.id())));
+    assert_eq!(cache.lines().count(), 4);
+    for line in cache.lines() {
+        let (remote_id, local_id) = line.split_once(' ').unwrap();
+        let local_id = git2::Oid::from_str(local_id).unwrap();
+        let local_ci = env.local_repo.find_commit(local_id).unwrap();
+        assert!(local_ci
+            .message()
+            .unwrap()
+            .contains(&format!("rip-it: {}", remote_id)));
+    }
+}
+
+/// Test enabling uprooting for a single branch in the configuration
+#[test]
+fn test_uproot_branch_cfg() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+
+    let c5 = env.remote_repo.revparse_single("c5").unwrap();
+    env.remote_repo.reset_hard(&c5);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let c8 = env.remote_repo.revparse_single("c8").unwrap();
+    env.remote_repo.reset_hard(&c8);
+    env.add_cfg("branches:\n  - name: master\n    uproot: false");
+    env.run_ripit_failure(
+        &["-y"],
+        Some("uprooting can be enabled for branch master with `uproot: true`"),
+    );
+
+    env.set_cfg("remote: private\nbranches:\n  - name: master\n    uproot: true\n");
+    env.run_ripit_success(&["-y"]);
+
+    let head_tgt = env.local_repo.head().unwrap().target().unwrap();
+    let head_ci = env.local_repo.find_commit(head_tgt).unwrap();
+    assert!(head_ci.summary().unwrap().contains("c8"));
+    let parents: Vec<git2::Commit> = head_ci.parents().collect();
+    assert!(parents[1].message().unwrap().contains("uprooted"));
 }
 
 /// Test uprooting with conflicts
@@ -283,6 +671,20 @@ fn test_uproot_sync_with_conflicts() {
     env.remote_repo.reset_hard(&c10);
     env.run_ripit_failure(&["-yu"], Some("due to conflicts"));
 
+    // the state of the synchronization is saved
+    let c12 = env.remote_repo.revparse_single("c12").unwrap();
+    let state_path = env.local_repo.path().join("ripit").join("STATE");
+    let head = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+    assert_eq!(
+        fs::read_to_string(&state_path).unwrap(),
+        format!(
+            "commit {id}\nbranch refs/heads/master\nuprooted true\nhead {head}\n\n\
+             c12\n\ndetails\n\nrip-it: {id} uprooted\n",
+            id = c12.id(),
+            head = head
+        )
+    );
+
     // Resolve conflict and do a commit
     env.local_repo.resolve_conflict_and_commit("c12");
 
@@ -299,6 +701,7 @@ fn test_uproot_sync_with_conflicts() {
     // Go-on with the synchronization, now that the conflict is
     // solved.
     env.run_ripit_success(&["-yu"]);
+    assert!(!state_path.exists());
 
     let head_tgt = env.local_repo.head().unwrap().target().unwrap();
     let head_ci = env.local_repo.find_commit(head_tgt).unwrap();
@@ -435,6 +838,55 @@ fn test_resync_uprooted_merge() {
     // TODO: test removing the cache file, then syncing
 }
 
+/// Test the synchronization of an octopus merge, with three parents
+#[test]
+fn test_octopus_merge() {
+    let env = env::TestEnv::new(None);
+    env.setup_octopus_merge(false);
+
+    let c0 = env.remote_repo.revparse_single("c0").unwrap();
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c0);
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_success(&["-y"]);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.summary(), Some("c4"));
+    assert_eq!(head.tree_id(), c4.peel_to_commit().unwrap().tree_id());
+    let parents: Vec<_> = head
+        .parents()
+        .map(|p| p.summary().unwrap().to_owned())
+        .collect();
+    assert_eq!(parents, ["c3", "c1", "c2"]);
+}
+
+/// Test the synchronization of an octopus merge whose first parent is unknown
+///
+/// Its changes are taken relative to its first known parent, on whose copy it is copied.
+#[test]
+fn test_uproot_octopus_merge() {
+    let env = env::TestEnv::new(None);
+    env.setup_octopus_merge(true);
+
+    let c1 = env.remote_repo.revparse_single("c1").unwrap();
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c1);
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_failure(&["-y"], Some("cannot be found in the local repository"));
+    env.run_ripit_success(&["-yu"]);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.summary(), Some("c4"));
+    assert_eq!(head.tree_id(), c4.peel_to_commit().unwrap().tree_id());
+    let parents: Vec<_> = head
+        .parents()
+        .map(|p| p.summary().unwrap().to_owned())
+        .collect();
+    assert_eq!(parents, ["c2", "c3"]);
+}
+
 /// Test sync of merge solving conflicts
 #[test]
 fn test_merge_solving_conflicts() {
@@ -508,6 +960,9 @@ fn test_uproot_merge_with_conflicts() {
 
     // conflicts on C3
     env.run_ripit_failure(&["-yu"], Some("due to conflicts"));
+    let c3 = env.remote_repo.revparse_single("c3").unwrap();
+    let state = fs::read_to_string(env.local_repo.path().join("ripit").join("STATE")).unwrap();
+    assert!(state.starts_with(&format!("commit {}\nbranch refs/heads/master\n", c3.id())));
     env.local_repo.resolve_conflict_and_commit("c1");
 
     // sync C5
@@ -558,6 +1013,8 @@ fn test_uproot_merge_with_conflicts() {
 fn test_cache_file() {
     let env = env::TestEnv::new(None);
     env.setup_symmetric_conflict();
+    // the conflicts must not be resolved with the resolutions of the previous ones
+    env.add_cfg("rerere: false");
 
     let c0 = env.remote_repo.revparse_single("c0").unwrap();
     env.remote_repo.reset_hard(&c0);
@@ -580,16 +1037,25 @@ fn test_cache_file() {
     let c3 = env.local_repo.find_commit(head_tgt).unwrap();
 
     // rename the cache file, to test the synchronization will be wrong
-    let cache_path = Path::new(env.local_repo.workdir().unwrap()).join(".ripit-cache");
-    let bkp_path = Path::new(env.local_repo.workdir().unwrap()).join(".ripit-cache.bkp");
+    let cache_path = env.cache_path();
+    let bkp_path = cache_path.with_extension("bkp");
     fs::rename(&cache_path, &bkp_path).unwrap();
 
     // it will try to synchronize c3 again
     env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c3"));
+    // a new synchronization cannot be started before aborting this one
+    env.run_ripit_failure(&["-yu"], Some("is stopped on the conflicts of commit"));
+    env.run_ripit_success(&["--abort"]);
     env.local_repo.reset_hard(c3.as_object());
 
-    // set the cache file again
-    fs::rename(&bkp_path, &cache_path).unwrap();
+    // rebuild the cache file from the tags of the branch and of the reflogs, which contain
+    // the copy of c2
+    env.run_ripit_success(&["--rebuild-cache"]);
+    let rebuilt = fs::read_to_string(&cache_path).unwrap();
+    for line in fs::read_to_string(&bkp_path).unwrap().lines() {
+        assert!(rebuilt.lines().any(|l| l == line));
+    }
+    assert!(cache_path.with_file_name("cache.bak").exists());
     // conflict on C4
     env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c4"));
     env.local_repo.resolve_conflict_and_commit("cb");
@@ -723,3 +1189,3886 @@ fn test_merge_multiple_branches() {
     let ci = branch.get().peel_to_commit().unwrap();
     assert!(ci.summary().unwrap().contains("c8"));
 }
+
+/// Test stopping the synchronization before a matching commit
+#[test]
+fn test_stop_before() {
+    let env = env::TestEnv::new(None);
+
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.remote_repo
+        .commit_file("b.txt", "b\n\nBREAKING CHANGE: b");
+    env.remote_repo.commit_file("c.txt", "c");
+
+    // only a is synchronized
+    env.run_ripit_success(&["-y", "--stop-before", "BREAKING CHANGE"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    env.local_repo.check_file("a.txt", true, true);
+    env.local_repo.check_file("b.txt", false, false);
+
+    // the matching commit is now first: nothing is synchronized
+    env.run_ripit_success(&["-y", "--stop-before", "BREAKING CHANGE"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+
+    // without the option, the synchronization is finished
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 4);
+    env.local_repo.check_file("b.txt", true, true);
+    env.local_repo.check_file("c.txt", true, true);
+
+    // an invalid regex is rejected
+    env.run_ripit_failure(
+        &["-y", "--stop-before", "("],
+        Some("Invalid stop-before option"),
+    );
+}
+
+/// Test limiting the number of commits synchronized by a run
+#[test]
+fn test_max_commits() {
+    let env = env::TestEnv::new(None);
+
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.remote_repo.reset_hard(a.as_object());
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    env.remote_repo.reset_hard(b.as_object());
+    env.remote_repo.do_merge(&c, "d");
+    env.remote_repo.commit_file("e.txt", "e");
+
+    env.run_ripit_success(&["-y", "--max-commits", "2"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+
+    // the merge is synchronized with its parents only
+    env.set_cfg("remote: private\nmax_commits: 2\n");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 5);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_count(), 2);
+    env.local_repo.check_file("c.txt", true, true);
+
+    env.run_ripit_success(&["-y", "--max-commits", "5"]);
+    assert_eq!(env.local_repo.count_commits(), 6);
+    env.local_repo.check_file("e.txt", true, true);
+
+    env.run_ripit_failure(&["-y", "--max-commits", "0"], None);
+}
+
+/// Test synchronizing up to a remote revision
+#[test]
+fn test_until() {
+    let env = env::TestEnv::new(None);
+
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.remote_repo
+        .tag_lightweight("v1", b.as_object(), false)
+        .unwrap();
+    env.remote_repo.commit_file("c.txt", "c");
+
+    let a_id = a.id().to_string();
+    env.run_ripit_success(&["--bootstrap", "--until", &a_id]);
+    env.local_repo.check_file("a.txt", true, true);
+    env.local_repo.check_file("b.txt", false, false);
+
+    env.run_ripit_success(&["-y", "--until", "v1"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    env.local_repo.check_file("b.txt", true, true);
+    env.local_repo.check_file("c.txt", false, false);
+
+    env.run_ripit_failure(
+        &["-y", "--until", "v2"],
+        Some("Cannot find revision v2 in the remote private."),
+    );
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    env.run_ripit_failure(
+        &["-y", "--until", &head.id().to_string()],
+        Some("is not reachable from branch private/master."),
+    );
+
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+}
+
+/// Test skipping the commits matching the skip_commits option
+#[test]
+fn test_skip_commits() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("remote: private\nskip_commits:\n  - ^fixup!\n  - \\[internal-only\\]\n");
+
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // linear history
+    env.remote_repo.commit_file("b.txt", "fixup! a");
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    env.local_repo.check_file("b.txt", false, false);
+    env.local_repo.check_file("c.txt", true, true);
+    let skipped = fs::read_to_string(env.skipped_path()).unwrap();
+    assert!(skipped.contains(" skip_commits"));
+
+    // the merge of a skipped commit is copied on the copy of its parent
+    env.remote_repo.reset_hard(a.as_object());
+    let d = env.remote_repo.commit_file("d.txt", "d [internal-only]");
+    env.remote_repo.reset_hard(c.as_object());
+    env.remote_repo.do_merge(&d, "e");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_count(), 2);
+    let bootstrap = head.parent(1).unwrap();
+    assert!(bootstrap.summary().unwrap().contains("Bootstrap"));
+}
+
+/// Test skipping the commits listed in the skip_shas option
+#[test]
+fn test_skip_shas() {
+    let env = env::TestEnv::new(None);
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // linear history, with an abbreviated id
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    env.remote_repo.reset_hard(a.as_object());
+    let d = env.remote_repo.commit_file("d.txt", "d");
+    env.remote_repo.reset_hard(c.as_object());
+    env.remote_repo.do_merge(&d, "e");
+    env.add_cfg(&format!(
+        "skip_shas:\n  - {}\n  - {}\n",
+        &b.id().to_string()[..10],
+        d.id().to_string().to_uppercase()
+    ));
+    env.run_ripit_success(&["-y"]);
+    env.local_repo.check_file("b.txt", false, false);
+    env.local_repo.check_file("c.txt", true, true);
+    let skipped = fs::read_to_string(env.skipped_path()).unwrap();
+    assert!(skipped.contains(&format!("{} skip_shas", b.id())));
+    assert!(skipped.contains(&format!("{} skip_shas", d.id())));
+
+    // the merge of the skipped commit is copied on the copy of its parent
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.summary().unwrap().contains('e'));
+    assert_eq!(head.parent_count(), 2);
+    assert!(head
+        .parent(1)
+        .unwrap()
+        .summary()
+        .unwrap()
+        .contains("Bootstrap"));
+
+    env.set_cfg("remote: private\nskip_shas: [xyz]\n");
+    env.run_ripit_failure(&["-y"], Some("4 to 40 hex digits"));
+    env.set_cfg("remote: private\nskip_shas: [0123456789]\n");
+    env.run_ripit_failure(&["-y"], Some("Cannot find commit 0123456789"));
+
+    // an abbreviation shared by several objects is refused
+    let mut prefixes = std::collections::HashSet::new();
+    let ambiguous = (0..)
+        .map(|i| env.local_repo.blob(format!("{}", i).as_bytes()).unwrap())
+        .find_map(|id| {
+            let prefix = id.to_string()[..4].to_owned();
+            (!prefixes.insert(prefix.clone())).then_some(prefix)
+        })
+        .unwrap();
+    env.set_cfg(&format!("remote: private\nskip_shas: ['{}']\n", ambiguous));
+    env.run_ripit_failure(&["-y"], Some("is ambiguous"));
+}
+
+/// Test replacing remote commits with local patches
+#[test]
+fn test_replacements() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo
+        .commit_file_with_content("a.txt", "a\n", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the patch replaces the changes of the commit, its children being copied on top of it
+    env.remote_repo.write_and_add_file("dep.txt", "dep");
+    let b = env
+        .remote_repo
+        .commit_file_with_content("a.txt", "a\nb\n", "b");
+    env.remote_repo.commit_file("c.txt", "c");
+    let workdir = env.local_repo.workdir().unwrap().to_owned();
+    fs::write(
+        workdir.join("b.patch"),
+        "diff --git a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n@@ -1 +1,2 @@\n a\n+patched b\n",
+    )
+    .unwrap();
+    env.add_cfg(&format!(
+        "replacements:\n  '{}': b.patch\n",
+        &b.id().to_string()[..10]
+    ));
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(
+        fs::read_to_string(workdir.join("a.txt")).unwrap(),
+        "a\npatched b\n"
+    );
+    env.local_repo.check_file("dep.txt", false, false);
+    env.local_repo.check_file("c.txt", true, true);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let replaced = head.parent(0).unwrap();
+    let msg = replaced.message().unwrap();
+    assert!(msg.contains("Replaced with the local patch b.patch."));
+    assert!(msg.contains(&b.id().to_string()));
+
+    // a patch that does not apply stops the synchronization, until applied by hand
+    let d = env
+        .remote_repo
+        .commit_file_with_content("a.txt", "d\n", "d");
+    fs::write(
+        workdir.join("d.patch"),
+        "diff --git a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-x\n+d\n",
+    )
+    .unwrap();
+    env.add_cfg(&format!("  '{}': d.patch\n", d.id()));
+    let (code, _) = env.run_ripit_status(&["-y"]);
+    assert_eq!(code, Some(2));
+    env.local_repo.resolve_conflict_and_commit("a.txt");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = head.message().unwrap();
+    assert!(msg.contains("Replaced with the local patch d.patch."));
+    assert!(msg.contains(&d.id().to_string()));
+
+    env.set_cfg("remote: private\nreplacements:\n  xyz: b.patch\n");
+    env.run_ripit_failure(&["-y"], Some("4 to 40 hex digits"));
+    env.set_cfg(&format!(
+        "remote: private\nreplacements:\n  '{}': missing.patch\n",
+        b.id()
+    ));
+    env.run_ripit_failure(&["-y"], Some("cannot read the patch missing.patch"));
+}
+
+/// Test never copying the excluded paths
+#[test]
+fn test_exclude_paths() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("remote: private\nexclude_paths:\n  - internal\n  - '*.secret'\n");
+
+    env.remote_repo
+        .write_and_add_file("internal/doc.txt", "doc");
+    env.remote_repo.write_and_add_file("keys.secret", "key");
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.local_repo.check_file("a.txt", true, true);
+    env.local_repo.check_file("internal/doc.txt", false, false);
+    env.local_repo.check_file("keys.secret", false, false);
+
+    // only the changes of the included paths are copied
+    env.remote_repo
+        .write_and_add_file("internal/doc.txt", "doc v2");
+    env.remote_repo
+        .write_and_add_file("internal/new.txt", "new");
+    env.remote_repo.commit_file("a.txt", "a v2");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    env.local_repo.check_file("internal/new.txt", false, false);
+    let content = fs::read_to_string(env.local_repo.workdir().unwrap().join("a.txt")).unwrap();
+    assert_eq!(content, "a v2");
+
+    // commits only changing excluded paths are skipped
+    env.remote_repo.commit_file("sub/other.secret", "other");
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    env.local_repo.check_file("b.txt", true, true);
+    env.local_repo.check_file("sub/other.secret", false, false);
+}
+
+/// Test copying only a subdirectory of the remote
+#[test]
+fn test_subtree() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("remote: private\nsubtree: sdk/\n");
+
+    env.remote_repo.write_and_add_file("sdk/a.txt", "a");
+    env.remote_repo.commit_file("internal.txt", "internal");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.local_repo.check_file("a.txt", true, true);
+    env.local_repo.check_file("internal.txt", false, false);
+
+    // only the changes of the subtree are copied, commits outside of it are skipped
+    env.remote_repo.write_and_add_file("sdk/b.txt", "b");
+    let b = env.remote_repo.commit_file("internal.txt", "b");
+    let c = env.remote_repo.commit_file("other.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    env.local_repo.check_file("b.txt", true, true);
+    env.local_repo.check_file("sdk/b.txt", false, false);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head
+        .message()
+        .unwrap()
+        .contains(&format!("rip-it: {}", b.id())));
+
+    // a merge of commits outside of the subtree collapses
+    env.remote_repo.reset_hard(b.as_object());
+    let d = env.remote_repo.commit_file("side.txt", "d");
+    env.remote_repo.reset_hard(c.as_object());
+    env.remote_repo.do_merge(&d, "e");
+    env.remote_repo.write_and_add_file("sdk/f.txt", "f");
+    env.remote_repo.commit_file("other.txt", "f");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_count(), 1);
+    env.local_repo.check_file("f.txt", true, true);
+}
+
+/// Test copying the content of the remote in a directory of the local repository
+#[test]
+fn test_prefix() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("remote: private\nprefix: vendor/upstream\n");
+
+    env.local_repo.commit_file("local.txt", "local");
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.local_repo
+        .check_file("vendor/upstream/a.txt", true, true);
+    env.local_repo.check_file("local.txt", true, true);
+
+    // local commits and changes outside of the prefix are kept
+    env.remote_repo.commit_file("b.txt", "b");
+    let local = env.local_repo.commit_file("local2.txt", "local2");
+    let local_path = env.local_repo.workdir().unwrap().join("local.txt");
+    fs::write(&local_path, "wip").unwrap();
+    env.run_ripit_success(&["-y"]);
+    env.local_repo
+        .check_file("vendor/upstream/b.txt", true, true);
+    env.local_repo.check_file("local2.txt", true, true);
+    assert_eq!(fs::read_to_string(&local_path).unwrap(), "wip");
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap(), local.id());
+
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    env.local_repo
+        .check_file("vendor/upstream/c.txt", true, true);
+    assert_eq!(env.local_repo.count_commits(), 5);
+
+    // changes in the prefix are still refused
+    let path = env
+        .local_repo
+        .workdir()
+        .unwrap()
+        .join("vendor/upstream/a.txt");
+    fs::write(path, "wip").unwrap();
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_failure(&["-y"], Some("non committed changes"));
+}
+
+/// Test pushing the synchronized branches to a publish remote
+#[test]
+fn test_push_remote() {
+    let env = env::TestEnv::new(None);
+    let public_dir = tempfile::tempdir().unwrap();
+    let public_repo = git2::Repository::init_bare(public_dir.path()).unwrap();
+    env.local_repo
+        .remote("public", public_dir.path().to_str().unwrap())
+        .unwrap();
+    env.set_cfg("remote: private\npush_remote: public\n");
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y", "--no-push"]);
+    assert!(public_repo.refname_to_id("refs/heads/master").is_err());
+
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+    assert_eq!(
+        public_repo.refname_to_id("refs/heads/master").unwrap(),
+        head
+    );
+    let tracking = env.local_repo.refname_to_id("refs/remotes/public/master");
+    assert_eq!(tracking.unwrap(), head);
+
+    // the branch is synchronized even if it cannot be pushed
+    env.set_cfg(
+        "remote: private\npush_remote: public\n\
+         branches:\n  - name: master\n    push_remote: missing\n",
+    );
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_failure(&["-y"], Some("could not be pushed: master"));
+    assert_eq!(env.local_repo.count_commits(), 4);
+    assert_eq!(
+        public_repo.refname_to_id("refs/heads/master").unwrap(),
+        head
+    );
+}
+
+/// Test the configuration of the credentials
+#[test]
+fn test_auth_cfg() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg(
+        "remote: private\n\
+         auth:\n  ssh_key: ${HOME}/.ssh/id_ed25519\n  ssh_agent: false\n",
+    );
+
+    // the credentials are only used by remotes requiring them
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+
+    env.set_cfg("remote: private\nauth:\n  ssh_key: ${RIPIT_TEST_UNSET_KEY}\n");
+    env.run_ripit_failure(
+        &["-y"],
+        Some("The environment variable RIPIT_TEST_UNSET_KEY used in the auth option is not set."),
+    );
+}
+
+/// Test fetching only the synchronized branches, unless the whole remote is asked for
+#[test]
+fn test_fetch_all() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    let head = env.remote_repo.head().unwrap().peel_to_commit().unwrap();
+    env.remote_repo.branch("other", &head, false).unwrap();
+    env.remote_repo
+        .tag_lightweight("v1", head.as_object(), false)
+        .unwrap();
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    assert!(env
+        .local_repo
+        .refname_to_id("refs/remotes/private/other")
+        .is_err());
+    assert!(env.local_repo.refname_to_id("refs/tags/v1").is_err());
+
+    env.set_cfg("remote: private\nfetch_all: true\n");
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    assert_eq!(
+        env.local_repo
+            .refname_to_id("refs/remotes/private/other")
+            .unwrap(),
+        head.id()
+    );
+}
+
+/// Test overriding the repository, remote and branches of the configuration
+#[test]
+fn test_cli_overrides() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("repo: /nonexistent\nremote: missing\nbranches:\n  - master\n");
+    let repo_path = env
+        .local_repo
+        .workdir()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_failure(&["--bootstrap"], None);
+    env.run_ripit_failure(
+        &["--bootstrap", "--repo", &repo_path],
+        Some("Remote missing does not exist in the repository."),
+    );
+    env.run_ripit_success(&["--bootstrap", "--repo", &repo_path, "--remote", "private"]);
+    assert_eq!(env.local_repo.count_commits(), 1);
+
+    // the branches given on the command line replace the configured ones
+    let head = env.remote_repo.head().unwrap().peel_to_commit().unwrap();
+    env.remote_repo.branch("dev", &head, false).unwrap();
+    let overrides = ["--repo", &repo_path, "--remote", "private"];
+    env.run_ripit_failure(
+        &[
+            &overrides[..],
+            &["--bootstrap", "--branch", "dev", "--branch", "typo"],
+        ]
+        .concat(),
+        Some("Branch typo does not exist in remote private."),
+    );
+    env.run_ripit_success(&[&overrides[..], &["--bootstrap", "--branch", "dev"]].concat());
+    assert!(env.local_repo.refname_to_id("refs/heads/dev").is_ok());
+
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&[&overrides[..], &["-y", "--branch", "dev"]].concat());
+    assert_eq!(env.local_repo.count_commits(), 1);
+    env.run_ripit_success(&[&overrides[..], &["-y"]].concat());
+    assert_eq!(env.local_repo.count_commits(), 2);
+}
+
+/// Test the rejection of the unknown options of the configuration file
+#[test]
+fn test_unknown_cfg_keys() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a.txt", "a");
+
+    env.set_cfg("remote: private\nfilter:\n  - ^Refs\n");
+    env.run_ripit_failure(
+        &["--bootstrap"],
+        Some("unknown option `filter`, did you mean `filters`?"),
+    );
+    env.set_cfg("remote: private\nbranches:\n  - name: master\n    uprot: true\n");
+    env.run_ripit_failure(
+        &["--bootstrap"],
+        Some("unknown option `uprot` in branches, did you mean `uproot`?"),
+    );
+    assert!(env.local_repo.head().is_err());
+
+    env.set_cfg("remote: private\nfilters:\n  - ^Refs\n");
+    env.run_ripit_success(&["--bootstrap"]);
+}
+
+/// Test checking the configuration without synchronizing
+#[test]
+fn test_check_config() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a.txt", "a");
+
+    let (code, stdout) = env.run_ripit_status(&["--check-config"]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("ok: branch master found in remote private"));
+    assert!(stdout.contains("The configuration is valid."));
+    // nothing is synchronized
+    assert!(env.local_repo.head().is_err());
+
+    // every problem is reported
+    env.set_cfg(
+        "remote: private\npush_remote: public\n\
+         branches:\n  - master\n  - missing\n",
+    );
+    let (code, stdout) = env.run_ripit_status(&["--check-config"]);
+    assert_eq!(code, Some(1));
+    assert!(stdout.contains("ok: branch master found in remote private"));
+    assert!(stdout.contains("error: Branch missing does not exist in remote private."));
+    assert_eq!(
+        stdout
+            .matches("error: Remote public does not exist")
+            .count(),
+        2
+    );
+
+    env.set_cfg("remote: unknown\n");
+    let (code, stdout) = env.run_ripit_status(&["--check-config"]);
+    assert_eq!(code, Some(1));
+    assert!(stdout.contains("error: Remote unknown does not exist"));
+    assert!(stdout.contains("error: Branch master does not exist in remote unknown."));
+}
+
+/// Test the JSON summary of a synchronization
+#[test]
+fn test_json_summary() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("skip_commits:\n  - ^wip");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+    let remote_before = env.remote_repo.refname_to_id("HEAD").unwrap();
+
+    let a = env.remote_repo.commit_file("a.txt", "a").id();
+    let wip = env.remote_repo.commit_file("b.txt", "wip b").id();
+    let (code, stdout) = env.run_ripit_status(&["-y", "--json"]);
+    assert_eq!(code, Some(0));
+
+    // the human-readable output is not on stdout
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let head = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+    let branch = &json["branches"][0];
+    assert_eq!(branch["branch"], "master");
+    assert_eq!(branch["remote_tip_before"], remote_before.to_string());
+    assert_eq!(branch["remote_tip_after"], wip.to_string());
+    assert_eq!(branch["local_tip_before"], bootstrap.to_string());
+    assert_eq!(branch["local_tip_after"], head.to_string());
+
+    let commits = branch["commits"].as_array().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[0]["remote_oid"], a.to_string());
+    assert_eq!(commits[0]["local_oid"], head.to_string());
+    assert_eq!(commits[0]["summary"], "a");
+    assert_eq!(commits[0]["skipped"], false);
+    assert_eq!(commits[0]["uprooted"], false);
+    assert_eq!(commits[1]["remote_oid"], wip.to_string());
+    assert!(commits[1]["local_oid"].is_null());
+    assert_eq!(commits[1]["skipped"], true);
+
+    // nothing is processed when up to date
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    let (_, stdout) = env.run_ripit_status(&["-y", "--json"]);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["branches"][0]["commits"].as_array().unwrap().len(), 0);
+}
+
+/// Test the exit codes distinguishing conflicts and local changes from other errors
+#[test]
+fn test_exit_codes() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+
+    let c9 = env.remote_repo.revparse_single("c9").unwrap();
+    env.remote_repo.reset_hard(&c9);
+    env.run_ripit_success(&["--bootstrap"]);
+    assert_eq!(env.run_ripit_status(&["-y"]).0, Some(0));
+
+    // local changes
+    let path = env.local_repo.workdir().unwrap().join("c12");
+    fs::write(&path, "local change").unwrap();
+    assert_eq!(env.run_ripit_status(&["-y"]).0, Some(3));
+    env.local_repo.force_checkout_head();
+
+    // c12 conflicts, and the synchronization stays stopped until resolved
+    let c10 = env.remote_repo.revparse_single("c10").unwrap();
+    env.remote_repo.reset_hard(&c10);
+    assert_eq!(env.run_ripit_status(&["-yu"]).0, Some(2));
+    assert_eq!(env.run_ripit_status(&["-yu"]).0, Some(2));
+    assert_eq!(env.run_ripit_status(&["--continue"]).0, Some(2));
+    assert_eq!(env.run_ripit_status(&["--abort"]).0, Some(0));
+
+    // other errors, including usage errors
+    assert_eq!(env.run_ripit_status(&["--unknown-option"]).0, Some(1));
+    env.set_cfg("remote: missing\n");
+    assert_eq!(env.run_ripit_status(&["-y"]).0, Some(1));
+}
+
+/// Test the lock preventing concurrent synchronizations
+#[test]
+fn test_lock() {
+    let env = env::TestEnv::new(None);
+    env.run_ripit_success(&["--bootstrap"]);
+    let lock_path = env.local_repo.path().join("ripit").join("lock");
+    assert!(!lock_path.exists());
+
+    // the lock is held by a running process: the test itself
+    fs::write(&lock_path, format!("{} 0\n", std::process::id())).unwrap();
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_failure(&["-y"], Some("Another ripit process"));
+    assert_eq!(env.local_repo.count_commits(), 1);
+    env.run_ripit_success(&["-y", "--break-lock"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    assert!(!lock_path.exists());
+
+    // stale lock of a process that is no longer running, above the maximum pid on linux
+    fs::write(&lock_path, "4194305 0\n").unwrap();
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    assert!(!lock_path.exists());
+}
+
+/// Test the hook commands run around each copied commit and at the end of the run
+#[test]
+fn test_hooks() {
+    let env = env::TestEnv::new(None);
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("hooks.log");
+    let write_script = |name: &str, script: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        format!("sh {}", path.display())
+    };
+    // the lockfile is regenerated in each copy, and secrets are rejected
+    let pre_commit = write_script(
+        "pre_commit.sh",
+        "[ ! -f secret.txt ] || exit 1\n\
+         echo \"$RIPIT_REMOTE_OID\" > lock.txt && git add lock.txt",
+    );
+    let post_commit = write_script(
+        "post_commit.sh",
+        &format!(
+            "echo \"$RIPIT_BRANCH $RIPIT_REMOTE_OID $RIPIT_LOCAL_OID\" >> {}",
+            log.display()
+        ),
+    );
+    let post_run = write_script(
+        "post_run.sh",
+        &format!(
+            "echo \"run $RIPIT_BRANCHES $RIPIT_NB_COPIED\" >> {}",
+            log.display()
+        ),
+    );
+    let set_hooks = |pre_commit: &str, post_commit: &str, fatal: bool| {
+        env.set_cfg(&format!(
+            "repo: {}\n\
+             remote: private\n\
+             hooks:\n  \
+               pre_commit: '{}'\n  \
+               post_commit: '{}'\n  \
+               post_commit_fatal: {}\n  \
+               post_run: '{}'\n",
+            env.local_repo.workdir().unwrap().display(),
+            pre_commit,
+            post_commit,
+            fatal,
+            post_run
+        ));
+    };
+    set_hooks(&pre_commit, &post_commit, false);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+
+    // the changes staged by pre_commit are part of the copy
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    env.local_repo.check_file("lock.txt", true, true);
+    let lock = std::fs::read_to_string(env.local_repo.workdir().unwrap().join("lock.txt"));
+    assert_eq!(lock.unwrap(), format!("{}\n", b.id()));
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        format!("master {} {}\nrun master 1\n", b.id(), head.id())
+    );
+
+    // a failing pre_commit aborts the run before creating the commit
+    let secret = env.remote_repo.commit_file("secret.txt", "secret");
+    env.run_ripit_failure(&["-y"], Some("The pre_commit hook failed on commit"));
+    assert_eq!(env.local_repo.head().unwrap().target(), Some(head.id()));
+    env.local_repo.check_file("secret.txt", false, false);
+    assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 2);
+
+    // post_commit failures are only reported, unless they are fatal
+    set_hooks("true", "exit 3", false);
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+
+    set_hooks("true", "exit 3", true);
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    let d = env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_failure(&["-y"], Some("The post_commit hook failed on commit"));
+    // the failing copy is kept, the next commit is not copied
+    assert_eq!(env.local_repo.count_commits(), 4);
+    env.local_repo.check_file("c.txt", true, true);
+    env.local_repo.check_file("d.txt", false, false);
+
+    set_hooks("true", "true", true);
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 5);
+    let log = std::fs::read_to_string(&log).unwrap();
+    assert!(!log.contains(&secret.id().to_string()));
+    assert!(!log.contains(&c.id().to_string()) && !log.contains(&d.id().to_string()));
+    assert!(log.ends_with("run master 1\n"));
+}
+
+/// Test piping the messages of the commits through an external command
+#[test]
+fn test_message_filter_cmd() {
+    let env = env::TestEnv::new(None);
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("filter.sh");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\n\
+         msg=$(cat)\n\
+         case \"$msg\" in fail*) echo unknown ticket >&2; exit 1;; esac\n\
+         printf '%s\\n' \"$msg\" | sed 's/INTERNAL-/#/g; s/^c\\([0-9]*\\)$/commit \\1/'\n",
+    )
+    .unwrap();
+    env.add_cfg(&format!("message_filter_cmd: sh {}", script.display()));
+    env.setup_branches();
+
+    let c9 = env.remote_repo.revparse_single("c9").unwrap();
+    env.remote_repo.reset_hard(&c9);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the messages of the uprooted commits, and of the conflicting ones, are filtered
+    let c10 = env.remote_repo.revparse_single("c10").unwrap();
+    env.remote_repo.reset_hard(&c10);
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c12"));
+    let merge_msg = std::fs::read_to_string(env.local_repo.path().join("MERGE_MSG")).unwrap();
+    assert!(merge_msg.starts_with("commit 12\n"));
+    env.local_repo
+        .write_and_add_file("c12", "resolved conflict");
+    env.local_repo.index().unwrap().write().unwrap();
+    env.run_ripit_success(&["-yu", "--continue"]);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.summary(), Some("commit 10"));
+    let c12 = head.parent(1).unwrap();
+    assert_eq!(c12.summary(), Some("commit 12"));
+    assert_eq!(c12.parent(0).unwrap().summary(), Some("commit 11"));
+
+    // the filtered message is written before the ripit tag
+    let d = env
+        .remote_repo
+        .commit_file("d", "Fix INTERNAL-4\n\nSee INTERNAL-5");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message(),
+        Some(format!("Fix #4\n\nSee #5\n\nrip-it: {}\n", d.id()).as_str())
+    );
+
+    // failures of the command abort the synchronization, leaving the repository clean
+    env.remote_repo.commit_file("e", "fail to translate");
+    env.run_ripit_failure(
+        &["-y"],
+        Some("The message filter command failed on message \"fail to translate\""),
+    );
+    assert_eq!(env.local_repo.head().unwrap().target(), Some(head.id()));
+    env.local_repo.check_file("e", false, false);
+
+    std::fs::write(&script, "#!/bin/sh\nprintf '\\377'\n").unwrap();
+    env.run_ripit_failure(&["-y"], Some("invalid UTF-8"));
+    assert_eq!(env.local_repo.head().unwrap().target(), Some(head.id()));
+}
+
+/// Test signing the commits created by ripit with an SSH key
+#[test]
+fn test_sign_commits() {
+    let env = env::TestEnv::new(None);
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key");
+    let status = std::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let pubkey = std::fs::read_to_string(dir.path().join("key.pub")).unwrap();
+    let signers = dir.path().join("allowed_signers");
+    std::fs::write(&signers, format!("Bar {}", pubkey)).unwrap();
+    {
+        let mut config = env.local_repo.config().unwrap();
+        config.set_str("gpg.format", "ssh").unwrap();
+        config
+            .set_str("gpg.ssh.allowedSignersFile", signers.to_str().unwrap())
+            .unwrap();
+    }
+    let set_key = |key: &str| {
+        env.set_cfg(&format!(
+            "repo: {}\nremote: private\nsign_commits: true\nsigning_key: {}\n",
+            env.local_repo.workdir().unwrap().display(),
+            key
+        ));
+    };
+
+    // an SSH key must be given
+    env.add_cfg("sign_commits: true");
+    env.run_ripit_failure(
+        &["--bootstrap"],
+        Some("Invalid signing_key option: a key is required to sign with SSH"),
+    );
+
+    // the bootstrap commit, the copies and the merges are signed
+    set_key(key.to_str().unwrap());
+    env.setup_branches();
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    let c8 = env.remote_repo.revparse_single("c8").unwrap();
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.reset_hard(&c8);
+    env.run_ripit_success(&["-y"]);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_count(), 2);
+    let mut revwalk = env.local_repo.revwalk().unwrap();
+    revwalk.push_head().unwrap();
+    let mut nb_signed = 0;
+    for oid in revwalk {
+        let oid = oid.unwrap();
+        let (signature, _) = env.local_repo.extract_signature(&oid, None).unwrap();
+        assert!(signature
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN SSH SIGNATURE-----"));
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(env.local_repo.workdir().unwrap())
+            .args(["verify-commit", &oid.to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        nb_signed += 1;
+    }
+    assert_eq!(nb_signed, 5);
+    assert_eq!(env.local_repo.head().unwrap().shorthand(), Some("master"));
+
+    // signing failures name the key
+    set_key("/nonexistent/key");
+    env.remote_repo.commit_file("d", "d");
+    env.run_ripit_failure(
+        &["-y"],
+        Some("Cannot sign the commit with key /nonexistent/key"),
+    );
+    assert_eq!(env.local_repo.head().unwrap().target(), Some(head.id()));
+    env.local_repo.check_file("d", false, false);
+}
+
+/// Test adding a Signed-off-by trailer to the synchronized commits
+#[test]
+fn test_signoff() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("signoff: true");
+    env.setup_branches();
+
+    let c9 = env.remote_repo.revparse_single("c9").unwrap();
+    env.remote_repo.reset_hard(&c9);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the sign-off is in the message prepared for the resolution of the conflicts
+    let c10 = env.remote_repo.revparse_single("c10").unwrap();
+    let c12 = env.remote_repo.revparse_single("c12").unwrap();
+    env.remote_repo.reset_hard(&c10);
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c12"));
+    let merge_msg = std::fs::read_to_string(env.local_repo.path().join("MERGE_MSG")).unwrap();
+    assert!(merge_msg.contains(&format!(
+        "\n\nSigned-off-by: Foo <Bar>\nrip-it: {} uprooted\n",
+        c12.id()
+    )));
+    env.local_repo
+        .write_and_add_file("c12", "resolved conflict");
+    env.local_repo.index().unwrap().write().unwrap();
+    env.run_ripit_success(&["-yu", "--continue"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let resolved = head.parent(1).unwrap();
+    assert!(resolved.message().unwrap().ends_with(&format!(
+        "\n\nSigned-off-by: Foo <Bar>\nrip-it: {} uprooted\n",
+        c12.id()
+    )));
+
+    // the sign-off precedes the ripit tag
+    let b = env.remote_repo.commit_file("b", "Add b");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message().unwrap(),
+        format!("Add b\n\nSigned-off-by: Foo <Bar>\nrip-it: {}\n", b.id())
+    );
+
+    // an existing sign-off of the same identity is not repeated
+    let c = env
+        .remote_repo
+        .commit_file("c", "Add c\n\nSigned-off-by: Foo <Bar>");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message().unwrap(),
+        format!("Add c\n\nSigned-off-by: Foo <Bar>\n\nrip-it: {}\n", c.id())
+    );
+
+    // the identity can be set explicitly, and the option given on the command line
+    env.set_cfg(&format!(
+        "repo: {}\nremote: private\nsignoff_identity: Jane Doe <jane@doe.com>\n",
+        env.local_repo.workdir().unwrap().display()
+    ));
+    let d = env.remote_repo.commit_file("d", "Add d");
+    env.run_ripit_success(&["-y", "--signoff"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message().unwrap(),
+        format!(
+            "Add d\n\nSigned-off-by: Jane Doe <jane@doe.com>\nrip-it: {}\n",
+            d.id()
+        )
+    );
+}
+
+/// Test adding configured trailers to the synchronized commits
+#[test]
+fn test_trailers() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("signoff: true");
+    env.add_cfg(
+        "trailers:\n  \
+           Sync-Tool: ripit\n  \
+           Upstream-Commit: '{remote}/{branch}@{remote_oid}'\n  \
+           Refs: public-42\n",
+    );
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the trailers are not filtered, and the ripit tag stays last
+    let b = env.remote_repo.commit_file(
+        "b",
+        "Add b\n\nRefs: internal-12\nReviewed-by: John Doe <jdoe@corp>",
+    );
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message().unwrap(),
+        format!(
+            "Add b\n\n\
+             Reviewed-by: John Doe <jdoe@corp>\n\n\
+             Sync-Tool: ripit\n\
+             Upstream-Commit: private/master@{id}\n\
+             Refs: public-42\n\
+             Signed-off-by: Foo <Bar>\n\
+             rip-it: {id}\n",
+            id = b.id()
+        )
+    );
+
+    // the synchronization goes on from the tag
+    let c = env.remote_repo.commit_file("c", "Add c");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head
+        .message()
+        .unwrap()
+        .starts_with("Add c\n\nSync-Tool: ripit\n"));
+    assert!(head
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c.id())));
+
+    env.set_cfg(&format!(
+        "repo: {}\nremote: private\ntrailers:\n  Invalid key: value\n",
+        env.local_repo.workdir().unwrap().display()
+    ));
+    env.run_ripit_failure(&["-y"], Some("Invalid trailers option"));
+}
+
+/// Test listing the commits to synchronize without synchronizing them
+#[test]
+fn test_dry_run() {
+    let env = env::TestEnv::new(None);
+    env.setup_merge_uproot(false);
+
+    // start syncing from c2
+    let c2 = env.remote_repo.revparse_single("c2").unwrap();
+    env.remote_repo.reset_hard(&c2);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let c5 = env.remote_repo.revparse_single("c5").unwrap();
+    env.remote_repo.reset_hard(&c5);
+    let c3 = env.remote_repo.revparse_single("c3").unwrap();
+
+    let cache_path = env.cache_path();
+    let cache = fs::read_to_string(&cache_path).unwrap();
+    let head = env.local_repo.head().unwrap().target().unwrap();
+
+    // c3 has a parent that is not synchronized
+    let (code, out) = env.run_ripit_status(&["-n"]);
+    assert_eq!(code, Some(0));
+    let c3_line = format!(
+        "  {} c3 (unknown parent, needs --uproot)",
+        &c3.id().to_string()[..10]
+    );
+    assert!(out.contains(&c3_line));
+    assert!(out.contains(" c5\n"));
+
+    let (code, out) = env.run_ripit_status(&["-nu"]);
+    assert_eq!(code, Some(0));
+    assert!(out.contains(&format!("  {} c3 (uprooted)", &c3.id().to_string()[..10])));
+
+    // nothing was modified
+    assert_eq!(env.local_repo.head().unwrap().target().unwrap(), head);
+    assert_eq!(env.local_repo.count_commits(), 1);
+    assert_eq!(fs::read_to_string(&cache_path).unwrap(), cache);
+
+    // once synchronized, the dry run exits with a distinct code
+    env.run_ripit_success(&["-yu"]);
+    let (code, _) = env.run_ripit_status(&["--dry-run"]);
+    assert_eq!(code, Some(4));
+}
+
+/// Test changing the prefix of the tags
+#[test]
+fn test_tag_prefix() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+
+    // the commits synchronized with the previous prefix are still recognized
+    env.add_cfg("tag_prefix: mirrored-from");
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = head.message().unwrap();
+    assert!(msg.contains(&format!("\nmirrored-from: {}\n", c.id())));
+    assert!(!msg.contains("rip-it"));
+
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 4);
+    env.local_repo.check_file("d.txt", true, true);
+
+    env.set_cfg("remote: private\ntag_prefix: 'bad prefix'\n");
+    env.run_ripit_failure(&["-y"], Some("Invalid tag_prefix option"));
+}
+
+/// Test the mapping of the identities of the synchronized commits
+#[test]
+fn test_authors_mapping() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.add_cfg(
+        "authors:\n\
+         \x20 BAR: 'Public Name <public@example.org>'\n\
+         keep_original_author_trailer: true\n",
+    );
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    for sig in [head.author(), head.committer()] {
+        assert_eq!(sig.name(), Some("Public Name"));
+        assert_eq!(sig.email(), Some("public@example.org"));
+    }
+    assert_eq!(head.author().when(), b.author().when());
+    let msg = head.message().unwrap();
+    assert!(msg.contains(&format!(
+        "\n\nOriginal-Author: Foo <Bar>\nrip-it: {}\n",
+        b.id()
+    )));
+
+    // unknown authors are kept as is, unless a default author is set
+    let mut config = env.remote_repo.config().unwrap();
+    config.set_str("user.email", "other@corp").unwrap();
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.author().email(), Some("other@corp"));
+    assert!(!head.message().unwrap().contains("Original-Author"));
+
+    env.add_cfg("default_author: 'Mirror <mirror@example.org>'\n");
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.author().email(), Some("mirror@example.org"));
+    assert!(head
+        .message()
+        .unwrap()
+        .contains("Original-Author: Foo <other@corp>"));
+
+    env.set_cfg("remote: private\ndefault_author: 'no email'\n");
+    env.run_ripit_failure(&["-y"], Some("Invalid default_author option"));
+}
+
+/// Test the synchronization of the remote tags
+#[test]
+fn test_sync_tags() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("remote: private\nsync_tags: true\nfilters:\n  - ^Internal\n");
+
+    let old = env.remote_repo.commit_file("a.txt", "a");
+    env.remote_repo
+        .tag_lightweight("old", old.as_object(), false)
+        .unwrap();
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    let sig = env.remote_repo.signature().unwrap();
+    env.remote_repo
+        .tag(
+            "v1",
+            c.as_object(),
+            &sig,
+            "Release 1\n\nInternal: secret\n",
+            false,
+        )
+        .unwrap();
+    let d = env.remote_repo.commit_file("d.txt", "d");
+    env.remote_repo
+        .tag_lightweight("v2", d.as_object(), false)
+        .unwrap();
+    env.run_ripit_success(&["-y"]);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let v2 = env.local_repo.find_reference("refs/tags/v2").unwrap();
+    assert_eq!(v2.target(), Some(head.id()));
+
+    let v1 = env
+        .local_repo
+        .find_reference("refs/tags/v1")
+        .unwrap()
+        .peel_to_tag()
+        .unwrap();
+    assert_eq!(v1.target_id(), head.parent_id(0).unwrap());
+    assert_eq!(v1.message(), Some("Release 1\n"));
+    assert_eq!(v1.tagger().unwrap().name(), Some("Foo"));
+
+    // the commit of this tag was not synchronized
+    assert!(env.local_repo.find_reference("refs/tags/old").is_err());
+
+    // existing tags are kept
+    env.remote_repo.commit_file("e.txt", "e");
+    env.run_ripit_success(&["-y"]);
+    let v2 = env.local_repo.find_reference("refs/tags/v2").unwrap();
+    assert_eq!(v2.target(), Some(head.id()));
+}
+
+/// Test synchronization in a temporary worktree
+#[test]
+fn test_detached_worktree() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo.commit_file("b.txt", "b");
+    env.remote_repo.commit_file("c.txt", "c");
+
+    // the synchronized branch cannot be checked out
+    env.run_ripit_failure(&["-y", "--detached-worktree"], Some("is checked out"));
+
+    // work on another branch
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    env.local_repo.branch("work", &head, false).unwrap();
+    env.local_repo.set_head("refs/heads/work").unwrap();
+
+    let path = Path::new(env.local_repo.workdir().unwrap()).join("a.txt");
+    let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+
+    env.run_ripit_success(&["-y", "--detached-worktree"]);
+
+    // master was updated
+    let branch = env
+        .local_repo
+        .find_branch("master", git2::BranchType::Local)
+        .unwrap();
+    let ci = branch.get().peel_to_commit().unwrap();
+    assert!(ci.summary().unwrap().contains('c'));
+    assert_eq!(ci.parent(0).unwrap().summary().unwrap(), "b");
+
+    // the working directory was not touched
+    let local_head = env.local_repo.head().unwrap();
+    assert_eq!(local_head.shorthand().unwrap(), "work");
+    assert_eq!(env.local_repo.count_commits(), 1);
+    env.local_repo.check_file("b.txt", false, false);
+    env.local_repo.check_file("c.txt", false, false);
+    assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a");
+
+    // the temporary worktree was removed
+    assert_eq!(env.local_repo.worktrees().unwrap().len(), 0);
+}
+
+/// Test statistics on the synchronized commits
+#[test]
+fn test_stats() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+
+    let c9 = env.remote_repo.revparse_single("c9").unwrap();
+    env.remote_repo.reset_hard(&c9);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // uproot c11 and c12, with a conflict on c12, solved by another committer
+    let c10 = env.remote_repo.revparse_single("c10").unwrap();
+    env.remote_repo.reset_hard(&c10);
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts"));
+    let mut config = env.local_repo.config().unwrap();
+    config.set_str("user.name", "Resolver").unwrap();
+    env.local_repo.resolve_conflict_and_commit("c12");
+    env.run_ripit_success(&["-yu"]);
+
+    // then a simple sync
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_success(&["-y"]);
+
+    let output = env.run_ripit_subcommand(&["stats"]);
+    assert!(output.contains("Total: 4 commits synchronized, 2 uprooted, 1 after conflicts."));
+
+    let output = env.run_ripit_subcommand(&["stats", "--json"]);
+    let stats: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(stats["total"]["synced"], 4);
+    assert_eq!(stats["total"]["uprooted"], 2);
+    assert_eq!(stats["total"]["conflicts"], 1);
+    assert_eq!(stats["months"].as_array().unwrap().len(), 1);
+    assert_eq!(stats["months"][0]["synced"], 4);
+
+    let authors = stats["authors"].as_array().unwrap();
+    assert_eq!(authors.len(), 2);
+    assert_eq!(authors[0]["key"], "Foo <Bar>");
+    assert_eq!(authors[0]["synced"], 3);
+    assert_eq!(authors[0]["uprooted"], 1);
+    assert_eq!(authors[1]["key"], "Resolver <Bar>");
+    assert_eq!(authors[1]["conflicts"], 1);
+
+    // no commits in the future
+    let output = env.run_ripit_subcommand(&["stats", "--json", "--since", "2999-01-01"]);
+    let stats: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(stats["total"]["synced"], 0);
+}
+
+/// Test exporting local commits to the remote, and synchronizing them back
+#[test]
+fn test_export() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // fix something locally
+    let fix = env.local_repo.commit_file("fix.txt", "local fix");
+
+    // the local commit would be lost by the synchronization
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_failure(&["-y"], Some("do not come from the remote"));
+
+    // export as patches
+    let dir = tempfile::tempdir().unwrap();
+    env.run_ripit_subcommand(&["export", "--patches", dir.path().to_str().unwrap()]);
+    let patch = fs::read_to_string(dir.path().join("0001-local-fix.patch")).unwrap();
+    assert!(patch.contains(&format!("rip-it-export: {}", fix.id())));
+    assert!(patch.contains("+local fix"));
+
+    // export in the remote, and merge the export branch. As pushing to a non-bare
+    // repository is not supported, the push is done in a bare repository, from which
+    // the remote then fetches.
+    let bare_dir = tempfile::tempdir().unwrap();
+    let bare_path = bare_dir.path().to_str().unwrap();
+    git2::Repository::init_bare(bare_path).unwrap();
+    let remote_url = env.local_repo.find_remote("private").unwrap();
+    let remote_url = remote_url.url().unwrap().to_owned();
+    env.local_repo.remote_set_url("private", bare_path).unwrap();
+    env.run_ripit_subcommand(&["export"]);
+    env.local_repo
+        .remote_set_url("private", &remote_url)
+        .unwrap();
+    env.remote_repo
+        .remote_anonymous(bare_path)
+        .unwrap()
+        .fetch(&["+refs/heads/*:refs/heads/*"], None, None)
+        .unwrap();
+    let branch = env
+        .remote_repo
+        .find_branch("ripit-export/master", git2::BranchType::Local)
+        .unwrap();
+    let exported = branch.get().peel_to_commit().unwrap();
+    assert!(exported
+        .message()
+        .unwrap()
+        .contains(&format!("rip-it-export: {}", fix.id())));
+    env.remote_repo.do_merge(&exported, "merge-export");
+
+    // the exported commit is not copied again
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 4); // bootstrap + fix + b + merge
+    env.local_repo.check_file("fix.txt", true, true);
+    env.local_repo.check_file("b.txt", true, true);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.summary().unwrap().contains("merge-export"));
+    assert_eq!(head.parent_id(1).unwrap(), fix.id());
+
+    // nothing left to export
+    let output = env.run_ripit_subcommand(&["export"]);
+    assert!(output.contains("Nothing to export"));
+}
+
+/// Test solving the conflicts of an export, or aborting it
+#[test]
+fn test_export_conflicts() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let b = env
+        .local_repo
+        .commit_file_with_content("a.txt", "b", "change a to b");
+    let c = env
+        .local_repo
+        .commit_file_with_content("a.txt", "c", "change a to c");
+    let d = env.local_repo.commit_file("d.txt", "add d");
+    let bare_dir = tempfile::tempdir().unwrap();
+    let bare_path = bare_dir.path().to_str().unwrap();
+    let bare_repo = git2::Repository::init_bare(bare_path).unwrap();
+    env.local_repo.remote_set_url("private", bare_path).unwrap();
+
+    // without b, c does not apply on the remote
+    let since = b.id().to_string();
+    let stopped = "The export of refs/heads/master is stopped on the conflicts of commit";
+    env.run_ripit_subcommand_failure(&["export", "--since", &since], Some(stopped));
+    assert!(env.local_repo.index().unwrap().has_conflicts());
+    env.run_ripit_failure(
+        &["-y"],
+        Some("run the `export` subcommand with `--continue`"),
+    );
+
+    env.run_ripit_success(&["--abort"]);
+    let head = env.local_repo.head().unwrap();
+    assert_eq!(head.shorthand(), Some("master"));
+    assert_eq!(head.target(), Some(d.id()));
+    assert!(!env.local_repo.index().unwrap().has_conflicts());
+
+    env.run_ripit_subcommand_failure(&["export", "--since", &since], Some(stopped));
+    env.run_ripit_subcommand_failure(
+        &["--continue", "export", "--since", &since],
+        Some("Conflicts remain in the following files:\n  a.txt"),
+    );
+    env.local_repo.write_and_add_file("a.txt", "resolved");
+    env.local_repo.index().unwrap().write().unwrap();
+    env.run_ripit_subcommand(&["--continue", "export", "--since", &since]);
+
+    // the branch is checked out again, and the resolution is exported before d
+    let head = env.local_repo.head().unwrap();
+    assert_eq!(head.shorthand(), Some("master"));
+    let workdir = Path::new(env.local_repo.workdir().unwrap());
+    assert_eq!(fs::read_to_string(workdir.join("a.txt")).unwrap(), "c");
+    let exported_d = bare_repo
+        .find_reference("refs/heads/ripit-export/master")
+        .unwrap()
+        .peel_to_commit()
+        .unwrap();
+    assert!(exported_d
+        .message()
+        .unwrap()
+        .contains(&format!("rip-it-export: {}", d.id())));
+    let exported = exported_d.parent(0).unwrap();
+    assert!(exported
+        .message()
+        .unwrap()
+        .contains(&format!("rip-it-export: {}", c.id())));
+    let blob = exported.tree().unwrap().get_name("a.txt").unwrap().id();
+    assert_eq!(bare_repo.find_blob(blob).unwrap().content(), b"resolved");
+}
+
+/// Test recreation of a missing local branch
+#[test]
+fn test_create_missing_branch() {
+    let env = env::TestEnv::new(Some(&["master", "dev"]));
+
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    env.remote_repo.branch("dev", &a, true).unwrap();
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo.set_head("refs/heads/dev").unwrap();
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+
+    // delete the dev branch, with the local repo on master
+    env.local_repo.set_head("refs/heads/master").unwrap();
+    env.local_repo.force_checkout_head();
+    let mut branch = env
+        .local_repo
+        .find_branch("dev", git2::BranchType::Local)
+        .unwrap();
+    branch.delete().unwrap();
+
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_failure(&["-y"], Some("--create-missing-branch"));
+
+    // the branch is recreated on b, then c is synchronized
+    env.run_ripit_success(&["-y", "--create-missing-branch"]);
+    let branch = env
+        .local_repo
+        .find_branch("dev", git2::BranchType::Local)
+        .unwrap();
+    let ci = branch.get().peel_to_commit().unwrap();
+    assert_eq!(ci.summary().unwrap(), "c");
+    assert_eq!(ci.parent(0).unwrap().summary().unwrap(), "b");
+}
+
+/// Test resuming a synchronization interrupted between checkpoints
+#[test]
+// the abort hook only exists in debug builds
+#[cfg_attr(not(debug_assertions), ignore)]
+fn test_checkpoint() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+    env.add_cfg("checkpoint_every: 2");
+
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // kill the synchronization of c5, c6, c7 and c8 after the third copy
+    let c8 = env.remote_repo.revparse_single("c8").unwrap();
+    env.remote_repo.reset_hard(&c8);
+    let output = env.run_ripit_aborted(&["-y"], 3);
+
+    // the branch contains the last checkpoint, the second copied commit
+    let created: Vec<git2::Oid> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix("Created commit "))
+        .map(|line| git2::Oid::from_str(line.trim_end_matches('.')).unwrap())
+        .collect();
+    assert_eq!(created.len(), 3);
+    let branch = env
+        .local_repo
+        .find_branch("master", git2::BranchType::Local)
+        .unwrap();
+    let branch_id = branch.get().target().unwrap();
+    assert!(
+        branch_id == created[1]
+            || env
+                .local_repo
+                .graph_descendant_of(branch_id, created[1])
+                .unwrap()
+    );
+
+    // resume the synchronization, without copying commits again
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 5);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.summary().unwrap().contains("c8"));
+    let parents: Vec<git2::Commit> = head.parents().collect();
+    assert_eq!(parents.len(), 2);
+    assert!(parents[0].summary().unwrap().contains("c5"));
+    assert!(parents[1].summary().unwrap().contains("c7"));
+}
+
+/// Test settings read from the git configuration
+#[test]
+fn test_git_config() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg("branch: master\n");
+
+    // the remote is required
+    env.run_ripit_failure(&["--bootstrap"], Some("Missing remote option"));
+
+    let mut config = env.local_repo.config().unwrap();
+    config.set_str("ripit.remote", "private").unwrap();
+    config.set_multivar("ripit.filter", "^$", "^Refs").unwrap();
+    config.set_multivar("ripit.filter", "^$", "test").unwrap();
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo
+        .commit_file("b.txt", "b\n\nRefs: #42\nline test filtered\ndetails");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = head.message().unwrap();
+    assert!(!msg.contains("Refs"));
+    assert!(!msg.contains("test"));
+    assert!(msg.contains("details"));
+
+    // the yaml config takes precedence
+    config.set_str("ripit.remote", "unknown").unwrap();
+    env.set_cfg("remote: private\nfilters:\n  - ^Refs\n");
+    env.remote_repo
+        .commit_file("c.txt", "c\n\nRefs: #43\nline test kept");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = head.message().unwrap();
+    assert!(!msg.contains("Refs"));
+    assert!(msg.contains("line test kept"));
+
+    // the messages are appended to the log file, and the committer email is replaced
+    let log_path = env.local_repo.path().join("ripit.log");
+    config
+        .set_str("ripit.logFile", log_path.to_str().unwrap())
+        .unwrap();
+    config
+        .set_str("ripit.committerEmail", "sync@example.com")
+        .unwrap();
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_success(&["-y", "--color=always"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.committer().email(), Some("sync@example.com"));
+    assert_eq!(head.committer().name(), head.author().name());
+    let log = std::fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("Copying commit"));
+    assert!(!log.contains('\x1b'));
+
+    // the command line and the yaml config take precedence
+    let other_log_path = env.local_repo.path().join("other.log");
+    env.set_cfg("remote: private\ncommitter_email: bot@example.com\n");
+    env.remote_repo.commit_file("e.txt", "e");
+    env.run_ripit_success(&["-y", "--log-file", other_log_path.to_str().unwrap()]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.committer().email(), Some("bot@example.com"));
+    assert_eq!(std::fs::read_to_string(&log_path).unwrap(), log);
+    assert!(std::fs::read_to_string(&other_log_path)
+        .unwrap()
+        .contains("Copying commit"));
+
+    // git config values are validated
+    env.set_cfg("remote: private\n");
+    config.set_str("ripit.committerEmail", "<bot>").unwrap();
+    env.run_ripit_failure(&["-y"], Some("Invalid ripit.committerEmail option"));
+    config.remove("ripit.committerEmail").unwrap();
+    config.remove_multivar("ripit.filter", ".*").unwrap();
+    config.set_str("ripit.filter", "(").unwrap();
+    env.run_ripit_failure(&["-y"], Some("Invalid filter option"));
+}
+
+/// Test skipping and rejecting commits with a commit gate
+#[test]
+fn test_commit_gate() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg(
+        "remote: private\n\
+         commit_gate_cmd: '[ \"$RIPIT_SUMMARY\" != secret ] || exit 10; \
+         ! grep -q password || { echo password leaked >&2; exit 1; }'\n",
+    );
+
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the secret commit is skipped, its child is copied on top of its parent
+    env.remote_repo.commit_file("secret.txt", "secret");
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+    env.local_repo.check_file("secret.txt", false, false);
+    env.local_repo.check_file("b.txt", true, true);
+
+    // a rejected commit aborts the synchronization
+    env.remote_repo.commit_file("password", "c");
+    env.remote_repo.commit_file("d.txt", "d");
+    env.run_ripit_failure(&["-y"], Some("password leaked"));
+    assert_eq!(env.local_repo.count_commits(), 2);
+    env.local_repo.check_file("d.txt", false, false);
+}
+
+/// Copy the master branch of a mirror in the remote repository of another environment
+fn update_from_mirror(env: &env::TestEnv, mirror: &git2::Repository) {
+    let url = mirror.path().to_str().unwrap();
+    let mut remote = env.remote_repo.remote_anonymous(url).unwrap();
+    remote
+        .fetch(
+            &["+refs/heads/master:refs/remotes/mirror/master"],
+            None,
+            None,
+        )
+        .unwrap();
+
+    let head = env.remote_repo.revparse_single("mirror/master").unwrap();
+    env.remote_repo.reset_hard(&head);
+}
+
+/// Test synchronization from a remote which is itself a mirror
+#[test]
+fn test_foreign_tags() {
+    // first level: private -> mirror
+    let env1 = env::TestEnv::new(None);
+    env1.remote_repo.commit_file("a.txt", "a");
+    env1.run_ripit_success(&["--bootstrap"]);
+    env1.remote_repo.commit_file("b.txt", "b");
+    env1.run_ripit_success(&["-y"]);
+
+    // second level: mirror -> public
+    let env2 = env::TestEnv::new(None);
+    update_from_mirror(&env2, &env1.local_repo);
+    env2.run_ripit_success(&["--bootstrap"]);
+
+    let c = env1.remote_repo.commit_file("c.txt", "c");
+    env1.run_ripit_success(&["-y"]);
+    let c_mirror = env1.local_repo.head().unwrap().peel_to_commit().unwrap();
+    update_from_mirror(&env2, &env1.local_repo);
+    env2.run_ripit_success(&["-y"]);
+
+    // both tags are kept, the last one is used to find the last synced commit
+    let head = env2.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = head.message().unwrap();
+    assert!(msg.contains(&format!("rip-it: {}\n", c.id())));
+    assert!(msg.ends_with(&format!("rip-it: {}\n", c_mirror.id())));
+
+    let d = env1.remote_repo.commit_file("d.txt", "d");
+    env1.run_ripit_success(&["-y"]);
+    update_from_mirror(&env2, &env1.local_repo);
+    env2.add_cfg("foreign_tags: neutralize");
+    env2.run_ripit_success(&["-y"]);
+    assert_eq!(env2.local_repo.count_commits(), 3);
+    env2.local_repo.check_file("d.txt", true, true);
+
+    let head = env2.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = head.message().unwrap();
+    assert!(msg.contains(&format!("rip-it-upstream: {}\n", d.id())));
+    assert_eq!(msg.matches("rip-it: ").count(), 1);
+}
+
+/// Test resolution of conflicts with a merge tool
+#[test]
+fn test_mergetool() {
+    let env = env::TestEnv::new(None);
+    env.setup_merge_solving_conflicts();
+
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let c5 = env.remote_repo.revparse_single("c5").unwrap();
+    env.remote_repo.reset_hard(&c5);
+
+    // the merge tool is required
+    env.run_ripit_failure(&["-yu", "--mergetool"], Some("Missing mergetool_cmd"));
+
+    // a failing tool leaves the conflicts to the user
+    env.add_cfg("mergetool_cmd: 'test -s \"$LOCAL\" && exit 1'");
+    env.run_ripit_failure(&["-yu", "--mergetool"], Some("due to conflicts"));
+    assert!(env.local_repo.index().unwrap().has_conflicts());
+    env.local_repo.resolve_conflict_and_commit("c1");
+
+    // the tool resolves the conflicts on C1 and C3
+    env.set_cfg("remote: private\nmergetool_cmd: 'echo merged > \"$MERGED\"'\n");
+    env.run_ripit_success(&["-yu", "--mergetool"]);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.summary().unwrap().contains("c5"));
+    let parents: Vec<git2::Commit> = head.parents().collect();
+    assert_eq!(parents.len(), 2);
+    assert!(parents[1].summary().unwrap().contains("c3"));
+    assert_eq!(parents[1].parent_count(), 2);
+
+    // c3 was resolved by the tool
+    let tree = parents[1].tree().unwrap();
+    let entry = tree.get_name("c1").unwrap();
+    let blob = env.local_repo.find_blob(entry.id()).unwrap();
+    assert_eq!(blob.content(), b"merged\n");
+}
+
+/// Test the automatic resolution of conflicts with the conflict_strategy option
+#[test]
+fn test_conflict_strategy() {
+    let env = env::TestEnv::new(None);
+    env.setup_merge_solving_conflicts();
+
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let c5 = env.remote_repo.revparse_single("c5").unwrap();
+    env.remote_repo.reset_hard(&c5);
+
+    env.add_cfg("conflict_strategy:\n  c1: mine\n");
+    env.run_ripit_failure(&["-yu"], Some("must be 'ours' or 'theirs'"));
+
+    // the conflicts of C2 and C1 on c1 are resolved with the remote version
+    env.set_cfg("remote: private\nconflict_strategy:\n  '*.po': ours\n  'c?': theirs\n");
+    let (code, stdout) = env.run_ripit_status(&["-yu", "--json"]);
+    assert_eq!(code, Some(0));
+    assert!(!env.local_repo.index().unwrap().has_conflicts());
+
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let commits = json["branches"][0]["commits"].as_array().unwrap();
+    let resolved = serde_json::json!([{ "path": "c1", "side": "theirs" }]);
+    assert_eq!(commits[0]["summary"], "c2");
+    assert_eq!(commits[0]["auto_resolved"], resolved);
+    assert_eq!(commits[1]["summary"], "c1");
+    assert_eq!(commits[1]["auto_resolved"], resolved);
+    assert_eq!(commits[3]["auto_resolved"], serde_json::json!([]));
+
+    // the copy of C3 has the content of C3
+    let c3 = env.remote_repo.revparse_single("c3").unwrap();
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.summary().unwrap().contains("c5"));
+    let copy = head.parent(1).unwrap();
+    assert!(copy.summary().unwrap().contains("c3"));
+    let remote_c1 = c3.peel_to_tree().unwrap().get_name("c1").unwrap().id();
+    assert_eq!(copy.tree().unwrap().get_name("c1").unwrap().id(), remote_c1);
+}
+
+/// Test the reuse of the recorded resolutions of conflicts
+#[test]
+fn test_rerere() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+
+    let c9 = env.remote_repo.revparse_single("c9").unwrap();
+    env.remote_repo.reset_hard(&c9);
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+
+    let c10 = env.remote_repo.revparse_single("c10").unwrap();
+    env.remote_repo.reset_hard(&c10);
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c12"));
+    env.local_repo
+        .write_and_add_file("c12", "resolved conflict");
+    env.local_repo.index().unwrap().write().unwrap();
+    let (code, stdout) = env.run_ripit_status(&["-yu", "--continue"]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("Recorded the resolution of the conflicts of c12."));
+
+    // the same conflicts are resolved by the recorded resolution
+    env.local_repo.reset_hard(bootstrap.as_object());
+    fs::remove_file(env.cache_path()).unwrap();
+    let (code, stdout) = env.run_ripit_status(&["-yu"]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("Resolved the conflicts of c12 with the recorded resolution."));
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.summary().unwrap().contains("c10"));
+    let copy = head
+        .parents()
+        .find(|ci| ci.summary() == Some("c12"))
+        .unwrap();
+    let entry = copy.tree().unwrap().get_name("c12").unwrap().id();
+    let blob = env.local_repo.find_blob(entry).unwrap();
+    assert_eq!(blob.content(), b"resolved conflict");
+
+    // unless disabled
+    env.local_repo.reset_hard(bootstrap.as_object());
+    fs::remove_file(env.cache_path()).unwrap();
+    env.add_cfg("rerere: false");
+    env.run_ripit_failure(&["-yu"], Some("due to conflicts:\n  c12"));
+}
+
+/// Test the merge drivers set in the .gitattributes files of the local branch
+#[test]
+fn test_merge_attributes() {
+    let env = env::TestEnv::new(None);
+    // local commits can be made on the files copied in the prefix
+    env.add_cfg("prefix: vendor");
+    env.remote_repo
+        .commit_file_with_content("CHANGELOG.md", "v1\n", "changelog");
+    env.remote_repo
+        .commit_file_with_content("data.bin", "1\n", "data");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the attributes are only set locally
+    env.local_repo.commit_file_with_content(
+        ".gitattributes",
+        "CHANGELOG.md merge=union\n/vendor/*.bin merge=custom\n",
+        "attributes",
+    );
+    env.local_repo.commit_file_with_content(
+        "vendor/CHANGELOG.md",
+        "v1\nlocal\n",
+        "local changelog",
+    );
+    env.local_repo
+        .commit_file_with_content("vendor/data.bin", "local\n", "local data");
+    env.local_repo
+        .config()
+        .unwrap()
+        .set_str("merge.custom.driver", "cat %B %A > %A.new && mv %A.new %A")
+        .unwrap();
+
+    env.remote_repo
+        .commit_file_with_content("CHANGELOG.md", "v1\nremote\n", "remote changelog");
+    env.remote_repo
+        .commit_file_with_content("data.bin", "remote\n", "remote data");
+    let (code, stdout) = env.run_ripit_status(&["-y"]);
+    assert_eq!(code, Some(0));
+    assert!(
+        stdout.contains("Resolved the conflicts of vendor/data.bin with the custom merge driver.")
+    );
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let tree = head.tree().unwrap();
+    let content = |path: &str| {
+        let id = tree.get_path(Path::new(path)).unwrap().id();
+        env.local_repo.find_blob(id).unwrap().content().to_vec()
+    };
+    assert_eq!(content("vendor/CHANGELOG.md"), b"v1\nlocal\nremote\n");
+    assert_eq!(content("vendor/data.bin"), b"remote\nlocal\n");
+}
+
+/// Test recording the ripit tags in notes instead of the commit messages
+#[test]
+fn test_provenance_notes() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("provenance: notes");
+    env.setup_merge_solving_conflicts();
+
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the resolutions committed by the user get their tags in notes as well
+    let c5 = env.remote_repo.revparse_single("c5").unwrap();
+    env.remote_repo.reset_hard(&c5);
+    for _ in 0..3 {
+        env.run_ripit_failure(&["-yu"], Some("due to conflicts"));
+        env.local_repo.resolve_conflict_and_commit("c1");
+    }
+    env.run_ripit_success(&["-y"]);
+
+    let note_of = |id| {
+        env.local_repo
+            .find_note(Some("refs/notes/ripit"), id)
+            .unwrap()
+            .message()
+            .unwrap()
+            .to_owned()
+    };
+    let mut revwalk = env.local_repo.revwalk().unwrap();
+    revwalk.push_head().unwrap();
+    for id in revwalk {
+        let commit = env.local_repo.find_commit(id.unwrap()).unwrap();
+        assert!(!commit.message().unwrap().contains("rip-it"));
+        assert!(note_of(commit.id()).starts_with("rip-it: "));
+    }
+    let head = env.local_repo.head().unwrap().target().unwrap();
+    assert_eq!(note_of(head), format!("rip-it: {}", c5.id()));
+
+    // the cache is rebuilt from the notes
+    env.run_ripit_success(&["--rebuild-cache"]);
+    let (code, stdout) = env.run_ripit_status(&["-y"]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("Nothing to synchronize"));
+}
+
+/// Test keeping the ripit tags out of the repository with the cache-only provenance
+#[test]
+fn test_provenance_cache_only() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("provenance: cache-only");
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+
+    let mut revwalk = env.local_repo.revwalk().unwrap();
+    revwalk.push_head().unwrap();
+    for id in revwalk {
+        let message = env.local_repo.find_commit(id.unwrap()).unwrap();
+        let message = message.message().unwrap();
+        assert!(!message.contains("rip-it"));
+        assert!(!message.contains(&a.id().to_string()));
+        assert!(!message.contains(&b.id().to_string()));
+    }
+    assert!(env.local_repo.find_reference("refs/notes/ripit").is_err());
+
+    // the tags file is the only record of the synchronization
+    let ripit_dir = env.local_repo.path().join("ripit");
+    std::fs::remove_file(ripit_dir.join("cache")).unwrap();
+    env.run_ripit_success(&["--rebuild-cache"]);
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+
+    std::fs::remove_file(ripit_dir.join("tags")).unwrap();
+    env.run_ripit_failure(&["-y"], Some("not recorded"));
+    std::fs::remove_file(ripit_dir.join("cache")).unwrap();
+    env.run_ripit_failure(&["-y"], Some("--rebuild-cache cannot recreate"));
+    env.run_ripit_failure(&["--rebuild-cache"], Some("not recorded"));
+
+    // the remote commit ids cannot be written in the messages
+    env.add_cfg("bootstrap_message: \"Bootstrap at {remote_oid}\"");
+    env.run_ripit_failure(&["-y"], Some("cache-only provenance"));
+}
+
+/// Test writing the MACs of the remote commit ids in the ripit tags
+#[test]
+fn test_provenance_hmac() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("provenance: hmac\nprovenance_key: RIPIT_TEST_KEY");
+    let old_key = [("RIPIT_TEST_KEY", "old")];
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_failure(&["--bootstrap"], Some("RIPIT_TEST_KEY"));
+    env.run_ripit_with_env(true, &["--bootstrap"], None, &old_key);
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_with_env(true, &["-y"], None, &old_key);
+
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let message = head.message().unwrap();
+    assert!(message.contains("\nrip-it: hmac-sha256:"));
+    assert!(!message.contains(&b.id().to_string()));
+    let bootstrap = head.parent(0).unwrap();
+    assert!(!bootstrap.message().unwrap().contains(&b.id().to_string()));
+
+    // the tags written with the previous key are still accepted while rotating the key
+    env.set_cfg(
+        "remote: private\nprovenance: hmac\nprovenance_key: RIPIT_TEST_KEY\n\
+         provenance_previous_key: RIPIT_TEST_OLD_KEY\n",
+    );
+    let keys = [("RIPIT_TEST_KEY", "new"), ("RIPIT_TEST_OLD_KEY", "old")];
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_with_env(true, &["-y"], None, &keys);
+    let new_head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(new_head.parent_id(0).unwrap(), head.id());
+
+    // the MACs of the remote commits are computed again to rebuild the cache
+    fs::remove_file(env.cache_path()).unwrap();
+    env.run_ripit_with_env(true, &["--rebuild-cache"], None, &keys);
+    let stdout = env.run_ripit_with_env(true, &["-y"], None, &keys);
+    assert!(stdout.contains("Nothing to synchronize"));
+    let stdout = env.run_ripit_with_env(true, &["--verify"], None, &keys);
+    assert!(stdout.contains("No inconsistencies found."));
+
+    // without the previous key, the MACs written with it are reported
+    env.set_cfg("remote: private\nprovenance: hmac\nprovenance_key: RIPIT_TEST_KEY\n");
+    let stdout = env.run_ripit_with_env(false, &["--verify"], None, &keys[..1]);
+    assert_eq!(stdout.matches("matches no remote commit").count(), 2);
+}
+
+/// Test recording in notes the ripit tags of the commit messages
+#[test]
+fn test_migrate_notes() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a.txt", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+
+    env.run_ripit_subcommand(&["migrate-notes"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let note = env
+        .local_repo
+        .find_note(Some("refs/notes/ripit"), head.id())
+        .unwrap();
+    assert_eq!(note.message().unwrap(), format!("rip-it: {}", b.id()));
+    assert!(head.message().unwrap().contains("rip-it: "));
+    assert!(env
+        .local_repo
+        .find_note(Some("refs/notes/ripit"), head.parent_id(0).unwrap())
+        .is_ok());
+
+    // the synchronization goes on with the tags of the notes
+    env.set_cfg("remote: private\nprovenance: notes\n");
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(!head.message().unwrap().contains("rip-it"));
+    let note = env
+        .local_repo
+        .find_note(Some("refs/notes/ripit"), head.id())
+        .unwrap();
+    assert_eq!(note.message().unwrap(), format!("rip-it: {}", c.id()));
+}
+
+/// List the references protecting commits created by ripit
+fn list_keep_refs(repo: &git2::Repository) -> Vec<git2::Oid> {
+    repo.references_glob("refs/ripit/keep/*")
+        .unwrap()
+        .map(|reference| reference.unwrap().target().unwrap())
+        .collect()
+}
+
+/// Test the protection of created commits until the branch references them
+#[test]
+// the abort hook only exists in debug builds
+#[cfg_attr(not(debug_assertions), ignore)]
+fn test_keep_refs() {
+    let env = env::TestEnv::new(None);
+    env.setup_branches();
+
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c4);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // interrupt the synchronization once c5 is copied on the branch, and c6 on a
+    // detached HEAD
+    let c8 = env.remote_repo.revparse_single("c8").unwrap();
+    env.remote_repo.reset_hard(&c8);
+    env.run_ripit_aborted(&["-y"], 2);
+
+    let keep_refs = list_keep_refs(&env.local_repo);
+    let master = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+    let detached: Vec<_> = keep_refs
+        .iter()
+        .filter(|id| **id != master && !env.local_repo.graph_descendant_of(master, **id).unwrap())
+        .collect();
+    assert_eq!(detached.len(), 1);
+    let commit = env.local_repo.find_commit(*detached[0]).unwrap();
+    assert!(commit.summary().unwrap().contains("c6"));
+
+    // once the branch is set, the references are removed
+    env.run_ripit_success(&["-y"]);
+    assert!(list_keep_refs(&env.local_repo).is_empty());
+}
+
+/// Test the removal of the references left by the runs that are no longer in progress
+#[test]
+fn test_prune_stale_keep_refs() {
+    let env = env::TestEnv::new(None);
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // a commit copied on a detached HEAD, which the branch does not contain
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let sig = env.local_repo.signature().unwrap();
+    let detached = env
+        .local_repo
+        .commit(
+            None,
+            &sig,
+            &sig,
+            "detached",
+            &head.tree().unwrap(),
+            &[&head],
+        )
+        .unwrap();
+
+    // a run whose process is dead, and one whose process is still running
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    let dead_pid = child.id();
+    child.wait().unwrap();
+    let dead = format!("refs/ripit/keep/1-{}/1", dead_pid);
+    let alive = format!("refs/ripit/keep/2-{}/1", std::process::id());
+    for refname in [&dead, &alive] {
+        env.local_repo
+            .reference(refname, detached, false, "test")
+            .unwrap();
+    }
+
+    env.remote_repo.commit_file("a.txt", "a");
+    let output = env.run_ripit_subcommand(&["-y"]);
+    assert!(output.contains("Removed the references of 1 interrupted synchronizations."));
+    assert!(env.local_repo.find_reference(&dead).is_err());
+    assert!(env.local_repo.find_reference(&alive).is_ok());
+}
+
+/// Test the mapping of the lines of a file to the remote commits
+#[test]
+fn test_blame() {
+    let env = env::TestEnv::new(None);
+
+    let a = env
+        .remote_repo
+        .commit_file_with_content("file", "a\n", "add file");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let b = env
+        .remote_repo
+        .commit_file_with_content("file", "a\nb\n", "add b");
+    let c = env
+        .remote_repo
+        .commit_file_with_content("file", "a\nb\nc\n", "add c");
+    env.run_ripit_success(&["-y"]);
+    env.local_repo
+        .commit_file_with_content("file", "a\nb\nc\nd\n", "add d");
+
+    let output = env.run_ripit_subcommand(&["blame", "file"]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 4);
+    let short = |id: git2::Oid| id.to_string()[..10].to_owned();
+
+    assert!(lines[0]
+        .trim_start()
+        .starts_with(&format!("1  {}", short(a.id()))));
+    assert!(lines[0].ends_with("add file (bootstrap)"));
+    assert!(lines[1]
+        .trim_start()
+        .starts_with(&format!("2  {}", short(b.id()))));
+    assert!(lines[1].ends_with("add b"));
+    assert!(lines[2]
+        .trim_start()
+        .starts_with(&format!("3  {}", short(c.id()))));
+    assert!(lines[3].trim_start().starts_with("4  local"));
+    assert!(lines[3].ends_with("add d (local commit)"));
+
+    // blame of a previous revision
+    let output = env.run_ripit_subcommand(&["blame", "file", "HEAD~2"]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1]
+        .trim_start()
+        .starts_with(&format!("2  {}", short(b.id()))));
+}
+
+/// Test the classification of remote commits by the audit
+#[test]
+fn test_audit() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("commit_gate_cmd: '[ \"$RIPIT_SUMMARY\" != s ] || exit 10'");
+
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // b is synced, s is skipped
+    let b = env.remote_repo.commit_file("b", "b");
+    let s = env.remote_repo.commit_file("s", "s");
+    env.run_ripit_success(&["-y"]);
+
+    // g1 is hidden by a commit copying g2 directly
+    let g1 = env.remote_repo.commit_file("g1", "g1");
+    let g2 = env.remote_repo.commit_file("g2", "g2");
+    env.run_ripit_success(&["-y", "--stop-before", "g1"]);
+    env.local_repo
+        .commit_file("g2", &format!("g2\n\nrip-it: {}\n", g2.id()));
+
+    // p is fetched but not synchronized
+    let p = env.remote_repo.commit_file("p", "p");
+    env.run_ripit_success(&["-y", "--stop-before", "^p"]);
+
+    let output = env.run_ripit_subcommand_failure(&["audit"], Some("1 remote commits"));
+    assert!(output.contains("2 synced, 0 exported, 1 skipped, 1 pending, 1 unexplained"));
+
+    let output = env.run_ripit_subcommand_failure(&["audit", "--json"], None);
+    let entries: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let entries = entries.as_array().unwrap();
+    let status = |id: git2::Oid| {
+        let entry = entries
+            .iter()
+            .find(|e| e["commit"] == id.to_string())
+            .unwrap();
+        entry["status"].as_str().unwrap().to_owned()
+    };
+    assert_eq!(entries.len(), 5);
+    assert_eq!(status(b.id()), "synced");
+    assert_eq!(status(s.id()), "skipped");
+    assert_eq!(status(g1.id()), "gap");
+    assert_eq!(status(g2.id()), "synced");
+    assert_eq!(status(p.id()), "pending");
+
+    // the gap is not listed when auditing after it
+    let since = g1.id().to_string();
+    let output = env.run_ripit_subcommand(&["audit", "--since", &since]);
+    assert!(output.contains("1 synced, 0 exported, 0 skipped, 1 pending, 0 unexplained"));
+}
+
+/// Test the split of a commit in parts by groups of paths
+#[test]
+fn test_split_rules() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg(
+        "split_rules:\n\
+         \x20 - name: core\n\
+         \x20   paths: [core]\n\
+         \x20 - name: docs\n\
+         \x20   paths: [docs, README]\n",
+    );
+
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo.write_and_add_file("docs/guide", "guide");
+    env.remote_repo.write_and_add_file("core/lib", "lib");
+    let c = env
+        .remote_repo
+        .commit_file("core/main", "update core and docs");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+
+    // the first part only contains the core changes
+    let last = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let first = last.parent(0).unwrap();
+    let first_tree = first.tree().unwrap();
+    assert!(first_tree.get_path(Path::new("core/lib")).is_ok());
+    assert!(first_tree.get_path(Path::new("core/main")).is_ok());
+    assert!(first_tree.get_path(Path::new("docs/guide")).is_err());
+    assert!(first
+        .message()
+        .unwrap()
+        .ends_with(&format!("[part: core]\nrip-it: {} part 1/2\n", c.id())));
+
+    // the last part contains all the changes
+    assert_eq!(last.tree_id(), c.tree_id());
+    assert!(last
+        .message()
+        .unwrap()
+        .ends_with(&format!("[part: docs]\nrip-it: {} part 2/2\n", c.id())));
+
+    // the parts are mapped to a single remote commit, even without the cache
+    let cache_path = env.cache_path();
+    fs::remove_file(&cache_path).unwrap();
+    env.remote_repo.commit_file("core/next", "next");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 4);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap(), last.id());
+}
+
+#[test]
+fn test_autosquash() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("autosquash: true\n");
+
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let c1 = env.remote_repo.commit_file_with_content("b", "b1", "add b");
+    let c2 = env.remote_repo.commit_file_with_content("c", "c1", "add c");
+    let c3 = env
+        .remote_repo
+        .commit_file_with_content("b", "b2", "fixup! add b");
+    let c4 = env
+        .remote_repo
+        .commit_file_with_content("c", "c2", "squash! add c\n\nmore details");
+    env.run_ripit_success(&["-y"]);
+
+    // the fixups are folded in the copies of their targets
+    assert_eq!(env.local_repo.count_commits(), 3);
+    let c2_local = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(c2_local.tree_id(), c4.tree_id());
+    let c1_local = c2_local.parent(0).unwrap();
+    let b = c1_local.tree().unwrap().get_path(Path::new("b")).unwrap();
+    assert_eq!(
+        b.id(),
+        c3.tree().unwrap().get_path(Path::new("b")).unwrap().id()
+    );
+    assert!(c1_local
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c1.id())));
+
+    // the squash message is appended, and the folded commits are listed in the tag
+    assert_eq!(
+        c2_local.message().unwrap(),
+        format!(
+            "add c\n\nmore details\n\nrip-it-squashed: {}\nrip-it-squashed: {}\nrip-it: {}\n",
+            c3.id(),
+            c4.id(),
+            c2.id()
+        )
+    );
+
+    // the folded commits are known as synchronized, even without the cache
+    let cache_path = env.cache_path();
+    fs::remove_file(&cache_path).unwrap();
+    env.run_ripit_subcommand(&["audit"]);
+
+    // a fixup of an already synchronized commit is copied as is
+    let c5 = env
+        .remote_repo
+        .commit_file_with_content("b", "b3", "fixup! add b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 4);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap(), c2_local.id());
+    assert_eq!(head.tree_id(), c5.tree_id());
+    assert_eq!(head.summary(), Some("fixup! add b"));
+}
+
+#[test]
+fn test_skip_empty() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("skip_empty: true\n");
+
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+
+    // the second parent chain of the merge only contains empty commits
+    let e1 = env.remote_repo.do_commit("empty 1");
+    let e2 = env.remote_repo.do_commit("empty 2");
+    env.remote_repo.reset_hard(c1.as_object());
+    let c2 = env.remote_repo.commit_file("b", "b");
+    let e3 = env.remote_repo.do_commit("empty 3");
+    let m = env.remote_repo.do_merge(&e2, "merge");
+    let (code, output) = env.run_ripit_status(&["-y"]);
+    assert_eq!(code, Some(0));
+    for ci in &[&e1, &e2, &e3] {
+        assert!(output.contains(&format!(
+            "Skipped commit {}: it is empty once copied.",
+            ci.id()
+        )));
+    }
+
+    // the merge is kept, its second parent being the commit on which the empty ones
+    // would have been copied
+    let merge = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(merge.parent_count(), 2);
+    assert!(merge
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", m.id())));
+    assert_eq!(merge.parent_id(1).unwrap(), bootstrap.id());
+    let c2_local = merge.parent(0).unwrap();
+    assert!(c2_local
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c2.id())));
+    assert_eq!(c2_local.parent_id(0).unwrap(), bootstrap.id());
+
+    // the empty commits are cached as synchronized
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    for ci in &[&e1, &e2, &e3] {
+        assert!(cache.contains(&ci.id().to_string()));
+    }
+    env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap(), merge.id());
+}
+
+#[test]
+fn test_linearize() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("linearize: true\n");
+
+    let c1 = env.remote_repo.commit_file("a", "a");
+    let c2 = env.remote_repo.commit_file("b", "b");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+
+    // the merged branch starts before the bootstrap, its commits are unknown
+    env.remote_repo.reset_hard(c1.as_object());
+    let f1 = env.remote_repo.commit_file("f1", "f1");
+    let f2 = env.remote_repo.commit_file("f2", "f2");
+    env.remote_repo.reset_hard(c2.as_object());
+    let c3 = env.remote_repo.commit_file("c", "c");
+    let m = env.remote_repo.do_merge(&f2, "merge");
+    env.run_ripit_success(&["-y"]);
+
+    // the merge is copied as a simple commit, with the merged commits in its tag
+    let merge = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(merge.parent_count(), 1);
+    assert_eq!(merge.tree_id(), m.tree_id());
+    assert!(merge.message().unwrap().ends_with(&format!(
+        "rip-it-squashed: {}\nrip-it-squashed: {}\nrip-it: {}\n",
+        f1.id(),
+        f2.id(),
+        m.id()
+    )));
+    let c3_local = merge.parent(0).unwrap();
+    assert!(c3_local
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c3.id())));
+    assert_eq!(c3_local.parent_id(0).unwrap(), bootstrap.id());
+
+    // the merged commits are cached as synchronized
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    for ci in &[&f1, &f2] {
+        assert!(cache.contains(&format!("{} {}", ci.id(), merge.id())));
+    }
+    env.remote_repo.commit_file("d", "d");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap(), merge.id());
+}
+
+#[test]
+fn test_group_runs() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("group_runs: true\n");
+
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let c1 = env.remote_repo.commit_file("b", "b");
+    let c2 = env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["-y"]);
+
+    // the copied commits are the second parent chain of a merge without tag
+    let merge = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = merge.message().unwrap();
+    assert!(msg.starts_with("Sync private master "));
+    assert!(msg.contains("(2 commits)"));
+    assert!(msg.contains(&format!("{}..{}", c1.parent_id(0).unwrap(), c2.id())));
+    assert!(!msg.contains("rip-it"));
+    assert_eq!(merge.parent_id(0).unwrap(), bootstrap.id());
+    assert_eq!(merge.tree_id(), c2.tree_id());
+    let c2_local = merge.parent(1).unwrap();
+    assert!(c2_local
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c2.id())));
+    let c1_local = c2_local.parent(0).unwrap();
+    assert!(c1_local
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c1.id())));
+    assert_eq!(c1_local.parent_id(0).unwrap(), bootstrap.id());
+
+    // the next run starts from the last copied commit, and copies on top of the merge
+    env.remote_repo.commit_file("d", "d");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.message().unwrap().contains("(1 commit)"));
+    assert_eq!(head.parent_id(0).unwrap(), merge.id());
+    assert_eq!(head.parent(1).unwrap().parent_id(0).unwrap(), merge.id());
+
+    let (code, stdout) = env.run_ripit_status(&["-y"]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("Nothing to synchronize"));
+    let (code, stdout) = env.run_ripit_status(&["--verify"]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("No inconsistencies found."));
+}
+
+#[test]
+fn test_record_runs() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg("record_runs: true\nruns_retention_days: 30\n");
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().target().unwrap();
+
+    // an old record, pruned by the next synchronization
+    let old = env
+        .local_repo
+        .blob(b"{\"date\":1,\"remote\":\"private\",\"remote_url\":\"\",\"branches\":[]}")
+        .unwrap();
+    env.local_repo
+        .reference("refs/ripit/runs/1", old, false, "")
+        .unwrap();
+
+    let c1 = env.remote_repo.commit_file("b", "b");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().target().unwrap();
+    assert!(env.local_repo.find_reference("refs/ripit/runs/1").is_err());
+
+    let records = || -> Vec<git2::Reference> {
+        env.local_repo
+            .references_glob("refs/ripit/runs/*")
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    };
+    let refs = records();
+    assert_eq!(refs.len(), 1);
+    let blob = refs[0].peel_to_blob().unwrap();
+    let summary = std::str::from_utf8(blob.content()).unwrap();
+    assert!(summary.contains(&format!("\"local_tip_before\": \"{}\"", bootstrap)));
+    assert!(summary.contains(&format!("\"remote\": \"{}\"", c1.id())));
+    assert!(summary.contains(&format!("\"local\": \"{}\"", head)));
+
+    // with tag_runs, the record is an annotated tag of the new tip
+    env.add_cfg("tag_runs: true\n");
+    let c2 = env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["-y"]);
+    let new_head = env.local_repo.head().unwrap().target().unwrap();
+    let refs = records();
+    assert_eq!(refs.len(), 2);
+    let tag = refs.iter().find_map(|r| r.peel_to_tag().ok()).unwrap();
+    assert_eq!(tag.target_id(), new_head);
+    assert!(tag.message().unwrap().contains(&c2.id().to_string()));
+
+    // nothing is recorded without changes
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(records().len(), 2);
+
+    // the records are listed newest first
+    let (code, stdout) = env.run_ripit_status(&["--list-runs"]);
+    assert_eq!(code, Some(0));
+    let newest = stdout
+        .find(&format!("{} -> {}", c2.id(), new_head))
+        .unwrap();
+    let oldest = stdout.find(&format!("{} -> {}", c1.id(), head)).unwrap();
+    assert!(newest < oldest);
+    assert!(stdout.contains(&format!("  master: {}..{}", head, new_head)));
+}
+
+#[test]
+fn test_export_map() {
+    let env = env::TestEnv::new(None);
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().target().unwrap();
+    let c2 = env.remote_repo.commit_file("b", "b, \"quoted\"");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().target().unwrap();
+
+    let json_path = env.local_repo.path().join("map.json");
+    env.run_ripit_success(&["--export-map", json_path.to_str().unwrap()]);
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+    let entries = json.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["remote"], c2.id().to_string());
+    assert_eq!(entries[0]["local"], head.to_string());
+    assert_eq!(entries[0]["branch"], "master");
+    assert_eq!(entries[0]["summary"], "b, \"quoted\"");
+    assert!(entries[0]["mismatch"].is_null());
+    assert_eq!(entries[1]["remote"], c1.id().to_string());
+    assert_eq!(entries[1]["local"], bootstrap.to_string());
+
+    // the disagreements between the tags and the cache are flagged
+    let mut cache = fs::read_to_string(env.cache_path()).unwrap();
+    cache.push_str(&format!("{} {}\n", c2.id(), bootstrap));
+    fs::write(env.cache_path(), cache).unwrap();
+    let csv_path = env.local_repo.path().join("map.csv");
+    env.run_ripit_success(&["--export-map", csv_path.to_str().unwrap()]);
+    let csv = fs::read_to_string(&csv_path).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "remote,local,branch,date,summary,mismatch");
+    assert!(lines[1].starts_with(&format!("{},{},master,", c2.id(), head)));
+    assert!(lines[1].ends_with(&format!(
+        ",\"b, \"\"quoted\"\"\",the cache maps it to {}",
+        bootstrap
+    )));
+    assert!(lines[2].ends_with(","));
+
+    // without the cache, the mapping is read from the tags only
+    fs::remove_file(env.cache_path()).unwrap();
+    env.run_ripit_success(&["--export-map", csv_path.to_str().unwrap()]);
+    let csv = fs::read_to_string(&csv_path).unwrap();
+    assert_eq!(csv.lines().count(), 3);
+    assert!(csv.lines().all(|line| !line.contains("cache")));
+
+    env.run_ripit_failure(&["--export-map", "map.txt"], Some(".json or .csv"));
+}
+
+#[test]
+fn test_import_map() {
+    let env = env::TestEnv::new(None);
+    let r0 = env.remote_repo.head().unwrap().peel_to_commit().unwrap();
+    let r1 = env.remote_repo.commit_file("a", "a");
+
+    // mirror maintained without ripit
+    let l0 = env.local_repo.do_commit("mirror root");
+    let l1 = env.local_repo.commit_file("a", "a");
+    env.local_repo
+        .find_remote("private")
+        .unwrap()
+        .fetch(&["+refs/heads/*:refs/remotes/private/*"], None, None)
+        .unwrap();
+
+    let map_path = env.local_repo.path().join("map.csv");
+    let map_path = map_path.to_str().unwrap();
+    fs::write(
+        map_path,
+        format!(
+            "local,remote\n{},{}\n{},{}\n",
+            l0.id(),
+            l0.id(),
+            l1.id(),
+            r1.id()
+        ),
+    )
+    .unwrap();
+    env.run_ripit_failure(
+        &["--import-map", map_path],
+        Some("is not in the fetched remote"),
+    );
+    assert!(fs::read_to_string(env.cache_path())
+        .unwrap_or_default()
+        .is_empty());
+
+    fs::write(
+        map_path,
+        format!(
+            "local,remote,note\n{},{},\"initial, mirrored\"\n{},{},\n",
+            l0.id(),
+            r0.id(),
+            l1.id(),
+            r1.id()
+        ),
+    )
+    .unwrap();
+    let output = env.run_ripit_with_env(
+        true,
+        &["--import-map", map_path, "--write-trailers"],
+        None,
+        &[],
+    );
+    assert!(output.contains(&format!("  {} mirror root", l0.id())));
+    assert!(output.contains(&format!("  {} a", l1.id())));
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    assert_eq!(
+        cache,
+        format!("{} {}\n{} {}\n", r0.id(), l0.id(), r1.id(), l1.id())
+    );
+
+    // the imported commits are synchronized as if ripit had copied them
+    let r2 = env.remote_repo.commit_file("b", "b");
+    env.remote_repo.reset_hard(r1.as_object());
+    env.remote_repo.commit_file("c", "c");
+    let r4 = env.remote_repo.do_merge(&r2, "merge");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.tree_id(), r4.tree_id());
+    assert_eq!(head.parent_count(), 2);
+    for parent in head.parents() {
+        assert_eq!(parent.parent_id(0).unwrap(), l1.id());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_watch() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.run_ripit_failure(&["--watch", "1d"], Some("expected a number of seconds"));
+
+    let child = env.spawn_ripit_background(&["--watch", "1"]);
+    let c2 = env.remote_repo.commit_file("b", "b");
+    let synced = || {
+        let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+        head.tree_id() == c2.tree_id()
+    };
+    for _ in 0..100 {
+        if synced() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert!(synced());
+    // a few cycles without changes
+    std::thread::sleep(std::time::Duration::from_millis(2500));
+
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    println!("stdout: {}", stdout);
+    assert!(output.status.success());
+    assert_eq!(stdout.matches("Copying commit").count(), 1);
+    assert!(stdout.contains("Stopped watching remote private."));
+}
+
+#[test]
+fn test_quiet_if_unchanged() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let run = |args: &[&str]| {
+        let output = env.spawn_ripit_background(args).wait_with_output().unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+    let nothing = (String::new(), String::new());
+
+    // nothing is printed when nothing is synchronized, even by a dry run
+    assert_eq!(run(&["-y", "--quiet-if-unchanged"]), nothing);
+    assert_eq!(run(&["--dry-run", "--quiet-if-unchanged"]), nothing);
+    assert_eq!(run(&["-y", "--json", "--quiet-if-unchanged"]), nothing);
+    let (stdout, stderr) = run(&["-y", "--json", "--quiet-if-unchanged=text"]);
+    assert!(stderr.is_empty());
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["branches"][0]["commits"].as_array().unwrap().len(), 0);
+
+    // the messages are printed as usual once commits are found
+    let c2 = env.remote_repo.commit_file("b", "b");
+    let (stdout, _) = run(&["-y", "--quiet-if-unchanged"]);
+    assert!(stdout.starts_with("Fetch branch master in remote private...\n"));
+    assert!(stdout.contains(&format!("Copying commit {}...", c2.id())));
+}
+
+#[test]
+fn test_squash() {
+    let env = env::TestEnv::new(None);
+
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+
+    let c2 = env.remote_repo.commit_file("b", "add b");
+    env.remote_repo.reset_hard(c1.as_object());
+    let c3 = env.remote_repo.commit_file("a", "change a");
+    let c4 = env.remote_repo.do_merge(&c2, "merge");
+    env.run_ripit_success(&["-y", "--squash"]);
+
+    // a single commit is created, with the tree of the remote head
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_count(), 1);
+    assert_eq!(head.parent_id(0).unwrap(), bootstrap.id());
+    assert_eq!(head.tree_id(), c4.tree_id());
+    let msg = head.message().unwrap();
+    assert!(msg.starts_with("Squash 3 commits from remote private\n\n"));
+    assert!(msg.contains("* add b\n") && msg.contains("* change a\n"));
+    for ci in &[&c2, &c3] {
+        assert!(msg.contains(&format!("rip-it-squashed: {}\n", ci.id())));
+    }
+    assert!(msg.ends_with(&format!("rip-it: {}\n", c4.id())));
+
+    // the squashed commits are known as synchronized
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    for ci in &[&c2, &c3, &c4] {
+        assert!(cache.contains(&format!("{} {}", ci.id(), head.id())));
+    }
+    let c5 = env.remote_repo.commit_file("c", "add c");
+    env.run_ripit_success(&["-y"]);
+    let last = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(last.parent_id(0).unwrap(), head.id());
+    assert_eq!(last.tree_id(), c5.tree_id());
+}
+
+#[test]
+fn test_rollback() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.run_ripit_failure(&["--rollback"], Some("No synchronization"));
+
+    env.remote_repo.commit_file("b", "b");
+    env.run_ripit_success(&["-y"]);
+    let first_tip = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let c3 = env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["-y"]);
+    let copy = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+
+    // the synchronizations are recorded in the journal
+    let journal_path = env.local_repo.path().join("ripit").join("journal");
+    let journal = fs::read_to_string(&journal_path).unwrap();
+    assert_eq!(journal.lines().count(), 2);
+    assert!(journal.contains(&format!("{} {}", c3.id(), copy.id())));
+
+    // the branch and the cache are restored as before the last synchronization
+    env.run_ripit_success(&["--rollback", "--branch", "master"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.id(), first_tip.id());
+    env.local_repo.check_file("c", false, false);
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    assert!(!cache.contains(&c3.id().to_string()));
+    let journal = fs::read_to_string(&journal_path).unwrap();
+    assert_eq!(journal.lines().count(), 1);
+
+    // the commit is copied again by the next synchronization
+    env.run_ripit_success(&["-y"]);
+    let copy = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_ne!(copy.id(), first_tip.id());
+    assert_eq!(copy.parent_id(0).unwrap(), first_tip.id());
+
+    // the branch is not rolled back once local commits are made on top of it
+    env.local_repo.commit_file("d", "d");
+    env.run_ripit_failure(&["--rollback"], Some("Cannot roll back branch master"));
+    let cache = fs::read_to_string(env.cache_path()).unwrap();
+    assert!(cache.contains(&c3.id().to_string()));
+}
+
+/// Test rolling back the branches synchronized before a branch failed
+#[test]
+fn test_rollback_after_failure() {
+    let env = env::TestEnv::new(Some(&["a", "b"]));
+    let base = env.remote_repo.commit_file("base.txt", "base");
+    for name in &["a", "b"] {
+        env.remote_repo.branch(name, &base, false).unwrap();
+    }
+    env.run_ripit_success(&["--bootstrap"]);
+    let local_tip = |name| {
+        env.local_repo
+            .find_branch(name, git2::BranchType::Local)
+            .unwrap()
+            .get()
+            .peel_to_commit()
+            .unwrap()
+    };
+    let a_before = local_tip("a");
+
+    // b cannot be synchronized, as it contains a local commit
+    env.local_repo.set_head("refs/heads/b").unwrap();
+    env.local_repo.reset_hard(local_tip("b").as_object());
+    env.local_repo.commit_file("local.txt", "local");
+    env.local_repo.set_head("refs/heads/master").unwrap();
+    env.local_repo.reset_hard(local_tip("master").as_object());
+    env.remote_repo.set_head("refs/heads/a").unwrap();
+    env.remote_repo.commit_file("a.txt", "a1");
+    env.remote_repo.set_head("refs/heads/b").unwrap();
+    env.remote_repo.commit_file("b.txt", "b1");
+    env.run_ripit_failure(
+        &["-y"],
+        Some("Branch b contains 1 commits that do not come from the remote"),
+    );
+    assert_eq!(local_tip("a").summary(), Some("a1"));
+
+    // the synchronization of a is journaled, and can be rolled back
+    env.run_ripit_success(&["--rollback", "--branch", "a"]);
+    assert_eq!(local_tip("a").id(), a_before.id());
+}
+
+#[test]
+fn test_verify() {
+    let env = env::TestEnv::new(None);
+
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let bootstrap = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let c2 = env.remote_repo.commit_file("b", "b");
+    env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["-y"]);
+
+    let (code, stdout) = env.run_ripit_status(&["--verify-trees"]);
+    assert_eq!(code, Some(0));
+    assert!(stdout.contains("No inconsistencies found."));
+
+    // a remote commit copied twice, a cache entry disagreeing with the tags, and a
+    // remote branch not synchronized are reported
+    env.local_repo
+        .do_commit(&format!("duplicate\n\nrip-it: {}\n", c2.id()));
+    let mut cache = fs::read_to_string(env.cache_path()).unwrap();
+    let head = env.local_repo.head().unwrap().target().unwrap();
+    cache.push_str(&format!("{} {}\n", c1.id(), head));
+    fs::write(env.cache_path(), cache).unwrap();
+    // fetch a remote commit without synchronizing it
+    env.remote_repo.commit_file("d", "d");
+    env.run_ripit_success(&["--dry-run"]);
+    let (code, stdout) = env.run_ripit_status(&["--verify-trees"]);
+    assert_eq!(code, Some(1));
+    assert!(stdout.contains(&format!(
+        "remote commit {} is copied by several local commits",
+        c2.id()
+    )));
+    assert!(stdout.contains(&format!("cache entry maps remote commit {} to", c1.id())));
+    assert!(stdout.contains(&format!("its copy is {}", bootstrap.id())));
+    assert!(stdout.contains("the tree of branch master differs"));
+    assert_eq!(stdout.matches("inconsistency:").count(), 3);
+}
+
+#[test]
+// forcing the interactive mode only exists in debug builds
+#[cfg_attr(not(debug_assertions), ignore)]
+fn test_confirm() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.commit_file("b", "b");
+
+    // the confirmation cannot be asked outside of a terminal
+    env.run_ripit_failure(&[], Some("Use -y to proceed without confirmation"));
+    assert_eq!(env.local_repo.count_commits(), 1);
+
+    // refusing leaves the branch untouched, and a closed input aborts
+    env.run_ripit_interactive(true, &[], "No\n", &[]);
+    env.run_ripit_interactive(true, &[], "\n", &[]);
+    env.run_ripit_interactive(false, &[], "", &[]);
+    assert_eq!(env.local_repo.count_commits(), 1);
+
+    // an empty answer gives the configured default answer
+    env.add_cfg("default_answer: yes");
+    env.run_ripit_interactive(true, &[], "\n", &[]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+}
+
+#[test]
+fn test_log_levels() {
+    let env = env::TestEnv::new(None);
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the quiet mode only prints warnings and errors
+    env.remote_repo.commit_file("b", "b");
+    let (status, stdout) = env.run_ripit_status(&["-y", "-q"]);
+    assert_eq!(status, Some(0));
+    assert_eq!(stdout, "");
+
+    // the verbose mode prints the details of the execution
+    let c3 = env.remote_repo.commit_file("c", "c");
+    let (_, stdout) = env.run_ripit_status(&["-y", "-v", "--color", "never"]);
+    assert!(stdout.contains("Found ripit tag"));
+    assert!(stdout.contains(&format!("Copying commit {}...", c3.id())));
+    assert!(!stdout.contains('\x1b'));
+    let (_, stdout) = env.run_ripit_status(&["-y"]);
+    assert!(!stdout.contains("Found ripit tag"));
+
+    // the remote commits are highlighted in colors
+    let c4 = env.remote_repo.commit_file("d", "d");
+    let (_, stdout) = env.run_ripit_status(&["-y", "--color", "always"]);
+    assert!(stdout.contains(&format!("Copying commit \x1b[33m{}\x1b[0m...", c4.id())));
+    assert!(!stdout.contains(&c1.id().to_string()));
+}
+
+#[cfg(feature = "notify")]
+/// Accept a single connection on the listener, answering 200 and returning the request body
+fn serve_once(listener: std::net::TcpListener) -> std::thread::JoinHandle<String> {
+    use std::io::{Read, Write};
+
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        let body_start = loop {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+            if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let headers = String::from_utf8_lossy(&request[..body_start]).into_owned();
+        let length: usize = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        while request.len() < body_start + length {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        String::from_utf8(request[body_start..].to_vec()).unwrap()
+    })
+}
+
+#[cfg(feature = "notify")]
+#[test]
+fn test_notify() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    env.add_cfg(&format!("notify_url: {}", url));
+
+    // the outcome of the run is posted
+    let server = serve_once(listener.try_clone().unwrap());
+    env.remote_repo.commit_file("b", "b");
+    env.run_ripit_success(&["-y"]);
+    let payload: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+    assert_eq!(payload["outcome"], "success");
+    assert_eq!(payload["remote"], "private");
+    assert_eq!(payload["branches"][0]["branch"], "master");
+    assert_eq!(payload["branches"][0]["synced_commits"], 1);
+    assert_eq!(payload["error"], serde_json::Value::Null);
+
+    // the successful runs can be left out
+    env.add_cfg("notify_on: [conflict, error]");
+    listener.set_nonblocking(true).unwrap();
+    env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["-y"]);
+    assert!(listener.accept().is_err());
+    listener.set_nonblocking(false).unwrap();
+
+    let server = serve_once(listener);
+    let local_file = env.local_repo.workdir().unwrap().join("a");
+    fs::write(&local_file, "changed").unwrap();
+    env.run_ripit_failure(&["-y"], None);
+    let payload: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+    assert_eq!(payload["outcome"], "error");
+    assert!(payload["error"].as_str().unwrap().contains("changes"));
+    assert_eq!(payload["conflict"], serde_json::Value::Null);
+
+    // a failed notification is only a warning
+    env.run_ripit_failure(&["-y"], Some("warning: cannot notify"));
+    fs::write(&local_file, "a").unwrap();
+    env.remote_repo.commit_file("d", "d");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 4);
+}
+
+#[test]
+fn test_dates() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a", "a");
+
+    // remote commit with dates in the past, and non-UTC offsets
+    let commit_with_dates = |msg: &str| {
+        let author =
+            git2::Signature::new("Foo", "Bar", &git2::Time::new(1_500_000_000, 330)).unwrap();
+        let committer =
+            git2::Signature::new("Baz", "Qux", &git2::Time::new(1_500_086_400, -240)).unwrap();
+        let repo = &env.remote_repo;
+        repo.write_and_add_file(msg, msg);
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let oid = repo
+            .commit(Some("HEAD"), &author, &committer, msg, &tree, &[&head])
+            .unwrap();
+        repo.force_checkout_head();
+        repo.find_commit(oid).unwrap()
+    };
+    let same_time = |a: git2::Time, b: git2::Time| {
+        a.seconds() == b.seconds() && a.offset_minutes() == b.offset_minutes()
+    };
+    let b = commit_with_dates("b");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // by default, only the author date is kept, also for the bootstrap commit
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(same_time(head.author().when(), b.author().when()));
+    assert!(!same_time(head.committer().when(), b.committer().when()));
+
+    let c = commit_with_dates("c");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(same_time(head.author().when(), c.author().when()));
+    assert!(head.committer().when().seconds() > c.committer().when().seconds());
+
+    env.add_cfg("dates: preserve");
+    let d = commit_with_dates("d");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(same_time(head.author().when(), d.author().when()));
+    assert!(same_time(head.committer().when(), d.committer().when()));
+    assert_eq!(head.committer().when().offset_minutes(), -240);
+    assert_eq!(head.committer().name(), Some("Baz"));
+
+    // both dates are the time of the synchronization
+    let cfg_path = env.local_repo.workdir().unwrap().join("cfg.yml");
+    let cfg = fs::read_to_string(&cfg_path).unwrap();
+    env.set_cfg(&cfg.replace("dates: preserve", "dates: now"));
+    let e = commit_with_dates("e");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head.author().when().seconds() > e.author().when().seconds());
+    assert!(head.committer().when().seconds() > e.committer().when().seconds());
+}
+
+#[test]
+fn test_bootstrap_message() {
+    // the default message describes the bootstrapped commit
+    let env = env::TestEnv::new(None);
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message().unwrap(),
+        format!(
+            "Bootstrap repository from private master at {}\n\nrip-it: {}\n",
+            c1.id(),
+            c1.id()
+        )
+    );
+
+    // the tag is added after the message built from the template
+    let env = env::TestEnv::new(None);
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.add_cfg(
+        "bootstrap_message: \"Import {branch} from {remote} ({remote_url})\\n\\n\
+         Copy of {remote_oid} of {date}.\\n\"",
+    );
+    env.run_ripit_success(&["--bootstrap"]);
+    let url = env.local_repo.find_remote("private").unwrap();
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    let msg = head.message().unwrap();
+    assert!(msg.starts_with(&format!(
+        "Import master from private ({})\n\nCopy of {} of 20",
+        url.url().unwrap(),
+        c1.id()
+    )));
+    assert!(msg.ends_with(&format!(".\n\nrip-it: {}\n", c1.id())));
+
+    // the synchronization finds the bootstrap commit through its tag
+    let c2 = env.remote_repo.commit_file("b", "b");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message().unwrap(),
+        format!("b\n\nrip-it: {}\n", c2.id())
+    );
+}
+
+/// Test bootstrapping the branches at given remote revisions
+#[test]
+fn test_bootstrap_at() {
+    let env = env::TestEnv::new(Some(&["dev", "master"]));
+
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.remote_repo
+        .tag_lightweight("v1", b.as_object(), false)
+        .unwrap();
+    let c = env.remote_repo.commit_file("c.txt", "c");
+    env.remote_repo.branch("dev", &a, false).unwrap();
+    env.remote_repo.set_head("refs/heads/dev").unwrap();
+    env.remote_repo.reset_hard(a.as_object());
+    env.remote_repo.commit_file("d.txt", "d");
+    env.remote_repo.set_head("refs/heads/master").unwrap();
+    env.remote_repo.reset_hard(c.as_object());
+    let (a_id, b_id) = (a.id().to_string(), b.id().to_string());
+
+    env.run_ripit_failure(&["--at", "v1"], None);
+    env.run_ripit_failure(
+        &["--bootstrap", "--at", "v1", "--at", "v2"],
+        Some("Invalid at option: a single revision can be given for all branches"),
+    );
+    env.run_ripit_failure(
+        &["--bootstrap", "--at", "unknown=v1"],
+        Some("Invalid at option: branch unknown is not synchronized"),
+    );
+    env.run_ripit_failure(
+        &["--bootstrap", "--at", "dev=v2"],
+        Some("Cannot find revision v2 in the remote private."),
+    );
+
+    // the revision given for dev overrides the one given for all branches
+    let dev_rev = format!("dev={}", a_id);
+    env.run_ripit_success(&["--bootstrap", "--at", "v1", "--at", &dev_rev]);
+    let tip = |name| {
+        env.local_repo
+            .find_branch(name, git2::BranchType::Local)
+            .unwrap()
+            .get()
+            .peel_to_commit()
+            .unwrap()
+    };
+    assert!(tip("master").message().unwrap().contains(&b_id));
+    assert!(tip("dev").message().unwrap().contains(&a_id));
+
+    // the next synchronization starts from these revisions
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(tip("master").summary(), Some("c"));
+    assert_eq!(tip("dev").summary(), Some("d"));
+}
+
+/// Test bootstrapping with the history of the last remote commits
+#[test]
+fn test_keep_history() {
+    let env = env::TestEnv::new(None);
+
+    env.remote_repo.commit_file("a.txt", "a");
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    let s = env.remote_repo.commit_file("s.txt", "s");
+    env.remote_repo.reset_hard(b.as_object());
+    env.remote_repo.commit_file("c.txt", "c");
+    env.remote_repo.do_merge(&s, "m");
+    env.remote_repo.commit_file("d.txt", "d");
+
+    env.run_ripit_failure(&["--keep-history", "2"], None);
+    env.add_cfg("keep_history: 0");
+    env.run_ripit_failure(&["--bootstrap"], Some("Invalid keep_history option"));
+    env.set_cfg("remote: private\nkeep_history: 3\n");
+
+    // bootstrapped on b, the merge being copied with its side branch
+    env.run_ripit_success(&["--bootstrap"]);
+    let head_ci = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head_ci.summary(), Some("d"));
+    let merge = head_ci.parent(0).unwrap();
+    assert_eq!(merge.summary(), Some("m"));
+    let parents: Vec<git2::Commit> = merge.parents().collect();
+    assert_eq!(parents[0].summary(), Some("c"));
+    assert_eq!(parents[1].summary(), Some("s"));
+    let bootstrap = parents[0].parent(0).unwrap();
+    assert_eq!(bootstrap.parent_count(), 0);
+    assert!(bootstrap.message().unwrap().contains(&b.id().to_string()));
+    assert_eq!(parents[1].parent_id(0).unwrap(), bootstrap.id());
+    env.local_repo.check_file("a.txt", true, true);
+    env.local_repo.check_file("d.txt", true, true);
+
+    // the copied commits are known as synchronized
+    let (_, stdout) = env.run_ripit_status(&["-y"]);
+    assert!(stdout.contains("Nothing to synchronize on branch master, already up to date"));
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.id(), head_ci.id());
+}
+
+/// Test adopting a local branch maintained without ripit
+#[test]
+fn test_adopt() {
+    let env = env::TestEnv::new(None);
+
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    let b = env.remote_repo.commit_file("b.txt", "b");
+    env.remote_repo
+        .tag_lightweight("v1", b.as_object(), false)
+        .unwrap();
+
+    // the mirror has the state of b, with a history of its own
+    env.local_repo.write_and_add_file("a.txt", "a");
+    env.local_repo.write_and_add_file("b.txt", "b");
+    let mirror = env.local_repo.do_commit("mirror of v1");
+
+    env.run_ripit_failure(&["--adopt", "v1", "--bootstrap"], None);
+    env.run_ripit_failure(
+        &["--adopt", &a.id().to_string()],
+        Some(&format!(
+            "Cannot adopt branch master: its tree differs from the one of {} in the remote \
+             private.\n b.txt | 1 -",
+            a.id()
+        )),
+    );
+    assert_eq!(env.local_repo.count_commits(), 1);
+
+    env.run_ripit_success(&["--adopt", "v1"]);
+    let head_ci = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head_ci.parent_id(0).unwrap(), mirror.id());
+    assert_eq!(head_ci.tree_id(), mirror.tree_id());
+    assert!(head_ci.message().unwrap().contains(&b.id().to_string()));
+
+    // the synchronizations start from the adopted revision
+    env.remote_repo.commit_file("c.txt", "c");
+    env.run_ripit_success(&["-y"]);
+    let head_ci = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head_ci.summary(), Some("c"));
+    assert_eq!(env.local_repo.count_commits(), 3);
+}
+
+/// Test creating the remote from the url of the configuration
+#[test]
+// forcing the interactive mode only exists in debug builds
+#[cfg_attr(not(debug_assertions), ignore)]
+fn test_remote_url() {
+    let env = env::TestEnv::new(None);
+    let url = env
+        .remote_repo
+        .workdir()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+    env.local_repo.remote_delete("private").unwrap();
+
+    env.run_ripit_failure(
+        &["--bootstrap"],
+        Some("Remote private does not exist in the repository. Add it with `git remote add`, or configure its url"),
+    );
+
+    env.set_cfg(&format!("remote:\n  name: upstream\n  url: {}\n", url));
+    let output = env.run_ripit_subcommand(&["--bootstrap"]);
+    assert!(output.contains(&format!("Created remote upstream with url {}.", url)));
+    let remote = env.local_repo.find_remote("upstream").unwrap();
+    assert_eq!(remote.url(), Some(url.as_str()));
+
+    // a different url is only set once confirmed
+    env.set_cfg("remote:\n  name: upstream\n  url: /nonexistent/repo\n");
+    let output = env.run_ripit_interactive(true, &[], "n\n", &[]);
+    assert!(output.contains(&format!(
+        "Update the url of remote upstream from {} to /nonexistent/repo?",
+        url
+    )));
+    let remote = env.local_repo.find_remote("upstream").unwrap();
+    assert_eq!(remote.url(), Some(url.as_str()));
+
+    env.run_ripit_failure(&["-y"], Some("Cannot fetch remote upstream:"));
+    let remote = env.local_repo.find_remote("upstream").unwrap();
+    assert_eq!(remote.url(), Some("/nonexistent/repo"));
+}
+
+/// Test synchronizing the remote branches matching glob patterns
+#[test]
+fn test_branch_patterns() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg(
+        "remote: private\n\
+         branches:\n  - master\n  - release/*\n\
+         exclude_branches:\n  - release/*-rc\n",
+    );
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    env.remote_repo.branch("release/1.0", &a, false).unwrap();
+    env.remote_repo.branch("release/1.1-rc", &a, false).unwrap();
+    env.remote_repo
+        .branch("release/1.1/fix", &a, false)
+        .unwrap();
+
+    let output = env.run_ripit_subcommand(&["--bootstrap"]);
+    assert!(output.contains("Branches matching release/*: release/1.0.\n"));
+    let local_branch = |name| env.local_repo.find_branch(name, git2::BranchType::Local);
+    assert!(local_branch("release/1.0").is_ok());
+    assert!(local_branch("release/1.1-rc").is_err());
+    assert!(local_branch("release/1.1/fix").is_err());
+
+    // a new matching branch must be bootstrapped
+    env.remote_repo.branch("release/1.2", &a, false).unwrap();
+    env.run_ripit_failure(&["-y"], Some("Local branch release/1.2 does not exist."));
+    let output = env.run_ripit_subcommand(&["--bootstrap", "--branch", "release/*"]);
+    assert!(output.contains("Branches matching release/*: release/1.2.\n"));
+    assert!(local_branch("release/1.2").is_ok());
+
+    env.remote_repo.set_head("refs/heads/release/1.2").unwrap();
+    env.remote_repo.commit_file("b.txt", "b");
+    let output = env.run_ripit_subcommand(&["-y"]);
+    assert!(output.contains("Branches matching release/*: release/1.0, release/1.2.\n"));
+    let tip = local_branch("release/1.2")
+        .unwrap()
+        .get()
+        .peel_to_commit()
+        .unwrap();
+    assert_eq!(tip.summary(), Some("b"));
+}
+
+/// Test synchronizing remote branches in local branches of other names
+#[test]
+fn test_branch_names_mapping() {
+    let env = env::TestEnv::new(None);
+    env.set_cfg(
+        "remote: private\n\
+         branches:\n  - remote: master\n    source_ref: refs/heads/master\n",
+    );
+    env.run_ripit_failure(
+        &["--bootstrap"],
+        Some("branch master cannot have both a name and a full ref name"),
+    );
+
+    env.set_cfg(
+        "remote: private\n\
+         branches:\n  - remote: master\n    local: upstream-main\n\
+         \x20 - name: rel-2.0\n    remote: release/2.0\n",
+    );
+    let a = env.remote_repo.commit_file("a.txt", "a");
+    env.remote_repo.branch("release/2.0", &a, false).unwrap();
+
+    env.run_ripit_success(&["--bootstrap"]);
+    let local_tip = |name| {
+        env.local_repo
+            .find_branch(name, git2::BranchType::Local)
+            .unwrap()
+            .get()
+            .peel_to_commit()
+            .unwrap()
+    };
+    assert_eq!(
+        local_tip("upstream-main").summary(),
+        Some(format!("Bootstrap repository from private master at {}", a.id()).as_str())
+    );
+    assert!(local_tip("rel-2.0")
+        .message()
+        .unwrap()
+        .contains(&a.id().to_string()));
+
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y", "--branch", "upstream-main"]);
+    assert_eq!(local_tip("upstream-main").summary(), Some("b"));
+    assert!(env
+        .local_repo
+        .find_reference("refs/remotes/private/master")
+        .is_ok());
+}
+
+/// Test the synchronization of a branch that is not checked out
+#[test]
+fn test_sync_not_checked_out() {
+    let env = env::TestEnv::new(Some(&["dev"]));
+    env.setup_merge_solving_conflicts();
+    let c3 = env.remote_repo.revparse_single("c3").unwrap();
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo
+        .branch("dev", &c4.peel_to_commit().unwrap(), false)
+        .unwrap();
+    env.run_ripit_success(&["--bootstrap"]);
+
+    let local_tip = |name| {
+        env.local_repo
+            .find_branch(name, git2::BranchType::Local)
+            .unwrap()
+            .get()
+            .peel_to_commit()
+            .unwrap()
+    };
+    let master = local_tip("master");
+    let workdir = env.local_repo.workdir().unwrap().to_owned();
+
+    // the commits are copied on dev, without touching the checked out master
+    env.remote_repo.set_head("refs/heads/dev").unwrap();
+    env.remote_repo.reset_hard(&c4);
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(local_tip("dev").summary(), Some("b"));
+    assert_eq!(local_tip("dev").parent(0).unwrap().id(), master.id());
+    assert_eq!(local_tip("master").id(), master.id());
+    let head = env.local_repo.head().unwrap();
+    assert_eq!(head.name(), Some("refs/heads/master"));
+    assert!(!workdir.join("b.txt").exists());
+
+    // the conflicts of the uprooted commits cannot be solved without checking dev out
+    env.remote_repo
+        .do_merge(&c3.peel_to_commit().unwrap(), "c5");
+    env.run_ripit_failure(
+        &["-yu"],
+        Some("Conflicting paths:\n  c1\nBranch dev is not checked out"),
+    );
+    assert_eq!(local_tip("dev").summary(), Some("b"));
+    assert!(!env.local_repo.path().join("ripit").join("STATE").exists());
+    let head = env.local_repo.head().unwrap();
+    assert_eq!(head.name(), Some("refs/heads/master"));
+}
+
+/// Test the synchronization of a bare repository
+#[test]
+fn test_bare_repository() {
+    let env = env::TestEnv::new(None);
+    env.setup_merge_solving_conflicts();
+    let c3 = env.remote_repo.revparse_single("c3").unwrap();
+    let c4 = env.remote_repo.revparse_single("c4").unwrap();
+    env.remote_repo.reset_hard(&c4);
+
+    let dir = tempfile::tempdir().unwrap();
+    let bare = git2::Repository::init_bare(dir.path()).unwrap();
+    let mut config = bare.config().unwrap();
+    config.set_str("user.name", "Foo").unwrap();
+    config.set_str("user.email", "Bar").unwrap();
+    let url = env.remote_repo.workdir().unwrap().to_str().unwrap();
+    bare.remote("private", url).unwrap();
+    env.set_cfg(&format!(
+        "repo: {}\nremote: private\nbranch: master\n",
+        dir.path().display()
+    ));
+
+    let tip = || {
+        bare.revparse_single("master")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap()
+    };
+    env.run_ripit_success(&["--bootstrap"]);
+    assert!(tip().summary().unwrap().starts_with("Bootstrap"));
+
+    env.remote_repo.commit_file("b.txt", "b");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(tip().summary(), Some("b"));
+    assert!(tip().tree().unwrap().get_name("b.txt").is_some());
+
+    // the conflicts can only be solved in a worktree
+    env.remote_repo
+        .do_merge(&c3.peel_to_commit().unwrap(), "c5");
+    env.run_ripit_failure(
+        &["-yu"],
+        Some("Conflicting paths:\n  c1\nThe repository is bare"),
+    );
+    assert_eq!(tip().summary(), Some("b"));
+    env.run_ripit_failure(
+        &["-yu", "--detached-worktree"],
+        Some("Solve the conflicts and commit the resolutions in this worktree"),
+    );
+}
+
+/// Test the synchronization of several branches in parallel
+#[test]
+fn test_parallel_sync() {
+    let env = env::TestEnv::new(Some(&["a", "b", "c"]));
+    let base = env.remote_repo.commit_file("base.txt", "base");
+    for name in &["a", "b", "c"] {
+        env.remote_repo.branch(name, &base, false).unwrap();
+    }
+    env.run_ripit_success(&["--bootstrap"]);
+
+    env.remote_repo.set_head("refs/heads/a").unwrap();
+    let a1 = env.remote_repo.commit_file("a.txt", "a1");
+    env.remote_repo.commit_file("a.txt", "a2");
+    env.remote_repo.set_head("refs/heads/b").unwrap();
+    env.remote_repo.reset_hard(base.as_object());
+    env.remote_repo.commit_file("b.txt", "b1");
+    // c depends on the commits of a, and is synchronized after it
+    env.remote_repo.set_head("refs/heads/c").unwrap();
+    env.remote_repo.reset_hard(a1.as_object());
+    env.remote_repo.commit_file("c.txt", "c1");
+
+    env.run_ripit_success(&["-y", "--jobs", "3"]);
+    let local_tip = |name| {
+        env.local_repo
+            .find_branch(name, git2::BranchType::Local)
+            .unwrap()
+            .get()
+            .peel_to_commit()
+            .unwrap()
+    };
+    assert_eq!(local_tip("a").summary(), Some("a2"));
+    assert_eq!(local_tip("b").summary(), Some("b1"));
+    assert_eq!(local_tip("c").summary(), Some("c1"));
+    // a1 is only copied once
+    assert_eq!(
+        local_tip("c").parent_id(0).unwrap(),
+        local_tip("a").parent_id(0).unwrap()
+    );
+    let head = env.local_repo.head().unwrap();
+    assert_eq!(head.name(), Some("refs/heads/master"));
+
+    // a branch failing does not prevent synchronizing the others
+    env.local_repo.set_head("refs/heads/b").unwrap();
+    env.local_repo.reset_hard(local_tip("b").as_object());
+    env.local_repo.commit_file("local.txt", "local");
+    env.local_repo.set_head("refs/heads/master").unwrap();
+    env.local_repo.reset_hard(local_tip("master").as_object());
+    env.remote_repo.set_head("refs/heads/a").unwrap();
+    env.remote_repo.commit_file("a.txt", "a3");
+    env.remote_repo.set_head("refs/heads/b").unwrap();
+    env.remote_repo.commit_file("b.txt", "b2");
+    env.run_ripit_failure(
+        &["-y", "--jobs", "2"],
+        Some("Branch b contains 1 commits that do not come from the remote"),
+    );
+    assert_eq!(local_tip("a").summary(), Some("a3"));
+    assert_eq!(local_tip("b").summary(), Some("local"));
+}
+
+#[test]
+fn test_arbitrary_refs() {
+    let env = env::TestEnv::new(None);
+    env.add_cfg(
+        "branches:\n\
+         \x20 - name: pr-1\n\
+         \x20   source_ref: refs/pull/1/head\n\
+         \x20   local_ref: refs/review/pr-1\n",
+    );
+
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.remote_repo
+        .reference("refs/pull/1/head", c1.id(), true, "")
+        .unwrap();
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the remote ref is fetched outside of the remote-tracking branches
+    let tracking = env
+        .local_repo
+        .refname_to_id("refs/ripit/remotes/private/pull/1/head")
+        .unwrap();
+    assert_eq!(tracking, c1.id());
+    let bootstrap = env
+        .local_repo
+        .find_reference("refs/review/pr-1")
+        .unwrap()
+        .peel_to_commit()
+        .unwrap();
+    assert_eq!(bootstrap.tree_id(), c1.tree_id());
+
+    let c2 = env.remote_repo.commit_file("b", "b");
+    let c3 = env.remote_repo.commit_file("c", "c");
+    env.remote_repo
+        .reference("refs/pull/1/head", c3.id(), true, "")
+        .unwrap();
+    env.run_ripit_success(&["-y"]);
+
+    let c3_local = env
+        .local_repo
+        .find_reference("refs/review/pr-1")
+        .unwrap()
+        .peel_to_commit()
+        .unwrap();
+    assert_eq!(c3_local.tree_id(), c3.tree_id());
+    assert!(c3_local
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c3.id())));
+    let c2_local = c3_local.parent(0).unwrap();
+    assert!(c2_local
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c2.id())));
+    assert_eq!(c2_local.parent_id(0).unwrap(), bootstrap.id());
+
+    // invalid ref names are rejected
+    env.add_cfg(
+        "\x20 - name: bad\n\
+         \x20   local_ref: refs/review/bad..name\n",
+    );
+    env.run_ripit_failure(&["-y"], Some("invalid ref name 'refs/review/bad..name'"));
+}
+
+#[test]
+fn test_maintenance_commit_graph() {
+    let graph_path = |env: &env::TestEnv| env.local_repo.path().join("objects/info/commit-graph");
+
+    for enabled in &[false, true] {
+        let env = env::TestEnv::new(None);
+        if *enabled {
+            env.add_cfg("maintenance: commit-graph\nmaintenance_min_commits: 3\n");
+        }
+        env.remote_repo.commit_file("a", "a");
+        env.run_ripit_success(&["--bootstrap"]);
+
+        // below the threshold, nothing is done
+        env.remote_repo.commit_file("b", "b");
+        env.run_ripit_success(&["-y"]);
+        assert!(!graph_path(&env).exists());
+
+        for i in 0..3 {
+            env.remote_repo.commit_file(&format!("c{}", i), "c");
+        }
+        env.run_ripit_success(&["-y"]);
+        assert_eq!(graph_path(&env).exists(), *enabled);
+    }
+}
+
+#[test]
+fn test_from_bundle() {
+    let env = env::TestEnv::new(None);
+    let bundle = env.remote_repo.path().join("sync.bundle");
+    let bundle_path = bundle.to_str().unwrap();
+    let create_bundle = |revs: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(env.remote_repo.workdir().unwrap())
+            .args(["bundle", "create", "--quiet", bundle_path])
+            .args(revs)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    let c1 = env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+    let c2 = env.remote_repo.commit_file("b", "b");
+    let c3 = env.remote_repo.commit_file("c", "c");
+
+    // the remote can no longer be reached
+    env.local_repo.remote_delete("private").unwrap();
+
+    // the prerequisites of the bundle must be known locally
+    create_bundle(&["master", &format!("^{}", c2.id())]);
+    env.run_ripit_failure(
+        &["--from-bundle", bundle_path, "-y"],
+        Some(&format!("Cannot fetch from the bundle {}", bundle_path)),
+    );
+
+    // the bundle must contain the branches to synchronize
+    env.remote_repo.branch("dev", &c3, false).unwrap();
+    create_bundle(&["dev", &format!("^{}", c1.id())]);
+    env.run_ripit_failure(
+        &["--from-bundle", bundle_path, "-y"],
+        Some("does not contain the ref refs/heads/master to synchronize"),
+    );
+
+    create_bundle(&["master", &format!("^{}", c1.id())]);
+    env.run_ripit_success(&["--from-bundle", bundle_path, "-y"]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.tree_id(), c3.tree_id());
+    assert!(head
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c3.id())));
+}
+
+#[test]
+fn test_emit_bundle() {
+    let env = env::TestEnv::new(None);
+    let bundle = env.remote_repo.path().join("out.bundle");
+    let bundle_path = bundle.to_str().unwrap();
+
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // a copy of the local repository, not reachable from the mirror
+    let copy_dir = tempfile::tempdir().unwrap();
+    let copy = git2::Repository::init_bare(copy_dir.path()).unwrap();
+    let local_url = env.local_repo.path().to_str().unwrap().to_owned();
+    copy.remote_anonymous(&local_url)
+        .unwrap()
+        .fetch(&["+refs/heads/master:refs/heads/master"], None, None)
+        .unwrap();
+
+    env.remote_repo.commit_file("b", "b");
+    env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["--emit-bundle", bundle_path, "-y"]);
+
+    let status = std::process::Command::new("git")
+        .arg("--git-dir")
+        .arg(copy.path())
+        .args(["fetch", "--quiet", bundle_path, "master:master"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let local_head = env.local_repo.refname_to_id("refs/heads/master").unwrap();
+    assert_eq!(copy.refname_to_id("refs/heads/master").unwrap(), local_head);
+    let copied = copy.find_commit(local_head).unwrap();
+    assert_eq!(
+        copied.tree_id(),
+        env.local_repo.find_commit(local_head).unwrap().tree_id()
+    );
+    assert_eq!(
+        copied.message(),
+        env.local_repo.find_commit(local_head).unwrap().message()
+    );
+
+    // no bundle is written when nothing was synchronized
+    fs::remove_file(&bundle).unwrap();
+    let output = env.run_ripit_subcommand(&["--emit-bundle", bundle_path, "-y"]);
+    assert!(output.contains("no bundle written"));
+    assert!(!bundle.exists());
+}
+
+#[test]
+fn test_remote_mirrors() {
+    let env = env::TestEnv::new(None);
+    // nothing listens on the discard port
+    env.add_cfg(&format!(
+        "remote_urls:\n\
+         \x20 - http://127.0.0.1:9/dead.git\n\
+         \x20 - {}\n",
+        env.remote_repo.workdir().unwrap().display()
+    ));
+    // the remote itself is not used
+    env.local_repo.remote_delete("private").unwrap();
+
+    let c1 = env.remote_repo.commit_file("a", "a");
+    let output = env.run_ripit_subcommand(&["--bootstrap"]);
+    assert!(output.contains(&format!(
+        "Fetched from mirror {}.",
+        env.remote_repo.workdir().unwrap().display()
+    )));
+    let tracking = env
+        .local_repo
+        .refname_to_id("refs/remotes/private/master")
+        .unwrap();
+    assert_eq!(tracking, c1.id());
+
+    let c2 = env.remote_repo.commit_file("b", "b");
+    env.run_ripit_success(&["-y"]);
+    let head = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(head
+        .message()
+        .unwrap()
+        .ends_with(&format!("rip-it: {}\n", c2.id())));
+
+    // a missing repository does not fail over to the next mirror
+    env.set_cfg(&format!(
+        "remote: private\n\
+         remote_urls:\n\
+         \x20 - file:///nonexistent/ripit/mirror\n\
+         \x20 - {}\n",
+        env.remote_repo.workdir().unwrap().display()
+    ));
+    env.remote_repo.commit_file("c", "c");
+    env.run_ripit_failure(&["-y"], Some("Fetch failed"));
+}
+
+#[test]
+// forcing the interactive mode only exists in debug builds
+#[cfg_attr(not(debug_assertions), ignore)]
+fn test_edit_messages() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a", "a");
+    env.run_ripit_success(&["--bootstrap"]);
+
+    // the editor capitalizes the summaries, and empties the messages of commits to drop
+    let editor = env.remote_repo.path().join("editor.sh");
+    fs::write(
+        &editor,
+        "#!/bin/sh\n\
+         if grep -q '^drop' \"$1\"; then : > \"$1\"; else sed -i 's/^add/Add/' \"$1\"; fi\n",
+    )
+    .unwrap();
+    let editor_cmd = format!("sh {}", editor.display());
+    let envs = [("EDITOR", editor_cmd.as_str())];
+
+    // a terminal is required, and the prompts cannot be skipped
+    env.run_ripit_failure(&["--edit-messages"], Some("can only be used in a terminal"));
+    env.run_ripit_failure(&["--edit-messages", "-y"], Some("cannot be used with"));
+
+    let c1 = env.remote_repo.commit_file("b", "add b");
+    let c2 = env.remote_repo.commit_file("c", "drop c");
+    let c3 = env.remote_repo.commit_file("d", "add d");
+    env.run_ripit_interactive(true, &["--edit-messages"], "y\ny\n", &envs);
+
+    assert_eq!(env.local_repo.count_commits(), 3);
+    let d = env.local_repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        d.message(),
+        Some(&*format!("Add d\n\nrip-it: {}\n", c3.id()))
+    );
+    let b = d.parent(0).unwrap();
+    assert_eq!(
+        b.message(),
+        Some(&*format!("Add b\n\nrip-it: {}\n", c1.id()))
+    );
+    // the skipped commit is recorded
+    let skipped = fs::read_to_string(env.skipped_path()).unwrap();
+    assert!(skipped.contains(&format!("{} empty message", c2.id())));
+
+    // failing the editor aborts the synchronization before copying anything
+    env.remote_repo.commit_file("e", "add e");
+    env.remote_repo.commit_file("f", "add f");
+    env.run_ripit_interactive(false, &["--edit-messages"], "y\n", &[("EDITOR", "false")]);
+    assert_eq!(env.local_repo.count_commits(), 3);
+    assert_eq!(env.local_repo.head().unwrap().target(), Some(d.id()));
+}
+
+#[test]
+fn test_shallow_clone() {
+    let env = env::TestEnv::new(None);
+    env.remote_repo.commit_file("a", "a");
+    env.remote_repo.commit_file("b", "b");
+    let remote_dir = env
+        .remote_repo
+        .workdir()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let local_dir = env.local_repo.workdir().unwrap();
+
+    // the history of a shallow remote cannot be fetched
+    let shallow_dir = tempfile::tempdir().unwrap();
+    let url = format!("file://{}", remote_dir);
+    env::run_git(
+        shallow_dir.path(),
+        &["clone", "--quiet", "--depth", "1", &url, "."],
+    );
+    let shallow_url = shallow_dir.path().to_str().unwrap();
+    env.local_repo
+        .remote_set_url("private", shallow_url)
+        .unwrap();
+    env.run_ripit_failure(
+        &["--bootstrap"],
+        Some(&format!(
+            "The remote private is a shallow clone, whose history cannot be fetched by \
+             ripit. Fetch its whole history with `git fetch --unshallow` in {} first.",
+            shallow_url
+        )),
+    );
+
+    // a shallow local repository is refused, even without fetching
+    env.local_repo.remote_set_url("private", &url).unwrap();
+    env::run_git(local_dir, &["fetch", "--quiet", "--depth", "1", "private"]);
+    env.run_ripit_failure(
+        &["--bootstrap", "-F"],
+        Some("is a shallow clone, but ripit needs the whole history"),
+    );
+    assert!(env.local_repo.head().is_err());
+
+    env::run_git(local_dir, &["fetch", "--quiet", "--unshallow", "private"]);
+    env.run_ripit_success(&["--bootstrap"]);
+    env.remote_repo.commit_file("c", "c");
+    env.run_ripit_success(&["-y"]);
+    assert_eq!(env.local_repo.count_commits(), 2);
+}